@@ -0,0 +1,450 @@
+use spin::Once;
+use x86_64::PhysAddr;
+
+use crate::{memory, platform};
+
+/// 最多记录的 CPU 个数。
+pub const MAX_CPUS: usize = 64;
+/// 最多记录的 IO APIC 个数。
+pub const MAX_IO_APICS: usize = 8;
+/// 最多记录的中断源重定向条目数。
+pub const MAX_OVERRIDES: usize = 16;
+/// 最多记录的 ACPI 表个数。
+pub const MAX_TABLES: usize = 32;
+
+// 所有系统描述表共用的头部长度
+const HEADER_LEN: usize = 36;
+// BIOS 区域中 RSDP 所在的范围，以及 EBDA 段地址所在的位置
+const BIOS_AREA: (u64, u64) = (0xe_0000, 0x10_0000);
+const EBDA_SEGMENT: u64 = 0x40e;
+const EBDA_LEN: u64 = 1024;
+// ACPI 2.0 之后 RSDP 的完整长度
+const RSDP_V2_LEN: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// 引导程序没有给出 RSDP，EBDA 和 BIOS 区域中也找不到
+    NoRsdp,
+    /// 表的地址没有映射
+    NotMapped(PhysAddr),
+    /// 表的长度比它的结构短
+    Truncated([u8; 4]),
+    /// 校验和不为 0
+    BadChecksum([u8; 4]),
+}
+
+/// 系统描述表的头部。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+}
+
+/// 一张已经校验过的 ACPI 表。
+#[derive(Debug, Clone, Copy)]
+pub struct Table {
+    pub address: PhysAddr,
+    pub header: SdtHeader,
+    /// 包括头部在内的全部内容
+    pub data: &'static [u8],
+}
+
+/// MADT 中的一个处理器（Local APIC）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cpu {
+    pub acpi_id: u32,
+    pub apic_id: u32,
+    /// 固件已经启用；为 `false` 时只是可以上线
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// 第一个输入引脚对应的全局中断号
+    pub gsi_base: u32,
+}
+
+/// ISA 中断 `source` 实际连到全局中断 `gsi` 上。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    /// MPS INTI 标志：位 0-1 是极性，位 2-3 是触发方式
+    pub flags: u16,
+}
+
+/// MADT（签名 `APIC`）中与 APIC 和 SMP 相关的信息。
+#[derive(Debug, Clone, Default)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// 同时装有 8259 PIC，使用 IO APIC 前要屏蔽它
+    pub has_8259: bool,
+    pub cpus: heapless::Vec<Cpu, MAX_CPUS>,
+    pub io_apics: heapless::Vec<IoApic, MAX_IO_APICS>,
+    pub overrides: heapless::Vec<InterruptOverride, MAX_OVERRIDES>,
+}
+
+impl Madt {
+    /// ISA 中断 `irq` 对应的全局中断号和标志，没有重定向时两者相同。
+    pub fn isa_irq(&self, irq: u8) -> (u32, u16) {
+        self.overrides
+            .iter()
+            .find(|entry| entry.source == irq)
+            .map_or((irq as u32, 0), |entry| (entry.gsi, entry.flags))
+    }
+}
+
+/// ACPI 的通用地址结构（GAS）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    /// 0 是内存，1 是 I/O 端口
+    pub space: u8,
+    pub bit_width: u8,
+    pub address: u64,
+}
+
+/// FADT（签名 `FACP`）中电源管理用到的字段。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    pub dsdt: PhysAddr,
+    pub flags: u32,
+    /// ACPI 2.0 之后的复位寄存器，`flags` 的位 10 表示它可用
+    pub reset: Option<(GenericAddress, u8)>,
+}
+
+/// 启动时从固件读到的 ACPI 信息。
+#[derive(Debug)]
+pub struct Acpi {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub tables: heapless::Vec<Table, MAX_TABLES>,
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+}
+
+impl Acpi {
+    /// 签名为 `signature` 的第一张表。
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&Table> {
+        self.tables
+            .iter()
+            .find(|table| &table.header.signature == signature)
+    }
+}
+
+// 初始化后只读，APIC 和 SMP 的代码直接读取
+static ACPI: Once<Acpi> = Once::new();
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// 物理内存 `address` 开始的 `len` 字节。范围内的每一页都已经映射时才返回。
+fn phys_slice(address: PhysAddr, len: usize) -> Result<&'static [u8], AcpiError> {
+    let start = memory::phys_to_virt(address);
+    let mut page = start.align_down(memory::PAGE_SIZE);
+    while page < start + len as u64 {
+        if !memory::is_mapped(page) {
+            return Err(AcpiError::NotMapped(address));
+        }
+        page += memory::PAGE_SIZE;
+    }
+    Ok(unsafe { core::slice::from_raw_parts(start.as_ptr(), len) })
+}
+
+fn parse_header(data: &[u8]) -> Option<SdtHeader> {
+    Some(SdtHeader {
+        signature: data.get(0..4)?.try_into().ok()?,
+        length: u32_at(data, 4)?,
+        revision: *data.get(8)?,
+        oem_id: data.get(10..16)?.try_into().ok()?,
+    })
+}
+
+/// 读取并校验物理地址 `address` 处的一张表。
+fn table_at(address: PhysAddr) -> Result<Table, AcpiError> {
+    let header = parse_header(phys_slice(address, HEADER_LEN)?).unwrap();
+    if (header.length as usize) < HEADER_LEN {
+        return Err(AcpiError::Truncated(header.signature));
+    }
+    let data = phys_slice(address, header.length as usize)?;
+    if checksum(data) != 0 {
+        return Err(AcpiError::BadChecksum(header.signature));
+    }
+    Ok(Table {
+        address,
+        header,
+        data,
+    })
+}
+
+/// RSDP 指向的根表地址，以及它是 XSDT（8 字节条目）还是 RSDT。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rsdp {
+    revision: u8,
+    oem_id: [u8; 6],
+    root: PhysAddr,
+    extended: bool,
+}
+
+/// 校验 `data` 开头的 RSDP：前 20 字节是 ACPI 1.0 的结构，2.0 之后整个 36
+/// 字节也要校验，并且改用 64 位的 XSDT 地址。
+fn parse_rsdp(data: &[u8]) -> Option<Rsdp> {
+    if !data.starts_with(b"RSD PTR ") || checksum(data.get(..20)?) != 0 {
+        return None;
+    }
+    let revision = data[15];
+    let oem_id = data[9..15].try_into().ok()?;
+    if revision >= 2 {
+        let length = u32_at(data, 20)? as usize;
+        let xsdt = u64_at(data, 24)?;
+        if length >= 36 && checksum(data.get(..length)?) == 0 && xsdt != 0 {
+            return Some(Rsdp {
+                revision,
+                oem_id,
+                root: PhysAddr::new(xsdt),
+                extended: true,
+            });
+        }
+    }
+    Some(Rsdp {
+        revision,
+        oem_id,
+        root: PhysAddr::new(u32_at(data, 16)? as u64),
+        extended: false,
+    })
+}
+
+/// 引导程序给出了 RSDP 地址（UEFI 启动）时直接用它，否则在 EBDA 的前 1 KiB
+/// 和 0xe0000..0x100000 中按 16 字节对齐查找。
+fn find_rsdp() -> Option<Rsdp> {
+    if let Some(addr) = platform::rsdp() {
+        return phys_slice(addr, RSDP_V2_LEN).ok().and_then(parse_rsdp);
+    }
+    let ebda = phys_slice(PhysAddr::new(EBDA_SEGMENT), 2)
+        .ok()
+        .and_then(|bytes| u16_at(bytes, 0))
+        .map(|segment| (segment as u64) << 4);
+    let areas = [ebda.map(|start| (start, start + EBDA_LEN)), Some(BIOS_AREA)];
+    areas.into_iter().flatten().find_map(|(start, end)| {
+        let area = phys_slice(PhysAddr::new(start), (end - start) as usize).ok()?;
+        (0..area.len())
+            .step_by(16)
+            .find_map(|offset| parse_rsdp(&area[offset..]))
+    })
+}
+
+/// 解析 MADT 的内容，`data` 包括表头。
+fn parse_madt(data: &[u8]) -> Option<Madt> {
+    let mut madt = Madt {
+        local_apic_address: u32_at(data, HEADER_LEN)? as u64,
+        has_8259: u32_at(data, HEADER_LEN + 4)? & 1 != 0,
+        ..Madt::default()
+    };
+    let mut offset = HEADER_LEN + 8;
+    while offset + 2 <= data.len() {
+        let (kind, len) = (data[offset], data[offset + 1] as usize);
+        let Some(entry) = data.get(offset..offset + len).filter(|_| len >= 2) else {
+            break;
+        };
+        offset += len;
+        match kind {
+            // Processor Local APIC
+            0 => {
+                let flags = u32_at(entry, 4)?;
+                let cpu = Cpu {
+                    acpi_id: *entry.get(2)? as u32,
+                    apic_id: *entry.get(3)? as u32,
+                    enabled: flags & 1 != 0,
+                };
+                // 位 1（Online Capable）为 0 的禁用处理器永远不能使用
+                if flags & 0b11 != 0 {
+                    let _ = madt.cpus.push(cpu);
+                }
+            }
+            1 => {
+                let _ = madt.io_apics.push(IoApic {
+                    id: *entry.get(2)?,
+                    address: PhysAddr::new(u32_at(entry, 4)? as u64),
+                    gsi_base: u32_at(entry, 8)?,
+                });
+            }
+            2 => {
+                let _ = madt.overrides.push(InterruptOverride {
+                    source: *entry.get(3)?,
+                    gsi: u32_at(entry, 4)?,
+                    flags: u16_at(entry, 8)?,
+                });
+            }
+            // Local APIC Address Override
+            5 => madt.local_apic_address = u64_at(entry, 4)?,
+            // Processor Local x2APIC
+            9 => {
+                let flags = u32_at(entry, 8)?;
+                if flags & 0b11 != 0 {
+                    let _ = madt.cpus.push(Cpu {
+                        acpi_id: u32_at(entry, 12)?,
+                        apic_id: u32_at(entry, 4)?,
+                        enabled: flags & 1 != 0,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(madt)
+}
+
+/// 解析 FADT 的内容，`data` 包括表头。
+fn parse_fadt(data: &[u8]) -> Option<Fadt> {
+    let flags = u32_at(data, 112)?;
+    // ACPI 2.0 之后的字段，表太短时当作不存在
+    let reset = data.get(116..129).and_then(|gas| {
+        (flags & (1 << 10) != 0).then(|| {
+            let address = GenericAddress {
+                space: gas[0],
+                bit_width: gas[1],
+                address: u64_at(gas, 4).unwrap(),
+            };
+            (address, gas[12])
+        })
+    });
+    let dsdt = u64_at(data, 140)
+        .filter(|&address| address != 0)
+        .unwrap_or(u32_at(data, 40)? as u64);
+    Some(Fadt {
+        sci_interrupt: u16_at(data, 46)?,
+        smi_command: u32_at(data, 48)?,
+        acpi_enable: *data.get(52)?,
+        pm1a_control: u32_at(data, 64)?,
+        pm1b_control: u32_at(data, 68)?,
+        dsdt: PhysAddr::new(dsdt),
+        flags,
+        reset,
+    })
+}
+
+fn discover() -> Result<Acpi, AcpiError> {
+    let rsdp = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+    let root = table_at(rsdp.root)?;
+    let entry_len = if rsdp.extended { 8 } else { 4 };
+    let mut acpi = Acpi {
+        revision: rsdp.revision,
+        oem_id: rsdp.oem_id,
+        tables: heapless::Vec::new(),
+        madt: None,
+        fadt: None,
+    };
+    for entry in root.data[HEADER_LEN..].chunks_exact(entry_len) {
+        let address = match *entry {
+            [a, b, c, d] => u32::from_le_bytes([a, b, c, d]) as u64,
+            _ => u64::from_le_bytes(entry.try_into().unwrap()),
+        };
+        let table = match table_at(PhysAddr::new(address)) {
+            Ok(table) => table,
+            Err(err) => {
+                log::warn!("acpi: skipping table at {address:#x}: {err:?}");
+                continue;
+            }
+        };
+        match &table.header.signature {
+            b"APIC" => acpi.madt = parse_madt(table.data),
+            b"FACP" => acpi.fadt = parse_fadt(table.data),
+            _ => {}
+        }
+        if acpi.tables.push(table).is_err() {
+            break;
+        }
+    }
+    // DSDT 不在根表里，只能通过 FADT 找到
+    if let Some(fadt) = acpi.fadt
+        && let Ok(dsdt) = table_at(fadt.dsdt)
+    {
+        let _ = acpi.tables.push(dsdt);
+    }
+    Ok(acpi)
+}
+
+/// 查找并解析 ACPI 表。需要 `memory::init` 已经建立物理内存映射。
+pub fn init() {
+    match discover() {
+        Ok(acpi) => {
+            let acpi = ACPI.call_once(|| acpi);
+            log::info!(
+                "acpi: revision {}, {} tables, {} cpus, {} io apics",
+                acpi.revision,
+                acpi.tables.len(),
+                acpi.madt.as_ref().map_or(0, |madt| madt.cpus.len()),
+                acpi.madt.as_ref().map_or(0, |madt| madt.io_apics.len())
+            );
+        }
+        Err(err) => log::warn!("acpi: {err:?}"),
+    }
+}
+
+/// 启动时读到的 ACPI 信息；固件没有提供或者还没有初始化时返回 `None`。
+pub fn get() -> Option<&'static Acpi> {
+    ACPI.r#try()
+}
+
+#[test_case]
+fn test_acpi_madt() {
+    let mut data = [0u8; HEADER_LEN + 8 + 8 + 8 + 12 + 10];
+    data[..4].copy_from_slice(b"APIC");
+    let len = data.len() as u32;
+    data[4..8].copy_from_slice(&len.to_le_bytes());
+    data[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&0xfee0_0000u32.to_le_bytes());
+    data[HEADER_LEN + 4] = 1;
+    let entries = &mut data[HEADER_LEN + 8..];
+    // 两个处理器，第二个既没有启用也不能上线
+    entries[..8].copy_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+    entries[8..16].copy_from_slice(&[0, 8, 1, 1, 0, 0, 0, 0]);
+    entries[16..28].copy_from_slice(&[1, 12, 2, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0]);
+    entries[28..38].copy_from_slice(&[2, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+
+    let madt = parse_madt(&data).unwrap();
+    assert_eq!(madt.local_apic_address, 0xfee0_0000);
+    assert!(madt.has_8259);
+    assert_eq!(
+        madt.cpus.as_slice(),
+        &[Cpu {
+            acpi_id: 0,
+            apic_id: 0,
+            enabled: true
+        }]
+    );
+    assert_eq!(madt.io_apics[0].address, PhysAddr::new(0xfec0_0000));
+    assert_eq!(madt.isa_irq(0), (2, 0));
+    assert_eq!(madt.isa_irq(1), (1, 0));
+
+    // QEMU 总是提供 ACPI
+    let acpi = get().expect("no ACPI tables");
+    assert!(acpi.find(b"APIC").is_some());
+    assert!(!acpi.madt.as_ref().unwrap().cpus.is_empty());
+}
@@ -0,0 +1,236 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use x86_64::PhysAddr;
+
+use crate::{
+    acpi, cmdline,
+    cpu::{self, Feature},
+    interrupts, memory,
+    regs::{self, msr},
+    time,
+};
+
+/// 伪中断（spurious interrupt）使用的向量，低 4 位必须全为 1。
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+/// Local APIC 定时器使用的向量，紧跟在两片 PIC 的向量之后。
+pub const TIMER_VECTOR: u8 = 0x30;
+
+// 寄存器相对于 Local APIC 基址的偏移
+const ID: usize = 0x20;
+const EOI: usize = 0xb0;
+const SPURIOUS: usize = 0xf0;
+// 在服务寄存器（ISR），8 个 32 位寄存器覆盖 256 个向量，间隔 16 字节
+const ISR: usize = 0x100;
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const TIMER_INITIAL: usize = 0x380;
+const TIMER_CURRENT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3e0;
+
+// 伪中断寄存器中的软件使能位
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+// ICR 中的投递模式和状态位
+const DELIVERY_INIT: u32 = 0b101 << 8;
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+const LEVEL_ASSERT: u32 = 1 << 14;
+const DELIVERY_PENDING: u32 = 1 << 12;
+// 等 IPI 送达的最长时间，正常情况下只需要几微秒
+const IPI_TIMEOUT_US: u64 = 1000;
+// 本地向量表（LVT）中的屏蔽位和定时器的周期模式位
+const LVT_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+// 定时器按总线时钟的 16 分频计数
+const DIVIDE_BY_16: u32 = 0b0011;
+// 校准时让定时器计数的长度
+const CALIBRATE_MS: u64 = 10;
+
+// APIC_BASE MSR 中基址所在的位
+const BASE_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+// 所有 CPU 的 Local APIC 都在同一个物理地址，各自只能看到自己的那一个
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+// 定时器每毫秒的计数（分频后），各 CPU 的总线时钟相同，校准一次即可
+static TIMER_PER_MS: AtomicU64 = AtomicU64::new(0);
+// 系统时钟是否已经由 Local APIC 定时器驱动
+static TIMER_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// 定时器的工作方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// 计到 0 时产生一次中断后停止
+    OneShot,
+    /// 计到 0 时产生中断并重新开始计数
+    Periodic,
+}
+
+fn read(offset: usize) -> u32 {
+    let base = BASE.load(Ordering::Relaxed) as *const u32;
+    unsafe { base.byte_add(offset).read_volatile() }
+}
+
+fn write(offset: usize, value: u32) {
+    let base = BASE.load(Ordering::Relaxed) as *mut u32;
+    unsafe { base.byte_add(offset).write_volatile(value) };
+}
+
+/// 映射 BSP 的 Local APIC 并打开它。地址优先取 MADT 中的，没有 ACPI 时读 MSR。
+pub fn init() {
+    if !cpu::has(Feature::Apic) {
+        log::warn!("apic: no local APIC");
+        return;
+    }
+    let address = acpi::get()
+        .and_then(|acpi| acpi.madt.as_ref())
+        .map(|madt| madt.local_apic_address)
+        .unwrap_or_else(|| unsafe { regs::read_msr(msr::APIC_BASE) } & BASE_MASK);
+    let Some(base) = memory::map_mmio(PhysAddr::new(address), memory::PAGE_SIZE) else {
+        log::warn!("apic: failed to map local APIC at {address:#x}");
+        return;
+    };
+    BASE.store(base.as_u64(), Ordering::Relaxed);
+    init_ap();
+}
+
+/// 打开当前 CPU 的 Local APIC。AP 共用 BSP 建立的映射。
+pub fn init_ap() {
+    write(SPURIOUS, SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+/// Local APIC 是否已经映射。
+pub fn available() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+/// 当前 CPU 的 APIC ID。
+pub fn id() -> u32 {
+    read(ID) >> 24
+}
+
+/// 通知 Local APIC 当前中断已经处理完。
+pub fn end_of_interrupt() {
+    write(EOI, 0);
+}
+
+/// 当前 CPU 正在服务（已经投递、还没有收到 EOI）的最高向量。
+pub fn in_service() -> Option<u8> {
+    if !available() {
+        return None;
+    }
+    (0..8usize).rev().find_map(|index| {
+        let bits = read(ISR + index * 0x10);
+        (bits != 0).then(|| (index * 32 + 31 - bits.leading_zeros() as usize) as u8)
+    })
+}
+
+/// 以已经校准好的 TSC 为准数出定时器每毫秒的计数。定时器在这期间是屏蔽的。
+fn calibrate_timer() -> u64 {
+    write(TIMER_DIVIDE, DIVIDE_BY_16);
+    write(LVT_TIMER, LVT_MASKED | TIMER_VECTOR as u32);
+    write(TIMER_INITIAL, u32::MAX);
+    time::delay_us(CALIBRATE_MS * 1000);
+    let elapsed = u32::MAX - read(TIMER_CURRENT);
+    write(TIMER_INITIAL, 0);
+    elapsed as u64 / CALIBRATE_MS
+}
+
+/// `us` 微秒对应的初始计数，至少为 1（写入 0 会停止定时器）。
+fn timer_count(us: u64, per_ms: u64) -> u32 {
+    (us * per_ms / 1000).clamp(1, u32::MAX as u64) as u32
+}
+
+/// 校准 BSP 的定时器，并让它代替 PIT 以 `time::TICK_HZ` 产生系统时钟。
+/// 之后 PIT 只用于校准。命令行带 `noapictimer` 时继续使用 PIT。
+///
+/// 要在 [`init`] 和 `time::init` 之后、启动 AP 之前调用，AP 据此决定是否
+/// 打开自己的定时器。
+pub fn init_timer() {
+    if !available() || cmdline::flag("noapictimer") {
+        return;
+    }
+    let per_ms = calibrate_timer();
+    if per_ms == 0 {
+        log::warn!("apic: timer did not count, keeping the PIT");
+        return;
+    }
+    TIMER_PER_MS.store(per_ms, Ordering::Relaxed);
+    start_timer(TimerMode::Periodic, 1_000_000 / time::TICK_HZ);
+    interrupts::mask_irq(0);
+    TIMER_ACTIVE.store(true, Ordering::Relaxed);
+    log::info!("apic: timer at {per_ms} kHz drives the system tick");
+}
+
+/// 系统时钟是否由 Local APIC 定时器驱动。
+pub fn timer_active() -> bool {
+    TIMER_ACTIVE.load(Ordering::Relaxed)
+}
+
+/// 定时器每毫秒的计数，还没有校准时为 0。
+pub fn timer_per_ms() -> u64 {
+    TIMER_PER_MS.load(Ordering::Relaxed)
+}
+
+/// 让当前 CPU 的定时器在 `us` 微秒后（周期模式下每隔 `us` 微秒）产生
+/// `TIMER_VECTOR` 中断。定时器是每个 CPU 私有的。
+pub fn start_timer(mode: TimerMode, us: u64) {
+    let mode = match mode {
+        TimerMode::OneShot => 0,
+        TimerMode::Periodic => TIMER_PERIODIC,
+    };
+    write(TIMER_DIVIDE, DIVIDE_BY_16);
+    write(LVT_TIMER, mode | TIMER_VECTOR as u32);
+    // 写初始计数时开始计时
+    write(TIMER_INITIAL, timer_count(us, timer_per_ms()));
+}
+
+/// 停止当前 CPU 的定时器。
+pub fn stop_timer() {
+    write(LVT_TIMER, LVT_MASKED | TIMER_VECTOR as u32);
+    write(TIMER_INITIAL, 0);
+}
+
+/// 发送一个处理器间中断，等它送达后返回。`IPI_TIMEOUT_US` 内没有送达
+/// 返回 false。
+fn send_ipi(apic_id: u32, command: u32) -> bool {
+    write(ICR_HIGH, apic_id << 24);
+    // 写低 32 位时才真正发送
+    write(ICR_LOW, command);
+    let deadline = time::tsc() + IPI_TIMEOUT_US * time::tsc_per_ms() / 1000;
+    while read(ICR_LOW) & DELIVERY_PENDING != 0 {
+        if time::tsc() > deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// 让 `apic_id` 对应的 CPU 复位并等待 SIPI。
+pub fn send_init(apic_id: u32) -> bool {
+    send_ipi(apic_id, DELIVERY_INIT | LEVEL_ASSERT)
+}
+
+/// 让处于等待状态的 CPU 从物理地址 `vector * 4096` 开始以实模式执行。
+pub fn send_startup(apic_id: u32, vector: u8) -> bool {
+    send_ipi(apic_id, DELIVERY_STARTUP | vector as u32)
+}
+
+#[test_case]
+fn test_apic_timer() {
+    assert_eq!(timer_count(10_000, 6250), 62500);
+    // 太短的时间至少计 1，太长的时间截断到 32 位
+    assert_eq!(timer_count(0, 6250), 1);
+    assert_eq!(timer_count(u32::MAX as u64 * 1000, 6250), u32::MAX);
+    if !timer_active() {
+        return;
+    }
+    assert!(timer_per_ms() > 0);
+    let ticks = &crate::per_cpu!(stats).timer_ticks;
+    let before = ticks.load(Ordering::Relaxed);
+    let start = time::ticks();
+    while time::ticks() < start + 2 {
+        x86_64::instructions::hlt();
+    }
+    assert!(ticks.load(Ordering::Relaxed) > before);
+}
@@ -0,0 +1,237 @@
+use core::arch::asm;
+
+use x86_64::VirtAddr;
+
+use crate::{memory, serial_println};
+
+// 链接后由 tools/embed_symbols.py 改写：填入 .text
+// 的范围和按地址排序的函数符号。 布局：头部之后是 `count` 个
+// `Entry`，紧接着是所有名字拼在一起的字符串
+#[repr(C)]
+struct SymbolTable {
+    magic: [u8; 8],
+    text_start: u64,
+    text_len: u64,
+    count: u64,
+    data: [u8; SYMBOL_SPACE],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Entry {
+    addr: u64,
+    // 名字在字符串区中的偏移和长度
+    name_offset: u32,
+    name_len: u32,
+}
+
+const MAGIC: [u8; 8] = *b"OSRSYMS\0";
+
+/// 为符号表预留的空间，放不下时 embed_symbols.py 会报错。
+pub const SYMBOL_SPACE: usize = 512 * 1024;
+
+#[used]
+#[unsafe(link_section = ".kernel_symbols")]
+static SYMBOLS: SymbolTable = SymbolTable {
+    magic: MAGIC,
+    text_start: 0,
+    text_len: 0,
+    count: 0,
+    data: [0; SYMBOL_SPACE],
+};
+
+/// 最多打印的栈帧数。
+pub const MAX_FRAMES: usize = 32;
+
+// 相邻两个栈帧的距离超过它就认为帧指针链已经坏了
+const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+/// 链接后嵌入的符号表。
+#[derive(Clone, Copy)]
+pub struct Symbols {
+    text_start: u64,
+    text_end: u64,
+    entries: &'static [Entry],
+    names: &'static [u8],
+}
+
+impl Symbols {
+    /// 镜像没有经过 embed_symbols.py 处理时返回 `None`。
+    pub fn embedded() -> Option<Symbols> {
+        // 编译器知道 SYMBOLS 的初始值，经过 black_box 的指针读到的才是回填后的内容
+        let table = core::hint::black_box(&raw const SYMBOLS);
+        let (magic, text_start, text_len, count) = unsafe {
+            (
+                (*table).magic,
+                (*table).text_start,
+                (*table).text_len,
+                (*table).count as usize,
+            )
+        };
+        let entries_len = count.checked_mul(size_of::<Entry>())?;
+        if magic != MAGIC || text_len == 0 || entries_len > SYMBOL_SPACE {
+            return None;
+        }
+        let data = unsafe { &(*table).data };
+        let entries = unsafe { core::slice::from_raw_parts(data.as_ptr().cast::<Entry>(), count) };
+        Some(Symbols {
+            text_start,
+            text_end: text_start + text_len,
+            entries,
+            names: &data[entries_len..],
+        })
+    }
+
+    /// `addr` 是否在内核的 .text 中。
+    pub fn in_text(&self, addr: u64) -> bool {
+        (self.text_start..self.text_end).contains(&addr)
+    }
+
+    /// `addr` 所在的函数名和它在函数内的偏移。
+    pub fn resolve(&self, addr: u64) -> Option<(&'static str, u64)> {
+        if !self.in_text(addr) {
+            return None;
+        }
+        // 最后一个起始地址不大于 `addr` 的符号
+        let index = self.entries.partition_point(|entry| entry.addr <= addr);
+        let entry = self.entries.get(index.checked_sub(1)?)?;
+        let start = entry.name_offset as usize;
+        let name = self.names.get(start..start + entry.name_len as usize)?;
+        Some((core::str::from_utf8(name).ok()?, addr - entry.addr))
+    }
+}
+
+/// 沿着帧指针链向上，把每一帧的返回地址交给 `f`。
+///
+/// 内核编译时保留了帧指针（见 x86_64-os.json 的 `frame-pointer`），每一帧的
+/// `[rbp]` 是调用者的 rbp，`[rbp + 8]` 是返回地址。
+#[inline(never)]
+pub fn walk(f: impl FnMut(u64)) {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+    walk_from(rbp, f);
+}
+
+/// 从帧指针 `rbp` 开始向上走，用于打印另一个上下文（比如触发异常的代码）的
+/// 调用栈。链上的每一帧都先确认已经映射再读，坏的 `rbp` 只会让遍历提前
+/// 结束，不会在 panic 或崩溃报告中再触发异常。
+pub fn walk_from(mut rbp: u64, mut f: impl FnMut(u64)) {
+    let symbols = Symbols::embedded();
+    for _ in 0..MAX_FRAMES {
+        // rbp 按 8 字节对齐时 `[rbp + 8]` 的 8 个字节不会跨页
+        if rbp == 0 || rbp % 8 != 0 || !readable(rbp) || !readable(rbp + 8) {
+            break;
+        }
+        let (next, return_addr) = unsafe {
+            let frame = rbp as *const u64;
+            (frame.read(), frame.add(1).read())
+        };
+        // 有符号表时，返回地址离开 .text 说明已经走到了栈底
+        if return_addr == 0 || symbols.is_some_and(|symbols| !symbols.in_text(return_addr)) {
+            break;
+        }
+        f(return_addr);
+        // 栈向下增长，调用者的帧总在更高的地址
+        if next <= rbp || next - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next;
+    }
+}
+
+fn readable(addr: u64) -> bool {
+    VirtAddr::try_new(addr).is_ok_and(memory::is_mapped)
+}
+
+/// 通过串口打印当前的调用栈。
+pub fn print() {
+    print_frames(None, |f| walk(f));
+}
+
+/// 通过串口打印触发异常的代码的调用栈：第一帧是出错的指令 `rip`，然后
+/// 沿着它的帧指针 `rbp` 向上走。
+pub fn print_from(rip: u64, rbp: u64) {
+    print_frames(Some(rip), |f| walk_from(rbp, f));
+}
+
+fn print_frames(rip: Option<u64>, walker: impl FnOnce(&mut dyn FnMut(u64))) {
+    let symbols = Symbols::embedded();
+    serial_println!("backtrace:");
+    let mut depth = 0;
+    // 按 `lookup` 查符号，显示 `addr`
+    let mut print = |addr: u64, lookup: u64| {
+        match symbols.and_then(|symbols| symbols.resolve(lookup)) {
+            Some((name, offset)) => serial_println!(
+                "  #{:<2} {:#018x} {}+{:#x}",
+                depth,
+                addr,
+                name,
+                offset + (addr - lookup)
+            ),
+            None => serial_println!("  #{:<2} {:#018x} ??", depth, addr),
+        }
+        depth += 1;
+    };
+    if let Some(rip) = rip {
+        print(rip, rip);
+    }
+    // 返回地址指向 call 的下一条指令，减一才落在调用者的函数里
+    walker(&mut |return_addr| print(return_addr, return_addr - 1));
+    if symbols.is_none() {
+        serial_println!("  (no symbol table, run tools/embed_symbols.py on the kernel)");
+    }
+}
+
+#[test_case]
+fn test_backtrace_walk() {
+    #[inline(never)]
+    fn depth() -> usize {
+        let mut frames = 0;
+        walk(|_| frames += 1);
+        frames
+    }
+
+    #[inline(never)]
+    fn nested() -> usize {
+        core::hint::black_box(depth())
+    }
+
+    let direct = depth();
+    assert!(direct > 0);
+    // 多一层调用，多一帧
+    assert_eq!(nested(), (direct + 1).min(MAX_FRAMES));
+
+    // 没有映射的和不规范的帧指针都不会被读
+    let mut frames = 0;
+    walk_from(0x7fff_dead_0000, |_| frames += 1);
+    walk_from(0x8000_0000_0000, |_| frames += 1);
+    assert_eq!(frames, 0);
+}
+
+#[test_case]
+fn test_backtrace_resolve() {
+    static NAMES: &[u8] = b"firstsecond";
+    static ENTRIES: [Entry; 2] = [
+        Entry {
+            addr: 0x1000,
+            name_offset: 0,
+            name_len: 5,
+        },
+        Entry {
+            addr: 0x1040,
+            name_offset: 5,
+            name_len: 6,
+        },
+    ];
+    let symbols = Symbols {
+        text_start: 0x1000,
+        text_end: 0x1100,
+        entries: &ENTRIES,
+        names: NAMES,
+    };
+    assert_eq!(symbols.resolve(0x1000), Some(("first", 0)));
+    assert_eq!(symbols.resolve(0x103f), Some(("first", 0x3f)));
+    assert_eq!(symbols.resolve(0x1080), Some(("second", 0x40)));
+    assert_eq!(symbols.resolve(0x0fff), None);
+    assert_eq!(symbols.resolve(0x1100), None);
+}
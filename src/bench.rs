@@ -0,0 +1,132 @@
+use crate::{
+    QemuExitCode, exit_qemu, power, serial_println,
+    testing::{Format, JsonEscaped},
+    time,
+};
+
+/// 最多保留的样本数。迭代次数更多时把相邻的几次合成一个样本，取平均。
+pub const MAX_SAMPLES: usize = 1024;
+
+// 正式计时前先跑几次，让缓存和 TLB 进入稳定状态
+const WARMUP_ITERATIONS: usize = 16;
+
+/// 一个基准测试，用 [`bench_case!`](crate::bench_case) 定义。
+pub struct Bench {
+    pub name: &'static str,
+    pub iterations: usize,
+    pub run: fn(),
+}
+
+/// 一个基准测试的结果，单位都是 TSC 周期。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub min: u64,
+    pub median: u64,
+    pub max: u64,
+}
+
+impl Summary {
+    /// 从每次迭代的耗时中统计，会把 `samples` 排序。
+    pub fn from_samples(samples: &mut [u64]) -> Summary {
+        samples.sort_unstable();
+        Summary {
+            min: samples.first().copied().unwrap_or(0),
+            median: samples.get(samples.len() / 2).copied().unwrap_or(0),
+            max: samples.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// 定义一个基准测试：`bench_case!(name, 迭代次数, { 被测的代码 })`。
+///
+/// 基准测试和普通测试分开，放在 `tests/bench.rs` 中，由 [`runner`] 运行。
+#[macro_export]
+macro_rules! bench_case {
+    ($name:ident, $iterations:expr, $body:block) => {
+        #[test_case]
+        #[allow(non_upper_case_globals)]
+        static $name: $crate::bench::Bench = $crate::bench::Bench {
+            name: concat!(module_path!(), "::", stringify!($name)),
+            iterations: $iterations,
+            run: || $body,
+        };
+    };
+}
+
+/// 运行一个基准测试，用 TSC 给每次迭代计时。
+pub fn measure(bench: &Bench) -> Summary {
+    for _ in 0..WARMUP_ITERATIONS.min(bench.iterations) {
+        (bench.run)();
+    }
+    let batch = bench.iterations.div_ceil(MAX_SAMPLES).max(1);
+    let mut samples = [0u64; MAX_SAMPLES];
+    let mut count = 0;
+    let mut remaining = bench.iterations;
+    while remaining > 0 {
+        let n = batch.min(remaining);
+        let start = time::tsc();
+        for _ in 0..n {
+            (bench.run)();
+        }
+        samples[count] = (time::tsc() - start) / n as u64;
+        count += 1;
+        remaining -= n;
+    }
+    Summary::from_samples(&mut samples[..count])
+}
+
+/// 基准测试的运行器：依次测量每个 [`Bench`]，把结果写到串口后退出 QEMU。
+///
+/// 命令行的 `test_format=json` 同样适用，每个基准测试输出一行 JSON。
+pub fn runner(benches: &[&Bench]) {
+    let format = Format::current();
+    if format != Format::Json {
+        serial_println!("Running {} benchmarks", benches.len());
+    }
+    for bench in benches {
+        let summary = measure(bench);
+        let median_ns = time::tsc_to_us(summary.median * 1000);
+        match format {
+            Format::Json => serial_println!(
+                r#"{{"type":"bench","name":"{}","iterations":{},"min_cycles":{},"median_cycles":{},"max_cycles":{},"median_ns":{}}}"#,
+                JsonEscaped(bench.name),
+                bench.iterations,
+                summary.min,
+                summary.median,
+                summary.max,
+                median_ns
+            ),
+            _ => serial_println!(
+                "{}: {} iterations, min {} median {} max {} cycles ({} ns)",
+                bench.name,
+                bench.iterations,
+                summary.min,
+                summary.median,
+                summary.max,
+                median_ns
+            ),
+        }
+    }
+    exit_qemu(QemuExitCode::Success);
+    power::shutdown();
+}
+
+#[test_case]
+fn test_bench_summary() {
+    let mut samples = [7, 3, 9, 1, 5];
+    assert_eq!(
+        Summary::from_samples(&mut samples),
+        Summary {
+            min: 1,
+            median: 5,
+            max: 9
+        }
+    );
+    let bench = Bench {
+        name: "noop",
+        iterations: 3000,
+        run: || {},
+    };
+    let summary = measure(&bench);
+    assert!(summary.min <= summary.median && summary.median <= summary.max);
+}
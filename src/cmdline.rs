@@ -0,0 +1,83 @@
+use lazy_static::lazy_static;
+
+use crate::fw_cfg;
+
+/// 命令行的最大长度，超出部分被截断。
+pub const MAX_LEN: usize = 512;
+
+/// QEMU 中通过 `-fw_cfg name=opt/os-rust/cmdline,string="loglevel=debug"`
+/// 传入命令行，无需重新编译。
+pub const FW_CFG_FILE: &str = "opt/os-rust/cmdline";
+
+// bootloader 0.9 不向内核传递命令行，所以在 QEMU 下从 fw_cfg 读取，
+// 其他情况下退回到编译时的 OS_RUST_CMDLINE 环境变量
+struct Cmdline {
+    buf: [u8; MAX_LEN],
+    len: usize,
+}
+
+lazy_static! {
+    static ref CMDLINE: Cmdline = {
+        let mut cmdline = Cmdline {
+            buf: [0; MAX_LEN],
+            len: 0,
+        };
+        let len = read_fw_cfg(&mut cmdline.buf).unwrap_or_else(|| {
+            let fallback = option_env!("OS_RUST_CMDLINE").unwrap_or("").as_bytes();
+            let len = fallback.len().min(MAX_LEN);
+            cmdline.buf[..len].copy_from_slice(&fallback[..len]);
+            len
+        });
+        // 截断可能切在多字节字符中间，只保留合法的 UTF-8 前缀
+        cmdline.len = match core::str::from_utf8(&cmdline.buf[..len]) {
+            Ok(_) => len,
+            Err(e) => e.valid_up_to(),
+        };
+        cmdline
+    };
+}
+
+/// 完整的命令行。
+pub fn as_str() -> &'static str {
+    core::str::from_utf8(&CMDLINE.buf[..CMDLINE.len]).unwrap_or("")
+}
+
+/// 查找 `key=value` 形式的选项，返回 `value`；只有 `key` 的开关返回 `""`。
+/// 同一个键出现多次时以最后一次为准。
+pub fn get(key: &str) -> Option<&'static str> {
+    lookup(as_str(), key)
+}
+
+/// 命令行中是否出现了这个键（不论有没有值）。
+pub fn flag(key: &str) -> bool {
+    get(key).is_some()
+}
+
+fn lookup<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    cmdline
+        .split_whitespace()
+        .filter_map(|option| match option.split_once('=') {
+            Some((k, value)) => (k == key).then_some(value),
+            None => (option == key).then_some(""),
+        })
+        .next_back()
+}
+
+/// 从 QEMU 的 fw_cfg 中读取命令行文件到 `buf`。
+fn read_fw_cfg(buf: &mut [u8]) -> Option<usize> {
+    let len = fw_cfg::find(FW_CFG_FILE)?.read(buf);
+    // `string=` 形式的文件不带结尾的 NUL，但手写的文件可能带
+    Some(buf[..len].iter().position(|&b| b == 0).unwrap_or(len))
+}
+
+#[test_case]
+fn test_cmdline_lookup() {
+    let cmdline = "loglevel=debug console=serial quiet keyboard=dvorak loglevel=warn";
+    assert_eq!(lookup(cmdline, "loglevel"), Some("warn"));
+    assert_eq!(lookup(cmdline, "console"), Some("serial"));
+    assert_eq!(lookup(cmdline, "quiet"), Some(""));
+    assert_eq!(lookup(cmdline, "keyboard"), Some("dvorak"));
+    assert_eq!(lookup(cmdline, "missing"), None);
+    assert_eq!(lookup("", "loglevel"), None);
+    assert_eq!(lookup("a=b=c", "a"), Some("b=c"));
+}
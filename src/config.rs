@@ -0,0 +1,520 @@
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    cmdline,
+    console::{self, LogLevel},
+    keyboard::{self, Layout},
+    storage::{self, SharedDevice},
+};
+
+/// 存储区的扇区大小。配置区由三个扇区组成：一个指针扇区和两个槽位（A/B）。
+pub const SECTOR_SIZE: usize = 512;
+/// 当前写入的配置格式版本。
+pub const VERSION: u16 = 1;
+
+const POINTER_SECTOR: usize = 0;
+const SLOT_SECTORS: [usize; 2] = [1, 2];
+
+const POINTER_MAGIC: u32 = 0x5254_504b; // "KPTR"
+const SLOT_MAGIC: u32 = 0x4746_434b; // "KCFG"
+
+// 记录头：magic(4) + version(2) + len/slot(2) + generation(4)
+const HEADER_LEN: usize = 12;
+// 每个扇区的最后 4 字节存放 CRC32
+const CRC_OFFSET: usize = SECTOR_SIZE - 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// 底层存储读写失败
+    Io,
+    /// 字符串超出字段容量
+    TooLong,
+    /// 不认识的键盘布局或日志级别
+    Invalid,
+}
+
+/// 配置区所在的存储。`index` 是配置区内部的扇区号（0..3）。
+pub trait ConfigStorage {
+    fn read_sector(&mut self, index: usize, buf: &mut [u8; SECTOR_SIZE])
+    -> Result<(), ConfigError>;
+    fn write_sector(&mut self, index: usize, buf: &[u8; SECTOR_SIZE]) -> Result<(), ConfigError>;
+    /// 确保之前的写入已经落盘
+    fn flush(&mut self) -> Result<(), ConfigError>;
+}
+
+/// 放在内存里的配置区，用于测试以及还没有磁盘驱动时的运行。
+pub struct MemoryStorage {
+    pub sectors: [[u8; SECTOR_SIZE]; 3],
+}
+
+impl MemoryStorage {
+    pub const fn new() -> Self {
+        MemoryStorage {
+            sectors: [[0; SECTOR_SIZE]; 3],
+        }
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigStorage for MemoryStorage {
+    fn read_sector(
+        &mut self,
+        index: usize,
+        buf: &mut [u8; SECTOR_SIZE],
+    ) -> Result<(), ConfigError> {
+        buf.copy_from_slice(self.sectors.get(index).ok_or(ConfigError::Io)?);
+        Ok(())
+    }
+
+    fn write_sector(&mut self, index: usize, buf: &[u8; SECTOR_SIZE]) -> Result<(), ConfigError> {
+        self.sectors
+            .get_mut(index)
+            .ok_or(ConfigError::Io)?
+            .copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ConfigError> {
+        Ok(())
+    }
+}
+
+/// 块设备上从 `start_lba` 开始的三个扇区。设备的块大小必须等于 `SECTOR_SIZE`。
+pub struct DeviceStorage {
+    device: SharedDevice,
+    start_lba: u64,
+}
+
+impl DeviceStorage {
+    pub fn new(device: SharedDevice, start_lba: u64) -> Self {
+        DeviceStorage { device, start_lba }
+    }
+
+    fn lba(&self, index: usize) -> Result<u64, ConfigError> {
+        if index > SLOT_SECTORS[1] || self.device.lock().block_size() != SECTOR_SIZE {
+            return Err(ConfigError::Io);
+        }
+        Ok(self.start_lba + index as u64)
+    }
+}
+
+impl ConfigStorage for DeviceStorage {
+    fn read_sector(
+        &mut self,
+        index: usize,
+        buf: &mut [u8; SECTOR_SIZE],
+    ) -> Result<(), ConfigError> {
+        let lba = self.lba(index)?;
+        self.device
+            .lock()
+            .read_blocks(lba, buf)
+            .map_err(|_| ConfigError::Io)
+    }
+
+    fn write_sector(&mut self, index: usize, buf: &[u8; SECTOR_SIZE]) -> Result<(), ConfigError> {
+        let lba = self.lba(index)?;
+        self.device
+            .lock()
+            .write_blocks(lba, buf)
+            .map_err(|_| ConfigError::Io)
+    }
+
+    fn flush(&mut self) -> Result<(), ConfigError> {
+        self.device.lock().flush().map_err(|_| ConfigError::Io)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FixedStr<const N: usize> {
+    len: u8,
+    bytes: [u8; N],
+}
+
+impl<const N: usize> FixedStr<N> {
+    const fn from_static(s: &str) -> Self {
+        let src = s.as_bytes();
+        let mut bytes = [0; N];
+        let mut i = 0;
+        while i < src.len() {
+            bytes[i] = src[i];
+            i += 1;
+        }
+        FixedStr {
+            len: src.len() as u8,
+            bytes,
+        }
+    }
+
+    fn set(&mut self, s: &str) -> Result<(), ConfigError> {
+        if s.len() > N {
+            return Err(ConfigError::TooLong);
+        }
+        self.bytes = [0; N];
+        self.bytes[..s.len()].copy_from_slice(s.as_bytes());
+        self.len = s.len() as u8;
+        Ok(())
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or("")
+    }
+
+    fn encode(&self, out: &mut [u8]) -> usize {
+        out[0] = self.len;
+        out[1..=N].copy_from_slice(&self.bytes);
+        N + 1
+    }
+
+    fn decode(input: &[u8]) -> Option<(Self, usize)> {
+        let len = *input.first()? as usize;
+        if len > N || input.len() < N + 1 {
+            return None;
+        }
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&input[1..=N]);
+        core::str::from_utf8(&bytes[..len]).ok()?;
+        Some((
+            FixedStr {
+                len: len as u8,
+                bytes,
+            },
+            N + 1,
+        ))
+    }
+}
+
+/// 运行时可修改、需要跨重启保存的设置。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelConfig {
+    keymap: FixedStr<15>,
+    hostname: FixedStr<63>,
+    log_level: FixedStr<15>,
+}
+
+impl KernelConfig {
+    pub const fn new() -> Self {
+        KernelConfig {
+            keymap: FixedStr::from_static("us"),
+            hostname: FixedStr::from_static("os-rust"),
+            log_level: FixedStr::from_static("info"),
+        }
+    }
+
+    pub fn keymap(&self) -> &str {
+        self.keymap.as_str()
+    }
+
+    pub fn hostname(&self) -> &str {
+        self.hostname.as_str()
+    }
+
+    pub fn log_level(&self) -> &str {
+        self.log_level.as_str()
+    }
+
+    pub fn set_keymap(&mut self, keymap: &str) -> Result<(), ConfigError> {
+        self.keymap.set(keymap)
+    }
+
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<(), ConfigError> {
+        self.hostname.set(hostname)
+    }
+
+    pub fn set_log_level(&mut self, level: &str) -> Result<(), ConfigError> {
+        self.log_level.set(level)
+    }
+
+    fn encode(&self, out: &mut [u8]) -> usize {
+        let mut pos = self.keymap.encode(out);
+        pos += self.hostname.encode(&mut out[pos..]);
+        pos += self.log_level.encode(&mut out[pos..]);
+        pos
+    }
+
+    fn decode(version: u16, input: &[u8]) -> Option<Self> {
+        // 只有 v1 格式；以后增加字段时，旧版本缺失的字段保持默认值
+        if version != 1 {
+            return None;
+        }
+        let (keymap, a) = FixedStr::decode(input)?;
+        let (hostname, b) = FixedStr::decode(&input[a..])?;
+        let (log_level, _) = FixedStr::decode(&input[a + b..])?;
+        Some(KernelConfig {
+            keymap,
+            hostname,
+            log_level,
+        })
+    }
+}
+
+impl Default for KernelConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 当前生效的配置。
+pub static CONFIG: Mutex<KernelConfig> = Mutex::new(KernelConfig::new());
+
+// 上一次成功加载或保存时所用的槽位和代数，save 会写入另一个槽位
+static ACTIVE: Mutex<(usize, u32)> = Mutex::new((1, 0));
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn seal(buf: &mut [u8; SECTOR_SIZE], magic: u32, field: u16, generation: u32) {
+    buf[0..4].copy_from_slice(&magic.to_le_bytes());
+    buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    buf[6..8].copy_from_slice(&field.to_le_bytes());
+    buf[8..12].copy_from_slice(&generation.to_le_bytes());
+    let crc = crc32(&buf[..CRC_OFFSET]);
+    buf[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// 校验扇区，返回 (version, field, generation)
+fn unseal(buf: &[u8; SECTOR_SIZE], magic: u32) -> Option<(u16, u16, u32)> {
+    let word = |at: usize| u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+    let half = |at: usize| u16::from_le_bytes(buf[at..at + 2].try_into().unwrap());
+    if word(0) != magic || word(CRC_OFFSET) != crc32(&buf[..CRC_OFFSET]) {
+        return None;
+    }
+    let version = half(4);
+    if version == 0 || version > VERSION {
+        return None;
+    }
+    Some((version, half(6), word(8)))
+}
+
+fn read_slot(storage: &mut dyn ConfigStorage, slot: usize) -> Option<(KernelConfig, u32)> {
+    let mut buf = [0; SECTOR_SIZE];
+    storage.read_sector(SLOT_SECTORS[slot], &mut buf).ok()?;
+    let (version, len, generation) = unseal(&buf, SLOT_MAGIC)?;
+    let payload = buf[HEADER_LEN..CRC_OFFSET].get(..len as usize)?;
+    Some((KernelConfig::decode(version, payload)?, generation))
+}
+
+/// 从存储中读取配置。
+///
+/// 优先使用指针扇区指向的槽位；指针损坏时退回到代数最大的有效槽位，
+/// 两个槽位都无效时使用默认配置。返回是否读到了已保存的配置。
+pub fn load(storage: &mut dyn ConfigStorage) -> bool {
+    let mut buf = [0; SECTOR_SIZE];
+    let pointed = storage
+        .read_sector(POINTER_SECTOR, &mut buf)
+        .ok()
+        .and_then(|_| unseal(&buf, POINTER_MAGIC))
+        .and_then(|(_, slot, generation)| {
+            let slot = slot as usize;
+            if slot >= SLOT_SECTORS.len() {
+                return None;
+            }
+            match read_slot(storage, slot) {
+                Some((config, g)) if g == generation => Some((slot, config, g)),
+                _ => None,
+            }
+        });
+    let found = pointed.or_else(|| {
+        let a = read_slot(storage, 0).map(|(c, g)| (0, c, g));
+        let b = read_slot(storage, 1).map(|(c, g)| (1, c, g));
+        match (a, b) {
+            // 代数会回绕，按差值的符号比较
+            (Some(a), Some(b)) => Some(if (b.2.wrapping_sub(a.2) as i32) > 0 {
+                b
+            } else {
+                a
+            }),
+            (a, b) => a.or(b),
+        }
+    });
+
+    match found {
+        Some((slot, config, generation)) => {
+            *CONFIG.lock() = config;
+            *ACTIVE.lock() = (slot, generation);
+            true
+        }
+        None => {
+            *CONFIG.lock() = KernelConfig::new();
+            *ACTIVE.lock() = (1, 0);
+            false
+        }
+    }
+}
+
+/// 原子地保存当前配置：先写非活动槽位并刷盘，再翻转指针扇区。
+///
+/// 任何一步中途崩溃，下次 `load` 要么得到旧配置，要么得到完整的新配置。
+pub fn save(storage: &mut dyn ConfigStorage) -> Result<(), ConfigError> {
+    let config = interrupts::without_interrupts(|| *CONFIG.lock());
+    let (active, generation) = *ACTIVE.lock();
+    let slot = 1 - active;
+    let generation = generation.wrapping_add(1);
+
+    let mut buf = [0; SECTOR_SIZE];
+    let len = config.encode(&mut buf[HEADER_LEN..CRC_OFFSET]);
+    seal(&mut buf, SLOT_MAGIC, len as u16, generation);
+    storage.write_sector(SLOT_SECTORS[slot], &buf)?;
+    storage.flush()?;
+
+    let mut buf = [0; SECTOR_SIZE];
+    seal(&mut buf, POINTER_MAGIC, slot as u16, generation);
+    storage.write_sector(POINTER_SECTOR, &buf)?;
+    storage.flush()?;
+
+    *ACTIVE.lock() = (slot, generation);
+    Ok(())
+}
+
+// 启动时打开的配置区，运行时的修改保存到这里
+static STORAGE: Mutex<Option<DeviceStorage>> = Mutex::new(None);
+
+/// 按命令行 `config=<设备>[:<起始扇区>]`（例如 `config=ata0:2048`）打开配置区，
+/// 读出保存的设置并应用。必须在块设备注册之后调用。
+///
+/// 命令行中直接给出的 `keymap=`、`loglevel=` 优先于保存的值。没有 `config=`
+/// 时只使用命令行和默认值，运行时的修改也不会保存。
+pub fn init() {
+    let Some(spec) = cmdline::get("config") else {
+        return;
+    };
+    let (name, lba) = spec.split_once(':').unwrap_or((spec, "0"));
+    let (Some(device), Ok(lba)) = (storage::get(name), lba.parse()) else {
+        log::warn!("cannot open '{spec}'");
+        return;
+    };
+    let mut storage = DeviceStorage::new(device, lba);
+    if load(&mut storage) {
+        let config = *CONFIG.lock();
+        if cmdline::get("keymap").is_none() {
+            match Layout::parse(config.keymap()) {
+                Some(layout) => keyboard::set_layout(layout),
+                None => log::warn!("unknown keymap '{}'", config.keymap()),
+            }
+        }
+        if cmdline::get("loglevel").is_none() {
+            match LogLevel::parse(config.log_level()) {
+                Some(level) => console::set_log_level(level),
+                None => log::warn!("unknown log level '{}'", config.log_level()),
+            }
+        }
+        log::info!("loaded from {name}");
+    }
+    *STORAGE.lock() = Some(storage);
+}
+
+// 修改当前配置，有配置区时立即保存
+fn update(f: impl FnOnce(&mut KernelConfig) -> Result<(), ConfigError>) -> Result<(), ConfigError> {
+    interrupts::without_interrupts(|| f(&mut CONFIG.lock()))?;
+    match STORAGE.lock().as_mut() {
+        Some(storage) => save(storage),
+        None => Ok(()),
+    }
+}
+
+/// 切换键盘布局并保存。
+pub fn set_keymap(name: &str) -> Result<(), ConfigError> {
+    let layout = Layout::parse(name).ok_or(ConfigError::Invalid)?;
+    update(|config| config.set_keymap(name))?;
+    keyboard::set_layout(layout);
+    Ok(())
+}
+
+/// 修改主机名并保存。
+pub fn set_hostname(hostname: &str) -> Result<(), ConfigError> {
+    update(|config| config.set_hostname(hostname))
+}
+
+/// 修改全局日志级别并保存。
+pub fn set_log_level(name: &str) -> Result<(), ConfigError> {
+    let level = LogLevel::parse(name).ok_or(ConfigError::Invalid)?;
+    update(|config| config.set_log_level(name))?;
+    console::set_log_level(level);
+    Ok(())
+}
+
+/// 当前的主机名。可以在中断处理函数中调用。
+pub fn hostname() -> heapless::String<63> {
+    let config = interrupts::without_interrupts(|| *CONFIG.lock());
+    heapless::String::try_from(config.hostname()).unwrap_or_default()
+}
+
+#[test_case]
+fn test_config_round_trip() {
+    let mut storage = MemoryStorage::new();
+    assert!(!load(&mut storage));
+    CONFIG.lock().set_hostname("qemu-box").unwrap();
+    save(&mut storage).unwrap();
+    CONFIG.lock().set_hostname("other").unwrap();
+    save(&mut storage).unwrap();
+
+    *CONFIG.lock() = KernelConfig::new();
+    assert!(load(&mut storage));
+    assert_eq!(CONFIG.lock().hostname(), "other");
+}
+
+#[test_case]
+fn test_config_torn_write_keeps_old_settings() {
+    let mut storage = MemoryStorage::new();
+    load(&mut storage);
+    CONFIG.lock().set_keymap("de").unwrap();
+    save(&mut storage).unwrap();
+    let before = storage.sectors;
+
+    // 模拟写新槽位时崩溃：槽位内容写了一半，指针还没有翻转
+    CONFIG.lock().set_keymap("fr").unwrap();
+    save(&mut storage).unwrap();
+    let (slot, _) = *ACTIVE.lock();
+    storage.sectors[SLOT_SECTORS[slot]][HEADER_LEN] ^= 0xff;
+    storage.sectors[POINTER_SECTOR] = before[POINTER_SECTOR];
+
+    load(&mut storage);
+    assert_eq!(CONFIG.lock().keymap(), "de");
+    *CONFIG.lock() = KernelConfig::new();
+}
+
+#[test_case]
+fn test_config_rejects_unknown_version() {
+    let mut storage = MemoryStorage::new();
+    load(&mut storage);
+    save(&mut storage).unwrap();
+    for sector in storage.sectors.iter_mut() {
+        sector[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let crc = crc32(&sector[..CRC_OFFSET]);
+        sector[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+    }
+    assert!(!load(&mut storage));
+}
+
+#[test_case]
+fn test_config_generation_wraps() {
+    let mut storage = MemoryStorage::new();
+    load(&mut storage);
+    // 下一次保存的代数回绕到 0，仍然应当被认为比 u32::MAX 新
+    *ACTIVE.lock() = (0, u32::MAX - 1);
+    CONFIG.lock().set_hostname("old").unwrap();
+    save(&mut storage).unwrap();
+    CONFIG.lock().set_hostname("new").unwrap();
+    save(&mut storage).unwrap();
+    assert_eq!(*ACTIVE.lock(), (0, 0));
+
+    // 指针扇区损坏时按代数挑选槽位
+    storage.sectors[POINTER_SECTOR][0] ^= 0xff;
+    assert!(load(&mut storage));
+    assert_eq!(CONFIG.lock().hostname(), "new");
+    *CONFIG.lock() = KernelConfig::new();
+}
@@ -0,0 +1,321 @@
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use pc_keyboard::KeyCode;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    cmdline,
+    debugcon::{self, DebugCon},
+    framebuffer,
+    keyboard::Modifiers,
+    logger, println,
+    serial::{self, Com},
+    status_bar, vga_buffer,
+};
+
+/// 控制台输出的一个去处。`print!`/`println!` 的输出会同时写到所有启用的去处。
+pub trait ConsoleSink: fmt::Write {
+    /// 输出一行日志。默认在行首加上级别前缀，能显示颜色的去处可以覆盖它。
+    fn write_level(&mut self, level: LogLevel, args: fmt::Arguments) -> fmt::Result {
+        writeln!(self, "{} {}", level.prefix(), args)
+    }
+}
+
+/// 一组启用的输出去处，由命令行 `console=` 选择，例如 `console=vga,serial`。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sinks(u8);
+
+impl Sinks {
+    pub const NONE: Sinks = Sinks(0);
+    /// VGA 文本缓冲区
+    pub const VGA: Sinks = Sinks(1 << 0);
+    /// COM1
+    pub const SERIAL: Sinks = Sinks(1 << 1);
+    /// QEMU 的 debugcon（端口 0xe9）
+    pub const DEBUGCON: Sinks = Sinks(1 << 2);
+    /// 图形模式的帧缓冲区
+    pub const FRAMEBUFFER: Sinks = Sinks(1 << 3);
+
+    const ALL: [(&'static str, Sinks); 4] = [
+        ("vga", Sinks::VGA),
+        ("serial", Sinks::SERIAL),
+        ("debugcon", Sinks::DEBUGCON),
+        ("fb", Sinks::FRAMEBUFFER),
+    ];
+
+    pub const fn union(self, other: Sinks) -> Sinks {
+        Sinks(self.0 | other.0)
+    }
+
+    pub const fn without(self, other: Sinks) -> Sinks {
+        Sinks(self.0 & !other.0)
+    }
+
+    pub const fn contains(self, other: Sinks) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// 解析逗号分隔的去处列表；`both` 是 `vga,serial` 的旧写法。
+    pub fn parse(s: &str) -> Option<Sinks> {
+        s.split(',').try_fold(Sinks::NONE, |sinks, name| {
+            let sink = match name {
+                "both" => Sinks::VGA.union(Sinks::SERIAL),
+                _ => Sinks::ALL.iter().find(|(n, _)| *n == name)?.1,
+            };
+            Some(sinks.union(sink))
+        })
+    }
+}
+
+/// 日志级别，由命令行 `loglevel=` 选择，数值越大越详细。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl LogLevel {
+    pub fn parse(s: &str) -> Option<LogLevel> {
+        match s {
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+
+    /// 输出时的文字前缀，保证不依赖颜色也能看出严重程度。
+    pub fn prefix(self) -> &'static str {
+        match self {
+            LogLevel::Error => "[ERROR]",
+            LogLevel::Warn => "[WARN ]",
+            LogLevel::Info => "[INFO ]",
+            LogLevel::Debug => "[DEBUG]",
+            LogLevel::Trace => "[TRACE]",
+        }
+    }
+}
+
+static SINKS: AtomicU8 = AtomicU8::new(Sinks::VGA.0);
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// 根据命令行配置输出目标和配色。日志级别由 `logger::init` 设置。
+pub fn init() {
+    debugcon::init();
+    if let Some(sinks) = cmdline::get("console").and_then(Sinks::parse) {
+        set_sinks(sinks);
+    }
+    if let Some(theme) = cmdline::get("theme") {
+        vga_buffer::set_theme(theme);
+    }
+}
+
+pub fn sinks() -> Sinks {
+    Sinks(SINKS.load(Ordering::Relaxed))
+}
+
+pub fn set_sinks(sinks: Sinks) {
+    SINKS.store(sinks.0, Ordering::Relaxed);
+}
+
+/// 依次把每个启用的去处交给 `f`，VGA 上写到虚拟终端 `vt`。
+fn for_each_sink(vt: usize, mut f: impl FnMut(&mut dyn ConsoleSink) -> fmt::Result) {
+    let sinks = sinks();
+    // 输出出错时没有别的地方可以报告，只能忽略
+    if sinks.contains(Sinks::VGA) {
+        vga_buffer::with_terminal(vt, |writer| {
+            let _ = f(writer);
+        });
+    }
+    if sinks.contains(Sinks::SERIAL) {
+        interrupts::without_interrupts(|| {
+            let _ = f(&mut *serial::port(Com::Com1));
+        });
+    }
+    if sinks.contains(Sinks::DEBUGCON) {
+        let _ = f(&mut DebugCon);
+    }
+    if sinks.contains(Sinks::FRAMEBUFFER) {
+        framebuffer::with_console(|console| {
+            let _ = f(console);
+        });
+    }
+}
+
+pub fn log_level() -> LogLevel {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LogLevel::Error,
+        1 => LogLevel::Warn,
+        2 => LogLevel::Info,
+        3 => LogLevel::Debug,
+        _ => LogLevel::Trace,
+    }
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+    logger::update_max_level();
+}
+
+/// 该级别的输出当前是否应当打印。
+pub fn enabled(level: LogLevel) -> bool {
+    level <= log_level()
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    for_each_sink(vga_buffer::CONSOLE_VT, |sink| sink.write_fmt(args));
+}
+
+/// 按日志级别输出一行：VGA 上写到日志终端（VT2），使用主题中该级别的颜色，
+/// 各处都带级别前缀。低于当前日志级别的输出被丢弃。
+pub fn print_level(level: LogLevel, args: fmt::Arguments) {
+    if enabled(level) {
+        write_level(level, args);
+    }
+}
+
+/// 不检查日志级别，直接以该级别的格式输出一行。
+pub fn write_level(level: LogLevel, args: fmt::Arguments) {
+    for_each_sink(vga_buffer::LOG_VT, |sink| sink.write_level(level, args));
+    // 错误同时显示在控制台终端上，免得 panic 之类的信息藏在看不到的终端里
+    if level == LogLevel::Error && sinks().contains(Sinks::VGA) {
+        vga_buffer::with_terminal(vga_buffer::CONSOLE_VT, |writer| {
+            let _ = writer.write_level(level, args);
+        });
+    }
+}
+
+/// 处理控制台自己的组合键，返回 `true` 表示已经处理，不再当作输入：
+/// Alt+F1..F4 切换虚拟终端，Shift+PageUp/PageDown 翻看历史。
+pub(crate) fn handle_chord(key: KeyCode, modifiers: &Modifiers) -> bool {
+    let terminal = match key {
+        KeyCode::F1 => 0,
+        KeyCode::F2 => 1,
+        KeyCode::F3 => 2,
+        KeyCode::F4 => 3,
+        KeyCode::PageUp if modifiers.shift => {
+            vga_buffer::scroll(vga_buffer::SCROLL_PAGE);
+            return true;
+        }
+        KeyCode::PageDown if modifiers.shift => {
+            vga_buffer::scroll(-vga_buffer::SCROLL_PAGE);
+            return true;
+        }
+        _ => return false,
+    };
+    if !modifiers.alt {
+        return false;
+    }
+    vga_buffer::switch_terminal(terminal);
+    // 新终端的状态栏还是它上次显示时的内容
+    status_bar::update();
+    true
+}
+
+/// 前台被 Ctrl+C 打断时的回调。它运行在键盘中断上下文中，必须短小且不能阻塞。
+pub type InterruptHandler = fn();
+
+#[derive(Clone, Copy)]
+struct Foreground {
+    name: &'static str,
+    on_interrupt: Option<InterruptHandler>,
+}
+
+// 当前占有控制台的前台。控制台拥有“前台”的概念，键盘只负责把 Ctrl+C 交给它。
+static FOREGROUND: Mutex<Option<Foreground>> = Mutex::new(None);
+static INTERRUPT_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// 离开作用域时把前台还给之前的持有者。
+pub struct ForegroundGuard {
+    previous: Option<Foreground>,
+}
+
+impl Drop for ForegroundGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        interrupts::without_interrupts(|| *FOREGROUND.lock() = previous);
+        INTERRUPT_PENDING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// 让调用者成为前台，直到返回的 guard 被丢弃。
+///
+/// 长时间运行的前台代码应当定期调用 [`take_interrupt`]；需要立即响应的可以额外
+/// 提供 `on_interrupt` 回调（例如取消一个异步任务）。
+pub fn enter_foreground(
+    name: &'static str,
+    on_interrupt: Option<InterruptHandler>,
+) -> ForegroundGuard {
+    INTERRUPT_PENDING.store(false, Ordering::SeqCst);
+    let previous = interrupts::without_interrupts(|| {
+        FOREGROUND.lock().replace(Foreground { name, on_interrupt })
+    });
+    ForegroundGuard { previous }
+}
+
+/// 当前前台的名字。
+pub fn foreground() -> Option<&'static str> {
+    interrupts::without_interrupts(|| FOREGROUND.lock().as_ref().map(|fg| fg.name))
+}
+
+/// 前台收到 Ctrl+C 后返回一次 `true`，并清除该请求。
+pub fn take_interrupt() -> bool {
+    INTERRUPT_PENDING.swap(false, Ordering::SeqCst)
+}
+
+/// 由键盘中断在识别到 Ctrl+C 时调用。
+pub(crate) fn interrupt_foreground() {
+    // 在中断上下文中只尝试加锁：被打断的代码可能正持有这把锁
+    let foreground = FOREGROUND.try_lock().and_then(|fg| *fg);
+    match foreground {
+        Some(fg) => {
+            INTERRUPT_PENDING.store(true, Ordering::SeqCst);
+            if let Some(handler) = fg.on_interrupt {
+                handler();
+            }
+        }
+        // 没有前台时像终端一样回显 ^C
+        None => println!("^C"),
+    }
+}
+
+#[test_case]
+fn test_ctrl_c_reaches_foreground() {
+    static CALLED: AtomicBool = AtomicBool::new(false);
+    fn on_interrupt() {
+        CALLED.store(true, Ordering::SeqCst);
+    }
+
+    let guard = enter_foreground("test", Some(on_interrupt));
+    assert_eq!(foreground(), Some("test"));
+    assert!(!take_interrupt());
+    interrupt_foreground();
+    assert!(CALLED.load(Ordering::SeqCst));
+    assert!(take_interrupt());
+    assert!(!take_interrupt());
+    drop(guard);
+    assert_eq!(foreground(), None);
+}
+
+#[test_case]
+fn test_console_sinks_parse() {
+    assert_eq!(Sinks::parse("vga"), Some(Sinks::VGA));
+    let both = Sinks::parse("both").unwrap();
+    assert_eq!(Sinks::parse("serial,vga"), Some(both));
+    assert!(both.contains(Sinks::SERIAL) && !both.contains(Sinks::DEBUGCON));
+    let all = Sinks::parse("vga,serial,debugcon").unwrap();
+    assert!(all.contains(both.union(Sinks::DEBUGCON)));
+    assert_eq!(Sinks::parse("vga,lp0"), None);
+    assert_eq!(Sinks::parse(""), None);
+}
@@ -0,0 +1,263 @@
+use core::{arch::x86_64::__cpuid_count, fmt};
+
+use spin::Once;
+
+/// 内核关心的 CPU 特性。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Fpu,
+    Tsc,
+    Apic,
+    Fxsr,
+    Sse,
+    Sse2,
+    Sse3,
+    Ssse3,
+    Sse41,
+    Sse42,
+    X2apic,
+    Xsave,
+    Avx,
+    Rdrand,
+    Rdseed,
+    Nx,
+    Pages1G,
+    /// TSC 以恒定频率计数，不受调频和 C 状态影响
+    TscInvariant,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Ecx,
+    Edx,
+    Ebx,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 18] = [
+        Feature::Fpu,
+        Feature::Tsc,
+        Feature::Apic,
+        Feature::Fxsr,
+        Feature::Sse,
+        Feature::Sse2,
+        Feature::Sse3,
+        Feature::Ssse3,
+        Feature::Sse41,
+        Feature::Sse42,
+        Feature::X2apic,
+        Feature::Xsave,
+        Feature::Avx,
+        Feature::Rdrand,
+        Feature::Rdseed,
+        Feature::Nx,
+        Feature::Pages1G,
+        Feature::TscInvariant,
+    ];
+
+    /// 特性所在的 CPUID 叶、寄存器和位。
+    fn location(self) -> (u32, Register, u32) {
+        use Register::*;
+        match self {
+            Feature::Fpu => (1, Edx, 0),
+            Feature::Tsc => (1, Edx, 4),
+            Feature::Apic => (1, Edx, 9),
+            Feature::Fxsr => (1, Edx, 24),
+            Feature::Sse => (1, Edx, 25),
+            Feature::Sse2 => (1, Edx, 26),
+            Feature::Sse3 => (1, Ecx, 0),
+            Feature::Ssse3 => (1, Ecx, 9),
+            Feature::Sse41 => (1, Ecx, 19),
+            Feature::Sse42 => (1, Ecx, 20),
+            Feature::X2apic => (1, Ecx, 21),
+            Feature::Xsave => (1, Ecx, 26),
+            Feature::Avx => (1, Ecx, 28),
+            Feature::Rdrand => (1, Ecx, 30),
+            Feature::Rdseed => (7, Ebx, 18),
+            Feature::Nx => (0x8000_0001, Edx, 20),
+            Feature::Pages1G => (0x8000_0001, Edx, 26),
+            Feature::TscInvariant => (0x8000_0007, Edx, 8),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Fpu => "fpu",
+            Feature::Tsc => "tsc",
+            Feature::Apic => "apic",
+            Feature::Fxsr => "fxsr",
+            Feature::Sse => "sse",
+            Feature::Sse2 => "sse2",
+            Feature::Sse3 => "sse3",
+            Feature::Ssse3 => "ssse3",
+            Feature::Sse41 => "sse4.1",
+            Feature::Sse42 => "sse4.2",
+            Feature::X2apic => "x2apic",
+            Feature::Xsave => "xsave",
+            Feature::Avx => "avx",
+            Feature::Rdrand => "rdrand",
+            Feature::Rdseed => "rdseed",
+            Feature::Nx => "nx",
+            Feature::Pages1G => "pdpe1gb",
+            Feature::TscInvariant => "invtsc",
+        }
+    }
+}
+
+/// 启动时读到的 CPU 信息。
+#[derive(Debug, Clone, Copy)]
+pub struct CpuInfo {
+    /// 比如 `GenuineIntel`、`AuthenticAMD`
+    pub vendor: [u8; 12],
+    /// 品牌字符串，不支持时为空
+    pub brand: [u8; 48],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    // 每个 `Feature` 一位
+    features: u32,
+}
+
+impl CpuInfo {
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("?")
+    }
+
+    pub fn brand(&self) -> &str {
+        let len = self.brand.iter().position(|&b| b == 0).unwrap_or(48);
+        core::str::from_utf8(&self.brand[..len])
+            .unwrap_or("?")
+            .trim()
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features & (1 << feature as u32) != 0
+    }
+}
+
+impl fmt::Display for CpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} family {:#x} model {:#x} stepping {}",
+            self.vendor(),
+            self.family,
+            self.model,
+            self.stepping
+        )?;
+        for feature in Feature::ALL
+            .into_iter()
+            .filter(|&feature| self.has(feature))
+        {
+            write!(f, " {}", feature.name())?;
+        }
+        Ok(())
+    }
+}
+
+static INFO: Once<CpuInfo> = Once::new();
+
+fn cpuid(leaf: u32) -> [u32; 4] {
+    let result = unsafe { __cpuid_count(leaf, 0) };
+    [result.eax, result.ebx, result.ecx, result.edx]
+}
+
+/// 从 CPUID 叶 1 的 EAX 中解出 family、model 和 stepping，扩展字段只在基本
+/// 值为 0xf（以及 model 在 family 6）时才参与计算。
+fn decode_signature(eax: u32) -> (u32, u32, u32) {
+    let stepping = eax & 0xf;
+    let base_model = (eax >> 4) & 0xf;
+    let base_family = (eax >> 8) & 0xf;
+    let family = if base_family == 0xf {
+        base_family + ((eax >> 20) & 0xff)
+    } else {
+        base_family
+    };
+    let model = if base_family == 0x6 || base_family == 0xf {
+        base_model | (((eax >> 16) & 0xf) << 4)
+    } else {
+        base_model
+    };
+    (family, model, stepping)
+}
+
+fn detect() -> CpuInfo {
+    let [max_leaf, ebx, ecx, edx] = cpuid(0);
+    let mut vendor = [0; 12];
+    for (chunk, reg) in vendor.chunks_exact_mut(4).zip([ebx, edx, ecx]) {
+        chunk.copy_from_slice(&reg.to_le_bytes());
+    }
+    let [max_extended, ..] = cpuid(0x8000_0000);
+    let supported = |leaf: u32| {
+        if leaf >= 0x8000_0000 {
+            leaf <= max_extended
+        } else {
+            leaf <= max_leaf
+        }
+    };
+
+    let mut brand = [0; 48];
+    if supported(0x8000_0004) {
+        for (i, leaf) in (0x8000_0002..=0x8000_0004).enumerate() {
+            for (j, reg) in cpuid(leaf).into_iter().enumerate() {
+                brand[i * 16 + j * 4..][..4].copy_from_slice(&reg.to_le_bytes());
+            }
+        }
+    }
+
+    let mut features = 0;
+    for feature in Feature::ALL {
+        let (leaf, register, index) = feature.location();
+        if !supported(leaf) {
+            continue;
+        }
+        let [_, ebx, ecx, edx] = cpuid(leaf);
+        let value = match register {
+            Register::Ebx => ebx,
+            Register::Ecx => ecx,
+            Register::Edx => edx,
+        };
+        if value & (1 << index) != 0 {
+            features |= 1 << feature as u32;
+        }
+    }
+
+    let (family, model, stepping) = decode_signature(cpuid(1)[0]);
+    CpuInfo {
+        vendor,
+        brand,
+        family,
+        model,
+        stepping,
+        features,
+    }
+}
+
+/// 读取并缓存 CPUID 信息。`init()` 中日志可用后立即调用，后面的模块据此
+/// 决定能用哪些特性。
+pub fn init() {
+    let info = INFO.call_once(detect);
+    log::info!("cpu: {info}");
+}
+
+/// 启动时读到的 CPU 信息。
+pub fn info() -> &'static CpuInfo {
+    INFO.call_once(detect)
+}
+
+/// CPU 是否支持 `feature`，比如 `cpu::has(Feature::Rdrand)`。
+pub fn has(feature: Feature) -> bool {
+    info().has(feature)
+}
+
+#[test_case]
+fn test_cpu_features() {
+    // Pentium 4（扩展 family）和 Ivy Bridge（family 6 的扩展 model）
+    assert_eq!(decode_signature(0x0000_0f29), (0xf, 0x2, 9));
+    assert_eq!(decode_signature(0x0010_0f43), (0x10, 0x4, 3));
+    assert_eq!(decode_signature(0x0003_06a9), (0x6, 0x3a, 9));
+    // 长模式下这些特性都是必需的
+    for feature in [Feature::Fpu, Feature::Tsc, Feature::Fxsr, Feature::Sse2] {
+        assert!(has(feature), "{} missing", feature.name());
+    }
+}
@@ -0,0 +1,202 @@
+use core::fmt;
+
+use x86_64::{VirtAddr, registers::control::Cr2};
+
+use crate::{
+    backtrace, framebuffer,
+    interrupts::FaultFrame,
+    memory, percpu,
+    regs::{self, msr},
+    serial, serial_print, serial_println, testing, vga_buffer,
+};
+
+// 报告中打印的栈顶字数
+const STACK_WORDS: usize = 16;
+
+// 报告时检查的全局锁：名字和“是否被持有”
+type LockProbe = (&'static str, fn() -> bool);
+
+const LOCKS: [LockProbe; 4] = [
+    ("vga", vga_buffer::is_locked),
+    ("serial", serial::is_locked),
+    ("framebuffer", framebuffer::is_locked),
+    ("memory", memory::is_locked),
+];
+
+/// 异常的名字，用于报告。
+pub fn exception_name(vector: u8) -> &'static str {
+    match vector {
+        8 => "DOUBLE FAULT",
+        10 => "INVALID TSS",
+        11 => "SEGMENT NOT PRESENT",
+        12 => "STACK-SEGMENT FAULT",
+        13 => "GENERAL PROTECTION FAULT",
+        14 => "PAGE FAULT",
+        _ => "EXCEPTION",
+    }
+}
+
+/// 段选择子错误码（#GP、#TS、#NP、#SS），按 Intel SDM 6.13 解读。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorError(pub u64);
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let table = match (self.0 >> 1) & 0b11 {
+            0 => "GDT",
+            2 => "LDT",
+            _ => "IDT",
+        };
+        write!(f, "{} index {:#x}", table, (self.0 >> 3) & 0x1fff)?;
+        if self.0 & 1 != 0 {
+            write!(f, " (external)")?;
+        }
+        Ok(())
+    }
+}
+
+/// 报告一个致命异常然后 panic。
+///
+/// 被打断的代码可能正持有控制台的锁，所以先记下哪些锁被持有，再强行释放
+/// 串口的锁，通过串口写出全部寄存器、控制寄存器、栈顶内容和出错代码的
+/// 调用栈。最后的 panic 会把简短的消息写到所有输出去处并停机。
+pub(crate) fn report(vector: u8, frame: &FaultFrame) -> ! {
+    let held = held_locks();
+    unsafe { serial::force_unlock() };
+
+    let name = exception_name(vector);
+    serial_println!("EXCEPTION: {} (vector {})", name, vector);
+    match vector {
+        10..=13 if frame.error_code != 0 => serial_println!(
+            "error code {:#x}: {}",
+            frame.error_code,
+            SelectorError(frame.error_code)
+        ),
+        _ => serial_println!("error code {:#x}", frame.error_code),
+    }
+    dump_registers(frame);
+    dump_context(&held);
+    dump_stack(frame.rsp);
+    if memory::is_mapped(VirtAddr::new_truncate(frame.rbp)) {
+        backtrace::print_from(frame.rip, frame.rbp);
+    }
+    panic!("EXCEPTION: {} at {:#x}", name, frame.rip);
+}
+
+fn dump_registers(frame: &FaultFrame) {
+    serial_println!(
+        "rip={:#018x} cs={:#06x} rflags={:#x}",
+        frame.rip,
+        frame.cs,
+        frame.rflags
+    );
+    serial_println!("rsp={:#018x} ss={:#06x}", frame.rsp, frame.ss);
+    let gprs = [
+        ("rax", frame.rax),
+        ("rbx", frame.rbx),
+        ("rcx", frame.rcx),
+        ("rdx", frame.rdx),
+        ("rsi", frame.rsi),
+        ("rdi", frame.rdi),
+        ("rbp", frame.rbp),
+        ("r8", frame.r8),
+        ("r9", frame.r9),
+        ("r10", frame.r10),
+        ("r11", frame.r11),
+        ("r12", frame.r12),
+        ("r13", frame.r13),
+        ("r14", frame.r14),
+        ("r15", frame.r15),
+    ];
+    for line in gprs.chunks(3) {
+        for (name, value) in line {
+            serial_print!("{:>3}={:#018x} ", name, value);
+        }
+        serial_println!();
+    }
+    let (cr3, _) = regs::cr3();
+    serial_println!(
+        "cr0={:#x} cr2={:#x} cr3={:#x} cr4={:#x}",
+        regs::cr0::read().bits(),
+        Cr2::read_raw(),
+        cr3.start_address().as_u64(),
+        regs::cr4::read().bits()
+    );
+}
+
+/// 检查全局锁，被持有的锁的名字填在对应位置，其余为空串。
+pub(crate) fn held_locks() -> [&'static str; LOCKS.len()] {
+    let mut held = [""; LOCKS.len()];
+    for ((name, locked), slot) in LOCKS.iter().zip(&mut held) {
+        if locked() {
+            *slot = *name;
+        }
+    }
+    held
+}
+
+/// 通过串口打印 [`held_locks`] 的结果。
+pub(crate) fn print_held_locks(held: &[&str]) {
+    serial_print!("held locks:");
+    let mut any = false;
+    for name in held.iter().filter(|name| !name.is_empty()) {
+        serial_print!(" {}", name);
+        any = true;
+    }
+    serial_println!("{}", if any { "" } else { " none" });
+}
+
+/// 出错时在哪个 CPU、哪个测试里，以及哪些锁被持有。内核还没有任务调度，
+/// 没有“当前任务”可报告。
+fn dump_context(held: &[&str]) {
+    // 集成测试可能没有调用 `init()`，GS 基址还是 0，不能读每 CPU 数据
+    let cpu = match unsafe { regs::read_msr(msr::GS_BASE) } {
+        0 => 0,
+        _ => percpu::id(),
+    };
+    serial_print!("cpu {}", cpu);
+    let test = testing::current_name();
+    if !test.is_empty() {
+        serial_print!(", test {}", test);
+    }
+    serial_println!();
+    print_held_locks(held);
+}
+
+/// 打印出错时的栈顶。栈溢出时 rsp 落在保护页里，这时只报告地址。
+fn dump_stack(rsp: u64) {
+    let Ok(start) = VirtAddr::try_new(rsp & !7) else {
+        serial_println!("stack: rsp is not canonical");
+        return;
+    };
+    let end = start + (STACK_WORDS * 8 - 1) as u64;
+    if !memory::is_mapped(start) || !memory::is_mapped(end) {
+        serial_println!("stack: {:#x} is not mapped", rsp);
+        return;
+    }
+    serial_println!("stack:");
+    let words = unsafe { core::slice::from_raw_parts(start.as_ptr::<u64>(), STACK_WORDS) };
+    for (index, pair) in words.chunks(2).enumerate() {
+        serial_println!(
+            "  {:#018x}: {:#018x} {:#018x}",
+            start.as_u64() + index as u64 * 16,
+            pair[0],
+            pair[1]
+        );
+    }
+}
+
+#[test_case]
+fn test_crash_selector_error() {
+    use core::fmt::Write;
+
+    let mut buf = heapless::String::<64>::new();
+    write!(buf, "{}", SelectorError(0x10)).unwrap();
+    assert_eq!(buf, "GDT index 0x2");
+    buf.clear();
+    // IDT 第 13 项，由外部事件引起
+    write!(buf, "{}", SelectorError((13 << 3) | 0b011)).unwrap();
+    assert_eq!(buf, "IDT index 0xd (external)");
+    assert_eq!(core::mem::size_of::<FaultFrame>(), 21 * 8);
+    assert_eq!(exception_name(13), "GENERAL PROTECTION FAULT");
+}
@@ -0,0 +1,80 @@
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use x86_64::instructions::port::Port;
+
+use crate::{cmdline, console::ConsoleSink};
+
+/// QEMU 的 isa-debugcon 设备默认所在的端口。
+pub const PORT: u16 = 0xe9;
+
+// 设备存在时读这个端口会得到 0xe9，没有设备时读到的是 0xff
+const READBACK: u8 = 0xe9;
+
+// 启用后 `serial_print!` 的输出改写到 debugcon。编译时打开 `debugcon` 特性
+// 即默认启用，否则由命令行的 `debugcon` 开关决定
+static ENABLED: AtomicBool = AtomicBool::new(cfg!(feature = "debugcon"));
+
+/// QEMU 的 debugcon：写到端口的每个字节原样出现在宿主机上，不需要任何初始化，
+/// 也不像 UART 那样要等发送寄存器空闲。用 `-debugcon stdio` 或
+/// `-debugcon file:out.log` 接收。
+pub struct DebugCon;
+
+impl DebugCon {
+    /// 端口上是否有 debugcon 设备。
+    pub fn present() -> bool {
+        unsafe { Port::<u8>::new(PORT).read() == READBACK }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe { Port::<u8>::new(PORT).write(byte) };
+    }
+}
+
+impl fmt::Write for DebugCon {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+impl ConsoleSink for DebugCon {}
+
+/// 命令行带有 `debugcon` 并且设备存在时，把主机输出切换到 debugcon。
+pub fn init() {
+    if cmdline::flag("debugcon") && DebugCon::present() {
+        set_enabled(true);
+    }
+}
+
+/// 主机输出当前是否写到 debugcon。
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    // 端口写入是单条指令，不需要锁，也就不必关中断
+    let _ = DebugCon.write_fmt(args);
+}
+
+#[test_case]
+fn test_debugcon_switch() {
+    // 测试时 QEMU 没有接 debugcon，写入的字节被丢弃，不会卡住
+    let was = enabled();
+    set_enabled(true);
+    crate::serial_print!("");
+    assert!(enabled());
+    set_enabled(was);
+    assert_eq!(enabled(), was);
+}
@@ -0,0 +1,171 @@
+use core::fmt;
+
+use spin::Mutex;
+
+use crate::{pci, println};
+
+/// 设备树最多容纳的设备数：PCI 扫描结果加上几个传统设备。
+pub const MAX_DEVICES: usize = pci::MAX_DEVICES + 8;
+/// 最多可注册的驱动数。
+pub const MAX_DRIVERS: usize = 16;
+
+/// 不在 PCI 总线上、位置固定的传统（ISA）设备，按名字匹配驱动。
+const PLATFORM_DEVICES: [&str; 5] = ["pit", "pic", "i8042", "com1", "ata"];
+
+/// 设备在哪里、怎么识别。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceId {
+    Pci(pci::PciDevice),
+    Platform(&'static str),
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceId::Pci(dev) => write!(
+                f,
+                "pci {} {:04x}:{:04x} {}",
+                dev.address,
+                dev.vendor_id,
+                dev.device_id,
+                dev.class_name()
+            ),
+            DeviceId::Platform(name) => write!(f, "platform {name}"),
+        }
+    }
+}
+
+/// 驱动能处理哪些设备。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    /// PCI 厂商 ID 和设备 ID
+    Pci { vendor_id: u16, device_id: u16 },
+    /// PCI 类别和子类别
+    PciClass { class: u8, subclass: u8 },
+    /// 传统设备的名字
+    Platform(&'static str),
+}
+
+impl Match {
+    pub fn matches(&self, id: &DeviceId) -> bool {
+        match (*self, id) {
+            (
+                Match::Pci {
+                    vendor_id,
+                    device_id,
+                },
+                DeviceId::Pci(dev),
+            ) => dev.vendor_id == vendor_id && dev.device_id == device_id,
+            (Match::PciClass { class, subclass }, DeviceId::Pci(dev)) => {
+                dev.class == class && dev.subclass == subclass
+            }
+            (Match::Platform(name), DeviceId::Platform(other)) => name == *other,
+            _ => false,
+        }
+    }
+}
+
+/// 驱动的描述，通常是驱动模块中的一个 static。
+///
+/// `probe` 返回 true 表示驱动接管了设备；返回 false 时继续尝试后面的驱动。
+/// 它在 `init` 中被调用，这时中断还没有打开。
+pub struct Driver {
+    pub name: &'static str,
+    pub matches: &'static [Match],
+    pub probe: fn(&DeviceId) -> bool,
+}
+
+/// 设备树中的一项。
+#[derive(Debug, Clone, Copy)]
+pub struct Device {
+    pub id: DeviceId,
+    /// 绑定的驱动名，没有驱动接管时为 `None`
+    pub driver: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// 驱动表已满
+    Full,
+    /// 同名驱动已经注册过
+    Duplicate,
+}
+
+static DRIVERS: Mutex<heapless::Vec<&'static Driver, MAX_DRIVERS>> =
+    Mutex::new(heapless::Vec::new());
+static DEVICES: Mutex<heapless::Vec<Device, MAX_DEVICES>> = Mutex::new(heapless::Vec::new());
+
+/// 注册一个驱动。要在 [`init`] 之前调用，之后注册的驱动不会再被绑定。
+pub fn register(driver: &'static Driver) -> Result<(), RegisterError> {
+    let mut drivers = DRIVERS.lock();
+    if drivers.iter().any(|other| other.name == driver.name) {
+        return Err(RegisterError::Duplicate);
+    }
+    drivers.push(driver).map_err(|_| RegisterError::Full)
+}
+
+/// 用 PCI 扫描结果和传统设备建立设备树，再按注册顺序为每个设备找驱动。
+/// 要在 `pci::init` 之后调用。
+pub fn init() {
+    let mut ids = heapless::Vec::<DeviceId, MAX_DEVICES>::new();
+    pci::for_each(|dev| {
+        let _ = ids.push(DeviceId::Pci(*dev));
+    });
+    for name in PLATFORM_DEVICES {
+        let _ = ids.push(DeviceId::Platform(name));
+    }
+
+    DEVICES.lock().clear();
+    for id in ids {
+        // 探测时不持有锁：驱动可能会注册块设备、网卡，甚至查询设备树
+        let driver = bind(&id);
+        let _ = DEVICES.lock().push(Device { id, driver });
+    }
+}
+
+fn bind(id: &DeviceId) -> Option<&'static str> {
+    let drivers = DRIVERS.lock().clone();
+    drivers
+        .iter()
+        .filter(|driver| driver.matches.iter().any(|m| m.matches(id)))
+        .find(|driver| (driver.probe)(id))
+        .map(|driver| driver.name)
+}
+
+/// 对设备树中的每个设备调用 `f`。
+pub fn for_each(mut f: impl FnMut(&Device)) {
+    for device in DEVICES.lock().iter() {
+        f(device);
+    }
+}
+
+/// 列出设备和绑定的驱动（`lsdev`）。
+pub fn lsdev() {
+    for_each(|device| {
+        println!("{:<12} {}", device.driver.unwrap_or("-"), device.id);
+    });
+}
+
+#[test_case]
+fn test_devices_tree() {
+    // 设备树在内核初始化时已经建立，再调用 init 会重新探测硬件
+    let mut host_bridge = false;
+    let mut platform = 0;
+    for_each(|device| match device.id {
+        DeviceId::Pci(dev) => host_bridge |= dev.class == 0x06 && dev.subclass == 0x00,
+        DeviceId::Platform(_) => platform += 1,
+    });
+    assert!(host_bridge);
+    assert_eq!(platform, PLATFORM_DEVICES.len());
+
+    let ata = Match::Platform("ata");
+    assert!(ata.matches(&DeviceId::Platform("ata")));
+    assert!(!ata.matches(&DeviceId::Platform("pit")));
+    let bridge = pci::find_class(0x06, 0x00).unwrap();
+    let class = Match::PciClass {
+        class: 0x06,
+        subclass: 0x00,
+    };
+    assert!(class.matches(&DeviceId::Pci(bridge)));
+    assert!(!ata.matches(&DeviceId::Pci(bridge)));
+}
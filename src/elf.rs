@@ -0,0 +1,193 @@
+/// 段可执行
+pub const PF_X: u32 = 1;
+/// 段可写
+pub const PF_W: u32 = 2;
+
+// ELF 头部各字段的位置
+const IDENT_CLASS: usize = 4;
+const IDENT_DATA: usize = 5;
+const TYPE: usize = 16;
+const MACHINE: usize = 18;
+const ENTRY: usize = 24;
+const PHOFF: usize = 32;
+const PHENTSIZE: usize = 54;
+const PHNUM: usize = 56;
+const HEADER_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+const CLASS_64: u8 = 2;
+const DATA_LSB: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+const PT_LOAD: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// 文件比头部或程序头表声明的短
+    Truncated,
+    /// 不是 ELF 文件
+    BadMagic,
+    /// 不是 x86-64 上小端的 64 位可执行文件（比如共享库、重定位文件）
+    Unsupported,
+    /// 段的大小或偏移不合理
+    BadSegment,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(ElfError::Truncated)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(ElfError::Truncated)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64, ElfError> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(ElfError::Truncated)
+}
+
+/// 一个要加载的段（`PT_LOAD`）：文件中 `[offset, offset + file_size)` 的内容
+/// 放到虚拟地址 `vaddr`，其余 `mem_size - file_size` 字节清零（.bss）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub offset: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+    /// `PF_X`、`PF_W` 等权限位
+    pub flags: u32,
+}
+
+impl Segment {
+    /// 段在文件中的内容。
+    pub fn data<'a>(&self, elf: &Elf<'a>) -> &'a [u8] {
+        &elf.data[self.offset as usize..(self.offset + self.file_size) as usize]
+    }
+}
+
+/// 静态链接的 ELF64 可执行文件。只支持用户程序需要的部分：入口地址和要
+/// 加载的段，不处理动态链接和重定位。
+#[derive(Debug, Clone, Copy)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub entry: u64,
+    phoff: usize,
+    phnum: usize,
+}
+
+impl<'a> Elf<'a> {
+    /// 检查头部和每个要加载的段，之后 [`segments`](Self::segments) 不会再失败。
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[..4] != *b"\x7fELF" {
+            return Err(ElfError::BadMagic);
+        }
+        if data[IDENT_CLASS] != CLASS_64
+            || data[IDENT_DATA] != DATA_LSB
+            || u16_at(data, TYPE)? != TYPE_EXEC
+            || u16_at(data, MACHINE)? != MACHINE_X86_64
+            || u16_at(data, PHENTSIZE)? as usize != PHDR_SIZE
+        {
+            return Err(ElfError::Unsupported);
+        }
+        let elf = Elf {
+            data,
+            entry: u64_at(data, ENTRY)?,
+            phoff: u64_at(data, PHOFF)? as usize,
+            phnum: u16_at(data, PHNUM)? as usize,
+        };
+        for index in 0..elf.phnum {
+            if let Some(segment) = elf.segment(index)? {
+                let end = segment.offset.checked_add(segment.file_size);
+                if end.is_none_or(|end| end > data.len() as u64)
+                    || segment.file_size > segment.mem_size
+                    || segment.vaddr.checked_add(segment.mem_size).is_none()
+                {
+                    return Err(ElfError::BadSegment);
+                }
+            }
+        }
+        Ok(elf)
+    }
+
+    // 第 `index` 个程序头，不是 PT_LOAD 时返回 `None`
+    fn segment(&self, index: usize) -> Result<Option<Segment>, ElfError> {
+        let header = self
+            .phoff
+            .checked_add(index * PHDR_SIZE)
+            .ok_or(ElfError::Truncated)?;
+        if u32_at(self.data, header)? != PT_LOAD {
+            return Ok(None);
+        }
+        Ok(Some(Segment {
+            flags: u32_at(self.data, header + 4)?,
+            offset: u64_at(self.data, header + 8)?,
+            vaddr: u64_at(self.data, header + 16)?,
+            file_size: u64_at(self.data, header + 32)?,
+            mem_size: u64_at(self.data, header + 40)?,
+        }))
+    }
+
+    /// 要加载的段。
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        (0..self.phnum).filter_map(|index| self.segment(index).ok().flatten())
+    }
+}
+
+/// 造一个只有一个可执行段的最小 ELF：段从文件开头加载到 `vaddr`，入口是
+/// 紧跟在头部后面的 `code`。
+#[cfg(test)]
+pub(crate) fn build_test_image(vaddr: u64, code: &[u8], image: &mut [u8]) -> usize {
+    let len = HEADER_SIZE + PHDR_SIZE + code.len();
+    image[..len].fill(0);
+    image[..4].copy_from_slice(b"\x7fELF");
+    image[IDENT_CLASS] = CLASS_64;
+    image[IDENT_DATA] = DATA_LSB;
+    image[6] = 1;
+    image[TYPE..TYPE + 2].copy_from_slice(&TYPE_EXEC.to_le_bytes());
+    image[MACHINE..MACHINE + 2].copy_from_slice(&MACHINE_X86_64.to_le_bytes());
+    let entry = vaddr + (HEADER_SIZE + PHDR_SIZE) as u64;
+    image[ENTRY..ENTRY + 8].copy_from_slice(&entry.to_le_bytes());
+    image[PHOFF..PHOFF + 8].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    image[PHENTSIZE..PHENTSIZE + 2].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    image[PHNUM..PHNUM + 2].copy_from_slice(&1u16.to_le_bytes());
+    let phdr = &mut image[HEADER_SIZE..HEADER_SIZE + PHDR_SIZE];
+    phdr[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+    phdr[4..8].copy_from_slice(&(PF_X | 4).to_le_bytes());
+    phdr[16..24].copy_from_slice(&vaddr.to_le_bytes());
+    phdr[32..40].copy_from_slice(&(len as u64).to_le_bytes());
+    phdr[40..48].copy_from_slice(&(len as u64 + 0x100).to_le_bytes());
+    image[HEADER_SIZE + PHDR_SIZE..len].copy_from_slice(code);
+    len
+}
+
+#[test_case]
+fn test_elf_parse() {
+    let mut image = [0u8; 256];
+    let len = build_test_image(0x40_0000, &[0x90, 0xc3], &mut image);
+    {
+        let elf = Elf::parse(&image[..len]).unwrap();
+        assert_eq!(elf.entry, 0x40_0078);
+        let mut segments = elf.segments();
+        let segment = segments.next().unwrap();
+        assert!(segments.next().is_none());
+        assert_eq!((segment.vaddr, segment.mem_size), (0x40_0000, 0x17a));
+        assert_eq!(segment.data(&elf)[len - 2..], [0x90, 0xc3]);
+    }
+
+    // 段超出文件末尾
+    assert_eq!(
+        Elf::parse(&image[..len - 1]).unwrap_err(),
+        ElfError::BadSegment
+    );
+    image[TYPE] = 3;
+    assert_eq!(Elf::parse(&image).unwrap_err(), ElfError::Unsupported);
+    assert_eq!(Elf::parse(&image[1..]).unwrap_err(), ElfError::BadMagic);
+}
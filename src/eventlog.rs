@@ -0,0 +1,97 @@
+use crate::{ring::Ring, serial_println, time};
+
+/// 环形缓冲区容量。时钟中断本身不记录，否则几秒钟就会把其他事件挤出去。
+pub const CAPACITY: usize = 256;
+/// panic 时回放的时间窗口。
+pub const DUMP_SECONDS: u64 = 5;
+
+/// 记录在“时间旅行”日志中的事件。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// 外部中断（中断向量号）
+    Irq(u8),
+    /// 键盘扫描码
+    Key(u8),
+    /// CPU 异常（异常向量号）
+    Exception(u8),
+    /// 调度器切换任务（旧任务 id，新任务 id）
+    TaskSwitch(u32, u32),
+}
+
+impl Event {
+    fn encode(self) -> u64 {
+        let (kind, data) = match self {
+            Event::Irq(vector) => (1, vector as u64),
+            Event::Key(scancode) => (2, scancode as u64),
+            Event::Exception(vector) => (3, vector as u64),
+            Event::TaskSwitch(from, to) => (
+                4,
+                ((from as u64 & 0xff_ffff) << 28) | (to as u64 & 0xfff_ffff),
+            ),
+        };
+        (kind << 56) | (data & 0x00ff_ffff_ffff_ffff)
+    }
+
+    fn decode(raw: u64) -> Option<Event> {
+        let data = raw & 0x00ff_ffff_ffff_ffff;
+        match raw >> 56 {
+            1 => Some(Event::Irq(data as u8)),
+            2 => Some(Event::Key(data as u8)),
+            3 => Some(Event::Exception(data as u8)),
+            4 => Some(Event::TaskSwitch(
+                (data >> 28) as u32,
+                (data & 0xfff_ffff) as u32,
+            )),
+            _ => None,
+        }
+    }
+}
+
+// 每条记录是时钟计数和编码后的事件
+static RING: Ring<CAPACITY, 2> = Ring::new();
+
+/// 记录一个事件。无锁，可以在中断处理函数中调用。
+pub fn record(event: Event) {
+    RING.push([time::ticks(), event.encode()]);
+}
+
+/// 按时间顺序遍历最近 `seconds` 秒内的事件。
+pub fn for_each_recent(seconds: u64, mut f: impl FnMut(u64, Event)) {
+    let since = time::ticks().saturating_sub(seconds * time::TICK_HZ);
+    RING.for_each(|[ticks, event]| {
+        if let Some(event) = Event::decode(event)
+            && ticks >= since
+        {
+            f(ticks, event);
+        }
+    });
+}
+
+/// 把最近的事件历史输出到串口，在 panic 时调用。
+pub fn dump() {
+    serial_println!("--- last {}s of events ---", DUMP_SECONDS);
+    for_each_recent(DUMP_SECONDS, |ticks, event| {
+        let ms = time::ticks_to_ms(ticks);
+        serial_println!("[{:>6}.{:03}] {:?}", ms / 1000, ms % 1000, event);
+    });
+    serial_println!("--- end of events ---");
+}
+
+#[test_case]
+fn test_eventlog_records_in_order() {
+    record(Event::Key(0x1e));
+    record(Event::TaskSwitch(3, 7));
+    record(Event::Exception(3));
+
+    let mut seen = [None; 3];
+    let mut count = 0;
+    for_each_recent(DUMP_SECONDS, |_, event| {
+        seen[count % 3] = Some(event);
+        count += 1;
+    });
+    assert!(count >= 3);
+    let last = (count - 3) % 3;
+    assert_eq!(seen[last], Some(Event::Key(0x1e)));
+    assert_eq!(seen[(last + 1) % 3], Some(Event::TaskSwitch(3, 7)));
+    assert_eq!(seen[(last + 2) % 3], Some(Event::Exception(3)));
+}
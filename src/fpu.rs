@@ -0,0 +1,99 @@
+use core::arch::asm;
+
+use spin::Once;
+use x86_64::registers::control::{Cr0Flags, Cr4Flags};
+
+use crate::{
+    cpu::{self, Feature},
+    regs,
+};
+
+/// FXSAVE 保存的 x87、MMX 和 SSE 状态，512 字节，必须 16 字节对齐。
+///
+/// 每个内核线程和进程各有一份，切换时用 [`switch`] 保存旧的、恢复新的。
+#[derive(Clone)]
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+// `fninit` 之后的状态，新任务从这里开始
+static INITIAL: Once<FpuState> = Once::new();
+
+impl FpuState {
+    /// 初始状态：x87 控制字 0x37f、MXCSR 0x1f80，屏蔽所有异常。
+    pub fn new() -> Self {
+        INITIAL.r#try().cloned().unwrap_or(FpuState([0; 512]))
+    }
+
+    /// 把当前 CPU 的浮点状态保存到这里。
+    pub fn save(&mut self) {
+        unsafe { asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack)) };
+    }
+
+    /// 把这里的状态装入当前 CPU。
+    pub fn restore(&self) {
+        unsafe { asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack, readonly)) };
+    }
+
+    /// 保存的 MXCSR（SSE 控制和状态寄存器）。
+    pub fn mxcsr(&self) -> u32 {
+        u32::from_le_bytes(self.0[24..28].try_into().unwrap())
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 在当前 CPU 上打开 x87 和 SSE：CR0 关掉模拟、打开 MP，CR4 打开 FXSAVE 和
+/// SSE 异常。BSP 在 `init()` 中调用，每个 AP 启动时也要调用。
+///
+/// 内核本身仍按 soft-float 编译，不会用到这些寄存器；这里是给将来的用户程序
+/// 和显式使用 SIMD 的代码准备的。
+pub fn init() {
+    if !cpu::has(Feature::Fxsr) || !cpu::has(Feature::Sse) {
+        log::warn!("fpu: no FXSR/SSE, floating point stays disabled");
+        return;
+    }
+    unsafe {
+        let _ = regs::cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        let _ = regs::cr4::update(|flags| {
+            flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+        });
+        asm!("fninit", options(nomem, nostack));
+    }
+    INITIAL.call_once(|| {
+        let mut state = FpuState([0; 512]);
+        state.save();
+        state
+    });
+}
+
+/// 上下文切换时调用：保存当前任务的浮点状态到 `prev`，装入 `next`。
+pub fn switch(prev: &mut FpuState, next: &FpuState) {
+    prev.save();
+    next.restore();
+}
+
+#[test_case]
+fn test_fpu_save_restore() {
+    // xmm0 在 FXSAVE 区域中的偏移
+    const XMM0: usize = 160;
+    let pattern = [0x5au8; 16];
+    let mut out = [0u8; 16];
+    let mut state = FpuState::new();
+    assert_eq!(state.mxcsr(), 0x1f80);
+
+    // 内核按 soft-float 编译，编译器不会用 xmm0，这里直接改它
+    unsafe { asm!("movdqu xmm0, [{}]", in(reg) pattern.as_ptr(), options(nostack, readonly)) };
+    state.save();
+    assert_eq!(state.0[XMM0..XMM0 + 16], pattern);
+    unsafe { asm!("pxor xmm0, xmm0", options(nomem, nostack)) };
+    state.restore();
+    unsafe { asm!("movdqu [{}], xmm0", in(reg) out.as_mut_ptr(), options(nostack)) };
+    assert_eq!(out, pattern);
+}
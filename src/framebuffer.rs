@@ -0,0 +1,366 @@
+use core::fmt;
+
+use spin::Mutex;
+use x86_64::{VirtAddr, instructions::interrupts};
+
+use crate::{
+    console::{self, ConsoleSink, LogLevel, Sinks},
+    fw_cfg, memory, speaker,
+    vga_buffer::{self, Color},
+};
+
+/// QEMU 中通过 `-fw_cfg name=opt/os-rust/font.psf,file=...` 提供控制台字体。
+pub const FONT_FW_CFG_FILE: &str = "opt/os-rust/font.psf";
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+// 16 色调色板，与 VGA 文本模式的颜色一一对应
+const PALETTE: [u32; 16] = [
+    0x000000, 0x0000aa, 0x00aa00, 0x00aaaa, 0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa, 0x555555,
+    0x5555ff, 0x55ff55, 0x55ffff, 0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 内存中依次是红、绿、蓝
+    Rgb,
+    /// 内存中依次是蓝、绿、红（UEFI GOP 最常见的格式）
+    Bgr,
+}
+
+/// 线性帧缓冲区的布局，由引导程序提供。
+#[derive(Debug, Clone, Copy)]
+pub struct FrameBufferInfo {
+    /// 帧缓冲区映射到的虚拟地址
+    pub base: VirtAddr,
+    pub width: usize,
+    pub height: usize,
+    /// 每行占用的像素数，可能大于 `width`
+    pub stride: usize,
+    /// 3 或 4
+    pub bytes_per_pixel: usize,
+    pub format: PixelFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// 不是 PSF1 或 PSF2 字体
+    BadMagic,
+    /// 头部声明的字形比数据多
+    Truncated,
+    /// 帧缓冲区的像素格式不支持
+    BadFormat,
+    /// 屏幕连一个字符都放不下
+    TooLarge,
+}
+
+/// PSF（Linux 控制台字体）位图字体。忽略 Unicode 映射表，按字节值取字形。
+#[derive(Debug, Clone, Copy)]
+pub struct Font {
+    glyphs: &'static [u8],
+    count: usize,
+    width: usize,
+    height: usize,
+    bytes_per_glyph: usize,
+}
+
+impl Font {
+    pub fn parse(data: &'static [u8]) -> Result<Font, FontError> {
+        let u32_at = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+                .ok_or(FontError::Truncated)
+        };
+        let (header, count, width, height, bytes_per_glyph) = if data.starts_with(&PSF2_MAGIC) {
+            let header = u32_at(8)?;
+            let (count, bytes_per_glyph) = (u32_at(16)?, u32_at(20)?);
+            (header, count, u32_at(28)?, u32_at(24)?, bytes_per_glyph)
+        } else if data.starts_with(&PSF1_MAGIC) && data.len() >= 4 {
+            let count = if data[2] & PSF1_MODE_512 != 0 {
+                512
+            } else {
+                256
+            };
+            let height = data[3] as usize;
+            (4, count, 8, height, height)
+        } else {
+            return Err(FontError::BadMagic);
+        };
+        let len = count
+            .checked_mul(bytes_per_glyph)
+            .and_then(|len| len.checked_add(header))
+            .ok_or(FontError::Truncated)?;
+        if width == 0
+            || height == 0
+            || len > data.len()
+            || bytes_per_glyph < width.div_ceil(8) * height
+        {
+            return Err(FontError::Truncated);
+        }
+        Ok(Font {
+            glyphs: &data[header..len],
+            count,
+            width,
+            height,
+            bytes_per_glyph,
+        })
+    }
+
+    /// 字节 `byte` 的字形，每行 `width.div_ceil(8)` 字节，高位在左。
+    fn glyph(&self, byte: u8) -> &'static [u8] {
+        let byte = if (byte as usize) < self.count {
+            byte
+        } else {
+            b'?'
+        };
+        let index = byte as usize;
+        &self.glyphs[index * self.bytes_per_glyph..(index + 1) * self.bytes_per_glyph]
+    }
+}
+
+/// 在帧缓冲区上用位图字体显示文字的控制台。
+pub struct FrameBufferConsole {
+    info: FrameBufferInfo,
+    font: Font,
+    column: usize,
+    row: usize,
+    foreground: Color,
+    background: Color,
+}
+
+// 帧缓冲区只通过持有锁的控制台访问
+unsafe impl Send for FrameBufferConsole {}
+
+impl FrameBufferConsole {
+    /// 在 `info` 描述的帧缓冲区上建立控制台并清屏。
+    ///
+    /// # Safety
+    /// `info` 描述的内存必须已经映射、可写，并且只由这个控制台使用。
+    pub unsafe fn new(info: FrameBufferInfo, font: Font) -> Result<Self, FontError> {
+        if !(3..=4).contains(&info.bytes_per_pixel) {
+            return Err(FontError::BadFormat);
+        }
+        // 至少要有一行一列，换行时才有地方可以滚动
+        if font.width > info.width || font.height > info.height {
+            return Err(FontError::TooLarge);
+        }
+        let theme = vga_buffer::theme();
+        let mut console = FrameBufferConsole {
+            info,
+            font,
+            column: 0,
+            row: 0,
+            foreground: theme.foreground,
+            background: theme.background,
+        };
+        console.clear();
+        Ok(console)
+    }
+
+    pub fn columns(&self) -> usize {
+        self.info.width / self.font.width
+    }
+
+    pub fn rows(&self) -> usize {
+        self.info.height / self.font.height
+    }
+
+    fn row_bytes(&self) -> usize {
+        self.info.stride * self.info.bytes_per_pixel
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let rgb = PALETTE[color as usize];
+        let [blue, green, red, _] = rgb.to_le_bytes();
+        let pixel = match self.info.format {
+            PixelFormat::Rgb => [red, green, blue, 0],
+            PixelFormat::Bgr => [blue, green, red, 0],
+        };
+        let offset = y * self.row_bytes() + x * self.info.bytes_per_pixel;
+        let ptr = self.info.base.as_mut_ptr::<u8>();
+        for (i, &byte) in pixel[..self.info.bytes_per_pixel].iter().enumerate() {
+            unsafe { ptr.add(offset + i).write_volatile(byte) };
+        }
+    }
+
+    fn draw_glyph(&mut self, column: usize, row: usize, byte: u8) {
+        let glyph = self.font.glyph(byte);
+        let bytes_per_line = self.font.width.div_ceil(8);
+        let (x0, y0) = (column * self.font.width, row * self.font.height);
+        for y in 0..self.font.height {
+            for x in 0..self.font.width {
+                let set = glyph[y * bytes_per_line + x / 8] & (0x80 >> (x % 8)) != 0;
+                let color = if set {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                self.put_pixel(x0 + x, y0 + y, color);
+            }
+        }
+    }
+
+    fn clear_rows(&mut self, rows: core::ops::Range<usize>) {
+        for y in rows.start * self.font.height..rows.end * self.font.height {
+            for x in 0..self.info.width {
+                self.put_pixel(x, y, self.background);
+            }
+        }
+    }
+
+    /// 用背景色清空屏幕，光标回到左上角。
+    pub fn clear(&mut self) {
+        self.clear_rows(0..self.rows());
+        self.column = 0;
+        self.row = 0;
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows() {
+            self.row += 1;
+            return;
+        }
+        // 整块内存一次搬移，比逐像素重画快得多
+        let line = self.font.height * self.row_bytes();
+        let len = (self.rows() - 1) * line;
+        unsafe {
+            let base = self.info.base.as_mut_ptr::<u8>();
+            core::ptr::copy(base.add(line), base, len);
+        }
+        self.clear_rows(self.row..self.row + 1);
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            0x07 => speaker::bell(),
+            byte => {
+                if self.column >= self.columns() {
+                    self.new_line();
+                }
+                let byte = if (0x20..=0x7e).contains(&byte) {
+                    byte
+                } else {
+                    0xfe
+                };
+                self.draw_glyph(self.column, self.row, byte);
+                self.column += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Write for FrameBufferConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.write_byte(byte));
+        Ok(())
+    }
+}
+
+impl ConsoleSink for FrameBufferConsole {
+    fn write_level(&mut self, level: LogLevel, args: fmt::Arguments) -> fmt::Result {
+        use core::fmt::Write;
+
+        let saved = self.foreground;
+        self.foreground = vga_buffer::theme().level_color(level);
+        let result = writeln!(self, "{} {}", level.prefix(), args);
+        self.foreground = saved;
+        result
+    }
+}
+
+static CONSOLE: Mutex<Option<FrameBufferConsole>> = Mutex::new(None);
+
+/// 在帧缓冲区上建立控制台，并让 `print!` 改为输出到这里而不是 VGA 文本缓冲区。
+///
+/// # Safety
+/// `info` 描述的内存必须已经映射、可写，并且不被别处使用。
+pub unsafe fn init(info: FrameBufferInfo, font: &'static [u8]) -> Result<(), FontError> {
+    let console = unsafe { FrameBufferConsole::new(info, Font::parse(font)?)? };
+    interrupts::without_interrupts(|| *CONSOLE.lock() = Some(console));
+    // 图形模式下 0xb8000 不存在
+    let sinks = console::sinks();
+    if sinks.contains(Sinks::VGA) {
+        console::set_sinks(sinks.without(Sinks::VGA).union(Sinks::FRAMEBUFFER));
+    }
+    Ok(())
+}
+
+/// 关中断后把帧缓冲区控制台交给 `f`；还没有初始化时返回 `None`。
+pub(crate) fn with_console<R>(f: impl FnOnce(&mut FrameBufferConsole) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| CONSOLE.lock().as_mut().map(f))
+}
+
+/// 强行释放控制台的锁，只在 panic 时使用。
+///
+/// # Safety
+/// 同 [`vga_buffer::force_unlock`]。
+pub(crate) unsafe fn force_unlock() {
+    unsafe { CONSOLE.force_unlock() };
+}
+
+/// 控制台的锁是否被持有。只在崩溃报告中使用，不等锁。
+pub(crate) fn is_locked() -> bool {
+    CONSOLE.try_lock().is_none()
+}
+
+/// 从 fw_cfg 读取控制台字体。
+pub fn load_font() -> Option<&'static [u8]> {
+    let file = fw_cfg::find(FONT_FW_CFG_FILE)?;
+    let buffer = memory::alloc_dma(file.size.div_ceil(memory::PAGE_SIZE as usize))?;
+    let data = buffer.leak();
+    let len = file.read(data);
+    Some(&data[..len])
+}
+
+#[test_case]
+fn test_framebuffer_console() {
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 16;
+    // PSF1 头部和 256 个 8x8 的字形；只有 'A' 有内容：左边一列竖线
+    static mut FONT: [u8; 4 + 256 * 8] = [0; 4 + 256 * 8];
+    static mut PIXELS: [u32; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
+
+    let font = &raw mut FONT;
+    let font: &'static mut [u8] = unsafe { &mut *font };
+    font[..4].copy_from_slice(&[0x36, 0x04, 0, 8]);
+    font[4 + b'A' as usize * 8..][..8].fill(0x80);
+    assert_eq!(Font::parse(&font[..100]).unwrap_err(), FontError::Truncated);
+    let font = Font::parse(font).unwrap();
+    assert_eq!((font.width, font.height, font.count), (8, 8, 256));
+
+    let info = FrameBufferInfo {
+        base: VirtAddr::from_ptr(&raw mut PIXELS),
+        width: WIDTH,
+        height: HEIGHT,
+        stride: WIDTH,
+        bytes_per_pixel: 4,
+        format: PixelFormat::Bgr,
+    };
+    let tiny = FrameBufferInfo { height: 4, ..info };
+    assert!(matches!(
+        unsafe { FrameBufferConsole::new(tiny, font) },
+        Err(FontError::TooLarge)
+    ));
+    let mut console = unsafe { FrameBufferConsole::new(info, font).unwrap() };
+    console.foreground = Color::White;
+    console.background = Color::Black;
+    assert_eq!((console.columns(), console.rows()), (2, 2));
+    let pixels = &raw const PIXELS;
+    let pixel = |x: usize, y: usize| unsafe { (*pixels)[y * WIDTH + x] };
+
+    console.write_byte(b'A');
+    assert_eq!(pixel(0, 7), 0xffffff);
+    assert_eq!(pixel(1, 0), 0);
+    // 写到第二行后再换行，整屏上移一行
+    console.write_byte(b'\n');
+    console.write_byte(b'B');
+    console.write_byte(b'A');
+    console.write_byte(b'\n');
+    assert_eq!(pixel(8, 0), 0xffffff);
+    assert_eq!(pixel(0, 0), 0);
+    assert_eq!(pixel(8, 8), 0);
+}
@@ -0,0 +1,442 @@
+use spin::Mutex;
+
+pub mod ext2;
+pub mod fat32;
+pub mod tarfs;
+pub mod tmpfs;
+
+use crate::storage::BlockError;
+
+/// 路径的最大长度（字节）。
+pub const PATH_MAX: usize = 256;
+/// 单个文件名的最大长度（字节）。
+pub const NAME_MAX: usize = 255;
+/// 挂载表最多容纳的挂载点数。
+pub const MAX_MOUNTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    /// 路径不是以 `/` 开头，或者超过了 `PATH_MAX` / `NAME_MAX`
+    InvalidPath,
+    AlreadyExists,
+    /// 目录非空，或挂载点正在使用
+    Busy,
+    NoSpace,
+    ReadOnly,
+    /// 文件系统不支持这个操作
+    Unsupported,
+    /// 磁盘上的数据结构损坏
+    Corrupt,
+    /// 挂载表已满
+    TooManyMounts,
+    Io(BlockError),
+}
+
+impl From<BlockError> for FsError {
+    fn from(error: BlockError) -> Self {
+        FsError::Io(error)
+    }
+}
+
+/// 文件系统内部的 inode 编号，只在同一个文件系统内有意义。
+pub type InodeId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub inode: InodeId,
+    pub kind: FileType,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: heapless::String<NAME_MAX>,
+    pub inode: InodeId,
+    pub kind: FileType,
+}
+
+/// 一个具体的文件系统（FAT32、ext2、tmpfs……）。
+///
+/// 文件和目录用 inode 编号标识，VFS 负责把路径翻译成编号。只读文件系统只需要
+/// 实现查找和读取，修改类的操作默认返回 `ReadOnly`。
+pub trait FileSystem: Send {
+    /// 文件系统的类型名，例如 `"fat32"`。
+    fn name(&self) -> &'static str;
+    fn root(&self) -> InodeId;
+    /// 在目录 `dir` 中查找名为 `name` 的项。
+    fn lookup(&mut self, dir: InodeId, name: &str) -> Result<InodeId, FsError>;
+    fn metadata(&mut self, inode: InodeId) -> Result<Metadata, FsError>;
+    /// 从 `offset` 处读取，返回读到的字节数；到达文件末尾时返回 0。
+    fn read_at(&mut self, inode: InodeId, offset: u64, buf: &mut [u8]) -> Result<usize, FsError>;
+    /// 返回目录中第 `index` 项，超出末尾时返回 `None`。
+    fn read_dir(&mut self, dir: InodeId, index: usize) -> Result<Option<DirEntry>, FsError>;
+
+    fn write_at(&mut self, _inode: InodeId, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn create(&mut self, _dir: InodeId, _name: &str, _kind: FileType) -> Result<InodeId, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn remove(&mut self, _dir: InodeId, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&mut self, _inode: InodeId, _size: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+/// 挂载表中保存的文件系统引用。和块设备一样，文件系统实例由创建它的模块
+/// 放在 static 中。
+pub type SharedFs = &'static Mutex<dyn FileSystem>;
+
+type Path = heapless::String<PATH_MAX>;
+
+struct Mount {
+    path: Path,
+    fs: SharedFs,
+}
+
+static MOUNTS: Mutex<heapless::Vec<Mount, MAX_MOUNTS>> = Mutex::new(heapless::Vec::new());
+
+/// 把绝对路径规范化：合并多余的 `/`，处理 `.` 和 `..`，去掉结尾的 `/`。
+fn normalize(path: &str) -> Result<Path, FsError> {
+    if !path.starts_with('/') {
+        return Err(FsError::InvalidPath);
+    }
+    let mut out = Path::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                let parent = out.rfind('/').unwrap_or(0);
+                out.truncate(parent);
+            }
+            name => {
+                if name.len() > NAME_MAX {
+                    return Err(FsError::InvalidPath);
+                }
+                out.push('/').map_err(|_| FsError::InvalidPath)?;
+                out.push_str(name).map_err(|_| FsError::InvalidPath)?;
+            }
+        }
+    }
+    if out.is_empty() {
+        let _ = out.push('/');
+    }
+    Ok(out)
+}
+
+/// `path` 是否位于挂载点 `mount` 之下，是则返回剩余部分。
+fn strip_mount<'a>(path: &'a str, mount: &str) -> Option<&'a str> {
+    if mount == "/" {
+        return Some(path);
+    }
+    let rest = path.strip_prefix(mount)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// 把已规范化的路径解析为 (文件系统, inode)。使用最长匹配的挂载点。
+fn resolve_normalized(path: &str) -> Result<(SharedFs, InodeId), FsError> {
+    let (fs, rest) = {
+        let mounts = MOUNTS.lock();
+        mounts
+            .iter()
+            .filter_map(|mount| Some((mount, strip_mount(path, &mount.path)?)))
+            .max_by_key(|(mount, _)| mount.path.len())
+            .map(|(mount, rest)| (mount.fs, rest))
+            .ok_or(FsError::NotFound)?
+    };
+    let mut locked = fs.lock();
+    let mut inode = locked.root();
+    for name in rest.split('/').filter(|name| !name.is_empty()) {
+        if locked.metadata(inode)?.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        inode = locked.lookup(inode, name)?;
+    }
+    Ok((fs, inode))
+}
+
+/// 把路径解析为它所在的文件系统和 inode。
+pub fn resolve(path: &str) -> Result<(SharedFs, InodeId), FsError> {
+    resolve_normalized(&normalize(path)?)
+}
+
+/// 把 `fs` 挂载到 `path`。如果 `path` 位于已挂载的文件系统中，它必须是已存在的
+/// 目录；否则（例如根目录还没有挂载任何东西）挂载点不需要事先存在。
+pub fn mount(path: &str, fs: SharedFs) -> Result<(), FsError> {
+    let path = normalize(path)?;
+    let covered = {
+        let mounts = MOUNTS.lock();
+        if mounts.iter().any(|mount| mount.path == path) {
+            return Err(FsError::Busy);
+        }
+        mounts
+            .iter()
+            .any(|mount| strip_mount(&path, &mount.path).is_some())
+    };
+    if covered {
+        let (parent, inode) = resolve_normalized(&path)?;
+        if parent.lock().metadata(inode)?.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+    }
+    MOUNTS
+        .lock()
+        .push(Mount { path, fs })
+        .map_err(|_| FsError::TooManyMounts)
+}
+
+/// 卸载 `path` 上的文件系统。其下还有其他挂载点时返回 `Busy`。
+pub fn unmount(path: &str) -> Result<(), FsError> {
+    let path = normalize(path)?;
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(FsError::NotFound)?;
+    let nested = mounts
+        .iter()
+        .any(|mount| mount.path != path && strip_mount(&mount.path, &path).is_some());
+    if nested {
+        return Err(FsError::Busy);
+    }
+    mounts.swap_remove(index);
+    Ok(())
+}
+
+/// 对每个挂载点调用 `f(路径, 文件系统类型)`。
+pub fn for_each_mount(mut f: impl FnMut(&str, &'static str)) {
+    for mount in MOUNTS.lock().iter() {
+        f(&mount.path, mount.fs.lock().name());
+    }
+}
+
+pub fn metadata(path: &str) -> Result<Metadata, FsError> {
+    let (fs, inode) = resolve(path)?;
+    fs.lock().metadata(inode)
+}
+
+/// 对目录中的每一项调用 `f`。
+pub fn read_dir(path: &str, mut f: impl FnMut(&DirEntry)) -> Result<(), FsError> {
+    let (fs, inode) = resolve(path)?;
+    for index in 0.. {
+        // 先释放锁再调用 `f`，它可能还要访问同一个文件系统
+        let entry = fs.lock().read_dir(inode, index)?;
+        let Some(entry) = entry else {
+            break;
+        };
+        f(&entry);
+    }
+    Ok(())
+}
+
+/// 把路径拆成父目录和最后一个名字。
+fn split_parent(path: &str) -> Result<(Path, heapless::String<NAME_MAX>), FsError> {
+    let path = normalize(path)?;
+    let slash = path.rfind('/').unwrap_or(0);
+    let name = &path[slash + 1..];
+    if name.is_empty() {
+        return Err(FsError::InvalidPath);
+    }
+    let parent = normalize(if slash == 0 { "/" } else { &path[..slash] })?;
+    let mut owned = heapless::String::new();
+    let _ = owned.push_str(name);
+    Ok((parent, owned))
+}
+
+/// 创建文件或目录。
+pub fn create(path: &str, kind: FileType) -> Result<(), FsError> {
+    let (parent, name) = split_parent(path)?;
+    let (fs, dir) = resolve_normalized(&parent)?;
+    fs.lock().create(dir, &name, kind).map(|_| ())
+}
+
+/// 删除文件或空目录。
+pub fn remove(path: &str) -> Result<(), FsError> {
+    let full = normalize(path)?;
+    if MOUNTS
+        .lock()
+        .iter()
+        .any(|mount| strip_mount(&mount.path, &full).is_some())
+    {
+        return Err(FsError::Busy);
+    }
+    let (parent, name) = split_parent(path)?;
+    let (fs, dir) = resolve_normalized(&parent)?;
+    fs.lock().remove(dir, &name)
+}
+
+/// 打开一个已存在的文件。
+pub fn open(path: &str) -> Result<File, FsError> {
+    let (fs, inode) = resolve(path)?;
+    if fs.lock().metadata(inode)?.kind == FileType::Directory {
+        return Err(FsError::IsADirectory);
+    }
+    Ok(File {
+        fs,
+        inode,
+        offset: 0,
+    })
+}
+
+/// 打开的文件，带有当前读写位置。
+pub struct File {
+    fs: SharedFs,
+    inode: InodeId,
+    offset: u64,
+}
+
+impl File {
+    pub fn metadata(&self) -> Result<Metadata, FsError> {
+        self.fs.lock().metadata(self.inode)
+    }
+
+    /// 从当前位置读取，返回读到的字节数。
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let read = self.fs.lock().read_at(self.inode, self.offset, buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+
+    /// 尽量填满 `buf`，返回读到的字节数（小于 `buf.len()` 说明到了文件末尾）。
+    pub fn read_all(&mut self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut total = 0;
+        while total < buf.len() {
+            match self.read(&mut buf[total..])? {
+                0 => break,
+                read => total += read,
+            }
+        }
+        Ok(total)
+    }
+
+    /// 在当前位置写入，返回写入的字节数。
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, FsError> {
+        let written = self.fs.lock().write_at(self.inode, self.offset, buf)?;
+        self.offset += written as u64;
+        Ok(written)
+    }
+
+    pub fn seek(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    pub fn position(&self) -> u64 {
+        self.offset
+    }
+}
+
+#[test_case]
+fn test_fs_normalize_paths() {
+    assert_eq!(normalize("/").unwrap(), "/");
+    assert_eq!(
+        normalize("//boot/./x/../config.txt/").unwrap(),
+        "/boot/config.txt"
+    );
+    assert_eq!(normalize("/../..").unwrap(), "/");
+    assert_eq!(normalize("relative"), Err(FsError::InvalidPath));
+    assert_eq!(strip_mount("/mnt/disk/a", "/mnt/disk"), Some("/a"));
+    assert_eq!(strip_mount("/mnt/diskette", "/mnt/disk"), None);
+}
+
+#[test_case]
+fn test_fs_mount_and_open() {
+    // 不挂载到 `/`，以免和启动时挂载的 initrd、/tmp 冲突
+    mount("/fixed", &test_fs::FS).unwrap();
+    assert_eq!(mount("/fixed/", &test_fs::FS), Err(FsError::Busy));
+
+    let mut file = open("/fixed/boot/config.txt").unwrap();
+    let mut buf = [0; 32];
+    let read = file.read_all(&mut buf).unwrap();
+    assert_eq!(&buf[..read], b"hostname=test\n");
+    assert_eq!(open("/fixed/boot").err(), Some(FsError::IsADirectory));
+    assert_eq!(open("/fixed/missing").err(), Some(FsError::NotFound));
+    assert_eq!(
+        create("/fixed/boot/new", FileType::File),
+        Err(FsError::ReadOnly)
+    );
+
+    // 挂载在子目录上的文件系统遮住了原来的内容，路径从它的根开始解析
+    mount("/fixed/boot", &test_fs::FS).unwrap();
+    assert_eq!(
+        metadata("/fixed/boot/boot").unwrap().kind,
+        FileType::Directory
+    );
+    assert!(open("/fixed/boot/boot/config.txt").is_ok());
+    assert_eq!(
+        open("/fixed/boot/config.txt").err(),
+        Some(FsError::NotFound)
+    );
+    assert_eq!(remove("/fixed/boot"), Err(FsError::Busy));
+
+    assert_eq!(unmount("/fixed"), Err(FsError::Busy));
+    unmount("/fixed/boot").unwrap();
+    unmount("/fixed").unwrap();
+}
+
+#[cfg(test)]
+mod test_fs {
+    use super::*;
+
+    // 固定的目录树：/ (0) -> boot (1) -> config.txt (2)
+    pub struct FixedFs;
+
+    const CONTENT: &[u8] = b"hostname=test\n";
+
+    impl FileSystem for FixedFs {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn root(&self) -> InodeId {
+            0
+        }
+
+        fn lookup(&mut self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+            match (dir, name) {
+                (0, "boot") => Ok(1),
+                (1, "config.txt") => Ok(2),
+                _ => Err(FsError::NotFound),
+            }
+        }
+
+        fn metadata(&mut self, inode: InodeId) -> Result<Metadata, FsError> {
+            let (kind, size) = match inode {
+                2 => (FileType::File, CONTENT.len() as u64),
+                _ => (FileType::Directory, 0),
+            };
+            Ok(Metadata { inode, kind, size })
+        }
+
+        fn read_at(
+            &mut self,
+            _inode: InodeId,
+            offset: u64,
+            buf: &mut [u8],
+        ) -> Result<usize, FsError> {
+            let rest = CONTENT.get(offset as usize..).unwrap_or(&[]);
+            let len = rest.len().min(buf.len()).min(4);
+            buf[..len].copy_from_slice(&rest[..len]);
+            Ok(len)
+        }
+
+        fn read_dir(&mut self, _dir: InodeId, _index: usize) -> Result<Option<DirEntry>, FsError> {
+            Ok(None)
+        }
+    }
+
+    pub static FS: Mutex<FixedFs> = Mutex::new(FixedFs);
+}
@@ -0,0 +1,383 @@
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, FileType, FsError, InodeId, Metadata};
+use crate::storage::SharedDevice;
+
+/// 支持的最大块大小。
+const MAX_BLOCK_SIZE: usize = 4096;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const MAGIC: u16 = 0xef53;
+const ROOT_INODE: InodeId = 2;
+
+// 不影响只读访问的不兼容特性：目录项中带文件类型
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+
+const MODE_TYPE_MASK: u16 = 0xf000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_REGULAR: u16 = 0x8000;
+
+// inode 中的 15 个块指针：12 个直接块，然后是一级、二级、三级间接块
+const DIRECT_BLOCKS: u64 = 12;
+
+/// 最多同时挂载的 ext2 卷数。
+pub const MAX_VOLUMES: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+struct Inode {
+    mode: u16,
+    size: u64,
+    blocks: [u32; 15],
+}
+
+impl Inode {
+    fn kind(&self) -> Result<FileType, FsError> {
+        match self.mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => Ok(FileType::Directory),
+            MODE_REGULAR => Ok(FileType::File),
+            // 符号链接、设备文件等暂不支持
+            _ => Err(FsError::Unsupported),
+        }
+    }
+}
+
+/// 文件内第 `index` 个块在块指针树中的路径：(间接层数, 每一层的下标)。
+fn block_path(index: u64, per_block: u64) -> Option<(usize, [u64; 4])> {
+    if index < DIRECT_BLOCKS {
+        return Some((0, [index, 0, 0, 0]));
+    }
+    let mut rest = index - DIRECT_BLOCKS;
+    let mut span = per_block;
+    for level in 1..=3 {
+        if rest < span {
+            let mut path = [0; 4];
+            path[0] = DIRECT_BLOCKS + level as u64 - 1;
+            for depth in (1..=level).rev() {
+                path[depth] = rest % per_block;
+                rest /= per_block;
+            }
+            return Some((level, path));
+        }
+        rest -= span;
+        span *= per_block;
+    }
+    None
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// 只读的 ext2 卷（也能读取没有启用 extents、不需要日志恢复的 ext3）。
+pub struct Ext2 {
+    device: Option<SharedDevice>,
+    block_size: usize,
+    inodes_count: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    // 块组描述符表所在的块
+    descriptor_block: u64,
+    filetype: bool,
+}
+
+impl Ext2 {
+    const fn unmounted() -> Self {
+        Ext2 {
+            device: None,
+            block_size: 1024,
+            inodes_count: 0,
+            inodes_per_group: 0,
+            inode_size: 128,
+            descriptor_block: 0,
+            filetype: false,
+        }
+    }
+
+    /// 读取超级块，识别设备上的 ext2 卷。
+    pub fn open(device: SharedDevice) -> Result<Ext2, FsError> {
+        let mut fs = Ext2::unmounted();
+        fs.device = Some(device);
+
+        let mut superblock = [0; 1024];
+        fs.read_bytes(SUPERBLOCK_OFFSET, &mut superblock)?;
+        if u16_at(&superblock, 56) != MAGIC {
+            return Err(FsError::Unsupported);
+        }
+        let log_block_size = u32_at(&superblock, 24);
+        if log_block_size > 2 {
+            return Err(FsError::Unsupported);
+        }
+        fs.block_size = 1024 << log_block_size;
+        fs.inodes_count = u32_at(&superblock, 0);
+        fs.inodes_per_group = u32_at(&superblock, 40);
+        // 版本 0 的 inode 固定为 128 字节，也没有特性字段
+        if u32_at(&superblock, 76) >= 1 {
+            fs.inode_size = u16_at(&superblock, 88) as usize;
+            let incompat = u32_at(&superblock, 96);
+            if incompat & !INCOMPAT_FILETYPE != 0 {
+                return Err(FsError::Unsupported);
+            }
+            fs.filetype = incompat & INCOMPAT_FILETYPE != 0;
+        }
+        if fs.inodes_per_group == 0 || fs.inode_size < 128 || fs.inode_size > fs.block_size {
+            return Err(FsError::Corrupt);
+        }
+        fs.descriptor_block = u32_at(&superblock, 20) as u64 + 1;
+        Ok(fs)
+    }
+
+    /// 从卷的字节偏移 `offset` 处读取 `buf.len()` 字节，可以不按扇区对齐。
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let device = self.device.ok_or(FsError::NotFound)?;
+        let mut device = device.lock();
+        let sector_size = device.block_size() as u64;
+        let mut sector = [0; MAX_BLOCK_SIZE];
+        if sector_size as usize > MAX_BLOCK_SIZE {
+            return Err(FsError::Unsupported);
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let start = (position % sector_size) as usize;
+            let count = (sector_size as usize - start).min(buf.len() - done);
+            device.read_blocks(position / sector_size, &mut sector[..sector_size as usize])?;
+            buf[done..done + count].copy_from_slice(&sector[start..start + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn read_block(&self, block: u64, buf: &mut [u8; MAX_BLOCK_SIZE]) -> Result<(), FsError> {
+        self.read_bytes(block * self.block_size as u64, &mut buf[..self.block_size])
+    }
+
+    fn inode(&self, number: InodeId) -> Result<Inode, FsError> {
+        if number == 0 || number > self.inodes_count as u64 {
+            return Err(FsError::NotFound);
+        }
+        let group = (number - 1) / self.inodes_per_group as u64;
+        let index = (number - 1) % self.inodes_per_group as u64;
+
+        let mut descriptor = [0; 32];
+        self.read_bytes(
+            self.descriptor_block * self.block_size as u64 + group * 32,
+            &mut descriptor,
+        )?;
+        let table = u32_at(&descriptor, 8) as u64;
+
+        let mut raw = [0; 128];
+        self.read_bytes(
+            table * self.block_size as u64 + index * self.inode_size as u64,
+            &mut raw,
+        )?;
+        let mode = u16_at(&raw, 0);
+        let mut size = u32_at(&raw, 4) as u64;
+        // 修订版 1 中普通文件的 i_dir_acl 字段是大小的高 32 位
+        if mode & MODE_TYPE_MASK == MODE_REGULAR {
+            size |= (u32_at(&raw, 108) as u64) << 32;
+        }
+        let mut blocks = [0; 15];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = u32_at(&raw, 40 + i * 4);
+        }
+        Ok(Inode { mode, size, blocks })
+    }
+
+    /// 文件第 `index` 个逻辑块对应的物理块号，0 表示空洞。
+    fn map_block(&self, inode: &Inode, index: u64) -> Result<u64, FsError> {
+        let per_block = self.block_size as u64 / 4;
+        let (levels, path) = block_path(index, per_block).ok_or(FsError::Corrupt)?;
+        let mut block = inode.blocks[path[0] as usize] as u64;
+        let mut table = [0; MAX_BLOCK_SIZE];
+        for &slot in &path[1..=levels] {
+            if block == 0 {
+                return Ok(0);
+            }
+            self.read_block(block, &mut table)?;
+            block = u32_at(&table, slot as usize * 4) as u64;
+        }
+        Ok(block)
+    }
+
+    /// 依次访问目录中的项（跳过 `.`、`..` 和已删除的项），`visit` 返回 `true`
+    /// 时停止。
+    fn scan_dir(
+        &self,
+        dir: InodeId,
+        mut visit: impl FnMut(&str, InodeId, Option<FileType>) -> bool,
+    ) -> Result<(), FsError> {
+        let inode = self.inode(dir)?;
+        if inode.kind()? != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let mut block = [0; MAX_BLOCK_SIZE];
+        let blocks = inode.size.div_ceil(self.block_size as u64);
+        for index in 0..blocks {
+            let physical = self.map_block(&inode, index)?;
+            if physical == 0 {
+                continue;
+            }
+            self.read_block(physical, &mut block)?;
+            let mut offset = 0;
+            while offset + 8 <= self.block_size {
+                let number = u32_at(&block, offset) as InodeId;
+                let record_len = u16_at(&block, offset + 4) as usize;
+                let name_len = block[offset + 6] as usize;
+                if record_len < 8 || offset + record_len > self.block_size {
+                    return Err(FsError::Corrupt);
+                }
+                let name = block
+                    .get(offset + 8..offset + 8 + name_len)
+                    .ok_or(FsError::Corrupt)?;
+                let name = core::str::from_utf8(name).unwrap_or("?");
+                offset += record_len;
+
+                if number == 0 || name == "." || name == ".." {
+                    continue;
+                }
+                // 目录项中的类型：1 普通文件，2 目录；没有这个特性时需要读 inode
+                let kind = match (self.filetype, block[offset - record_len + 7]) {
+                    (true, 1) => Some(FileType::File),
+                    (true, 2) => Some(FileType::Directory),
+                    _ => None,
+                };
+                if visit(name, number, kind) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT_INODE
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        let mut found = None;
+        self.scan_dir(dir, |entry_name, inode, _| {
+            if entry_name == name {
+                found = Some(inode);
+            }
+            found.is_some()
+        })?;
+        found.ok_or(FsError::NotFound)
+    }
+
+    fn metadata(&mut self, inode: InodeId) -> Result<Metadata, FsError> {
+        let raw = self.inode(inode)?;
+        Ok(Metadata {
+            inode,
+            kind: raw.kind()?,
+            size: raw.size,
+        })
+    }
+
+    fn read_at(&mut self, inode: InodeId, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let raw = self.inode(inode)?;
+        if raw.kind()? == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        if offset >= raw.size {
+            return Ok(0);
+        }
+        let len = ((raw.size - offset) as usize).min(buf.len());
+        let block_size = self.block_size as u64;
+        let mut block = [0; MAX_BLOCK_SIZE];
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let start = (position % block_size) as usize;
+            let count = (self.block_size - start).min(len - done);
+            match self.map_block(&raw, position / block_size)? {
+                0 => buf[done..done + count].fill(0),
+                physical => {
+                    self.read_block(physical, &mut block)?;
+                    buf[done..done + count].copy_from_slice(&block[start..start + count]);
+                }
+            }
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn read_dir(&mut self, dir: InodeId, index: usize) -> Result<Option<DirEntry>, FsError> {
+        let mut seen = 0;
+        let mut result = None;
+        self.scan_dir(dir, |name, inode, kind| {
+            if seen == index {
+                let mut owned = heapless::String::new();
+                let _ = owned.push_str(name);
+                result = Some((owned, inode, kind));
+            }
+            seen += 1;
+            result.is_some()
+        })?;
+        let Some((name, inode, kind)) = result else {
+            return Ok(None);
+        };
+        let kind = match kind {
+            Some(kind) => kind,
+            None => self.inode(inode)?.kind().unwrap_or(FileType::File),
+        };
+        Ok(Some(DirEntry { name, inode, kind }))
+    }
+}
+
+static VOLUMES: [Mutex<Ext2>; MAX_VOLUMES] = [const { Mutex::new(Ext2::unmounted()) }; MAX_VOLUMES];
+
+/// 识别 `device` 上的 ext2 卷并挂载到 `path`。
+pub fn mount(device: SharedDevice, path: &str) -> Result<(), FsError> {
+    let fs = Ext2::open(device)?;
+    let slot = VOLUMES
+        .iter()
+        .find(|slot| slot.lock().device.is_none())
+        .ok_or(FsError::TooManyMounts)?;
+    *slot.lock() = fs;
+    super::mount(path, slot).inspect_err(|_| *slot.lock() = Ext2::unmounted())
+}
+
+#[test_case]
+fn test_ext2_block_path() {
+    // 1 KiB 的块，每个间接块 256 个指针
+    assert_eq!(block_path(11, 256), Some((0, [11, 0, 0, 0])));
+    assert_eq!(block_path(12, 256), Some((1, [12, 0, 0, 0])));
+    assert_eq!(block_path(12 + 255, 256), Some((1, [12, 255, 0, 0])));
+    assert_eq!(block_path(12 + 256, 256), Some((2, [13, 0, 0, 0])));
+    assert_eq!(block_path(12 + 256 + 257, 256), Some((2, [13, 1, 1, 0])));
+    let triple = 12 + 256 + 256 * 256;
+    assert_eq!(block_path(triple, 256), Some((3, [14, 0, 0, 0])));
+    assert_eq!(block_path(triple + 256 * 256 * 256, 256), None);
+}
+
+#[test_case]
+fn test_ext2_read_from_virtio() {
+    // 需要用 `-drive file=ext2.img,if=virtio,format=raw` 启动
+    let Some(device) = crate::storage::get("vda") else {
+        crate::testing::skip("no virtio disk");
+        return;
+    };
+    let Ok(mut fs) = Ext2::open(device) else {
+        crate::testing::skip("virtio disk is not ext2");
+        return;
+    };
+    assert_eq!(fs.metadata(ROOT_INODE).unwrap().kind, FileType::Directory);
+    if let Ok(Some(first)) = fs.read_dir(ROOT_INODE, 0) {
+        assert_eq!(fs.lookup(ROOT_INODE, &first.name), Ok(first.inode));
+    }
+}
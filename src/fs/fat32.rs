@@ -0,0 +1,519 @@
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, FileType, FsError, InodeId, Metadata, NAME_MAX};
+use crate::storage::SharedDevice;
+
+/// 支持的最大扇区大小。
+const MAX_SECTOR_SIZE: usize = 4096;
+const ENTRY_SIZE: usize = 32;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+// 短文件名的大小写标志（Windows NT 扩展，位于目录项偏移 12）
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+const END_OF_CHAIN: u32 = 0x0fff_fff8;
+const BAD_CLUSTER: u32 = 0x0fff_fff7;
+
+// 根目录没有自己的目录项，用 0 表示（0 号扇区是引导扇区，不会是目录项）
+const ROOT: InodeId = 0;
+
+/// 最多同时挂载的 FAT32 卷数。
+pub const MAX_VOLUMES: usize = 4;
+
+/// 目录项中与 VFS 相关的字段。
+#[derive(Debug, Clone, Copy)]
+struct RawEntry {
+    attr: u8,
+    cluster: u32,
+    size: u32,
+}
+
+impl RawEntry {
+    fn parse(bytes: &[u8]) -> RawEntry {
+        let word = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]) as u32;
+        RawEntry {
+            attr: bytes[11],
+            cluster: (word(20) << 16) | word(26),
+            size: u32::from_le_bytes([bytes[28], bytes[29], bytes[30], bytes[31]]),
+        }
+    }
+
+    fn kind(&self) -> FileType {
+        if self.attr & ATTR_DIRECTORY != 0 {
+            FileType::Directory
+        } else {
+            FileType::File
+        }
+    }
+}
+
+/// 长文件名的校验和，用来确认长名属于紧随其后的短名目录项。
+fn lfn_checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// 把 8.3 短文件名还原成 `NAME.EXT` 的形式。
+fn short_name(entry: &[u8], out: &mut heapless::String<NAME_MAX>) {
+    let flags = entry[12];
+    let mut base = [0; 8];
+    base.copy_from_slice(&entry[..8]);
+    // 0x05 是被转义的 0xe5（首字节 0xe5 表示已删除）
+    if base[0] == 0x05 {
+        base[0] = 0xe5;
+    }
+    push_short_part(out, &base, flags & NT_LOWER_BASE != 0);
+    if entry[8] != b' ' {
+        let _ = out.push('.');
+        push_short_part(out, &entry[8..11], flags & NT_LOWER_EXT != 0);
+    }
+}
+
+fn push_short_part(out: &mut heapless::String<NAME_MAX>, part: &[u8], lower: bool) {
+    let len = part.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    for &byte in &part[..len] {
+        let c = if lower {
+            byte.to_ascii_lowercase()
+        } else {
+            byte
+        };
+        // 代码页中的非 ASCII 字符无法可靠转换
+        let _ = out.push(if c.is_ascii() { c as char } else { '?' });
+    }
+}
+
+/// 正在拼接的长文件名。长名目录项按逆序出现在短名目录项之前，每项 13 个 UCS-2
+/// 字符。
+struct LongName {
+    chars: [u16; 260],
+    checksum: u8,
+    // 还缺少的目录项个数；为 0 且 `valid` 时长名完整
+    remaining: u8,
+    valid: bool,
+}
+
+impl LongName {
+    const fn new() -> Self {
+        LongName {
+            chars: [0xffff; 260],
+            checksum: 0,
+            remaining: 0,
+            valid: false,
+        }
+    }
+
+    fn push(&mut self, entry: &[u8]) {
+        let sequence = entry[0];
+        let index = (sequence & 0x1f) as usize;
+        if sequence & 0x40 != 0 {
+            self.chars = [0xffff; 260];
+            self.checksum = entry[13];
+            self.remaining = index as u8;
+            self.valid = index > 0 && index <= 20;
+        }
+        if !self.valid || index as u8 != self.remaining || entry[13] != self.checksum {
+            self.valid = false;
+            return;
+        }
+        const OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (i, offset) in OFFSETS.iter().enumerate() {
+            self.chars[(index - 1) * 13 + i] =
+                u16::from_le_bytes([entry[*offset], entry[offset + 1]]);
+        }
+        self.remaining -= 1;
+    }
+
+    /// 若长名完整且属于这个短名目录项，把它写入 `out`。
+    fn take(&mut self, short_entry: &[u8], out: &mut heapless::String<NAME_MAX>) -> bool {
+        let complete = self.valid && self.remaining == 0;
+        self.valid = false;
+        if !complete || lfn_checksum(&short_entry[..11]) != self.checksum {
+            return false;
+        }
+        let len = self
+            .chars
+            .iter()
+            .position(|&c| c == 0 || c == 0xffff)
+            .unwrap_or(self.chars.len());
+        for c in char::decode_utf16(self.chars[..len].iter().copied()) {
+            if out.push(c.unwrap_or('?')).is_err() {
+                break;
+            }
+        }
+        true
+    }
+}
+
+/// 只读的 FAT32 卷。
+///
+/// inode 编号是目录项在磁盘上的位置（字节偏移 / 32），这样不需要额外的内存
+/// 就能从编号找回文件的起始簇和大小。
+pub struct Fat32 {
+    device: Option<SharedDevice>,
+    sector_size: usize,
+    sectors_per_cluster: u64,
+    fat_start: u64,
+    data_start: u64,
+    root_cluster: u32,
+    cluster_count: u32,
+    // 最近一次定位到的 (inode, 簇序号, 簇号)，顺序读取时不必每次从头遍历簇链
+    cursor: Option<(InodeId, u64, u32)>,
+}
+
+impl Fat32 {
+    const fn unmounted() -> Self {
+        Fat32 {
+            device: None,
+            sector_size: 512,
+            sectors_per_cluster: 1,
+            fat_start: 0,
+            data_start: 0,
+            root_cluster: 0,
+            cluster_count: 0,
+            cursor: None,
+        }
+    }
+
+    /// 读取引导扇区，识别设备上的 FAT32 卷。设备上有 MBR 分区表时使用第一个
+    /// FAT32 分区。
+    pub fn open(device: SharedDevice) -> Result<Fat32, FsError> {
+        let mut fs = Fat32::unmounted();
+        fs.device = Some(device);
+        fs.sector_size = device.lock().block_size();
+        if fs.sector_size > MAX_SECTOR_SIZE {
+            return Err(FsError::Unsupported);
+        }
+
+        let mut sector = [0; MAX_SECTOR_SIZE];
+        fs.read_sector(0, &mut sector)?;
+        let mut start = 0;
+        if !is_fat32_boot_sector(&sector) {
+            start = (0..4)
+                .map(|i| &sector[446 + i * 16..462 + i * 16])
+                .find(|entry| matches!(entry[4], 0x0b | 0x0c))
+                .map(|entry| u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as u64)
+                .ok_or(FsError::Unsupported)?;
+            fs.read_sector(start, &mut sector)?;
+            if !is_fat32_boot_sector(&sector) {
+                return Err(FsError::Unsupported);
+            }
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([sector[i], sector[i + 1]]) as u64;
+        let u32_at = |i: usize| {
+            u32::from_le_bytes([sector[i], sector[i + 1], sector[i + 2], sector[i + 3]]) as u64
+        };
+        if u16_at(11) as usize != fs.sector_size {
+            return Err(FsError::Unsupported);
+        }
+        fs.sectors_per_cluster = sector[13] as u64;
+        let reserved = u16_at(14);
+        let fat_count = sector[16] as u64;
+        let total_sectors = if u16_at(19) != 0 {
+            u16_at(19)
+        } else {
+            u32_at(32)
+        };
+        let fat_size = u32_at(36);
+        fs.root_cluster = u32_at(44) as u32;
+        fs.fat_start = start + reserved;
+        fs.data_start = fs.fat_start + fat_count * fat_size;
+        if fs.sectors_per_cluster == 0 || total_sectors <= fs.data_start - start {
+            return Err(FsError::Corrupt);
+        }
+        fs.cluster_count =
+            ((total_sectors - (fs.data_start - start)) / fs.sectors_per_cluster) as u32;
+        Ok(fs)
+    }
+
+    fn read_sector(&self, lba: u64, buf: &mut [u8; MAX_SECTOR_SIZE]) -> Result<(), FsError> {
+        let device = self.device.ok_or(FsError::NotFound)?;
+        device
+            .lock()
+            .read_blocks(lba, &mut buf[..self.sector_size])?;
+        Ok(())
+    }
+
+    /// 数据簇 `cluster` 的第一个扇区。簇号从 2 开始编号，超出范围说明
+    /// 目录项或 FAT 已损坏。
+    fn cluster_sector(&self, cluster: u32) -> Result<u64, FsError> {
+        if !(2..self.cluster_count + 2).contains(&cluster) {
+            return Err(FsError::Corrupt);
+        }
+        Ok(self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster)
+    }
+
+    fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster * self.sector_size as u64
+    }
+
+    /// 簇链中 `cluster` 的下一个簇，链结束时返回 `None`。
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FsError> {
+        let offset = cluster as u64 * 4;
+        let mut sector = [0; MAX_SECTOR_SIZE];
+        self.read_sector(
+            self.fat_start + offset / self.sector_size as u64,
+            &mut sector,
+        )?;
+        let i = (offset % self.sector_size as u64) as usize;
+        let next = u32::from_le_bytes([sector[i], sector[i + 1], sector[i + 2], sector[i + 3]])
+            & 0x0fff_ffff;
+        match next {
+            END_OF_CHAIN.. => Ok(None),
+            BAD_CLUSTER | 0 | 1 => Err(FsError::Corrupt),
+            n if n >= self.cluster_count + 2 => Err(FsError::Corrupt),
+            n => Ok(Some(n)),
+        }
+    }
+
+    /// 读取 inode 对应的目录项。
+    fn entry(&self, inode: InodeId) -> Result<RawEntry, FsError> {
+        if inode == ROOT {
+            return Ok(RawEntry {
+                attr: ATTR_DIRECTORY,
+                cluster: self.root_cluster,
+                size: 0,
+            });
+        }
+        let position = inode * ENTRY_SIZE as u64;
+        let mut sector = [0; MAX_SECTOR_SIZE];
+        self.read_sector(position / self.sector_size as u64, &mut sector)?;
+        let i = (position % self.sector_size as u64) as usize;
+        Ok(RawEntry::parse(&sector[i..i + ENTRY_SIZE]))
+    }
+
+    /// 依次访问目录中的有效项（跳过已删除项、卷标以及 `.`、`..`），
+    /// `visit` 返回 `true` 时停止。
+    fn scan_dir(
+        &self,
+        dir: InodeId,
+        mut visit: impl FnMut(&heapless::String<NAME_MAX>, InodeId, RawEntry) -> bool,
+    ) -> Result<(), FsError> {
+        let entry = self.entry(dir)?;
+        if entry.kind() != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let mut long_name = LongName::new();
+        let mut sector = [0; MAX_SECTOR_SIZE];
+        let mut cluster = Some(entry.cluster);
+        // 簇链出现环时不至于死循环
+        for _ in 0..self.cluster_count {
+            let Some(current) = cluster else {
+                return Ok(());
+            };
+            let first = self.cluster_sector(current)?;
+            for lba in first..first + self.sectors_per_cluster {
+                self.read_sector(lba, &mut sector)?;
+                for (i, raw) in sector[..self.sector_size]
+                    .chunks_exact(ENTRY_SIZE)
+                    .enumerate()
+                {
+                    match raw[0] {
+                        0x00 => return Ok(()),
+                        0xe5 => continue,
+                        _ => {}
+                    }
+                    if raw[11] == ATTR_LONG_NAME {
+                        long_name.push(raw);
+                        continue;
+                    }
+                    let mut name = heapless::String::new();
+                    if !long_name.take(raw, &mut name) {
+                        short_name(raw, &mut name);
+                    }
+                    let parsed = RawEntry::parse(raw);
+                    if parsed.attr & ATTR_VOLUME_ID != 0 || name == "." || name == ".." {
+                        continue;
+                    }
+                    let inode = (lba * self.sector_size as u64) / ENTRY_SIZE as u64 + i as u64;
+                    if visit(&name, inode, parsed) {
+                        return Ok(());
+                    }
+                }
+            }
+            cluster = self.next_cluster(current)?;
+        }
+        Err(FsError::Corrupt)
+    }
+}
+
+fn is_fat32_boot_sector(sector: &[u8]) -> bool {
+    matches!(sector[0], 0xeb | 0xe9)
+        && &sector[82..90] == b"FAT32   "
+        && sector[510..512] == [0x55, 0xaa]
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        let mut found = None;
+        // FAT 的文件名不区分大小写
+        self.scan_dir(dir, |entry_name, inode, _| {
+            if entry_name.eq_ignore_ascii_case(name) {
+                found = Some(inode);
+            }
+            found.is_some()
+        })?;
+        found.ok_or(FsError::NotFound)
+    }
+
+    fn metadata(&mut self, inode: InodeId) -> Result<Metadata, FsError> {
+        let entry = self.entry(inode)?;
+        Ok(Metadata {
+            inode,
+            kind: entry.kind(),
+            size: entry.size as u64,
+        })
+    }
+
+    fn read_at(&mut self, inode: InodeId, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let entry = self.entry(inode)?;
+        if entry.kind() == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let size = entry.size as u64;
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let cluster_size = self.cluster_size();
+        let wanted = cluster_size.min(size - offset).min(buf.len() as u64);
+        let index = offset / cluster_size;
+
+        // 从缓存的位置或文件开头沿簇链走到 `offset` 所在的簇
+        let (mut at, mut cluster) = match self.cursor {
+            Some((cached, at, cluster)) if cached == inode && at <= index => (at, cluster),
+            _ => (0, entry.cluster),
+        };
+        while at < index {
+            cluster = self.next_cluster(cluster)?.ok_or(FsError::Corrupt)?;
+            at += 1;
+        }
+        self.cursor = Some((inode, at, cluster));
+
+        // 一次最多读到当前簇的末尾
+        let in_cluster = offset % cluster_size;
+        let len = wanted.min(cluster_size - in_cluster) as usize;
+        let mut sector = [0; MAX_SECTOR_SIZE];
+        let mut done = 0;
+        while done < len {
+            let position = in_cluster + done as u64;
+            let lba = self.cluster_sector(cluster)? + position / self.sector_size as u64;
+            let start = (position % self.sector_size as u64) as usize;
+            let count = (self.sector_size - start).min(len - done);
+            self.read_sector(lba, &mut sector)?;
+            buf[done..done + count].copy_from_slice(&sector[start..start + count]);
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn read_dir(&mut self, dir: InodeId, index: usize) -> Result<Option<DirEntry>, FsError> {
+        let mut seen = 0;
+        let mut result = None;
+        self.scan_dir(dir, |name, inode, entry| {
+            if seen == index {
+                result = Some(DirEntry {
+                    name: name.clone(),
+                    inode,
+                    kind: entry.kind(),
+                });
+            }
+            seen += 1;
+            result.is_some()
+        })?;
+        Ok(result)
+    }
+}
+
+static VOLUMES: [Mutex<Fat32>; MAX_VOLUMES] =
+    [const { Mutex::new(Fat32::unmounted()) }; MAX_VOLUMES];
+
+/// 识别 `device` 上的 FAT32 卷并挂载到 `path`。
+pub fn mount(device: SharedDevice, path: &str) -> Result<(), FsError> {
+    let fs = Fat32::open(device)?;
+    let slot = VOLUMES
+        .iter()
+        .find(|slot| slot.lock().device.is_none())
+        .ok_or(FsError::TooManyMounts)?;
+    *slot.lock() = fs;
+    super::mount(path, slot).inspect_err(|_| *slot.lock() = Fat32::unmounted())
+}
+
+#[test_case]
+fn test_fat32_names() {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].copy_from_slice(b"README  TXT");
+    let mut name = heapless::String::new();
+    short_name(&entry, &mut name);
+    assert_eq!(name, "README.TXT");
+
+    entry[12] = NT_LOWER_BASE | NT_LOWER_EXT;
+    name.clear();
+    short_name(&entry, &mut name);
+    assert_eq!(name, "readme.txt");
+
+    // 单个长名目录项 "hello world"，属于上面的短名
+    let mut lfn = [0u8; ENTRY_SIZE];
+    lfn[0] = 0x41;
+    lfn[11] = ATTR_LONG_NAME;
+    lfn[13] = lfn_checksum(&entry[..11]);
+    let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    for (offset, c) in offsets.iter().zip("hello world\0".encode_utf16()) {
+        lfn[*offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+    }
+    let mut long_name = LongName::new();
+    long_name.push(&lfn);
+    name.clear();
+    assert!(long_name.take(&entry, &mut name));
+    assert_eq!(name, "hello world");
+
+    // 校验和不符时退回短名
+    long_name.push(&lfn);
+    entry[0] = b'X';
+    assert!(!long_name.take(&entry, &mut name));
+}
+
+#[test_case]
+fn test_fat32_cluster_range() {
+    let fs = Fat32 {
+        data_start: 100,
+        sectors_per_cluster: 4,
+        cluster_count: 10,
+        ..Fat32::unmounted()
+    };
+    assert_eq!(fs.cluster_sector(2), Ok(100));
+    assert_eq!(fs.cluster_sector(11), Ok(136));
+    // 空文件的起始簇为 0，1 是保留值，都不能换算成扇区
+    assert_eq!(fs.cluster_sector(0), Err(FsError::Corrupt));
+    assert_eq!(fs.cluster_sector(1), Err(FsError::Corrupt));
+    assert_eq!(fs.cluster_sector(12), Err(FsError::Corrupt));
+}
+
+#[test_case]
+fn test_fat32_read_from_virtio() {
+    // 需要用 `-drive file=fat32.img,if=virtio,format=raw` 启动
+    let Some(device) = crate::storage::get("vda") else {
+        crate::testing::skip("no virtio disk");
+        return;
+    };
+    let Ok(mut fs) = Fat32::open(device) else {
+        crate::testing::skip("virtio disk is not FAT32");
+        return;
+    };
+    let root = fs.root();
+    if let Ok(Some(first)) = fs.read_dir(root, 0) {
+        assert_eq!(fs.lookup(root, &first.name), Ok(first.inode));
+    }
+}
@@ -0,0 +1,316 @@
+use super::{DirEntry, FileSystem, FileType, FsError, InodeId, Metadata};
+
+const BLOCK: usize = 512;
+
+/// 归档中最多容纳的文件和目录数（包括没有单独条目、由路径隐含的目录）。
+pub const MAX_NODES: usize = 512;
+
+// ustar 头部各字段的位置
+const NAME: (usize, usize) = (0, 100);
+const SIZE: (usize, usize) = (124, 12);
+const CHECKSUM: (usize, usize) = (148, 8);
+const TYPEFLAG: usize = 156;
+const MAGIC: (usize, usize) = (257, 5);
+const PREFIX: (usize, usize) = (345, 155);
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    name: &'static str,
+    parent: u32,
+    kind: FileType,
+    data: &'static [u8],
+}
+
+/// 以内存中的 ustar 归档为介质的只读文件系统。
+///
+/// 挂载时扫描一遍归档，为每个文件和目录建立节点，文件内容直接引用归档本身，
+/// 不做复制。inode 编号是节点下标，0 是根目录。
+pub struct TarFs {
+    nodes: heapless::Vec<Node, MAX_NODES>,
+}
+
+fn field(header: &[u8], (start, len): (usize, usize)) -> &[u8] {
+    let bytes = &header[start..start + len];
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+    &bytes[..end]
+}
+
+fn parse_octal(bytes: &[u8]) -> Option<u64> {
+    let digits = bytes
+        .iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|&&b| b != b' ' && b != 0);
+    let mut value = 0u64;
+    for &digit in digits {
+        if !(b'0'..=b'7').contains(&digit) {
+            return None;
+        }
+        value = value.checked_mul(8)? + (digit - b'0') as u64;
+    }
+    Some(value)
+}
+
+/// 头部校验和：校验和字段本身按空格计算。
+fn checksum_ok(header: &[u8]) -> bool {
+    let Some(expected) = parse_octal(field(header, CHECKSUM)) else {
+        return false;
+    };
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (CHECKSUM.0..CHECKSUM.0 + CHECKSUM.1).contains(&i) {
+                b' ' as u64
+            } else {
+                b as u64
+            }
+        })
+        .sum();
+    sum == expected
+}
+
+impl TarFs {
+    pub const fn new() -> Self {
+        TarFs {
+            nodes: heapless::Vec::new(),
+        }
+    }
+
+    /// 解析归档。不认识的条目类型（链接、设备文件、扩展头等）被跳过。
+    pub fn parse(archive: &'static [u8]) -> Result<TarFs, FsError> {
+        let mut fs = TarFs::new();
+        fs.push(Node {
+            name: "",
+            parent: 0,
+            kind: FileType::Directory,
+            data: &[],
+        })?;
+
+        let mut offset = 0;
+        while offset + BLOCK <= archive.len() {
+            let header = &archive[offset..offset + BLOCK];
+            // 全零的块表示归档结束
+            if header.iter().all(|&b| b == 0) {
+                break;
+            }
+            if field(header, MAGIC) != b"ustar" || !checksum_ok(header) {
+                return Err(FsError::Corrupt);
+            }
+            let size = parse_octal(field(header, SIZE)).ok_or(FsError::Corrupt)? as usize;
+            let data_start = offset + BLOCK;
+            let data = archive
+                .get(data_start..data_start + size)
+                .ok_or(FsError::Corrupt)?;
+            offset = data_start + size.next_multiple_of(BLOCK);
+
+            let kind = match header[TYPEFLAG] {
+                b'0' | 0 => FileType::File,
+                b'5' => FileType::Directory,
+                _ => continue,
+            };
+            let prefix =
+                core::str::from_utf8(field(header, PREFIX)).map_err(|_| FsError::Corrupt)?;
+            let name = core::str::from_utf8(field(header, NAME)).map_err(|_| FsError::Corrupt)?;
+            fs.insert(prefix, name, kind, data)?;
+        }
+        Ok(fs)
+    }
+
+    fn push(&mut self, node: Node) -> Result<u32, FsError> {
+        let index = self.nodes.len() as u32;
+        self.nodes.push(node).map_err(|_| FsError::NoSpace)?;
+        Ok(index)
+    }
+
+    fn child(&self, dir: u32, name: &str) -> Option<u32> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find(|(_, node)| node.parent == dir && node.name == name)
+            .map(|(index, _)| index as u32)
+    }
+
+    /// 按 `prefix/name` 插入节点，途经的目录不存在时自动创建。路径中含
+    /// `..` 的条目视为损坏，不能借它跳出根目录或者改写别的目录下的文件。
+    fn insert(
+        &mut self,
+        prefix: &'static str,
+        name: &'static str,
+        kind: FileType,
+        data: &'static [u8],
+    ) -> Result<(), FsError> {
+        let components = prefix
+            .split('/')
+            .chain(name.split('/'))
+            .filter(|component| !component.is_empty() && *component != ".");
+        if components.clone().any(|component| component == "..") {
+            return Err(FsError::Corrupt);
+        }
+        let mut dir = 0;
+        let mut last = None;
+        for component in components {
+            if let Some(previous) = last.replace(component) {
+                dir = match self.child(dir, previous) {
+                    Some(index) => index,
+                    None => self.push(Node {
+                        name: previous,
+                        parent: dir,
+                        kind: FileType::Directory,
+                        data: &[],
+                    })?,
+                };
+            }
+        }
+        let Some(name) = last else {
+            return Ok(());
+        };
+        match self.child(dir, name) {
+            // 目录可能先被路径隐含创建，之后才出现自己的条目
+            Some(_) if kind == FileType::Directory => Ok(()),
+            // 同名文件出现多次时，以归档中靠后的为准（与 tar 解包的行为一致）
+            Some(index) => {
+                self.nodes[index as usize].data = data;
+                Ok(())
+            }
+            None => self
+                .push(Node {
+                    name,
+                    parent: dir,
+                    kind,
+                    data,
+                })
+                .map(|_| ()),
+        }
+    }
+
+    fn node(&self, inode: InodeId) -> Result<&Node, FsError> {
+        self.nodes.get(inode as usize).ok_or(FsError::NotFound)
+    }
+}
+
+impl Default for TarFs {
+    fn default() -> Self {
+        TarFs::new()
+    }
+}
+
+impl FileSystem for TarFs {
+    fn name(&self) -> &'static str {
+        "tarfs"
+    }
+
+    fn root(&self) -> InodeId {
+        0
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        self.child(dir as u32, name)
+            .map(InodeId::from)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn metadata(&mut self, inode: InodeId) -> Result<Metadata, FsError> {
+        let node = self.node(inode)?;
+        Ok(Metadata {
+            inode,
+            kind: node.kind,
+            size: node.data.len() as u64,
+        })
+    }
+
+    fn read_at(&mut self, inode: InodeId, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let node = self.node(inode)?;
+        if node.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let rest = node.data.get(offset as usize..).unwrap_or(&[]);
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        Ok(len)
+    }
+
+    fn read_dir(&mut self, dir: InodeId, index: usize) -> Result<Option<DirEntry>, FsError> {
+        if self.node(dir)?.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let found = self
+            .nodes
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, node)| node.parent == dir as u32)
+            .nth(index);
+        Ok(found.map(|(inode, node)| {
+            let mut name = heapless::String::new();
+            let _ = name.push_str(node.name);
+            DirEntry {
+                name,
+                inode: inode as InodeId,
+                kind: node.kind,
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+fn test_header(name: &str, typeflag: u8, size: usize) -> [u8; BLOCK] {
+    use core::fmt::Write;
+
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    let mut octal = heapless::String::<12>::new();
+    write!(octal, "{size:011o}").unwrap();
+    header[SIZE.0..SIZE.0 + 11].copy_from_slice(octal.as_bytes());
+    header[TYPEFLAG] = typeflag;
+    header[MAGIC.0..MAGIC.0 + 6].copy_from_slice(b"ustar\0");
+    header[CHECKSUM.0..CHECKSUM.0 + 8].fill(b' ');
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    let mut octal = heapless::String::<8>::new();
+    write!(octal, "{sum:06o}\0").unwrap();
+    header[CHECKSUM.0..CHECKSUM.0 + 7].copy_from_slice(octal.as_bytes());
+    header
+}
+
+#[test_case]
+fn test_tarfs_parse_archive() {
+    static mut ARCHIVE: [u8; BLOCK * 6] = [0; BLOCK * 6];
+    let archive = &raw mut ARCHIVE;
+    {
+        let archive = unsafe { &mut *archive };
+        archive[..BLOCK].copy_from_slice(&test_header("./etc/", b'5', 0));
+        archive[BLOCK..BLOCK * 2].copy_from_slice(&test_header("./etc/hostname", b'0', 5));
+        archive[BLOCK * 2..BLOCK * 2 + 5].copy_from_slice(b"qemu\n");
+        // bin/ 没有自己的条目，由路径隐含
+        archive[BLOCK * 3..BLOCK * 4].copy_from_slice(&test_header("bin/init", b'0', 0));
+    }
+
+    let mut fs = TarFs::parse(unsafe { &*archive }).unwrap();
+    let etc = fs.lookup(0, "etc").unwrap();
+    let hostname = fs.lookup(etc, "hostname").unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(fs.read_at(hostname, 0, &mut buf), Ok(5));
+    assert_eq!(&buf[..5], b"qemu\n");
+    assert_eq!(fs.read_at(hostname, 3, &mut buf), Ok(2));
+    let bin = fs.lookup(0, "bin").unwrap();
+    assert_eq!(fs.metadata(bin).unwrap().kind, FileType::Directory);
+    assert_eq!(fs.read_dir(0, 1).unwrap().unwrap().name, "bin");
+    assert!(fs.read_dir(0, 2).unwrap().is_none());
+
+    // 路径不能跳出归档的根目录
+    static mut ESCAPE: [u8; BLOCK * 3] = [0; BLOCK * 3];
+    let escape = &raw mut ESCAPE;
+    {
+        let escape = unsafe { &mut *escape };
+        escape[..BLOCK].copy_from_slice(&test_header("etc/../../passwd", b'0', 0));
+    }
+    assert!(matches!(
+        TarFs::parse(unsafe { &*escape }),
+        Err(FsError::Corrupt)
+    ));
+
+    let mut header = test_header("corrupt", b'0', 0);
+    assert!(checksum_ok(&header));
+    header[0] ^= 1;
+    assert!(!checksum_ok(&header));
+}
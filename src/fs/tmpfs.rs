@@ -0,0 +1,525 @@
+#[cfg(feature = "heapcheck")]
+use core::panic::Location;
+
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, FileType, FsError, InodeId, Metadata};
+use crate::memory::{self, PAGE_SIZE};
+
+/// 数据块大小。
+pub const BLOCK_SIZE: usize = 1024;
+/// 最多容纳的文件和目录数（包括根目录）。
+pub const MAX_NODES: usize = 128;
+/// 最多管理的数据块数，即容量上限 `MAX_BLOCKS * BLOCK_SIZE`。
+pub const MAX_BLOCKS: usize = 1024;
+/// tmpfs 中文件名的最大长度。
+pub const NAME_MAX: usize = 63;
+
+/// 启动时为 `/tmp` 分配的页数。
+const PAGES: usize = MAX_BLOCKS * BLOCK_SIZE / PAGE_SIZE as usize;
+
+// 块链和节点中表示“没有”的值
+const NONE: u16 = u16::MAX;
+
+// 启用 `heapcheck` 特性时空闲块填满这个值，分配时检查它有没有被改过
+#[cfg(feature = "heapcheck")]
+const POISON: u8 = 0x6b;
+
+#[derive(Debug, Clone)]
+struct Node {
+    used: bool,
+    name: heapless::String<NAME_MAX>,
+    parent: u16,
+    kind: FileType,
+    size: u64,
+    // 文件的第一个数据块，后续块通过 `next` 表串起来（和 FAT 的簇链一样）
+    first: u16,
+}
+
+impl Node {
+    const fn free() -> Self {
+        Node {
+            used: false,
+            name: heapless::String::new(),
+            parent: NONE,
+            kind: FileType::File,
+            size: 0,
+            first: NONE,
+        }
+    }
+}
+
+/// 可读写的内存文件系统，数据放在挂载时交给它的一块内存中，卸载后内容丢失。
+pub struct Tmpfs {
+    storage: Option<&'static mut [u8]>,
+    nodes: [Node; MAX_NODES],
+    // 每个块的下一个块；空闲块用 `free` 串成链表
+    next: [u16; MAX_BLOCKS],
+    free: u16,
+    blocks: usize,
+    // 每个已分配的块是在哪里分配的
+    #[cfg(feature = "heapcheck")]
+    sites: [Option<&'static Location<'static>>; MAX_BLOCKS],
+}
+
+impl Tmpfs {
+    pub const fn new() -> Self {
+        let mut nodes = [const { Node::free() }; MAX_NODES];
+        nodes[0].used = true;
+        nodes[0].kind = FileType::Directory;
+        Tmpfs {
+            storage: None,
+            nodes,
+            next: [NONE; MAX_BLOCKS],
+            free: NONE,
+            blocks: 0,
+            #[cfg(feature = "heapcheck")]
+            sites: [None; MAX_BLOCKS],
+        }
+    }
+
+    /// 使用 `storage` 作为数据区，已有的内容被清空。
+    pub fn format(&mut self, storage: &'static mut [u8]) {
+        *self = Tmpfs::new();
+        self.blocks = (storage.len() / BLOCK_SIZE).min(MAX_BLOCKS);
+        for block in 0..self.blocks {
+            self.next[block] = if block + 1 < self.blocks {
+                block as u16 + 1
+            } else {
+                NONE
+            };
+        }
+        self.free = if self.blocks > 0 { 0 } else { NONE };
+        #[cfg(feature = "heapcheck")]
+        storage.fill(POISON);
+        self.storage = Some(storage);
+    }
+
+    /// 剩余的空闲字节数。
+    pub fn free_space(&self) -> usize {
+        let mut count = 0;
+        let mut block = self.free;
+        while block != NONE {
+            count += 1;
+            block = self.next[block as usize];
+        }
+        count * BLOCK_SIZE
+    }
+
+    fn node(&self, inode: InodeId) -> Result<&Node, FsError> {
+        self.nodes
+            .get(inode as usize)
+            .filter(|node| node.used)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn block(&mut self, block: u16) -> &mut [u8] {
+        let start = block as usize * BLOCK_SIZE;
+        let storage = self.storage.as_deref_mut().unwrap_or(&mut []);
+        &mut storage[start..start + BLOCK_SIZE]
+    }
+
+    #[track_caller]
+    fn alloc_block(&mut self) -> Result<u16, FsError> {
+        let block = self.free;
+        if block == NONE {
+            return Err(FsError::NoSpace);
+        }
+        self.free = self.next[block as usize];
+        self.next[block as usize] = NONE;
+        #[cfg(feature = "heapcheck")]
+        if let Some(offset) = self.block(block).iter().position(|&byte| byte != POISON) {
+            log::error!("tmpfs: block {block} written after free (offset {offset})");
+        }
+        #[cfg(feature = "heapcheck")]
+        {
+            self.sites[block as usize] = Some(Location::caller());
+        }
+        self.block(block).fill(0);
+        Ok(block)
+    }
+
+    /// 释放从 `block` 开始的整条块链。
+    fn free_chain(&mut self, mut block: u16) {
+        while block != NONE {
+            let next = self.next[block as usize];
+            #[cfg(feature = "heapcheck")]
+            {
+                self.block(block).fill(POISON);
+                self.sites[block as usize] = None;
+            }
+            self.next[block as usize] = self.free;
+            self.free = block;
+            block = next;
+        }
+    }
+
+    /// 文件第 `index` 个块；`grow` 为真时沿途补齐缺少的块。
+    #[track_caller]
+    fn nth_block(&mut self, inode: usize, index: usize, grow: bool) -> Result<u16, FsError> {
+        if self.nodes[inode].first == NONE {
+            if !grow {
+                return Err(FsError::Corrupt);
+            }
+            self.nodes[inode].first = self.alloc_block()?;
+        }
+        let mut block = self.nodes[inode].first;
+        for _ in 0..index {
+            if self.next[block as usize] == NONE {
+                if !grow {
+                    return Err(FsError::Corrupt);
+                }
+                self.next[block as usize] = self.alloc_block()?;
+            }
+            block = self.next[block as usize];
+        }
+        Ok(block)
+    }
+
+    /// 把所有已分配的块和分配它们的位置写到日志，不在任何文件块链上的块
+    /// 记为泄漏。返回泄漏的块数。
+    #[cfg(feature = "heapcheck")]
+    fn report_outstanding(&self) -> usize {
+        let mut owner = [NONE; MAX_BLOCKS];
+        let mut free = [false; MAX_BLOCKS];
+        for (inode, node) in self.nodes.iter().enumerate().filter(|(_, node)| node.used) {
+            let mut block = node.first;
+            // 块链损坏成环时不至于死循环
+            for _ in 0..self.blocks {
+                if block == NONE {
+                    break;
+                }
+                owner[block as usize] = inode as u16;
+                block = self.next[block as usize];
+            }
+        }
+        let mut block = self.free;
+        for _ in 0..self.blocks {
+            if block == NONE {
+                break;
+            }
+            free[block as usize] = true;
+            block = self.next[block as usize];
+        }
+        let mut leaked = 0;
+        for block in (0..self.blocks).filter(|&block| !free[block]) {
+            let Some(site) = self.sites[block] else {
+                continue;
+            };
+            match owner[block] {
+                NONE => {
+                    leaked += 1;
+                    log::error!("tmpfs: block {block} leaked, allocated at {site}");
+                }
+                inode => {
+                    log::info!("tmpfs: block {block} owned by inode {inode}, allocated at {site}")
+                }
+            }
+        }
+        leaked
+    }
+
+    fn child(&self, dir: InodeId, name: &str) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| node.used && node.parent as InodeId == dir && node.name == name)
+    }
+}
+
+impl Default for Tmpfs {
+    fn default() -> Self {
+        Tmpfs::new()
+    }
+}
+
+impl FileSystem for Tmpfs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> InodeId {
+        0
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        self.child(dir, name)
+            .map(|index| index as InodeId)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn metadata(&mut self, inode: InodeId) -> Result<Metadata, FsError> {
+        let node = self.node(inode)?;
+        Ok(Metadata {
+            inode,
+            kind: node.kind,
+            size: node.size,
+        })
+    }
+
+    fn read_at(&mut self, inode: InodeId, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let node = self.node(inode)?;
+        if node.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        if offset >= node.size {
+            return Ok(0);
+        }
+        let len = ((node.size - offset) as usize).min(buf.len());
+        let mut done = 0;
+        while done < len {
+            let position = offset as usize + done;
+            let block = self.nth_block(inode as usize, position / BLOCK_SIZE, false)?;
+            let start = position % BLOCK_SIZE;
+            let count = (BLOCK_SIZE - start).min(len - done);
+            buf[done..done + count].copy_from_slice(&self.block(block)[start..start + count]);
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn read_dir(&mut self, dir: InodeId, index: usize) -> Result<Option<DirEntry>, FsError> {
+        if self.node(dir)?.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let found = self
+            .nodes
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, node)| node.used && node.parent as InodeId == dir)
+            .nth(index);
+        Ok(found.map(|(inode, node)| {
+            let mut name = heapless::String::new();
+            let _ = name.push_str(&node.name);
+            DirEntry {
+                name,
+                inode: inode as InodeId,
+                kind: node.kind,
+            }
+        }))
+    }
+
+    fn write_at(&mut self, inode: InodeId, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        if self.node(inode)?.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let index = inode as usize;
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset as usize + done;
+            let block = match self.nth_block(index, position / BLOCK_SIZE, true) {
+                Ok(block) => block,
+                // 空间不够时返回已经写入的部分
+                Err(FsError::NoSpace) if done > 0 => break,
+                Err(error) => return Err(error),
+            };
+            let start = position % BLOCK_SIZE;
+            let count = (BLOCK_SIZE - start).min(buf.len() - done);
+            self.block(block)[start..start + count].copy_from_slice(&buf[done..done + count]);
+            done += count;
+        }
+        let node = &mut self.nodes[index];
+        node.size = node.size.max(offset + done as u64);
+        Ok(done)
+    }
+
+    fn create(&mut self, dir: InodeId, name: &str, kind: FileType) -> Result<InodeId, FsError> {
+        if self.node(dir)?.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        if name.is_empty() || name.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+        if self.child(dir, name).is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let mut owned = heapless::String::new();
+        owned.push_str(name).map_err(|_| FsError::InvalidPath)?;
+        let index = self
+            .nodes
+            .iter()
+            .position(|node| !node.used)
+            .ok_or(FsError::NoSpace)?;
+        self.nodes[index] = Node {
+            used: true,
+            name: owned,
+            parent: dir as u16,
+            kind,
+            size: 0,
+            first: NONE,
+        };
+        Ok(index as InodeId)
+    }
+
+    fn remove(&mut self, dir: InodeId, name: &str) -> Result<(), FsError> {
+        let index = self.child(dir, name).ok_or(FsError::NotFound)?;
+        let has_children = self
+            .nodes
+            .iter()
+            .any(|node| node.used && node.parent as usize == index);
+        if has_children {
+            return Err(FsError::Busy);
+        }
+        self.free_chain(self.nodes[index].first);
+        self.nodes[index] = Node::free();
+        Ok(())
+    }
+
+    fn truncate(&mut self, inode: InodeId, size: u64) -> Result<(), FsError> {
+        if self.node(inode)?.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let index = inode as usize;
+        let old = self.nodes[index].size;
+        if size < old {
+            // 保留的块数；被截掉的块释放，最后一个块中超出 `size` 的部分清零，
+            // 这样以后再变长时读到的是 0
+            let keep = (size as usize).div_ceil(BLOCK_SIZE);
+            if keep == 0 {
+                let first = self.nodes[index].first;
+                self.free_chain(first);
+                self.nodes[index].first = NONE;
+            } else {
+                let last = self.nth_block(index, keep - 1, false)?;
+                let rest = self.next[last as usize];
+                self.next[last as usize] = NONE;
+                self.free_chain(rest);
+                let tail = size as usize % BLOCK_SIZE;
+                if tail != 0 {
+                    self.block(last)[tail..].fill(0);
+                }
+            }
+        } else if size > old && size > 0 {
+            // 新分配的块已经清零
+            self.nth_block(index, (size as usize - 1) / BLOCK_SIZE, true)?;
+        }
+        self.nodes[index].size = size;
+        Ok(())
+    }
+}
+
+static TMP: Mutex<Tmpfs> = Mutex::new(Tmpfs::new());
+
+/// 分配内存并把 tmpfs 挂载到 `/tmp`。
+pub fn init() {
+    let Some(buffer) = memory::alloc_dma(PAGES) else {
+        log::error!("tmpfs: no memory for /tmp");
+        return;
+    };
+    TMP.lock().format(buffer.leak());
+    if let Err(err) = super::mount("/tmp", &TMP) {
+        log::error!("tmpfs: cannot mount /tmp: {err:?}");
+    }
+}
+
+/// 列出 `/tmp` 中所有已分配的块及其分配位置，返回泄漏的块数。关机时调用；
+/// tmpfs 的锁被占用时跳过，返回 `None`。
+#[cfg(feature = "heapcheck")]
+pub fn heapcheck() -> Option<usize> {
+    TMP.try_lock().map(|fs| fs.report_outstanding())
+}
+
+#[cfg(test)]
+static TEST_FS: Mutex<Tmpfs> = Mutex::new(Tmpfs::new());
+
+#[cfg(test)]
+fn test_fs() -> spin::MutexGuard<'static, Tmpfs> {
+    static mut STORAGE: [u8; BLOCK_SIZE * 4] = [0; BLOCK_SIZE * 4];
+    let storage = &raw mut STORAGE;
+    let mut fs = TEST_FS.lock();
+    fs.format(unsafe { &mut *storage });
+    fs
+}
+
+#[test_case]
+fn test_tmpfs_write_read_truncate() {
+    let mut fs = test_fs();
+    let dir = fs.create(0, "dir", FileType::Directory).unwrap();
+    let file = fs.create(dir, "file", FileType::File).unwrap();
+    assert_eq!(
+        fs.create(dir, "file", FileType::File),
+        Err(FsError::AlreadyExists)
+    );
+
+    // 跨越块边界写入，并在中间留下空洞
+    let data = [0x42; 1500];
+    assert_eq!(fs.write_at(file, 100, &data), Ok(1500));
+    assert_eq!(fs.metadata(file).unwrap().size, 1600);
+    let mut buf = [0xff; 1600];
+    assert_eq!(fs.read_at(file, 0, &mut buf), Ok(1600));
+    assert!(buf[..100].iter().all(|&b| b == 0));
+    assert!(buf[100..].iter().all(|&b| b == 0x42));
+
+    fs.truncate(file, 50).unwrap();
+    fs.truncate(file, 200).unwrap();
+    assert_eq!(fs.read_at(file, 0, &mut buf), Ok(200));
+    assert!(buf[..200].iter().all(|&b| b == 0));
+    assert_eq!(fs.free_space(), 3 * BLOCK_SIZE);
+
+    // 空间用完时只写入能放下的部分
+    assert_eq!(
+        fs.write_at(file, 0, &[1; BLOCK_SIZE * 5]),
+        Ok(BLOCK_SIZE * 4)
+    );
+    assert_eq!(
+        fs.write_at(file, BLOCK_SIZE as u64 * 4, &[1]),
+        Err(FsError::NoSpace)
+    );
+
+    assert_eq!(fs.remove(0, "dir"), Err(FsError::Busy));
+    fs.remove(dir, "file").unwrap();
+    fs.remove(0, "dir").unwrap();
+    assert_eq!(fs.free_space(), 4 * BLOCK_SIZE);
+    assert!(fs.read_dir(0, 0).unwrap().is_none());
+}
+
+#[test_case]
+fn test_tmpfs_through_vfs() {
+    drop(test_fs());
+    super::mount("/test-tmp", &TEST_FS).unwrap();
+    super::create("/test-tmp/a", FileType::Directory).unwrap();
+    super::create("/test-tmp/a/log", FileType::File).unwrap();
+
+    // 两个句柄交替写同一个文件，各自维护读写位置
+    let mut first = super::open("/test-tmp/a/log").unwrap();
+    let mut second = super::open("/test-tmp/a/log").unwrap();
+    first.write(b"hello ").unwrap();
+    second.seek(6);
+    second.write(b"world").unwrap();
+    let mut buf = [0; 16];
+    first.seek(0);
+    assert_eq!(first.read_all(&mut buf), Ok(11));
+    assert_eq!(&buf[..11], b"hello world");
+
+    assert_eq!(super::remove("/test-tmp/a"), Err(FsError::Busy));
+    super::remove("/test-tmp/a/log").unwrap();
+    super::remove("/test-tmp/a").unwrap();
+    super::unmount("/test-tmp").unwrap();
+}
+
+#[cfg(feature = "heapcheck")]
+#[test_case]
+fn test_tmpfs_poisons_free_blocks() {
+    let mut fs = test_fs();
+    let file = fs.create(0, "file", FileType::File).unwrap();
+    fs.write_at(file, 0, &[1; BLOCK_SIZE]).unwrap();
+    let block = fs.nodes[file as usize].first;
+    fs.truncate(file, 0).unwrap();
+    assert!(fs.block(block).iter().all(|&byte| byte == POISON));
+    fs.remove(0, "file").unwrap();
+}
+
+#[cfg(feature = "heapcheck")]
+#[test_case]
+fn test_tmpfs_reports_leaked_blocks() {
+    let mut fs = test_fs();
+    let file = fs.create(0, "file", FileType::File).unwrap();
+    fs.write_at(file, 0, &[1; 2 * BLOCK_SIZE]).unwrap();
+    assert_eq!(fs.report_outstanding(), 0);
+    // 分配出来却没有挂到任何文件上
+    let block = fs.alloc_block().unwrap();
+    assert_eq!(fs.report_outstanding(), 1);
+    fs.free_chain(block);
+    assert_eq!(fs.report_outstanding(), 0);
+    fs.remove(0, "file").unwrap();
+}
@@ -0,0 +1,72 @@
+use x86_64::instructions::port::Port;
+
+// QEMU 的 fw_cfg 接口：向选择端口写入条目编号，再从数据端口逐字节读出内容
+const SELECTOR: u16 = 0x510;
+const DATA: u16 = 0x511;
+const SIGNATURE: u16 = 0x0000;
+const FILE_DIR: u16 = 0x0019;
+
+/// fw_cfg 中的一个具名文件（`-fw_cfg name=...,file=...` 或 `string=...`）。
+#[derive(Debug, Clone, Copy)]
+pub struct FwFile {
+    pub size: usize,
+    select: u16,
+}
+
+impl FwFile {
+    /// 从头读取文件内容，返回读入的字节数。
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let len = self.size.min(buf.len());
+        select(self.select);
+        read(&mut buf[..len]);
+        len
+    }
+}
+
+fn select(key: u16) {
+    unsafe { Port::new(SELECTOR).write(key) }
+}
+
+fn read(buf: &mut [u8]) {
+    let mut data: Port<u8> = Port::new(DATA);
+    for byte in buf {
+        *byte = unsafe { data.read() };
+    }
+}
+
+/// 是否运行在提供 fw_cfg 的 QEMU 中。
+pub fn present() -> bool {
+    let mut signature = [0; 4];
+    select(SIGNATURE);
+    read(&mut signature);
+    signature == *b"QEMU"
+}
+
+/// 在 fw_cfg 文件目录中按名字查找文件。
+pub fn find(name: &str) -> Option<FwFile> {
+    if !present() {
+        return None;
+    }
+
+    // 目录格式：大端 u32 文件数，随后每项是 size(u32) select(u16) reserved(u16)
+    // name[56]，全部是大端序
+    let mut count = [0; 4];
+    select(FILE_DIR);
+    read(&mut count);
+    for _ in 0..u32::from_be_bytes(count) {
+        let mut entry = [0; 64];
+        read(&mut entry);
+        let file_name = &entry[8..];
+        let name_len = file_name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(file_name.len());
+        if &file_name[..name_len] == name.as_bytes() {
+            return Some(FwFile {
+                size: u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize,
+                select: u16::from_be_bytes([entry[4], entry[5]]),
+            });
+        }
+    }
+    None
+}
@@ -0,0 +1,442 @@
+use core::{
+    arch::asm,
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use x86_64::{
+    VirtAddr,
+    registers::{
+        control::Cr0Flags,
+        rflags::{self, RFlags},
+    },
+};
+
+use crate::{
+    cmdline,
+    interrupts::TrapFrame,
+    memory, regs,
+    serial::{self, Com, SerialPort},
+};
+
+/// 停下的原因，对应 GDB 的信号编号。
+pub const SIGTRAP: u8 = 5;
+pub const SIGABRT: u8 = 6;
+
+/// 一个包的最大长度（不含 `$`、`#` 和校验和）。
+pub const PACKET_SIZE: usize = 1024;
+
+/// 最多同时插入的软件断点数。
+pub const MAX_BREAKPOINTS: usize = 16;
+
+// 通用寄存器（含 rip）的个数，GDB 的 amd64 寄存器表里它们排在最前面
+const GPR_COUNT: usize = 17;
+// 随后是 32 位的 eflags、cs、ss、ds、es、fs、gs
+const SEGMENT_COUNT: usize = 7;
+
+const INT3: u8 = 0xcc;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// 调试器已经让内核继续运行过，下次停下时要主动报告
+static RESUMED: AtomicBool = AtomicBool::new(false);
+
+type Packet = heapless::Vec<u8, PACKET_SIZE>;
+type Reply = heapless::String<PACKET_SIZE>;
+
+/// 命令行带有 `gdb` 时启用：断点、单步和 panic 都会停下来等 COM2 上的 GDB，
+/// 例如 `qemu -serial stdio -serial tcp::1234,server` 后 `target remote
+/// :1234`。
+///
+/// COM2 同时也是 `vga-mirror` 的输出，两者不能一起用。
+pub fn init() {
+    if cmdline::flag("gdb") {
+        ENABLED.store(true, Ordering::Relaxed);
+        log::info!("gdb: stub enabled on COM2");
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 处理断点或单步陷入：与 GDB 交互，直到它让内核继续运行或单步。
+pub fn handle_trap(frame: &mut TrapFrame, signal: u8) {
+    let mut port = serial::port(Com::Com2);
+    if RESUMED.swap(false, Ordering::Relaxed) {
+        let mut reply = Reply::new();
+        let _ = write!(reply, "S{signal:02x}");
+        send_packet(&mut port, reply.as_bytes());
+    }
+    let mut breakpoints = BREAKPOINTS.lock();
+    let mut stub = Stub {
+        frame,
+        signal,
+        breakpoints: &mut breakpoints,
+    };
+    loop {
+        let packet = receive_packet(&mut port);
+        let mut reply = Reply::new();
+        match stub.handle(&packet, &mut reply) {
+            None => send_packet(&mut port, reply.as_bytes()),
+            // `k` 不需要回复
+            Some(Resume::Detach) => {
+                if !reply.is_empty() {
+                    send_packet(&mut port, reply.as_bytes());
+                }
+                return;
+            }
+            Some(Resume::Continue | Resume::Step) => {
+                RESUMED.store(true, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+}
+
+/// panic 时停下来让 GDB 查看现场。GDB 让内核继续后回到 panic 处理函数。
+pub fn handle_panic() {
+    if !enabled() {
+        return;
+    }
+    let mut frame = TrapFrame::default();
+    unsafe {
+        asm!(
+            "lea {rip}, [rip]",
+            "mov {rsp}, rsp",
+            "mov {rbp}, rbp",
+            rip = out(reg) frame.rip,
+            rsp = out(reg) frame.rsp,
+            rbp = out(reg) frame.rbp,
+            options(nomem, nostack),
+        );
+    }
+    frame.rflags = rflags::read().bits();
+    crate::serial_println!("gdb: waiting for the debugger on COM2");
+    handle_trap(&mut frame, SIGABRT);
+}
+
+static BREAKPOINTS: spin::Mutex<heapless::Vec<(u64, u8), MAX_BREAKPOINTS>> =
+    spin::Mutex::new(heapless::Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+}
+
+struct Stub<'a> {
+    frame: &'a mut TrapFrame,
+    signal: u8,
+    // 插入的断点和被 int3 覆盖的原字节
+    breakpoints: &'a mut heapless::Vec<(u64, u8), MAX_BREAKPOINTS>,
+}
+
+impl Stub<'_> {
+    /// 处理一个命令，把回复写入 `reply`；需要恢复运行时返回怎样恢复。
+    /// 不认识的命令回复空包。
+    fn handle(&mut self, packet: &[u8], reply: &mut Reply) -> Option<Resume> {
+        let (&command, args) = packet.split_first()?;
+        let result = match command {
+            b'?' => write!(reply, "S{:02x}", self.signal).ok(),
+            b'g' => self.read_registers(reply),
+            b'G' => self
+                .write_registers(args)
+                .and_then(|()| reply.push_str("OK").ok()),
+            b'm' => self.read_memory(args, reply),
+            b'M' => self
+                .write_memory(args)
+                .and_then(|()| reply.push_str("OK").ok()),
+            // 只支持软件断点 `Z0,addr,kind`
+            b'Z' | b'z' if args.starts_with(b"0,") => self
+                .breakpoint(command == b'Z', &args[2..])
+                .and_then(|()| reply.push_str("OK").ok()),
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    self.frame.rip = addr;
+                }
+                let mut rflags = RFlags::from_bits_retain(self.frame.rflags);
+                rflags.set(RFlags::TRAP_FLAG, command == b's');
+                self.frame.rflags = rflags.bits();
+                return Some(if command == b's' {
+                    Resume::Step
+                } else {
+                    Resume::Continue
+                });
+            }
+            b'D' | b'k' => {
+                self.frame.rflags &= !RFlags::TRAP_FLAG.bits();
+                if command == b'D' {
+                    let _ = reply.push_str("OK");
+                }
+                return Some(Resume::Detach);
+            }
+            b'q' if args.starts_with(b"Supported") => {
+                write!(reply, "PacketSize={PACKET_SIZE:x}").ok()
+            }
+            _ => Some(()),
+        };
+        if result.is_none() {
+            reply.clear();
+            let _ = reply.push_str("E01");
+        }
+        None
+    }
+
+    // 按 GDB 的顺序排列的通用寄存器
+    fn gprs(&self) -> [u64; GPR_COUNT] {
+        let f = &*self.frame;
+        [
+            f.rax, f.rbx, f.rcx, f.rdx, f.rsi, f.rdi, f.rbp, f.rsp, f.r8, f.r9, f.r10, f.r11,
+            f.r12, f.r13, f.r14, f.r15, f.rip,
+        ]
+    }
+
+    fn set_gprs(&mut self, values: [u64; GPR_COUNT]) {
+        let f = &mut *self.frame;
+        [
+            f.rax, f.rbx, f.rcx, f.rdx, f.rsi, f.rdi, f.rbp, f.rsp, f.r8, f.r9, f.r10, f.r11,
+            f.r12, f.r13, f.r14, f.r15, f.rip,
+        ] = values;
+    }
+
+    fn read_registers(&self, reply: &mut Reply) -> Option<()> {
+        for value in self.gprs() {
+            write_hex(reply, &value.to_le_bytes())?;
+        }
+        let f = &*self.frame;
+        let segments: [u64; SEGMENT_COUNT] = [f.rflags, f.cs, f.ss, 0, 0, 0, 0];
+        for value in segments {
+            write_hex(reply, &(value as u32).to_le_bytes())?;
+        }
+        Some(())
+    }
+
+    fn write_registers(&mut self, args: &[u8]) -> Option<()> {
+        let mut values = self.gprs();
+        // GDB 可以只发前面一部分寄存器
+        for (value, hex) in values.iter_mut().zip(args.chunks_exact(16)) {
+            let mut bytes = [0u8; 8];
+            decode_hex(hex, &mut bytes)?;
+            *value = u64::from_le_bytes(bytes);
+        }
+        // 段寄存器由内核管理，只接受 eflags
+        if let Some(hex) = args.get(GPR_COUNT * 16..GPR_COUNT * 16 + 8) {
+            let mut bytes = [0u8; 4];
+            decode_hex(hex, &mut bytes)?;
+            self.frame.rflags = u32::from_le_bytes(bytes) as u64;
+        }
+        self.set_gprs(values);
+        Some(())
+    }
+
+    fn read_memory(&self, args: &[u8], reply: &mut Reply) -> Option<()> {
+        let (addr, len) = parse_range(args)?;
+        if len * 2 > PACKET_SIZE || !mapped(addr, len) {
+            return None;
+        }
+        for i in 0..len {
+            let byte = unsafe { ((addr + i as u64) as *const u8).read_volatile() };
+            write_hex(reply, &[byte])?;
+        }
+        Some(())
+    }
+
+    fn write_memory(&mut self, args: &[u8]) -> Option<()> {
+        let colon = args.iter().position(|&b| b == b':')?;
+        let (addr, len) = parse_range(&args[..colon])?;
+        let hex = &args[colon + 1..];
+        if hex.len() != len * 2 || !mapped(addr, len) {
+            return None;
+        }
+        let mut data = [0u8; PACKET_SIZE / 2];
+        decode_hex(hex, &mut data[..len])?;
+        unsafe { poke(addr, &data[..len]) };
+        Some(())
+    }
+
+    // `addr,kind`
+    fn breakpoint(&mut self, insert: bool, args: &[u8]) -> Option<()> {
+        let addr = parse_hex(args.split(|&b| b == b',').next()?)?;
+        if insert {
+            self.insert_breakpoint(addr)
+        } else {
+            self.remove_breakpoint(addr)
+        }
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> Option<()> {
+        if self.breakpoints.iter().any(|&(at, _)| at == addr) {
+            return Some(());
+        }
+        if !mapped(addr, 1) {
+            return None;
+        }
+        let original = unsafe { (addr as *const u8).read_volatile() };
+        self.breakpoints.push((addr, original)).ok()?;
+        unsafe { poke(addr, &[INT3]) };
+        Some(())
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64) -> Option<()> {
+        let index = self.breakpoints.iter().position(|&(at, _)| at == addr)?;
+        let (_, original) = self.breakpoints.swap_remove(index);
+        unsafe { poke(addr, &[original]) };
+        Some(())
+    }
+}
+
+// `addr..addr + len` 中的每一页是否都已映射
+fn mapped(addr: u64, len: usize) -> bool {
+    let Some(end) = addr.checked_add(len.max(1) as u64 - 1) else {
+        return false;
+    };
+    (addr & !(memory::PAGE_SIZE - 1)..=end)
+        .step_by(memory::PAGE_SIZE as usize)
+        .all(|page| VirtAddr::try_new(page).is_ok_and(memory::is_mapped))
+}
+
+// 写内存，临时关闭 CR0.WP，这样断点也能写进只读的 .text
+unsafe fn poke(addr: u64, data: &[u8]) {
+    unsafe {
+        let _ = regs::cr0::update(|flags| flags.remove(Cr0Flags::WRITE_PROTECT));
+        for (i, &byte) in data.iter().enumerate() {
+            ((addr + i as u64) as *mut u8).write_volatile(byte);
+        }
+        let _ = regs::cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+fn parse_hex(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    hex.iter().try_fold(0u64, |value, &digit| {
+        Some(value << 4 | hex_value(digit)? as u64)
+    })
+}
+
+// `addr,len`
+fn parse_range(args: &[u8]) -> Option<(u64, usize)> {
+    let comma = args.iter().position(|&b| b == b',')?;
+    Some((
+        parse_hex(&args[..comma])?,
+        parse_hex(&args[comma + 1..])? as usize,
+    ))
+}
+
+fn decode_hex(hex: &[u8], out: &mut [u8]) -> Option<()> {
+    if hex.len() != out.len() * 2 {
+        return None;
+    }
+    for (byte, pair) in out.iter_mut().zip(hex.chunks(2)) {
+        *byte = hex_value(pair[0])? << 4 | hex_value(pair[1])?;
+    }
+    Some(())
+}
+
+fn write_hex(reply: &mut Reply, bytes: &[u8]) -> Option<()> {
+    bytes
+        .iter()
+        .try_for_each(|byte| write!(reply, "{byte:02x}"))
+        .ok()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn wait_byte(port: &mut SerialPort) -> u8 {
+    loop {
+        if let Some(byte) = port.try_receive() {
+            return byte;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+// 接收一个 `$data#cs` 包，校验和正确时回 `+`，否则回 `-` 等待重发
+fn receive_packet(port: &mut SerialPort) -> Packet {
+    loop {
+        while wait_byte(port) != b'$' {}
+        let mut packet = Packet::new();
+        let mut overflow = false;
+        loop {
+            match wait_byte(port) {
+                b'#' => break,
+                byte => overflow |= packet.push(byte).is_err(),
+            }
+        }
+        let sum = [wait_byte(port), wait_byte(port)];
+        let mut expected = [0u8];
+        if !overflow
+            && decode_hex(&sum, &mut expected).is_some()
+            && expected[0] == checksum(&packet)
+        {
+            port.send_raw(b'+');
+            return packet;
+        }
+        port.send_raw(b'-');
+    }
+}
+
+// 发送一个包，直到对方回 `+`
+fn send_packet(port: &mut SerialPort, data: &[u8]) {
+    loop {
+        port.send_raw(b'$');
+        data.iter().for_each(|&byte| port.send_raw(byte));
+        port.send_raw(b'#');
+        let mut sum = Reply::new();
+        let _ = write!(sum, "{:02x}", checksum(data));
+        sum.bytes().for_each(|byte| port.send_raw(byte));
+        if wait_byte(port) != b'-' {
+            return;
+        }
+    }
+}
+
+#[test_case]
+fn test_gdbstub_commands() {
+    fn run(frame: &mut TrapFrame, packet: &[u8]) -> (Option<Resume>, Reply) {
+        let mut breakpoints = heapless::Vec::new();
+        let mut stub = Stub {
+            frame,
+            signal: SIGTRAP,
+            breakpoints: &mut breakpoints,
+        };
+        let mut reply = Reply::new();
+        (stub.handle(packet, &mut reply), reply)
+    }
+
+    let mut frame = TrapFrame {
+        rax: 0x1122_3344_5566_7788,
+        ..TrapFrame::default()
+    };
+    assert_eq!(run(&mut frame, b"?").1, "S05");
+    let (_, registers) = run(&mut frame, b"g");
+    assert!(registers.starts_with("8877665544332211"));
+    assert_eq!(registers.len(), GPR_COUNT * 16 + SEGMENT_COUNT * 8);
+
+    // 只写前两个寄存器：rax = 1，rbx = 2
+    let (_, reply) = run(&mut frame, b"G01000000000000000200000000000000");
+    assert_eq!(reply, "OK");
+    assert_eq!((frame.rax, frame.rbx, frame.rip), (1, 2, 0));
+
+    static DATA: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+    let mut packet = Reply::new();
+    write!(packet, "m{:x},4", &raw const DATA as u64).unwrap();
+    assert_eq!(run(&mut frame, packet.as_bytes()).1, "deadbeef");
+    // 非规范地址
+    assert_eq!(run(&mut frame, b"m800000000000,4").1, "E01");
+    assert_eq!(run(&mut frame, b"vMustReplyEmpty").1, "");
+
+    assert_eq!(run(&mut frame, b"s").0, Some(Resume::Step));
+    assert_ne!(frame.rflags & RFlags::TRAP_FLAG.bits(), 0);
+    assert_eq!(run(&mut frame, b"c1234").0, Some(Resume::Continue));
+    assert_eq!(frame.rflags & RFlags::TRAP_FLAG.bits(), 0);
+    assert_eq!(frame.rip, 0x1234);
+}
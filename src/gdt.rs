@@ -1,47 +1,116 @@
+use core::cell::UnsafeCell;
+
 use lazy_static::lazy_static;
+use spin::Once;
 use x86_64::{VirtAddr, structures::tss::TaskStateSegment};
+
+use crate::{memory, smp};
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// 每个 CPU 双重错误栈的大小。
+pub const DOUBLE_FAULT_STACK_SIZE: u64 = 4096 * 5;
+// 运行用户程序时要改写 TSS 中的 rsp0，所以放在 UnsafeCell 里。GDT 中的描述符
+// 只记录它的地址
+struct Tss(UnsafeCell<TaskStateSegment>);
+
+unsafe impl Sync for Tss {}
+
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
+    static ref TSS: Tss = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: u64 = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE as usize] = [0; STACK_SIZE as usize];
+            const STACK_SIZE: usize = DOUBLE_FAULT_STACK_SIZE as usize;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
             let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            stack_start + STACK_SIZE
+            stack_start + DOUBLE_FAULT_STACK_SIZE
         };
-        tss
+        Tss(UnsafeCell::new(tss))
     };
 }
 
+/// BSP 双重错误栈的栈顶。集成测试用它确认处理函数确实运行在 IST 栈上。
+pub fn double_fault_stack_top() -> VirtAddr {
+    unsafe { (*TSS.0.get()).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] }
+}
+
+/// 设置 BSP 从用户态陷入内核时使用的栈（TSS 的 rsp0），返回原来的栈顶。
+/// 用户程序只在 BSP 上运行。
+///
+/// # Safety
+/// `top` 必须是一个足够大、没有别人使用的内核栈的栈顶。
+pub unsafe fn set_kernel_stack(top: VirtAddr) -> VirtAddr {
+    let tss = unsafe { &mut *TSS.0.get() };
+    // TSS 是 packed 结构，不能借用它的字段
+    let old = tss.privilege_stack_table[0];
+    tss.privilege_stack_table[0] = top;
+    old
+}
+
+/// 用户态的代码段和数据段选择子，特权级为 3。
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector) {
+    (GDT.1.user_code_selector, GDT.1.user_data_selector)
+}
+
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-            },
-        )
-    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = build(unsafe { &*TSS.0.get() });
 }
-pub fn init() {
+
+fn build(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let tss_selector = gdt.append(Descriptor::tss_segment(tss));
+    let user_data_selector = gdt.append(Descriptor::user_data_segment());
+    let user_code_selector = gdt.append(Descriptor::user_code_segment());
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            tss_selector,
+            user_code_selector,
+            user_data_selector,
+        },
+    )
+}
+
+fn load(gdt: &'static (GlobalDescriptorTable, Selectors)) {
     use x86_64::instructions::{
         segmentation::{CS, Segment},
         tables::load_tss,
     };
-    GDT.0.load();
+    gdt.0.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(gdt.1.code_selector);
+        load_tss(gdt.1.tss_selector);
     }
 }
+
+pub fn init() {
+    load(&GDT);
+}
+
+// 加载 TSS 会把 GDT 中的描述符标记为忙，不能被第二个 CPU 再加载；每个 CPU
+// 也需要自己的双重错误栈，所以 AP 各有一份 TSS 和 GDT
+static AP_TSS: [Once<TaskStateSegment>; smp::MAX_CPUS] = [const { Once::new() }; smp::MAX_CPUS];
+static AP_GDT: [Once<(GlobalDescriptorTable, Selectors)>; smp::MAX_CPUS] =
+    [const { Once::new() }; smp::MAX_CPUS];
+
+/// 为 AP `cpu` 建立并加载它自己的 GDT 和 TSS，双重错误栈映射在内核栈区，
+/// 下面有保护页。
+pub fn init_ap(cpu: usize) {
+    const STACK_PAGES: usize = (DOUBLE_FAULT_STACK_SIZE / memory::PAGE_SIZE) as usize;
+    let tss = AP_TSS[cpu].call_once(|| {
+        let stack_top = memory::alloc_stack(STACK_PAGES).expect("no memory for double fault stack");
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_top;
+        tss
+    });
+    load(AP_GDT[cpu].call_once(|| build(tss)));
+}
 struct Selectors {
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
 }
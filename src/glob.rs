@@ -0,0 +1,130 @@
+/// 判断 `text` 是否匹配 shell 风格的通配模式。
+///
+/// 支持 `*`（任意长度）、`?`（单个字符）、`[a-z]` / `[!a-z]` 字符类和 `\`
+/// 转义。不完整的 `[` 按普通字符处理。整个过程不分配内存，
+/// `*` 的回溯只记录最近一个星号的位置，因此最坏情况是 O(模式长度 × 文本长度)。
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let (mut p, mut t) = (pattern, text);
+    // 最近一个 `*` 之后的模式，以及当时它开始吞掉的文本位置
+    let mut star: Option<(&str, &str)> = None;
+
+    loop {
+        let mut pattern_chars = p.chars();
+        match pattern_chars.next() {
+            Some('*') => {
+                p = pattern_chars.as_str();
+                star = Some((p, t));
+                continue;
+            }
+            Some(c) => {
+                let mut text_chars = t.chars();
+                if let Some(ch) = text_chars.next() {
+                    let rest = pattern_chars.as_str();
+                    let matched = match c {
+                        '?' => Some(rest),
+                        '[' => match class(rest, ch) {
+                            Some((true, after)) => Some(after),
+                            Some((false, _)) => None,
+                            None => (ch == '[').then_some(rest),
+                        },
+                        '\\' => {
+                            let mut escaped = rest.chars();
+                            match escaped.next() {
+                                Some(e) if e == ch => Some(escaped.as_str()),
+                                None if ch == '\\' => Some(rest),
+                                _ => None,
+                            }
+                        }
+                        _ => (c == ch).then_some(rest),
+                    };
+                    if let Some(rest) = matched {
+                        p = rest;
+                        t = text_chars.as_str();
+                        continue;
+                    }
+                }
+            }
+            None if t.is_empty() => return true,
+            None => {}
+        }
+
+        // 失配：让最近的 `*` 多吞一个字符后重试
+        let Some((after_star, start)) = star else {
+            return false;
+        };
+        let mut skipped = start.chars();
+        if skipped.next().is_none() {
+            return false;
+        }
+        p = after_star;
+        t = skipped.as_str();
+        star = Some((p, t));
+    }
+}
+
+/// 模式中是否含有通配符，shell 用它决定参数要不要展开。
+pub fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// 解析 `[` 之后的字符类。返回 (是否匹配, 类之后的模式)；没有闭合的 `]` 时
+/// 返回 `None`。
+fn class(pattern: &str, ch: char) -> Option<(bool, &str)> {
+    let mut chars = pattern.chars();
+    let mut negate = false;
+    let mut first = true;
+    let mut matched = false;
+
+    let mut peek = chars.clone();
+    if let Some('!' | '^') = peek.next() {
+        negate = true;
+        chars = peek;
+    }
+
+    loop {
+        let c = chars.next()?;
+        // `]` 紧跟在 `[` 或 `[!` 之后时表示它本身
+        if c == ']' && !first {
+            return Some((matched != negate, chars.as_str()));
+        }
+        first = false;
+
+        let mut lookahead = chars.clone();
+        if let (Some('-'), Some(end)) = (lookahead.next(), lookahead.next())
+            && end != ']'
+        {
+            matched |= (c..=end).contains(&ch);
+            chars = lookahead;
+        } else {
+            matched |= c == ch;
+        }
+    }
+}
+
+#[test_case]
+fn test_glob_literals_and_wildcards() {
+    assert!(matches("config.txt", "config.txt"));
+    assert!(!matches("config.txt", "config.txt2"));
+    assert!(matches("*.txt", "notes.txt"));
+    assert!(!matches("*.txt", "notes.rs"));
+    assert!(matches("a*b*c", "aXXbYYc"));
+    assert!(!matches("a*b*c", "aXXbYY"));
+    assert!(matches("fil?", "file"));
+    assert!(!matches("fil?", "fil"));
+    assert!(matches("*", ""));
+    assert!(matches("**a", "bba"));
+}
+
+#[test_case]
+fn test_glob_classes_and_escapes() {
+    assert!(matches("log[0-9].txt", "log7.txt"));
+    assert!(!matches("log[0-9].txt", "logx.txt"));
+    assert!(matches("[!a-c]*", "dog"));
+    assert!(!matches("[!a-c]*", "cat"));
+    assert!(matches("[]x]", "]"));
+    assert!(matches("[a-]", "-"));
+    assert!(matches("[unclosed", "[unclosed"));
+    assert!(matches("\\*", "*"));
+    assert!(!matches("\\*", "x"));
+    assert!(is_pattern("*.rs") && !is_pattern("main.rs"));
+}
@@ -0,0 +1,207 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+pub const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+pub const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// 输出缓冲区中的字节来自第二个端口（鼠标）。
+pub const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND: u8 = 0xa7;
+const ENABLE_SECOND: u8 = 0xa8;
+const TEST_SECOND: u8 = 0xa9;
+const SELF_TEST: u8 = 0xaa;
+const TEST_FIRST: u8 = 0xab;
+const DISABLE_FIRST: u8 = 0xad;
+const ENABLE_FIRST: u8 = 0xae;
+const WRITE_SECOND: u8 = 0xd4;
+// 输出端口的位 0 接在 CPU 的复位线上，这个命令让它产生一个脉冲
+const PULSE_RESET: u8 = 0xfe;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+// 配置字节的各位
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_FIRST_CLOCK_OFF: u8 = 1 << 4;
+const CONFIG_SECOND_CLOCK_OFF: u8 = 1 << 5;
+const CONFIG_TRANSLATE: u8 = 1 << 6;
+
+// 轮询次数上限，控制器不存在或没有响应时不至于卡死
+const WAIT_SPINS: u32 = 100_000;
+
+// 冲掉输出缓冲区时最多读的字节数
+const FLUSH_LIMIT: usize = 16;
+
+static FIRST_PORT: AtomicBool = AtomicBool::new(false);
+static SECOND_PORT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitError {
+    /// 控制器没有响应
+    Timeout,
+    /// 控制器自检失败
+    SelfTest(u8),
+}
+
+pub fn read_status() -> u8 {
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+fn wait_input_empty() -> bool {
+    (0..WAIT_SPINS).any(|_| read_status() & STATUS_INPUT_FULL == 0)
+}
+
+fn wait_output_full() -> bool {
+    (0..WAIT_SPINS).any(|_| read_status() & STATUS_OUTPUT_FULL != 0)
+}
+
+fn command(command: u8) -> Result<(), InitError> {
+    if !wait_input_empty() {
+        return Err(InitError::Timeout);
+    }
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+/// 等待并读取输出缓冲区中的一个字节。
+pub fn read_data() -> Option<u8> {
+    wait_output_full().then(|| unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+/// 向第一个端口（键盘）发送一个字节。
+pub fn write_data(byte: u8) -> bool {
+    if !wait_input_empty() {
+        return false;
+    }
+    unsafe { Port::<u8>::new(DATA_PORT).write(byte) };
+    true
+}
+
+/// 经控制器向第二个端口（鼠标）发送一个字节。
+pub fn write_second(byte: u8) -> bool {
+    command(WRITE_SECOND).is_ok() && write_data(byte)
+}
+
+/// 让控制器拉低 CPU 的复位线，命令发出后机器很快就会复位。
+pub fn pulse_reset() -> Result<(), InitError> {
+    command(PULSE_RESET)
+}
+
+fn command_with_reply(byte: u8) -> Result<u8, InitError> {
+    command(byte)?;
+    read_data().ok_or(InitError::Timeout)
+}
+
+fn write_config(config: u8) -> Result<(), InitError> {
+    command(WRITE_CONFIG)?;
+    write_data(config).then_some(()).ok_or(InitError::Timeout)
+}
+
+fn flush_output() {
+    for _ in 0..FLUSH_LIMIT {
+        if read_status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+}
+
+/// 根据检测到的端口计算最终的配置字节：打开存在的端口的中断和时钟，
+/// 按需打开扫描码转换（把第 2 套扫描码转换为第 1 套）。
+fn final_config(config: u8, first: bool, second: bool, translate: bool) -> u8 {
+    let mut config = (config & !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATE))
+        | CONFIG_FIRST_CLOCK_OFF
+        | CONFIG_SECOND_CLOCK_OFF;
+    if first {
+        config = (config & !CONFIG_FIRST_CLOCK_OFF) | CONFIG_FIRST_IRQ;
+    }
+    if second {
+        config = (config & !CONFIG_SECOND_CLOCK_OFF) | CONFIG_SECOND_IRQ;
+    }
+    if translate {
+        config |= CONFIG_TRANSLATE;
+    }
+    config
+}
+
+/// 初始化 PS/2 控制器：自检、检测两个端口，然后打开可用端口和它们的中断。
+///
+/// 必须在开中断、注册键盘和鼠标的处理函数之前调用。键盘驱动按第 1
+/// 套扫描码解码， 所以通常应当打开转换（`translate`）。
+pub fn init(translate: bool) -> Result<(), InitError> {
+    // 先关掉两个端口，免得初始化过程中设备发来的数据混进应答里
+    command(DISABLE_FIRST)?;
+    command(DISABLE_SECOND)?;
+    flush_output();
+
+    // 关掉中断和转换，自检期间不希望产生中断
+    let config = command_with_reply(READ_CONFIG)?;
+    let quiet = config & !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATE);
+    write_config(quiet)?;
+
+    let result = command_with_reply(SELF_TEST)?;
+    if result != SELF_TEST_PASSED {
+        return Err(InitError::SelfTest(result));
+    }
+    // 有的控制器自检后会复位配置字节
+    write_config(quiet)?;
+
+    // 单端口的控制器不认识打开第二个端口的命令，它的时钟关闭位不会变化
+    command(ENABLE_SECOND)?;
+    let dual = command_with_reply(READ_CONFIG)? & CONFIG_SECOND_CLOCK_OFF == 0;
+    command(DISABLE_SECOND)?;
+
+    let first = command_with_reply(TEST_FIRST)? == PORT_TEST_PASSED;
+    let second = dual && command_with_reply(TEST_SECOND)? == PORT_TEST_PASSED;
+
+    if first {
+        command(ENABLE_FIRST)?;
+    }
+    if second {
+        command(ENABLE_SECOND)?;
+    }
+    write_config(final_config(quiet, first, second, translate))?;
+    flush_output();
+
+    FIRST_PORT.store(first, Ordering::Relaxed);
+    SECOND_PORT.store(second, Ordering::Relaxed);
+    Ok(())
+}
+
+/// 第一个端口（键盘）是否可用。
+pub fn first_port() -> bool {
+    FIRST_PORT.load(Ordering::Relaxed)
+}
+
+/// 第二个端口（鼠标）是否可用。
+pub fn second_port() -> bool {
+    SECOND_PORT.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_i8042_final_config() {
+    // 只有键盘，打开转换：系统标志（位 2）保留
+    assert_eq!(
+        final_config(0x04, true, false, true),
+        0x04 | 0x01 | 0x20 | 0x40
+    );
+    // 两个端口都有，不转换
+    assert_eq!(final_config(0x74, true, true, false), 0x04 | 0x01 | 0x02);
+    // 都没有：两个时钟都关掉
+    assert_eq!(final_config(0x03, false, false, false), 0x30);
+}
+
+#[test_case]
+fn test_i8042_ports_detected() {
+    // QEMU 的 PC 机型总是带有键盘和鼠标
+    assert!(first_port());
+    assert!(second_port());
+}
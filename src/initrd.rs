@@ -0,0 +1,46 @@
+use spin::Mutex;
+
+use crate::{
+    console::{self, LogLevel},
+    fs::{self, tarfs::TarFs},
+    fw_cfg,
+    memory::{self, PAGE_SIZE},
+};
+
+/// QEMU 中通过 `-fw_cfg name=opt/os-rust/initrd,file=initrd.tar` 传入 initrd，
+/// 归档用 `tar --format=ustar -cf initrd.tar -C rootfs .` 生成。
+pub const FW_CFG_FILE: &str = "opt/os-rust/initrd";
+
+static INITRD: Mutex<TarFs> = Mutex::new(TarFs::new());
+
+/// 读取 initrd（ustar 归档）并以只读方式挂载到 `/`。没有 initrd 时什么也不做。
+pub fn init() {
+    // bootloader 0.9 不支持加载额外的模块，所以和命令行一样从 fw_cfg 读取
+    let Some(file) = fw_cfg::find(FW_CFG_FILE) else {
+        return;
+    };
+    let Some(buffer) = memory::alloc_dma(file.size.div_ceil(PAGE_SIZE as usize)) else {
+        console::print_level(
+            LogLevel::Error,
+            format_args!("initrd: no memory for {} bytes", file.size),
+        );
+        return;
+    };
+    let archive = buffer.leak();
+    let len = file.read(archive);
+
+    let result = TarFs::parse(&archive[..len]).and_then(|tarfs| {
+        *INITRD.lock() = tarfs;
+        fs::mount("/", &INITRD)
+    });
+    match result {
+        Ok(()) => console::print_level(
+            LogLevel::Info,
+            format_args!("initrd: {} KiB mounted at /", len / 1024),
+        ),
+        Err(error) => console::print_level(
+            LogLevel::Error,
+            format_args!("initrd: cannot mount: {error:?}"),
+        ),
+    }
+}
@@ -0,0 +1,80 @@
+use sha2::{Digest, Sha256};
+
+use crate::console::{self, LogLevel};
+
+// 链接后由 tools/embed_digest.py 改写：填入 .text 的虚拟地址、长度和 SHA-256。
+// 编译时无法得到最终 .text 的哈希，所以只能先占位，链接完成后再回填
+#[repr(C)]
+struct DigestRecord {
+    magic: [u8; 8],
+    text_start: u64,
+    text_len: u64,
+    sha256: [u8; 32],
+}
+
+const MAGIC: [u8; 8] = *b"OSRDIGST";
+
+#[used]
+#[unsafe(link_section = ".kernel_digest")]
+static DIGEST: DigestRecord = DigestRecord {
+    magic: MAGIC,
+    text_start: 0,
+    text_len: 0,
+    sha256: [0; 32],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// 镜像没有经过 embed_digest.py 处理
+    NoDigest,
+    Match,
+    Mismatch,
+}
+
+/// 计算内存中 .text 的哈希并与链接后嵌入的摘要比较，结果输出到控制台。
+pub fn verify() -> Status {
+    // 编译器知道 DIGEST 的初始值，必须用 volatile 读取才能看到回填后的内容
+    let record = unsafe { core::ptr::read_volatile(&raw const DIGEST) };
+    if record.magic != MAGIC || record.text_len == 0 {
+        console::print_level(
+            LogLevel::Debug,
+            format_args!("integrity: no embedded digest, skipping .text check"),
+        );
+        return Status::NoDigest;
+    }
+
+    let text = unsafe {
+        core::slice::from_raw_parts(record.text_start as *const u8, record.text_len as usize)
+    };
+    if check(text, &record.sha256) {
+        console::print_level(
+            LogLevel::Info,
+            format_args!("integrity: .text matches embedded digest"),
+        );
+        Status::Match
+    } else {
+        console::print_level(
+            LogLevel::Error,
+            format_args!(
+                "integrity: .text at {:#x} ({} bytes) DOES NOT MATCH the embedded digest",
+                record.text_start, record.text_len
+            ),
+        );
+        Status::Mismatch
+    }
+}
+
+fn check(text: &[u8], expected: &[u8; 32]) -> bool {
+    Sha256::digest(text).as_slice() == expected
+}
+
+#[test_case]
+fn test_integrity_digest_check() {
+    let expected = [
+        0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22,
+        0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00,
+        0x15, 0xad,
+    ];
+    assert!(check(b"abc", &expected));
+    assert!(!check(b"abd", &expected));
+}
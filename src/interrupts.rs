@@ -1,18 +1,49 @@
+use core::{
+    arch::naked_asm,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::{
+    PrivilegeLevel, VirtAddr,
+    registers::rflags::RFlags,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+};
 
-use crate::{gdt, print, println};
+use crate::{
+    apic,
+    console::{self, LogLevel},
+    crash,
+    eventlog::{self, Event},
+    gdbstub, gdt, keyboard, percpu, print, println, process, profile, rand, speaker,
+    storage::ata,
+    syscall, testing, time, watchdog,
+};
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        // 调试器要读写全部通用寄存器，这两个异常经由保存寄存器的入口进入
+        unsafe {
+            idt.debug.set_handler_addr(VirtAddr::new(debug_entry as usize as u64));
+            idt.breakpoint.set_handler_addr(VirtAddr::new(breakpoint_entry as usize as u64));
+        }
 
+       // 这两个异常要报告全部寄存器，也经由保存寄存器的入口进入
        unsafe {
-               idt.double_fault.set_handler_fn(double_fault_handler)
+               idt.double_fault
+               .set_handler_addr(VirtAddr::new(double_fault_entry as usize as u64))
                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX );
+               idt.general_protection_fault
+               .set_handler_addr(VirtAddr::new(general_protection_entry as usize as u64));
+               idt.page_fault
+               .set_handler_addr(VirtAddr::new(page_fault_entry as usize as u64));
+               // 系统调用由用户态的 int 0x80 触发，门的特权级要是 3
+               idt[syscall::VECTOR]
+               .set_handler_addr(VirtAddr::new(syscall_entry as usize as u64))
+               .set_privilege_level(PrivilegeLevel::Ring3);
                 }
 
          // 注册时 cast 成期望类型
@@ -20,6 +51,17 @@ lazy_static! {
          // 因为中断向量号本身就是 u8（0–255）
        idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
        idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+       idt[InterruptIndex::PrimaryAta.as_u8()].set_handler_fn(primary_ata_interrupt_handler);
+       idt[InterruptIndex::SecondaryAta.as_u8()].set_handler_fn(secondary_ata_interrupt_handler);
+       // 其余的 IRQ 线（PCI 设备的中断线由固件分配）由驱动在运行时注册处理函数
+       for &(irq, handler) in SHARED_IRQS {
+           idt[PIC_1_OFFSET + irq].set_handler_fn(handler);
+       }
+       for (index, &handler) in MSI_ENTRIES.iter().enumerate() {
+           idt[MSI_VECTOR_BASE + index as u8].set_handler_fn(handler);
+       }
+       idt[apic::TIMER_VECTOR].set_handler_fn(apic_timer_handler);
+       idt[apic::SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
 
     idt
     };
@@ -29,19 +71,203 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+/// 陷入时的全部通用寄存器和 CPU 压入的中断栈帧，处理函数可以修改它们，
+/// 返回时按修改后的值恢复。
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// 生成一个没有错误码的异常入口：按 `TrapFrame` 的顺序保存通用寄存器，
+// 把它的地址交给 `$handler`，返回后恢复寄存器并 iretq
+macro_rules! trap_entry {
+    ($name:ident => $handler:ident) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            naked_asm!(
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                // CPU 压入的 40 字节加上 120 字节寄存器，栈正好 16 字节对齐
+                "mov rdi, rsp",
+                "cld",
+                "call {handler}",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "iretq",
+                handler = sym $handler,
+            )
+        }
+    };
+}
+
+/// 带错误码的异常陷入时的全部通用寄存器、错误码和中断栈帧。
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FaultFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// 生成一个带错误码、不返回的异常入口：保存通用寄存器后把 `FaultFrame`
+// 的地址交给 `$handler`
+macro_rules! fault_entry {
+    ($name:ident => $handler:ident) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            naked_asm!(
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rdi, rsp",
+                // CPU 压入的 48 字节加上 120 字节寄存器，差 8 字节才 16 字节对齐
+                "sub rsp, 8",
+                "cld",
+                "call {handler}",
+                "ud2",
+                handler = sym $handler,
+            )
+        }
+    };
+}
+
+trap_entry!(breakpoint_entry => breakpoint_handler);
+trap_entry!(debug_entry => debug_handler);
+trap_entry!(syscall_entry => syscall_handler);
+fault_entry!(double_fault_entry => double_fault_handler);
+fault_entry!(general_protection_entry => general_protection_handler);
+fault_entry!(page_fault_entry => page_fault_handler);
+
+extern "C" fn breakpoint_handler(frame: &mut TrapFrame) {
+    percpu::restore_gs(frame.cs);
+    eventlog::record(Event::Exception(3));
+    println!("EXCEPTION: BREAKPOINT\n{:#x?}", frame);
+    if gdbstub::enabled() {
+        gdbstub::handle_trap(frame, gdbstub::SIGTRAP);
+    }
+}
+
+// 单步（RFLAGS.TF）结束后触发
+extern "C" fn debug_handler(frame: &mut TrapFrame) {
+    percpu::restore_gs(frame.cs);
+    eventlog::record(Event::Exception(1));
+    if gdbstub::enabled() {
+        gdbstub::handle_trap(frame, gdbstub::SIGTRAP);
+    } else {
+        frame.rflags &= !RFlags::TRAP_FLAG.bits();
+    }
+}
+
+extern "C" fn double_fault_handler(frame: &FaultFrame) -> ! {
+    percpu::restore_gs(frame.cs);
+    eventlog::record(Event::Exception(8));
+    crash::report(8, frame);
 }
 
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+extern "C" fn general_protection_handler(frame: &FaultFrame) -> ! {
+    percpu::restore_gs(frame.cs);
+    eventlog::record(Event::Exception(13));
+    if frame.cs & 3 == 3 {
+        process::kill(13, frame);
+    }
+    crash::report(13, frame);
+}
+
+// 用户程序出错时只杀死进程，内核自己出错时报告后停机
+extern "C" fn page_fault_handler(frame: &FaultFrame) -> ! {
+    percpu::restore_gs(frame.cs);
+    eventlog::record(Event::Exception(14));
+    if frame.cs & 3 == 3 {
+        process::kill(14, frame);
+    }
+    crash::report(14, frame);
+}
+
+extern "C" fn syscall_handler(frame: &mut TrapFrame) {
+    percpu::restore_gs(frame.cs);
+    syscall::dispatch(frame);
 }
 // CPU 对异常和外部中断的反应相同（唯一的区别是某些异常会推送错误代码）
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs(stack_frame.code_segment.0 as u64);
+    count_irq(0);
+    system_tick(&stack_frame);
     unsafe {
         // 我们需要小心使用正确的中断向量号，
         // 否则我们可能会意外删除重要的未发送中断或导致我们的系统挂起。
@@ -49,48 +275,261 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+    process::check_interrupt(stack_frame.code_segment.0 as u64);
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+/// Local APIC 定时器中断。每个 CPU 都有自己的定时器，将来用作调度器的
+/// 抢占时钟；系统时钟只由 BSP 推进。
+extern "x86-interrupt" fn apic_timer_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs(stack_frame.code_segment.0 as u64);
+    let stats = crate::per_cpu!(stats);
+    stats.interrupts.fetch_add(1, Ordering::Relaxed);
+    stats.timer_ticks.fetch_add(1, Ordering::Relaxed);
+    if percpu::id() == 0 {
+        system_tick(&stack_frame);
+    }
+    apic::end_of_interrupt();
+    process::check_interrupt(stack_frame.code_segment.0 as u64);
+}
+
+/// 时钟中断要做的事，时钟来自 PIT 或者 BSP 的 Local APIC 定时器。
+fn system_tick(stack_frame: &InterruptStackFrame) {
+    time::tick();
+    speaker::tick();
+    testing::tick();
+    watchdog::tick();
+    crate::trace!("timer");
+    if profile::running() {
+        profile::sample(stack_frame.instruction_pointer.as_u64());
+    }
+    // 每个时钟中断都打印会淹没其他输出，只在 loglevel=trace 时打印
+    if console::enabled(LogLevel::Trace) {
+        print!(".");
+    }
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs(stack_frame.code_segment.0 as u64);
     // 键盘按下产生 扫描码 (scan code)，键盘控制器把它放到 输出缓冲区 (Output
     // Buffer)。
     // 同时，键盘控制器会向 CPU 发送 中断请求 (IRQ1)。
     // CPU 响应中断后，内核的键盘中断处理函数会读取扫描码。
     // 关键点：在你读取扫描码之前，键盘控制器不会发送新的中断。
     // 换句话说，如果缓冲区里还有未读取的数据，中断不会再触发。
-    use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1, layouts};
-    use spin::Mutex;
     use x86_64::instructions::port::Port;
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Us104Key,
-                HandleControl::Ignore
-            ));
-    }
-    let mut keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
-    // Option<KeyEvent> 结构。KeyEvent
-    // 包括了触发本次中断的按键信息，以及子动作是按下还是释放。
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        // 要处理KeyEvent，我们还需要将其传入 process_keyevent
-        // 函数，将其转换为人类可读的字符
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!("{:?}", key),
-            }
-        }
-    }
+    count_irq(1);
+    eventlog::record(Event::Key(scancode));
+    crate::trace!("keyboard", scancode);
+    // 解码、修饰键和指示灯由 keyboard 模块处理
+    keyboard::handle_scancode(scancode);
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
     }
 }
 
+extern "x86-interrupt" fn primary_ata_interrupt_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs(stack_frame.code_segment.0 as u64);
+    eventlog::record(Event::Irq(InterruptIndex::PrimaryAta.as_u8()));
+    count_irq(14);
+    ata::handle_irq(ata::Channel::Primary);
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::PrimaryAta.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn secondary_ata_interrupt_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs(stack_frame.code_segment.0 as u64);
+    eventlog::record(Event::Irq(InterruptIndex::SecondaryAta.as_u8()));
+    count_irq(15);
+    ata::handle_irq(ata::Channel::Secondary);
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SecondaryAta.as_u8());
+    }
+}
+
+// Local APIC 撤销了一个已经开始投递的中断，不需要 EOI
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+// 每条 IRQ 线收到的中断次数
+static IRQ_COUNTS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+
+fn count_irq(irq: u8) {
+    IRQ_COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+    crate::per_cpu!(stats)
+        .interrupts
+        .fetch_add(1, Ordering::Relaxed);
+    rand::add_interrupt_entropy(irq);
+}
+
+/// IRQ 线 `irq` 到目前为止收到的中断次数。
+pub fn irq_count(irq: u8) -> u64 {
+    IRQ_COUNTS
+        .get(irq as usize)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// 所有 IRQ 线收到的中断总数。
+pub fn irq_total() -> u64 {
+    IRQ_COUNTS
+        .iter()
+        .map(|count| count.load(Ordering::Relaxed))
+        .sum()
+}
+
+/// 每条共享 IRQ 线上最多注册的处理函数个数。
+pub const MAX_IRQ_HANDLERS: usize = 4;
+
+// 一条 IRQ 线上注册的处理函数
+type IrqHandlers = heapless::Vec<fn(), MAX_IRQ_HANDLERS>;
+
+// PCI 的 INTx 中断是电平触发、可以共享的，同一条线上的每个处理函数都要被调用，
+// 由它们各自检查是不是自己的设备发出的中断
+static IRQ_HANDLERS: spin::Mutex<[IrqHandlers; 16]> =
+    spin::Mutex::new([const { IrqHandlers::new() }; 16]);
+
+/// 为 IRQ 线 `irq` 注册一个处理函数并取消屏蔽。处理函数在中断上下文中运行，
+/// 不需要自己发送 EOI。线已满或没有对应的 IDT 项时返回 `false`。
+pub fn register_irq_handler(irq: u8, handler: fn()) -> bool {
+    if !SHARED_IRQS.iter().any(|&(line, _)| line == irq) {
+        return false;
+    }
+    let registered = x86_64::instructions::interrupts::without_interrupts(|| {
+        IRQ_HANDLERS.lock()[irq as usize].push(handler).is_ok()
+    });
+    if registered {
+        unmask_irq(irq);
+    }
+    registered
+}
+
+fn dispatch_irq(irq: u8) {
+    let vector = PIC_1_OFFSET + irq;
+    eventlog::record(Event::Irq(vector));
+    count_irq(irq);
+    crate::trace!("irq", irq);
+    // 复制出来再调用，处理函数里不会持有 IRQ_HANDLERS 的锁
+    let handlers = IRQ_HANDLERS.lock()[irq as usize].clone();
+    for handler in handlers {
+        handler();
+    }
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(vector);
+    }
+}
+
+type HandlerFn = extern "x86-interrupt" fn(InterruptStackFrame);
+
+// 为每条可共享的 IRQ 线生成一个转发到 dispatch_irq 的中断处理函数
+macro_rules! shared_irqs {
+    ($($irq:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+                percpu::restore_gs(stack_frame.code_segment.0 as u64);
+                dispatch_irq($irq);
+            }
+        )*
+
+        const SHARED_IRQS: &[(u8, HandlerFn)] = &[$(($irq, $name)),*];
+    };
+}
+
+shared_irqs! {
+    3 => irq3_handler,
+    4 => irq4_handler,
+    5 => irq5_handler,
+    6 => irq6_handler,
+    9 => irq9_handler,
+    10 => irq10_handler,
+    11 => irq11_handler,
+    12 => irq12_handler,
+}
+
+/// 分配给 MSI/MSI-X 的第一个向量。
+pub const MSI_VECTOR_BASE: u8 = 0x50;
+/// 可分配的 MSI 向量个数。
+pub const MSI_VECTORS: usize = 16;
+
+// 每个 MSI 向量的处理函数（`fn()` 的地址），0 表示空闲
+static MSI_HANDLERS: [AtomicUsize; MSI_VECTORS] = [const { AtomicUsize::new(0) }; MSI_VECTORS];
+
+/// 为消息信号中断分配一个向量，中断到来时调用 `handler`。
+///
+/// MSI 直接投递到 Local APIC，不经过 PIC：没有 Local APIC 或者向量用完时
+/// 返回 `None`，驱动应当退回到传统的中断线。MSI 不共享，处理函数收到的
+/// 一定是自己设备的中断；它在中断上下文中运行，不需要自己发送 EOI。
+pub fn allocate_msi_vector(handler: fn()) -> Option<u8> {
+    if !apic::available() {
+        return None;
+    }
+    let index = MSI_HANDLERS.iter().position(|slot| {
+        slot.compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    })?;
+    Some(MSI_VECTOR_BASE + index as u8)
+}
+
+/// 释放 [`allocate_msi_vector`] 分配的向量。
+pub fn free_msi_vector(vector: u8) {
+    if let Some(slot) = MSI_HANDLERS.get(vector.wrapping_sub(MSI_VECTOR_BASE) as usize) {
+        slot.store(0, Ordering::Release);
+    }
+}
+
+fn dispatch_msi(index: usize) {
+    let vector = MSI_VECTOR_BASE + index as u8;
+    eventlog::record(Event::Irq(vector));
+    crate::per_cpu!(stats)
+        .interrupts
+        .fetch_add(1, Ordering::Relaxed);
+    rand::add_interrupt_entropy(vector);
+    crate::trace!("msi", vector);
+    let handler = MSI_HANDLERS[index].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: fn() = unsafe { core::mem::transmute(handler) };
+        handler();
+    }
+    apic::end_of_interrupt();
+}
+
+// 为每个 MSI 向量生成一个转发到 dispatch_msi 的中断处理函数
+macro_rules! msi_entries {
+    ($($index:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+                percpu::restore_gs(stack_frame.code_segment.0 as u64);
+                dispatch_msi($index);
+            }
+        )*
+
+        const MSI_ENTRIES: [HandlerFn; MSI_VECTORS] = [$($name),*];
+    };
+}
+
+msi_entries! {
+    0 => msi0_handler,
+    1 => msi1_handler,
+    2 => msi2_handler,
+    3 => msi3_handler,
+    4 => msi4_handler,
+    5 => msi5_handler,
+    6 => msi6_handler,
+    7 => msi7_handler,
+    8 => msi8_handler,
+    9 => msi9_handler,
+    10 => msi10_handler,
+    11 => msi11_handler,
+    12 => msi12_handler,
+    13 => msi13_handler,
+    14 => msi14_handler,
+    15 => msi15_handler,
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     // invoke a breakpoint exception
@@ -109,6 +548,9 @@ pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     // Keyboard 没有显式赋值，所以 Rust 会自动给它赋值 紧接上一个值 +1。
     Keyboard,
+    // 从片上的 IRQ 14/15
+    PrimaryAta = PIC_2_OFFSET + 6,
+    SecondaryAta,
 }
 
 impl InterruptIndex {
@@ -116,3 +558,83 @@ impl InterruptIndex {
         self as u8
     }
 }
+
+/// 两片 PIC 的请求（IRR）、在服务（ISR）和屏蔽寄存器，位 n 对应 IRQ n。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PicState {
+    pub requested: u16,
+    pub in_service: u16,
+    pub masked: u16,
+}
+
+/// 读取 PIC 的状态。PIC 正被别处使用时不等待，返回 `None`。
+pub fn pic_state() -> Option<PicState> {
+    use x86_64::instructions::{interrupts, port::Port};
+
+    // OCW3：下一次读命令端口时返回 IRR 或 ISR
+    const READ_IRR: u8 = 0x0a;
+    const READ_ISR: u8 = 0x0b;
+    let mut master: Port<u8> = Port::new(0x20);
+    let mut slave: Port<u8> = Port::new(0xa0);
+    let mut master_data: Port<u8> = Port::new(0x21);
+    let mut slave_data: Port<u8> = Port::new(0xa1);
+    let read_pair = |command: u8, master: &mut Port<u8>, slave: &mut Port<u8>| unsafe {
+        master.write(command);
+        slave.write(command);
+        u16::from_le_bytes([master.read(), slave.read()])
+    };
+    interrupts::without_interrupts(|| {
+        let _pics = PICS.try_lock()?;
+        let in_service = read_pair(READ_ISR, &mut master, &mut slave);
+        // 最后切回默认的 IRR
+        let requested = read_pair(READ_IRR, &mut master, &mut slave);
+        let masked = unsafe { u16::from_le_bytes([master_data.read(), slave_data.read()]) };
+        Some(PicState {
+            requested,
+            in_service,
+            masked,
+        })
+    })
+}
+
+/// 屏蔽 PIC 上的某条 IRQ 线。
+pub fn mask_irq(irq: u8) {
+    use x86_64::instructions::{interrupts, port::Port};
+
+    let (mut port, bit): (Port<u8>, u8) = if irq < 8 {
+        (Port::new(0x21), irq)
+    } else {
+        (Port::new(0xa1), irq - 8)
+    };
+    interrupts::without_interrupts(|| {
+        // 持有 PICS 的锁，避免与 EOI 等其他 PIC 操作交错
+        let _pics = PICS.lock();
+        unsafe {
+            let mask = port.read();
+            port.write(mask | 1 << bit);
+        }
+    });
+}
+
+/// 取消 PIC 上某条 IRQ 线的屏蔽。从片上的 IRQ 还需要打开主片的级联线 IRQ 2。
+pub fn unmask_irq(irq: u8) {
+    use x86_64::instructions::{interrupts, port::Port};
+
+    let mut master: Port<u8> = Port::new(0x21);
+    let mut slave: Port<u8> = Port::new(0xa1);
+    interrupts::without_interrupts(|| {
+        // 持有 PICS 的锁，避免与 EOI 等其他 PIC 操作交错
+        let _pics = PICS.lock();
+        unsafe {
+            if irq < 8 {
+                let mask = master.read();
+                master.write(mask & !(1 << irq));
+            } else {
+                let mask = slave.read();
+                slave.write(mask & !(1 << (irq - 8)));
+                let mask = master.read();
+                master.write(mask & !(1 << 2));
+            }
+        }
+    });
+}
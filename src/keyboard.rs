@@ -0,0 +1,406 @@
+use lazy_static::lazy_static;
+use pc_keyboard::{
+    DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard, KeyboardLayout, ScancodeSet1,
+    layouts,
+};
+use spin::Mutex;
+
+use crate::{cmdline, console, i8042, print};
+
+// 键盘对命令的应答
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+// 设置指示灯，后面跟一个字节的掩码
+const SET_LEDS: u8 = 0xed;
+
+/// 可以在运行时切换的键盘布局。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    Uk,
+    De,
+    Fr,
+    Dvorak,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 5] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::De,
+        Layout::Fr,
+        Layout::Dvorak,
+    ];
+
+    /// 命令行 `keymap=` 和切换布局时使用的名字。
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::De => "de",
+            Layout::Fr => "fr",
+            Layout::Dvorak => "dvorak",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Layout> {
+        Layout::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+}
+
+impl KeyboardLayout for Layout {
+    fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &pc_keyboard::Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        match self {
+            Layout::Us => layouts::Us104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Uk => layouts::Uk105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::De => layouts::De105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Fr => layouts::Azerty.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Dvorak => layouts::Dvorak104Key.map_keycode(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+/// 修饰键和锁定键的当前状态。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    /// 指示灯命令的掩码：位 0 ScrollLock，位 1 NumLock，位 2 CapsLock。
+    fn led_mask(&self) -> u8 {
+        self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+/// 正在进行的指示灯更新，每发一个字节都要等键盘应答。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Leds {
+    Idle,
+    /// 已发送 0xED，等应答后发送掩码
+    SentCommand(u8),
+    /// 已发送掩码，等应答
+    SentMask(u8),
+}
+
+/// 处理一个字节的结果：要发给键盘的字节和解码出的按键。
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Output {
+    send: Option<u8>,
+    key: Option<DecodedKey>,
+}
+
+fn decoder(layout: Layout) -> Keyboard<Layout, ScancodeSet1> {
+    Keyboard::new(
+        ScancodeSet1::new(),
+        layout,
+        // Ctrl+字母 映射为 U+0001..U+001A，这样才能识别出 Ctrl+C (U+0003)
+        HandleControl::MapLettersToUnicode,
+    )
+}
+
+struct State {
+    decoder: Keyboard<Layout, ScancodeSet1>,
+    layout: Layout,
+    modifiers: Modifiers,
+    leds: Leds,
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            decoder: decoder(Layout::Us),
+            layout: Layout::Us,
+            // 与解码器的初始状态一致：NumLock 打开
+            modifiers: Modifiers {
+                shift: false,
+                ctrl: false,
+                alt: false,
+                caps_lock: false,
+                num_lock: true,
+                scroll_lock: false,
+            },
+            leds: Leds::Idle,
+        }
+    }
+
+    /// 换用新布局的解码器。解码器自己也记录着锁定键，
+    /// 按当前状态把它们重新按一遍， 切换前后大小写和小键盘的行为不变。
+    fn set_layout(&mut self, layout: Layout) {
+        self.decoder = decoder(layout);
+        self.layout = layout;
+        let fresh = State::new().modifiers;
+        let toggles = [
+            (
+                KeyCode::CapsLock,
+                fresh.caps_lock != self.modifiers.caps_lock,
+            ),
+            (
+                KeyCode::NumpadLock,
+                fresh.num_lock != self.modifiers.num_lock,
+            ),
+        ];
+        for (code, toggle) in toggles {
+            if toggle {
+                self.decoder
+                    .process_keyevent(KeyEvent::new(code, KeyState::Down));
+                self.decoder
+                    .process_keyevent(KeyEvent::new(code, KeyState::Up));
+            }
+        }
+    }
+
+    /// 需要更新指示灯时开始一次更新，返回要发送的命令。
+    fn start_led_update(&mut self) -> Option<u8> {
+        if self.leds != Leds::Idle {
+            // 正在更新，结束时会发现掩码变了再更新一次
+            return None;
+        }
+        self.leds = Leds::SentCommand(self.modifiers.led_mask());
+        Some(SET_LEDS)
+    }
+
+    fn handle_reply(&mut self, byte: u8) -> Option<u8> {
+        match (self.leds, byte) {
+            (Leds::SentCommand(mask), ACK) => {
+                self.leds = Leds::SentMask(mask);
+                Some(mask)
+            }
+            (Leds::SentCommand(_), _) => Some(SET_LEDS),
+            (Leds::SentMask(mask), ACK) => {
+                self.leds = Leds::Idle;
+                if mask != self.modifiers.led_mask() {
+                    self.start_led_update()
+                } else {
+                    None
+                }
+            }
+            (Leds::SentMask(mask), _) => Some(mask),
+            (Leds::Idle, _) => None,
+        }
+    }
+
+    fn track(&mut self, event: &KeyEvent) -> bool {
+        let down = event.state == KeyState::Down;
+        let modifiers = &mut self.modifiers;
+        match event.code {
+            KeyCode::LShift | KeyCode::RShift => modifiers.shift = down,
+            KeyCode::LControl | KeyCode::RControl => modifiers.ctrl = down,
+            KeyCode::LAlt | KeyCode::RAltGr => modifiers.alt = down,
+            KeyCode::CapsLock if down => modifiers.caps_lock = !modifiers.caps_lock,
+            KeyCode::NumpadLock if down => modifiers.num_lock = !modifiers.num_lock,
+            KeyCode::ScrollLock if down => modifiers.scroll_lock = !modifiers.scroll_lock,
+            _ => return false,
+        }
+        // 只有锁定键改变指示灯
+        down && matches!(
+            event.code,
+            KeyCode::CapsLock | KeyCode::NumpadLock | KeyCode::ScrollLock
+        )
+    }
+
+    fn add_byte(&mut self, byte: u8) -> Output {
+        // 应答不是扫描码，不能交给解码器
+        if (byte == ACK || byte == RESEND) && self.leds != Leds::Idle {
+            return Output {
+                send: self.handle_reply(byte),
+                key: None,
+            };
+        }
+        let Ok(Some(event)) = self.decoder.add_byte(byte) else {
+            return Output::default();
+        };
+        let send = if self.track(&event) {
+            self.start_led_update()
+        } else {
+            None
+        };
+        // 大小写由解码器按 CapsLock 和 Shift 的状态决定
+        Output {
+            send,
+            key: self.decoder.process_keyevent(event),
+        }
+    }
+}
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<State> = Mutex::new(State::new());
+}
+
+/// 输入缓冲区的字节数，满了以后新键入的字符被丢弃。
+pub const INPUT_LEN: usize = 256;
+
+// 键入的字符（UTF-8），等着 `read_input` 取走
+static INPUT: Mutex<heapless::Deque<u8, INPUT_LEN>> = Mutex::new(heapless::Deque::new());
+
+/// 由键盘中断调用，处理一个扫描码。
+pub(crate) fn handle_scancode(scancode: u8) {
+    let (output, modifiers) = {
+        let mut keyboard = KEYBOARD.lock();
+        let output = keyboard.add_byte(scancode);
+        (output, keyboard.modifiers)
+    };
+    if let Some(byte) = output.send {
+        i8042::write_data(byte);
+    }
+    match output.key {
+        Some(DecodedKey::Unicode('\u{3}')) => console::interrupt_foreground(),
+        // 控制台的组合键（切换终端、翻看历史）不回显
+        Some(DecodedKey::RawKey(key)) if console::handle_chord(key, &modifiers) => {}
+        Some(DecodedKey::Unicode(character)) => {
+            print!("{}", character);
+            push_input(character);
+        }
+        Some(DecodedKey::RawKey(key)) => print!("{:?}", key),
+        None => {}
+    }
+}
+
+// 放不下整个字符时丢掉它，不留下半个 UTF-8 序列
+fn push_input(character: char) {
+    let mut bytes = [0; 4];
+    let bytes = character.encode_utf8(&mut bytes).as_bytes();
+    let mut input = INPUT.lock();
+    if input.capacity() - input.len() >= bytes.len() {
+        for &byte in bytes {
+            let _ = input.push_back(byte);
+        }
+    }
+}
+
+/// 取走已经键入的字符（UTF-8），返回取到的字节数，还没有输入时返回 0。
+/// 字符在键入时已经回显过了。
+pub fn read_input(buf: &mut [u8]) -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut input = INPUT.lock();
+        let mut len = 0;
+        while len < buf.len() {
+            let Some(byte) = input.pop_front() else {
+                break;
+            };
+            buf[len] = byte;
+            len += 1;
+        }
+        len
+    })
+}
+
+/// 按命令行 `keymap=us|uk|de|fr|dvorak` 选择键盘布局，默认是 US。
+pub fn init() {
+    let Some(name) = cmdline::get("keymap") else {
+        return;
+    };
+    match Layout::parse(name) {
+        Some(layout) => set_layout(layout),
+        None => log::warn!("unknown keymap '{name}', using us"),
+    }
+}
+
+/// 切换键盘布局，已按下的锁定键保持不变。
+pub fn set_layout(layout: Layout) {
+    x86_64::instructions::interrupts::without_interrupts(|| KEYBOARD.lock().set_layout(layout));
+}
+
+pub fn layout() -> Layout {
+    x86_64::instructions::interrupts::without_interrupts(|| KEYBOARD.lock().layout)
+}
+
+/// 修饰键和锁定键的当前状态。
+pub fn modifiers() -> Modifiers {
+    x86_64::instructions::interrupts::without_interrupts(|| KEYBOARD.lock().modifiers)
+}
+
+#[test_case]
+fn test_keyboard_caps_lock_and_leds() {
+    const CAPS_DOWN: u8 = 0x3a;
+    const CAPS_UP: u8 = 0xba;
+    const A_DOWN: u8 = 0x1e;
+    const A_UP: u8 = 0x9e;
+    const LSHIFT_DOWN: u8 = 0x2a;
+    const LSHIFT_UP: u8 = 0xaa;
+
+    let mut state = State::new();
+    assert_eq!(state.add_byte(A_DOWN).key, Some(DecodedKey::Unicode('a')));
+    state.add_byte(A_UP);
+
+    // 按下 CapsLock：开始更新指示灯（NumLock + CapsLock）
+    assert_eq!(state.add_byte(CAPS_DOWN).send, Some(SET_LEDS));
+    assert!(state.modifiers.caps_lock);
+    assert_eq!(state.add_byte(ACK).send, Some(0b110));
+    assert_eq!(state.add_byte(ACK).send, None);
+    assert_eq!(state.leds, Leds::Idle);
+    state.add_byte(CAPS_UP);
+
+    assert_eq!(state.add_byte(A_DOWN).key, Some(DecodedKey::Unicode('A')));
+    state.add_byte(A_UP);
+    // CapsLock 打开时 Shift 反过来得到小写
+    state.add_byte(LSHIFT_DOWN);
+    assert!(state.modifiers.shift);
+    assert_eq!(state.add_byte(A_DOWN).key, Some(DecodedKey::Unicode('a')));
+    state.add_byte(A_UP);
+    state.add_byte(LSHIFT_UP);
+
+    // 更新过程中再次切换：完成后按新状态再发一次，键盘要求重发时照做
+    assert_eq!(state.add_byte(CAPS_DOWN).send, Some(SET_LEDS));
+    state.add_byte(CAPS_UP);
+    assert_eq!(state.add_byte(RESEND).send, Some(SET_LEDS));
+    assert_eq!(state.add_byte(ACK).send, Some(0b010));
+    assert_eq!(state.add_byte(CAPS_DOWN).send, None);
+    state.add_byte(CAPS_UP);
+    assert_eq!(state.add_byte(ACK).send, Some(SET_LEDS));
+    assert_eq!(state.add_byte(ACK).send, Some(0b110));
+    assert_eq!(state.add_byte(ACK).send, None);
+}
+
+#[test_case]
+fn test_keyboard_layouts() {
+    const Y_DOWN: u8 = 0x15;
+    const Q_DOWN: u8 = 0x10;
+    const CAPS_DOWN: u8 = 0x3a;
+
+    let mut state = State::new();
+    assert_eq!(Layout::parse("de"), Some(Layout::De));
+    assert_eq!(Layout::parse("xx"), None);
+
+    // 德语布局交换了 Y 和 Z
+    state.set_layout(Layout::De);
+    assert_eq!(state.add_byte(Y_DOWN).key, Some(DecodedKey::Unicode('z')));
+    // AZERTY 的 Q 位置是 A，Dvorak 的是单引号
+    state.set_layout(Layout::Fr);
+    assert_eq!(state.add_byte(Q_DOWN).key, Some(DecodedKey::Unicode('a')));
+    state.set_layout(Layout::Dvorak);
+    assert_eq!(state.add_byte(Q_DOWN).key, Some(DecodedKey::Unicode('\'')));
+
+    // 切换布局后 CapsLock 仍然有效
+    state.add_byte(CAPS_DOWN);
+    state.add_byte(ACK);
+    state.add_byte(ACK);
+    state.set_layout(Layout::De);
+    assert!(state.modifiers.caps_lock);
+    assert_eq!(state.add_byte(Y_DOWN).key, Some(DecodedKey::Unicode('Z')));
+}
+
+#[test_case]
+fn test_keyboard_input_queue() {
+    let mut buf = [0; 8];
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        while read_input(&mut buf) > 0 {}
+        push_input('l');
+        push_input('é');
+        push_input('\n');
+    });
+    assert_eq!(read_input(&mut buf[..2]), 2);
+    assert_eq!(read_input(&mut buf[2..]), 2);
+    assert_eq!(&buf[..4], "lé\n".as_bytes());
+    assert_eq!(read_input(&mut buf), 0);
+}
@@ -4,47 +4,72 @@
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 #![feature(abi_x86_interrupt)]
+pub mod acpi;
+pub mod apic;
+pub mod backtrace;
+pub mod bench;
+pub mod cmdline;
+pub mod config;
+pub mod console;
+pub mod cpu;
+pub mod crash;
+pub mod debugcon;
+pub mod devices;
+pub mod elf;
+pub mod eventlog;
+pub mod fpu;
+pub mod framebuffer;
+pub mod fs;
+pub mod fw_cfg;
+pub mod gdbstub;
 pub mod gdt;
+pub mod glob;
+pub mod i8042;
+pub mod initrd;
+pub mod integrity;
 pub mod interrupts;
+pub mod keyboard;
+pub mod logger;
+pub mod memory;
+pub mod mouse;
+pub mod multiboot2;
+pub mod net;
+pub mod panic;
+pub mod pci;
+pub mod percpu;
+pub mod platform;
+pub mod power;
+pub mod process;
+pub mod profile;
+pub mod rand;
+pub mod regs;
+pub mod ring;
 pub mod serial;
+pub mod smp;
+pub mod speaker;
+pub mod status_bar;
+pub mod storage;
+pub mod syscall;
+pub mod testing;
+pub mod time;
+pub mod trace;
 pub mod vga_buffer;
+pub mod watchdog;
+#[cfg(test)]
 use core::panic::PanicInfo;
 
-pub trait Testable {
-    fn run(&self) -> ();
-}
-
-impl<T> Testable for T
-where
-    T: Fn(),
-{
-    fn run(&self) {
-        serial_print!("{}...\t", core::any::type_name::<T>());
-        self();
-        serial_println!("[ok]");
-    }
-}
-
-pub fn test_runner(tests: &[&dyn Testable]) {
-    serial_println!("Running {} tests", tests.len());
-    for test in tests {
-        test.run();
-    }
-    exit_qemu(QemuExitCode::Success);
-}
+use bootloader::BootInfo;
+#[cfg(test)]
+use bootloader::entry_point;
+pub use testing::{Testable, test_panic_handler, test_runner};
 
-pub fn test_panic_handler(info: &PanicInfo) -> ! {
-    serial_println!("[failed]\n");
-    serial_println!("Error: {}\n", info);
-    exit_qemu(QemuExitCode::Failed);
-    hlt_loop();
-}
+#[cfg(test)]
+entry_point!(test_kernel_main);
 
 /// Entry point for `cargo test`
 #[cfg(test)]
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() -> ! {
-    init(); // new
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
     test_main();
     hlt_loop();
 }
@@ -71,15 +96,81 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
     }
 }
 
-pub fn init() {
+pub fn init(boot_info: &'static BootInfo) {
+    platform::init(boot_info);
+    percpu::init(0);
+    memory::init();
+    console::init();
+    logger::init();
+    platform::init_display();
+    cpu::init();
+    fpu::init();
+    rand::init();
+    memory::randomize_layout();
+    acpi::init();
+    trace::init();
+    profile::init();
+    keyboard::init();
     gdt::init();
     interrupts::init_idt();
     unsafe {
         interrupts::PICS.lock().initialize();
     }
+    time::init();
+    apic::init();
+    apic::init_timer();
+    smp::init();
+    if let Err(err) = i8042::init(true) {
+        log::error!("i8042: {err:?}");
+    }
+    mouse::init();
+    serial::init();
+    gdbstub::init();
+    pci::init();
+    for driver in [
+        &storage::ata::DRIVER,
+        &storage::virtio_blk::DRIVER,
+        &net::e1000::DRIVER,
+    ] {
+        if let Err(err) = devices::register(driver) {
+            log::error!("devices: cannot register {}: {:?}", driver.name, err);
+        }
+    }
+    devices::init();
+    config::init();
+    net::dhcp::init();
+    initrd::init();
+    fs::tmpfs::init();
     x86_64::instructions::interrupts::enable();
+    integrity::verify();
+}
+
+/// 等到 `ready()` 返回 true，期间用 `hlt` 让出 CPU，每来一个中断检查一次。
+///
+/// 检查时关着中断，不满足再用 `sti; hlt` 原子地打开中断并停下，中断不会
+/// 恰好落在检查和 `hlt` 之间而被错过。调用时中断是关着的则退化为忙等，
+/// 这时 `ready` 不能依赖中断处理函数来改变状态。
+pub fn wait_until(mut ready: impl FnMut() -> bool) {
+    use x86_64::instructions::interrupts;
+
+    if !interrupts::are_enabled() {
+        while !ready() {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+    loop {
+        interrupts::disable();
+        if ready() {
+            interrupts::enable();
+            return;
+        }
+        interrupts::enable_and_hlt();
+    }
 }
 
+/// 停机等待中断，永不返回。空闲时、panic 之后和测试结束时都用它代替空循环，
+/// 免得在 QEMU 里占满宿主机的一个核。
 pub fn hlt_loop() -> ! {
     loop {
         x86_64::instructions::hlt();
@@ -0,0 +1,149 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::Once;
+
+use crate::{
+    cmdline,
+    console::{self, LogLevel},
+    time,
+};
+
+/// 命令行中最多能设置的模块过滤规则数，多出的被忽略。
+pub const MAX_FILTERS: usize = 16;
+
+// 内核自己的 target 都以 crate 名开头，过滤规则里可以省略
+const CRATE_PREFIX: &str = "os_rust::";
+
+/// 一条过滤规则：`module` 及其子模块只输出不低于 `level` 的日志。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Filter {
+    module: &'static str,
+    level: LogLevel,
+}
+
+type Filters = heapless::Vec<Filter, MAX_FILTERS>;
+
+// 初始化后只读，日志可能来自中断处理函数，不能用锁
+static FILTERS: Once<Filters> = Once::new();
+
+struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+fn to_log_level(level: Level) -> LogLevel {
+    match level {
+        Level::Error => LogLevel::Error,
+        Level::Warn => LogLevel::Warn,
+        Level::Info => LogLevel::Info,
+        Level::Debug => LogLevel::Debug,
+        Level::Trace => LogLevel::Trace,
+    }
+}
+
+fn to_level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    }
+}
+
+/// 解析 `loglevel=info,net=debug,time=warn`：不带模块名的一项是全局级别，
+/// 其余的是模块规则，不认识的项被跳过。
+fn parse_spec(spec: &'static str) -> (Option<LogLevel>, Filters) {
+    let mut global = None;
+    let mut filters = Filters::new();
+    for rule in spec.split(',') {
+        let Some((module, level)) = rule.split_once('=') else {
+            global = LogLevel::parse(rule).or(global);
+            continue;
+        };
+        if let Some(level) = LogLevel::parse(level) {
+            let module = module.strip_prefix(CRATE_PREFIX).unwrap_or(module);
+            let _ = filters.push(Filter { module, level });
+        }
+    }
+    (global, filters)
+}
+
+/// `target` 适用的级别：最长的匹配规则优先，没有规则时使用全局级别。
+fn level_for(filters: &[Filter], target: &str, default: LogLevel) -> LogLevel {
+    let target = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+    filters
+        .iter()
+        .filter(|filter| {
+            target
+                .strip_prefix(filter.module)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|filter| filter.module.len())
+        .map_or(default, |filter| filter.level)
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let filters = FILTERS.r#try().map_or(&[][..], |filters| &filters[..]);
+        to_log_level(metadata.level())
+            <= level_for(filters, metadata.target(), console::log_level())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let ms = time::uptime_ms();
+        let target = record.target();
+        // 级别的颜色和前缀由控制台负责；这里已经按模块过滤过，不再检查全局级别
+        console::write_level(
+            to_log_level(record.level()),
+            format_args!(
+                "[{:>5}.{:03}] {}: {}",
+                ms / 1000,
+                ms % 1000,
+                target.strip_prefix(CRATE_PREFIX).unwrap_or(target),
+                record.args()
+            ),
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+/// 读取命令行中的 `loglevel=` 并安装 logger，之后 `log` 的各个宏就能使用了。
+/// 必须在 `console::init` 之后调用。
+pub fn init() {
+    let (global, filters) = parse_spec(cmdline::get("loglevel").unwrap_or(""));
+    FILTERS.call_once(|| filters);
+    match global {
+        Some(level) => console::set_log_level(level),
+        None => update_max_level(),
+    }
+    let _ = log::set_logger(&LOGGER);
+}
+
+/// 按全局级别和模块规则重新计算 `log` 的最大级别，让 `log` 在宏里提前丢掉
+/// 任何规则都不需要的级别，免得格式化参数。全局级别改变时由
+/// `console::set_log_level` 调用。
+pub(crate) fn update_max_level() {
+    let filters = FILTERS.r#try().map_or(&[][..], |filters| &filters[..]);
+    let max = filters
+        .iter()
+        .map(|filter| filter.level)
+        .fold(console::log_level(), LogLevel::max);
+    log::set_max_level(to_level_filter(max));
+}
+
+#[test_case]
+fn test_logger_module_filters() {
+    let (global, filters) = parse_spec("warn,net=debug,os_rust::net::e1000=error,bogus,time=loud");
+    assert_eq!(global, Some(LogLevel::Warn));
+    assert_eq!(filters.len(), 2);
+    let level = |target| level_for(&filters, target, LogLevel::Info);
+    assert_eq!(level("os_rust::net::dhcp"), LogLevel::Debug);
+    assert_eq!(level("os_rust::net::e1000"), LogLevel::Error);
+    assert_eq!(level("os_rust::net"), LogLevel::Debug);
+    // 只按整段模块名匹配，`network` 不属于 `net`
+    assert_eq!(level("os_rust::network"), LogLevel::Info);
+    assert_eq!(level("os_rust::time"), LogLevel::Info);
+}
@@ -6,26 +6,30 @@
 
 use core::panic::PanicInfo;
 
+use bootloader::{BootInfo, entry_point};
 use os_rust::println;
 
-#[unsafe(no_mangle)] // 不重整函数名
-pub extern "C" fn _start() -> ! {
+// entry_point! 检查入口函数的签名，并替我们定义 `_start`
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
     println!("Hello World{}", "!");
 
-    os_rust::init(); // new
+    os_rust::init(boot_info);
+    os_rust::pci::print_devices();
     #[cfg(test)]
     test_main();
 
     println!("It did not crash!");
-    os_rust::hlt_loop();
+    os_rust::process::start_init();
+    os_rust::net::run();
 }
 
 /// 这个函数将在 panic 时被调用
 #[cfg(not(test))] // new attribute
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    os_rust::hlt_loop();
+    os_rust::panic::handle(info, os_rust::panic::Action::Halt)
 }
 
 // our panic handler in test mode
@@ -0,0 +1,577 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB, Translate,
+        mapper::{MapToError, TranslateResult},
+    },
+};
+
+use crate::{
+    cmdline,
+    platform::{self, MemoryRegion, RegionKind},
+    rand,
+};
+
+pub const PAGE_SIZE: u64 = 4096;
+/// 命令行带 `page_poison` 时释放的内存填充的字节，与 Linux 的 PAGE_POISON
+/// 相同。
+pub const POISON_BYTE: u8 = 0xaa;
+
+// 低于 1 MiB 的内存里有 BIOS 数据区、EBDA 等遗留结构，不拿来做 DMA
+const DMA_FLOOR: u64 = 0x10_0000;
+
+// bootloader 把全部物理内存线性映射到这个偏移处
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+// 设备寄存器（MMIO）映射到从这里开始的虚拟地址，线性映射区是可缓存的，
+// 不能用来访问设备
+const MMIO_BASE: u64 = 0x5555_0000_0000;
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_BASE);
+
+// 内核栈映射到从这里开始的虚拟地址，每个栈下面留一页不映射的保护页
+const STACK_BASE: u64 = 0x5556_0000_0000;
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACK_BASE);
+
+/// 用户空间：4 级页表中一项覆盖的 512 GiB。内核自己的映射都不在这里，每个
+/// 进程有自己的一个 3 级页表，运行时挂到这一项上。
+pub const USER_BASE: u64 = 0x4000_0000_0000;
+pub const USER_END: u64 = USER_BASE + (1 << 39);
+const USER_L4_INDEX: usize = (USER_BASE >> 39) as usize;
+
+// MMIO 和栈区各占 4 GiB，随机化时起点在前一半内滑动
+const REGION_SIZE: u64 = 0x1_0000_0000;
+const MAX_SLIDE_PAGES: u64 = REGION_SIZE / 2 / PAGE_SIZE;
+// 随机化时相邻两个栈之间最多空出的页数
+const MAX_STACK_GAP_PAGES: u64 = 256;
+
+// 布局是否已经随机化
+static RANDOMIZED: AtomicBool = AtomicBool::new(false);
+// 随机化用的随机数：命令行给了 `kaslr_seed` 时由它确定，否则来自 `rand`
+static KASLR_SEED: AtomicU64 = AtomicU64::new(0);
+
+// 释放内存时是否立刻清零（`init_on_free`）、是否填充 POISON_BYTE
+// （`page_poison`），在 `init` 时从命令行读出
+static ZERO_ON_FREE: AtomicBool = AtomicBool::new(false);
+static POISON_ON_FREE: AtomicBool = AtomicBool::new(false);
+
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+
+struct FrameBump {
+    memory_map: Option<&'static [MemoryRegion]>,
+    // 下一次分配从这个物理地址开始找
+    next: u64,
+    // 释放的页组成的链表，每页开头 8 字节存下一页的物理地址，0 表示结尾
+    free: u64,
+}
+
+static FRAMES: Mutex<FrameBump> = Mutex::new(FrameBump {
+    memory_map: None,
+    next: DMA_FLOOR,
+    free: 0,
+});
+
+// 已经分配出去的物理内存字节数
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+/// 记录物理内存映射的偏移和内存布局。必须在 `platform::init` 之后、其他需要
+/// 物理地址的模块之前调用。
+pub fn init() {
+    let offset = platform::physical_memory_offset();
+    PHYS_OFFSET.store(offset, Ordering::Relaxed);
+
+    let (level_4_frame, _) = Cr3::read();
+    let virt = VirtAddr::new(offset) + level_4_frame.start_address().as_u64();
+    let level_4_table = unsafe { &mut *virt.as_mut_ptr::<PageTable>() };
+    *MAPPER.lock() = Some(unsafe { OffsetPageTable::new(level_4_table, VirtAddr::new(offset)) });
+    FRAMES.lock().memory_map = Some(platform::memory_map());
+    ZERO_ON_FREE.store(cmdline::flag("init_on_free"), Ordering::Relaxed);
+    POISON_ON_FREE.store(cmdline::flag("page_poison"), Ordering::Relaxed);
+}
+
+/// 页表或物理内存分配器的锁是否被持有。只在崩溃报告中使用，不等锁。
+pub(crate) fn is_locked() -> bool {
+    MAPPER.try_lock().is_none() || FRAMES.try_lock().is_none()
+}
+
+/// 物理地址在线性映射区中对应的虚拟地址。
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYS_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
+/// 查询页表，得到虚拟地址对应的物理地址；未映射时返回 `None`。
+pub fn virt_to_phys(addr: VirtAddr) -> Option<PhysAddr> {
+    MAPPER.lock().as_ref()?.translate_addr(addr)
+}
+
+/// `addr` 所在的页是否已经映射。不会等锁：页表正在被修改时（比如在异常或
+/// panic 中）保守地返回 `false`。
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    MAPPER.try_lock().is_some_and(|mapper| {
+        mapper
+            .as_ref()
+            .is_some_and(|mapper| mapper.translate_addr(addr).is_some())
+    })
+}
+
+/// 一段物理上连续、已清零的内存，设备可以直接对它做 DMA。
+///
+/// 不再使用时可以用 [`free_dma`] 归还。
+#[derive(Debug)]
+pub struct DmaBuffer {
+    phys: PhysAddr,
+    virt: VirtAddr,
+    size: usize,
+}
+
+impl DmaBuffer {
+    /// 交给设备的物理地址。
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// 指向偏移 `offset` 处的指针，用于以 volatile 方式读写设备共享的结构。
+    pub fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(offset + core::mem::size_of::<T>() <= self.size);
+        (self.virt + offset as u64).as_mut_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.virt.as_ptr(), self.size) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt.as_mut_ptr(), self.size) }
+    }
+
+    /// 放弃物理地址，把这段内存当作一直存在的普通内存使用（例如存放 initrd）。
+    pub fn leak(self) -> &'static mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt.as_mut_ptr(), self.size) }
+    }
+}
+
+/// 从可用内存区域中分配 `pages` 个连续的物理页作为 DMA 缓冲区。单页的
+/// 分配优先使用 [`free_dma`] 归还的页；空闲页不合并，多页的分配总是取
+/// 还没用过的内存。
+///
+/// 分配出去的内存总是清零的，上一个使用者的数据不会泄露给下一个。
+pub fn alloc_dma(pages: usize) -> Option<DmaBuffer> {
+    let size = pages as u64 * PAGE_SIZE;
+    let mut frames = FRAMES.lock();
+    let reused = pages == 1 && frames.free != 0;
+    let start = if reused {
+        let start = frames.free;
+        frames.free = unsafe { phys_to_virt(PhysAddr::new(start)).as_ptr::<u64>().read() };
+        start
+    } else {
+        let memory_map = frames.memory_map?;
+        let start = memory_map
+            .iter()
+            .filter(|region| region.kind == RegionKind::Usable)
+            .find_map(|region| {
+                let start = frames.next.max(region.start);
+                (start + size <= region.end).then_some(start)
+            })?;
+        frames.next = start + size;
+        start
+    };
+    drop(frames);
+    ALLOCATED.fetch_add(size, Ordering::Relaxed);
+
+    let phys = PhysAddr::new(start);
+    let mut buffer = DmaBuffer {
+        phys,
+        virt: phys_to_virt(phys),
+        size: size as usize,
+    };
+    // 开头 8 字节是空闲链表的指针
+    if reused && POISON_ON_FREE.load(Ordering::Relaxed) && !poisoned(&buffer.as_slice()[8..]) {
+        log::error!("memory: frame {start:#x} was written after free");
+    }
+    buffer.as_mut_slice().fill(0);
+    Some(buffer)
+}
+
+/// 归还 [`alloc_dma`] 分配的内存，设备必须已经不再访问它。
+///
+/// 内存按页放回空闲链表，以后只有单页的分配会用到：多页的缓冲区归还后
+/// 不能再作为一整块分配出去，频繁分配、释放大块内存最终会耗尽物理内存。
+///
+/// 命令行带 `init_on_free` 时立刻清零，不让数据留在空闲内存里；带
+/// `page_poison` 时填充 [`POISON_BYTE`]，再次分配时检查有没有被改写，
+/// 以发现释放后仍在使用的代码。
+pub fn free_dma(mut buffer: DmaBuffer) {
+    scrub(buffer.as_mut_slice());
+    let mut frames = FRAMES.lock();
+    for offset in (0..buffer.size as u64).step_by(PAGE_SIZE as usize) {
+        let phys = buffer.phys + offset;
+        unsafe { phys_to_virt(phys).as_mut_ptr::<u64>().write(frames.free) };
+        frames.free = phys.as_u64();
+    }
+    ALLOCATED.fetch_sub(buffer.size as u64, Ordering::Relaxed);
+}
+
+fn free_frame(frame: PhysFrame) {
+    let phys = frame.start_address();
+    free_dma(DmaBuffer {
+        phys,
+        virt: phys_to_virt(phys),
+        size: PAGE_SIZE as usize,
+    });
+}
+
+fn scrub(data: &mut [u8]) {
+    if POISON_ON_FREE.load(Ordering::Relaxed) {
+        data.fill(POISON_BYTE);
+    } else if ZERO_ON_FREE.load(Ordering::Relaxed) {
+        data.fill(0);
+    }
+}
+
+fn poisoned(data: &[u8]) -> bool {
+    data.iter().all(|&byte| byte == POISON_BYTE)
+}
+
+/// 已经分配出去、还没有归还的物理内存（DMA 缓冲区、栈和页表）的字节数。
+pub fn allocated_bytes() -> u64 {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+// 建立页表时需要的新页表页从 DMA 内存中分配，它们本来就是清零的
+struct DmaFrames;
+
+unsafe impl FrameAllocator<Size4KiB> for DmaFrames {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let buffer = alloc_dma(1)?;
+        Some(PhysFrame::containing_address(buffer.phys_addr()))
+    }
+}
+
+/// 把物理地址 `phys` 开始的 `size`
+/// 字节设备寄存器映射为不可缓存的内存，返回对应的虚拟地址。
+///
+/// 映射不会撤销，驱动应当在初始化时映射一次。
+pub fn map_mmio(phys: PhysAddr, size: u64) -> Option<VirtAddr> {
+    let start = phys.align_down(PAGE_SIZE);
+    let end = (phys + size).align_up(PAGE_SIZE);
+    let pages = (end - start) / PAGE_SIZE;
+    let virt = NEXT_MMIO.fetch_add(pages * PAGE_SIZE, Ordering::Relaxed);
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH;
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut()?;
+    for i in 0..pages {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(virt + i * PAGE_SIZE));
+        let frame = PhysFrame::containing_address(start + i * PAGE_SIZE);
+        unsafe { mapper.map_to(page, frame, flags, &mut DmaFrames) }
+            .ok()?
+            .flush();
+    }
+    Some(VirtAddr::new(virt) + (phys - start))
+}
+
+/// 在 1 MiB 以下找一个可用的物理页，并把它恒等映射（虚拟地址等于物理
+/// 地址）。AP 的启动代码从实模式开始执行，打开分页的那一刻也要在这一页上。
+///
+/// `alloc_dma` 不会分配 1 MiB 以下的内存，这一页不会被别人用到。
+pub fn low_identity_page() -> Option<PhysAddr> {
+    let memory_map = FRAMES.lock().memory_map?;
+    // 跳过第 0 页，里面是实模式的中断向量表
+    let start = memory_map
+        .iter()
+        .filter(|region| region.kind == RegionKind::Usable)
+        .find_map(|region| {
+            let start = region.start.max(PAGE_SIZE);
+            let end = region.end.min(DMA_FLOOR);
+            (start + PAGE_SIZE <= end).then_some(start)
+        })?;
+    let frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(start));
+    let page = Page::containing_address(VirtAddr::new(start));
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut()?;
+    match mapper.translate_addr(page.start_address()) {
+        Some(phys) if phys == frame.start_address() => {}
+        Some(_) => return None,
+        None => {
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+            unsafe { mapper.map_to(page, frame, flags, &mut DmaFrames) }
+                .ok()?
+                .flush();
+        }
+    }
+    Some(frame.start_address())
+}
+
+/// 打乱 MMIO 区和内核栈区的起点（KASLR-lite），让依赖固定地址的代码尽早
+/// 暴露出来。要在 `rand::init` 之后、第一次 `map_mmio` 之前调用。
+///
+/// 命令行带 `nokaslr` 时保持固定布局；带 `kaslr_seed=<数字>` 时布局由种子
+/// 决定，便于复现测试。内核堆还不存在，以后加上时也从这里取随机起点。
+pub fn randomize_layout() {
+    if cmdline::flag("nokaslr") {
+        log::info!("memory: layout randomization disabled");
+        return;
+    }
+    let seed = cmdline::get("kaslr_seed")
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(rand::next_u64);
+    KASLR_SEED.store(seed, Ordering::Relaxed);
+    RANDOMIZED.store(true, Ordering::Relaxed);
+    let mmio = MMIO_BASE + kaslr_random(MAX_SLIDE_PAGES) * PAGE_SIZE;
+    let stack = STACK_BASE + kaslr_random(MAX_SLIDE_PAGES) * PAGE_SIZE;
+    NEXT_MMIO.store(mmio, Ordering::Relaxed);
+    NEXT_STACK.store(stack, Ordering::Relaxed);
+    log::info!("memory: mmio at {mmio:#x}, stacks at {stack:#x}");
+}
+
+/// 随机化打开时返回 `0..bound` 中的一个数，否则返回 0。
+///
+/// 用 splitmix64 从种子往后推，同一个 `kaslr_seed` 总是得到同样的序列。
+fn kaslr_random(bound: u64) -> u64 {
+    if !RANDOMIZED.load(Ordering::Relaxed) {
+        return 0;
+    }
+    let seed = KASLR_SEED.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed);
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) % bound
+}
+
+/// 分配一个 `pages` 页的内核栈，映射到栈区中，返回栈顶。
+///
+/// 栈底下面的一页不映射，栈溢出时触发缺页异常而不是悄悄改写别的内存。
+/// 随机化打开时每个栈前面还会空出随机的若干页。
+pub fn alloc_stack(pages: usize) -> Option<VirtAddr> {
+    let gap = kaslr_random(MAX_STACK_GAP_PAGES);
+    let size = (gap + 1 + pages as u64) * PAGE_SIZE;
+    let bottom = NEXT_STACK.fetch_add(size, Ordering::Relaxed) + (gap + 1) * PAGE_SIZE;
+    let frames = alloc_dma(pages)?;
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut()?;
+    for i in 0..pages as u64 {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(bottom + i * PAGE_SIZE));
+        let frame = PhysFrame::containing_address(frames.phys_addr() + i * PAGE_SIZE);
+        unsafe { mapper.map_to(page, frame, flags, &mut DmaFrames) }
+            .ok()?
+            .flush();
+    }
+    Some(VirtAddr::new(bottom + pages as u64 * PAGE_SIZE))
+}
+
+/// 释放 [`alloc_stack`] 分配的栈：撤销映射，再像 [`free_dma`] 一样归还物理
+/// 页。虚拟地址不会再用，之后访问这个栈会触发缺页异常。
+///
+/// # Safety
+/// `top` 和 `pages` 必须与 `alloc_stack` 的参数和返回值一致，并且没有任何
+/// CPU 还在使用这个栈。
+pub unsafe fn free_stack(top: VirtAddr, pages: usize) {
+    let bottom = top - pages as u64 * PAGE_SIZE;
+    let mut guard = MAPPER.lock();
+    let Some(mapper) = guard.as_mut() else {
+        return;
+    };
+    let mut phys = None;
+    for i in 0..pages as u64 {
+        let page = Page::<Size4KiB>::containing_address(bottom + i * PAGE_SIZE);
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            phys.get_or_insert(frame.start_address());
+        }
+    }
+    drop(guard);
+    // 栈的物理页是一次分配的，连续
+    if let Some(phys) = phys {
+        free_dma(DmaBuffer {
+            phys,
+            virt: phys_to_virt(phys),
+            size: pages * PAGE_SIZE as usize,
+        });
+    }
+}
+
+// 当前的 4 级页表。调用者要持有 MAPPER 的锁，避免和页表的其他修改交错
+unsafe fn level_4_table() -> &'static mut PageTable {
+    let (frame, _) = Cr3::read();
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr() }
+}
+
+/// 换上另一个用户空间，返回原来的。用户空间用它的 3 级页表表示，`None`
+/// 表示没有用户空间。
+///
+/// # Safety
+/// 之后不能再有代码使用原来用户空间中的地址。
+pub unsafe fn switch_user_space(space: Option<PhysFrame>) -> Option<PhysFrame> {
+    let _mapper = MAPPER.lock();
+    let entry = unsafe { &mut level_4_table()[USER_L4_INDEX] };
+    let old = entry.frame().ok();
+    match space {
+        Some(frame) => entry.set_frame(
+            frame,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+        ),
+        None => entry.set_unused(),
+    }
+    tlb::flush_all();
+    old
+}
+
+/// 释放当前用户空间中的全部页和页表，然后把它从 4 级页表上摘掉。
+///
+/// # Safety
+/// 之后不能再有代码使用用户空间中的地址。
+pub unsafe fn free_user_space() {
+    let Some(space) = (unsafe { switch_user_space(None) }) else {
+        return;
+    };
+    free_table(space, 3);
+}
+
+// 释放 `level` 级页表和它下面的全部页；用户空间只有 4 KiB 的页
+fn free_table(table: PhysFrame, level: u8) {
+    let entries = unsafe { &*phys_to_virt(table.start_address()).as_ptr::<PageTable>() };
+    for frame in entries.iter().filter_map(|entry| entry.frame().ok()) {
+        if level > 1 {
+            free_table(frame, level - 1);
+        } else {
+            free_frame(frame);
+        }
+    }
+    free_frame(table);
+}
+
+/// 为用户空间中的 `page` 分配一个清零的物理页并映射，用户态可以访问，
+/// 返回这个物理页。`flags` 只需给出 `WRITABLE`、`NO_EXECUTE` 等权限。
+pub fn map_user_page(page: Page, flags: PageTableFlags) -> Result<PhysFrame, MapToError<Size4KiB>> {
+    assert!((USER_BASE..USER_END).contains(&page.start_address().as_u64()));
+    let buffer = alloc_dma(1).ok_or(MapToError::FrameAllocationFailed)?;
+    let frame = PhysFrame::containing_address(buffer.phys_addr());
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    // 上级页表项要放开所有权限，每一页的权限由最后一级决定
+    let table_flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    match unsafe { mapper.map_to_with_table_flags(page, frame, flags, table_flags, &mut DmaFrames) }
+    {
+        Ok(flush) => {
+            flush.flush();
+            Ok(frame)
+        }
+        Err(err) => {
+            free_dma(buffer);
+            Err(err)
+        }
+    }
+}
+
+/// `[start, start + len)` 是否整个落在用户空间中并且用户态可以访问，用于
+/// 检查系统调用传进来的指针。
+pub fn is_user_range(start: u64, len: u64) -> bool {
+    user_range_has(start, len, PageTableFlags::USER_ACCESSIBLE)
+}
+
+/// 与 [`is_user_range`] 相同，另外要求整段可写，用于检查系统调用要写入的
+/// 缓冲区。
+pub fn is_user_writable(start: u64, len: u64) -> bool {
+    user_range_has(
+        start,
+        len,
+        PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE,
+    )
+}
+
+fn user_range_has(start: u64, len: u64, required: PageTableFlags) -> bool {
+    let Some(end) = start.checked_add(len) else {
+        return false;
+    };
+    if start < USER_BASE || end > USER_END {
+        return false;
+    }
+    let mapper = MAPPER.lock();
+    let Some(mapper) = mapper.as_ref() else {
+        return false;
+    };
+    (start & !(PAGE_SIZE - 1)..end)
+        .step_by(PAGE_SIZE as usize)
+        .all(|addr| {
+            matches!(
+                mapper.translate(VirtAddr::new(addr)),
+                TranslateResult::Mapped { flags, .. } if flags.contains(required)
+            )
+        })
+}
+
+#[test_case]
+fn test_memory_dma_translation() {
+    let buffer = alloc_dma(2).unwrap();
+    assert!(buffer.phys_addr().is_aligned(PAGE_SIZE));
+    assert!(buffer.as_slice().iter().all(|&byte| byte == 0));
+    let virt = VirtAddr::from_ptr(buffer.ptr::<u8>(PAGE_SIZE as usize + 8));
+    assert_eq!(virt_to_phys(virt), Some(buffer.phys_addr() + PAGE_SIZE + 8));
+    let other = alloc_dma(1).unwrap();
+    assert!(other.phys_addr() >= buffer.phys_addr() + buffer.size() as u64);
+}
+
+#[test_case]
+fn test_memory_map_mmio() {
+    // 用一页普通内存代替设备寄存器，两个映射应当看到同样的内容
+    let buffer = alloc_dma(1).unwrap();
+    let mmio = map_mmio(buffer.phys_addr() + 16, 8).unwrap();
+    assert_eq!(mmio.as_u64() % PAGE_SIZE, 16);
+    assert_eq!(virt_to_phys(mmio), Some(buffer.phys_addr() + 16));
+    unsafe { core::ptr::write_volatile(mmio.as_mut_ptr::<u64>(), 0x1234_5678) };
+    assert_eq!(
+        unsafe { core::ptr::read_volatile(buffer.ptr::<u64>(16)) },
+        0x1234_5678
+    );
+}
+
+#[test_case]
+fn test_memory_alloc_stack() {
+    let top = alloc_stack(2).unwrap();
+    assert!(top.as_u64() > STACK_BASE && top.as_u64() < STACK_BASE + REGION_SIZE);
+    let bottom = top - 2 * PAGE_SIZE;
+    assert!(is_mapped(bottom));
+    assert!(is_mapped(top - 1u64));
+    // 保护页
+    assert!(!is_mapped(bottom - 1u64));
+    unsafe { (top - 8u64).as_mut_ptr::<u64>().write(42) };
+}
+
+#[test_case]
+fn test_memory_free() {
+    let mut buffer = alloc_dma(1).unwrap();
+    let phys = buffer.phys_addr();
+    buffer.as_mut_slice().fill(0x5a);
+    let allocated = allocated_bytes();
+    free_dma(buffer);
+    assert_eq!(allocated_bytes(), allocated - PAGE_SIZE);
+    // 归还的页最先被再次分配，而且已经清零
+    let buffer = alloc_dma(1).unwrap();
+    assert_eq!(buffer.phys_addr(), phys);
+    assert!(buffer.as_slice().iter().all(|&byte| byte == 0));
+
+    let top = alloc_stack(1).unwrap();
+    unsafe { free_stack(top, 1) };
+    assert!(!is_mapped(top - 8u64));
+
+    let mut page = [POISON_BYTE; 16];
+    assert!(poisoned(&page));
+    page[3] = 0;
+    assert!(!poisoned(&page));
+}
@@ -0,0 +1,151 @@
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::i8042;
+
+const MOUSE_IRQ: u8 = 12;
+const ACK: u8 = 0xfa;
+
+/// 事件队列的容量，满了之后丢弃新事件。
+pub const QUEUE_LEN: usize = 64;
+
+/// 一次移动或按键变化。`dy` 向上为正（PS/2 的约定，与屏幕坐标相反）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// 把鼠标发来的字节流拼成 3 字节的标准数据包。
+struct Decoder {
+    packet: [u8; 3],
+    len: usize,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Decoder {
+            packet: [0; 3],
+            len: 0,
+        }
+    }
+
+    fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // 第一个字节的位 3 恒为 1，用它在丢字节后重新对齐
+        if self.len == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < 3 {
+            return None;
+        }
+        self.len = 0;
+        let [flags, x, y] = self.packet;
+        // 位 6、7 表示溢出，此时位移没有意义
+        if flags & 0xc0 != 0 {
+            return None;
+        }
+        // 位移是 9 位补码，符号位在第一个字节的位 4、5
+        let sign = |bit: u8| if flags & bit != 0 { -256 } else { 0 };
+        Some(MouseEvent {
+            dx: x as i16 + sign(0x10),
+            dy: y as i16 + sign(0x20),
+            left: flags & 0x01 != 0,
+            right: flags & 0x02 != 0,
+            middle: flags & 0x04 != 0,
+        })
+    }
+}
+
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+static EVENTS: Mutex<heapless::Deque<MouseEvent, QUEUE_LEN>> = Mutex::new(heapless::Deque::new());
+
+/// 经控制器转发给鼠标一个命令并等待应答。
+fn mouse_command(command: u8) -> bool {
+    i8042::write_second(command) && i8042::read_data() == Some(ACK)
+}
+
+/// IRQ 12 的处理函数：读出一个字节，凑满一个数据包后放入事件队列。
+fn handle_irq() {
+    let ready = i8042::STATUS_OUTPUT_FULL | i8042::STATUS_AUX_DATA;
+    if i8042::read_status() & ready != ready {
+        return;
+    }
+    let Some(byte) = i8042::read_data() else {
+        return;
+    };
+    if let Some(event) = DECODER.lock().add_byte(byte) {
+        let _ = EVENTS.lock().push_back(event);
+    }
+}
+
+/// 让鼠标开始报告数据并注册 IRQ 12。必须在 `i8042::init` 之后、开中断之前调用；
+/// 控制器没有第二个端口或鼠标没有应答时返回 `false`。
+pub fn init() -> bool {
+    if !i8042::second_port() {
+        return false;
+    }
+    // 恢复默认设置（采样率、分辨率），然后开始报告
+    if !mouse_command(0xf6) || !mouse_command(0xf4) {
+        return false;
+    }
+    crate::interrupts::register_irq_handler(MOUSE_IRQ, handle_irq)
+}
+
+/// 取出下一个鼠标事件，没有时立即返回 `None`。
+pub fn read_event() -> Option<MouseEvent> {
+    interrupts::without_interrupts(|| EVENTS.lock().pop_front())
+}
+
+/// 等待下一个鼠标事件。
+pub fn wait_event() -> MouseEvent {
+    loop {
+        // 关中断检查队列后再 `sti; hlt`，避免在两者之间到来的中断被错过
+        interrupts::disable();
+        if let Some(event) = EVENTS.lock().pop_front() {
+            interrupts::enable();
+            return event;
+        }
+        interrupts::enable_and_hlt();
+    }
+}
+
+#[test_case]
+fn test_mouse_packet_decoding() {
+    let mut decoder = Decoder::new();
+    // 左键按下，向右 5、向下 3（-3 的 9 位补码）
+    assert_eq!(decoder.add_byte(0x08 | 0x01 | 0x20), None);
+    assert_eq!(decoder.add_byte(5), None);
+    assert_eq!(
+        decoder.add_byte(0xfd),
+        Some(MouseEvent {
+            dx: 5,
+            dy: -3,
+            left: true,
+            right: false,
+            middle: false,
+        })
+    );
+    // 位 3 为 0 的字节不能作为包头，被丢弃
+    assert_eq!(decoder.add_byte(0x00), None);
+    assert_eq!(decoder.add_byte(0x08 | 0x10 | 0x02), None);
+    assert_eq!(decoder.add_byte(0xff), None);
+    assert_eq!(
+        decoder.add_byte(1),
+        Some(MouseEvent {
+            dx: -1,
+            dy: 1,
+            left: false,
+            right: true,
+            middle: false,
+        })
+    );
+    // 溢出的包被丢弃
+    for byte in [0x08 | 0x40, 0x10, 0x10] {
+        assert_eq!(decoder.add_byte(byte), None);
+    }
+}
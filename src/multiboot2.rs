@@ -0,0 +1,337 @@
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::{
+    framebuffer::{FrameBufferInfo, PixelFormat},
+    platform::{self, Display, Firmware, MemoryRegion, Module, Platform, RegionKind},
+};
+
+/// 符合 Multiboot2 的引导程序跳到内核时放在 EAX 中的值。
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+// 标签类型
+const TAG_END: u32 = 0;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_EFI64: u32 = 12;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+
+// 内存布局中的区域类型
+const MEMORY_AVAILABLE: u32 = 1;
+const MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+
+// 帧缓冲区类型：直接 RGB 颜色，或者 EGA 文本模式
+const FRAMEBUFFER_RGB: u8 = 1;
+const FRAMEBUFFER_TEXT: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiboot2Error {
+    /// EAX 中不是 Multiboot2 的魔数
+    BadMagic(u32),
+    /// 信息结构或者某个标签的长度不对
+    Truncated,
+    /// 没有内存布局标签
+    NoMemoryMap,
+    /// 模块或内存区域比 `platform` 能记录的多
+    TooMany,
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, Multiboot2Error> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(Multiboot2Error::Truncated)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64, Multiboot2Error> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(Multiboot2Error::Truncated)
+}
+
+/// 信息结构中的一个标签：类型、在结构中的偏移和内容（不含 8 字节的头）。
+struct Tag<'a> {
+    kind: u32,
+    offset: usize,
+    body: &'a [u8],
+}
+
+/// 依次取出标签，每个标签按 8 字节对齐，遇到结束标签为止。
+fn tags(data: &[u8]) -> impl Iterator<Item = Result<Tag<'_>, Multiboot2Error>> {
+    // 信息结构以总长度和一个保留字段开头
+    let mut offset = 8;
+    let mut done = false;
+    core::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let tag = (|| -> Result<_, Multiboot2Error> {
+            let kind = u32_at(data, offset)?;
+            let size = u32_at(data, offset + 4)? as usize;
+            let body = data
+                .get(offset + 8..offset + size.max(8))
+                .ok_or(Multiboot2Error::Truncated)?;
+            Ok((
+                Tag { kind, offset, body },
+                offset + size.max(8).next_multiple_of(8),
+            ))
+        })();
+        match tag {
+            Ok((tag, next)) => {
+                done = tag.kind == TAG_END;
+                offset = next;
+                (!done).then_some(Ok(tag))
+            }
+            Err(err) => {
+                done = true;
+                Some(Err(err))
+            }
+        }
+    })
+}
+
+/// 把物理地址为 `phys` 的信息结构 `data` 转换成平台信息。
+///
+/// 全部物理内存要已经映射到 `physical_memory_offset` 处。Multiboot2 的内存
+/// 布局不会标出内核和模块占用的内存，所以把 `kernel`（物理地址范围）、
+/// 模块和信息结构本身从可用内存中扣掉。ACPI 标签里是 RSDP 的副本，
+/// 这里记录副本的物理地址。
+pub fn parse(
+    data: &'static [u8],
+    phys: PhysAddr,
+    physical_memory_offset: u64,
+    kernel: (u64, u64),
+) -> Result<Platform, Multiboot2Error> {
+    let total = u32_at(data, 0)? as usize;
+    let data = data.get(..total).ok_or(Multiboot2Error::Truncated)?;
+    let mut platform = Platform {
+        firmware: Firmware::Bios,
+        physical_memory_offset,
+        display: Display::VgaText,
+        rsdp: None,
+        memory_map: heapless::Vec::new(),
+        modules: heapless::Vec::new(),
+    };
+    let mut has_memory_map = false;
+
+    for tag in tags(data) {
+        let tag = tag?;
+        let body = tag.body;
+        match tag.kind {
+            TAG_MODULE => {
+                let start = u32_at(body, 0)? as u64;
+                let end = u32_at(body, 4)? as u64;
+                // 以 NUL 结尾的命令行
+                let cmdline = body.get(8..).unwrap_or(&[]);
+                let len = cmdline
+                    .iter()
+                    .position(|&b| b == 0)
+                    .unwrap_or(cmdline.len());
+                let cmdline = core::str::from_utf8(&cmdline[..len]).unwrap_or("");
+                platform
+                    .modules
+                    .push(Module {
+                        start,
+                        end,
+                        cmdline,
+                    })
+                    .map_err(|_| Multiboot2Error::TooMany)?;
+            }
+            TAG_MEMORY_MAP => {
+                has_memory_map = true;
+                let entry_size = u32_at(body, 0)? as usize;
+                if entry_size < 24 {
+                    return Err(Multiboot2Error::Truncated);
+                }
+                let entries = body.get(8..).ok_or(Multiboot2Error::Truncated)?;
+                for entry in entries.chunks_exact(entry_size) {
+                    let start = u64_at(entry, 0)?;
+                    let end = start
+                        .checked_add(u64_at(entry, 8)?)
+                        .ok_or(Multiboot2Error::Truncated)?;
+                    let kind = match u32_at(entry, 16)? {
+                        MEMORY_AVAILABLE => RegionKind::Usable,
+                        MEMORY_ACPI_RECLAIMABLE => RegionKind::AcpiReclaimable,
+                        _ => RegionKind::Reserved,
+                    };
+                    platform
+                        .memory_map
+                        .push(MemoryRegion { start, end, kind })
+                        .map_err(|_| Multiboot2Error::TooMany)?;
+                }
+            }
+            TAG_FRAMEBUFFER => {
+                if let Some(display) = parse_framebuffer(body, physical_memory_offset)? {
+                    platform.display = display;
+                }
+            }
+            TAG_EFI64 => platform.firmware = Firmware::Uefi,
+            // 新的（ACPI 2.0）RSDP 优先
+            TAG_ACPI_OLD | TAG_ACPI_NEW if platform.rsdp.is_none() || tag.kind == TAG_ACPI_NEW => {
+                platform.rsdp = Some(phys + (tag.offset + 8) as u64);
+            }
+            _ => {}
+        }
+    }
+    if !has_memory_map {
+        return Err(Multiboot2Error::NoMemoryMap);
+    }
+
+    let mut used = heapless::Vec::<(u64, u64), { platform::MAX_MODULES + 2 }>::new();
+    let _ = used.push(kernel);
+    let _ = used.push((phys.as_u64(), phys.as_u64() + total as u64));
+    for module in &platform.modules {
+        let _ = used.push((module.start, module.end));
+    }
+    for (start, end) in used {
+        if !platform::reserve(&mut platform.memory_map, start, end) {
+            return Err(Multiboot2Error::TooMany);
+        }
+    }
+    Ok(platform)
+}
+
+/// 解析帧缓冲区标签。不认识的颜色类型（比如调色板模式）返回 `None`，
+/// 继续使用默认的显示方式。
+fn parse_framebuffer(
+    body: &[u8],
+    physical_memory_offset: u64,
+) -> Result<Option<Display>, Multiboot2Error> {
+    let address = u64_at(body, 0)?;
+    let pitch = u32_at(body, 8)? as usize;
+    let width = u32_at(body, 12)? as usize;
+    let height = u32_at(body, 16)? as usize;
+    let bits_per_pixel = *body.get(20).ok_or(Multiboot2Error::Truncated)?;
+    let kind = *body.get(21).ok_or(Multiboot2Error::Truncated)?;
+    if kind == FRAMEBUFFER_TEXT {
+        return Ok(Some(Display::VgaText));
+    }
+    let bytes_per_pixel = bits_per_pixel as usize / 8;
+    if kind != FRAMEBUFFER_RGB || !matches!(bytes_per_pixel, 3 | 4) {
+        return Ok(None);
+    }
+    // 颜色信息依次是红、绿、蓝的位置和位数；红色在最低位时内存中先红后蓝
+    let red_position = *body.get(24).ok_or(Multiboot2Error::Truncated)?;
+    let format = match red_position {
+        0 => PixelFormat::Rgb,
+        16 => PixelFormat::Bgr,
+        _ => return Ok(None),
+    };
+    let base = physical_memory_offset
+        .checked_add(address)
+        .and_then(|base| VirtAddr::try_new(base).ok())
+        .ok_or(Multiboot2Error::Truncated)?;
+    Ok(Some(Display::FrameBuffer(FrameBufferInfo {
+        base,
+        width,
+        height,
+        stride: pitch / bytes_per_pixel,
+        bytes_per_pixel,
+        format,
+    })))
+}
+
+#[test_case]
+fn test_multiboot2_parse() {
+    // 信息结构：模块、内存布局、帧缓冲区、新 ACPI 标签和结束标签
+    static mut INFO: [u8; 200] = [0; 200];
+    let info = &raw mut INFO;
+    let info: &'static mut [u8] = unsafe { &mut *info };
+    let mut offset = 8;
+    let mut tag = |kind: u32, body: &[u8]| {
+        let start = offset;
+        info[offset..offset + 4].copy_from_slice(&kind.to_le_bytes());
+        info[offset + 4..offset + 8].copy_from_slice(&(8 + body.len() as u32).to_le_bytes());
+        info[offset + 8..offset + 8 + body.len()].copy_from_slice(body);
+        offset += (8 + body.len()).next_multiple_of(8);
+        start
+    };
+    let mut module = [0u8; 16];
+    module[..4].copy_from_slice(&0x20_0000u32.to_le_bytes());
+    module[4..8].copy_from_slice(&0x20_1000u32.to_le_bytes());
+    module[8..12].copy_from_slice(b"init");
+    tag(TAG_MODULE, &module);
+    let mut map = [0u8; 8 + 2 * 24];
+    map[..4].copy_from_slice(&24u32.to_le_bytes());
+    for (i, (start, len, kind)) in [(0u64, 0x9_f000u64, 1u32), (0x10_0000, 0x70_0000, 1)]
+        .into_iter()
+        .enumerate()
+    {
+        let entry = &mut map[8 + i * 24..][..24];
+        entry[..8].copy_from_slice(&start.to_le_bytes());
+        entry[8..16].copy_from_slice(&len.to_le_bytes());
+        entry[16..20].copy_from_slice(&kind.to_le_bytes());
+    }
+    tag(TAG_MEMORY_MAP, &map);
+    let mut framebuffer = [0u8; 30];
+    framebuffer[..8].copy_from_slice(&0xfd00_0000u64.to_le_bytes());
+    framebuffer[8..12].copy_from_slice(&4096u32.to_le_bytes());
+    framebuffer[12..16].copy_from_slice(&1024u32.to_le_bytes());
+    framebuffer[16..20].copy_from_slice(&768u32.to_le_bytes());
+    framebuffer[20] = 32;
+    framebuffer[21] = FRAMEBUFFER_RGB;
+    framebuffer[24] = 16;
+    tag(TAG_FRAMEBUFFER, &framebuffer);
+    let acpi = tag(TAG_ACPI_NEW, b"RSD PTR ");
+    tag(TAG_END, &[]);
+    let total = offset as u32;
+    info[..4].copy_from_slice(&total.to_le_bytes());
+
+    let phys = PhysAddr::new(0x9_0000);
+    let info: &'static [u8] = info;
+    let platform = parse(info, phys, 0, (0x10_0000, 0x18_0000)).unwrap();
+    assert_eq!(platform.modules.len(), 1);
+    assert_eq!(platform.modules[0].cmdline, "init");
+    assert_eq!(platform.rsdp, Some(phys + (acpi + 8) as u64));
+    let Display::FrameBuffer(fb) = platform.display else {
+        panic!("no framebuffer");
+    };
+    assert_eq!((fb.width, fb.height, fb.stride), (1024, 768, 1024));
+    assert_eq!(fb.format, PixelFormat::Bgr);
+    // 内核、模块和信息结构都不在可用内存中
+    for addr in [0x10_0000, 0x17_f000, 0x20_0000, 0x9_0000] {
+        assert!(
+            !platform
+                .memory_map
+                .iter()
+                .any(|r| r.kind == RegionKind::Usable && (r.start..r.end).contains(&addr))
+        );
+    }
+    assert!(
+        platform
+            .memory_map
+            .iter()
+            .any(|r| r.kind == RegionKind::Usable && (r.start..r.end).contains(&0x18_0000))
+    );
+
+    // 内存布局标签没有内容
+    static BAD: [u8; 24] = [
+        24, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0,
+    ];
+    assert_eq!(
+        parse(&BAD, PhysAddr::new(0), 0, (0, 0)).map(|_| ()),
+        Err(Multiboot2Error::Truncated)
+    );
+
+    // 区域的结束地址超出 64 位
+    static OVERFLOW: [u8; 56] = {
+        let mut info = [0; 56];
+        info[0] = 56;
+        info[8] = TAG_MEMORY_MAP as u8;
+        info[12] = 40;
+        info[16] = 24;
+        let mut i = 24;
+        while i < 40 {
+            info[i] = 0xff;
+            i += 1;
+        }
+        info[40] = 1;
+        // 结束标签
+        info[52] = 8;
+        info
+    };
+    assert_eq!(
+        parse(&OVERFLOW, PhysAddr::new(0), 0, (0, 0)).map(|_| ()),
+        Err(Multiboot2Error::Truncated)
+    );
+}
@@ -0,0 +1,425 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet, SocketStorage},
+    phy::{self, Device, DeviceCapabilities, Medium},
+    socket::AnySocket,
+    time::Instant,
+    wire::{EthernetAddress, HardwareAddress},
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{memory, rand, time};
+
+pub mod dhcp;
+pub mod e1000;
+pub mod ping;
+
+/// 以太网帧的最大长度（不含 FCS）。
+pub const MAX_FRAME: usize = 1514;
+
+/// 接收队列能缓存的帧数，满了之后新到的帧被丢弃。
+pub const RX_QUEUE_LEN: usize = 16;
+
+/// 协议栈最多同时存在的 socket 数。
+pub const MAX_SOCKETS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// 没有网卡接入协议栈
+    NoDevice,
+    /// 已经有网卡接入了（目前只支持一个接口）
+    AlreadyAttached,
+    /// 网卡的发送队列已满
+    Busy,
+    /// 帧超过 `MAX_FRAME`
+    TooLarge,
+    /// socket 表已满
+    TooManySockets,
+}
+
+/// 网卡驱动实现的接口，协议栈通过它收发以太网帧。
+pub trait NetDevice: Send {
+    fn mac_address(&self) -> [u8; 6];
+
+    /// 取出一个收到的帧复制到 `buf`，返回帧长；没有帧时返回 `None`。
+    fn receive(&mut self, buf: &mut [u8]) -> Option<usize>;
+
+    /// 把一个帧放入发送队列。
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), NetError>;
+
+    /// 发送队列是否还有空位。
+    fn can_transmit(&self) -> bool {
+        true
+    }
+}
+
+/// 接入协议栈的网卡。驱动自己拥有设备（通常是一个 static）。
+pub type SharedNetDevice = &'static Mutex<dyn NetDevice>;
+
+struct Frame {
+    len: usize,
+    data: [u8; MAX_FRAME],
+}
+
+/// 由中断处理函数填充、协议栈轮询时取出的帧队列。
+///
+/// 中断处理函数不能去拿设备的锁（轮询方可能正持有它），驱动在中断里把帧
+/// 放进这里，`NetDevice::receive` 再从这里取。
+pub struct FrameQueue {
+    frames: Mutex<heapless::Deque<Frame, RX_QUEUE_LEN>>,
+    dropped: AtomicU64,
+}
+
+impl FrameQueue {
+    pub const fn new() -> Self {
+        FrameQueue {
+            frames: Mutex::new(heapless::Deque::new()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// 在中断处理函数中调用。队列满或帧过长时丢弃并返回 `false`。
+    pub fn push(&self, frame: &[u8]) -> bool {
+        let mut frames = self.frames.lock();
+        if frame.len() > MAX_FRAME || frames.is_full() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let mut data = [0; MAX_FRAME];
+        data[..frame.len()].copy_from_slice(frame);
+        let _ = frames.push_back(Frame {
+            len: frame.len(),
+            data,
+        });
+        true
+    }
+
+    /// 取出最早的帧复制到 `buf`，返回帧长（`buf` 不够长时截断）。
+    pub fn pop(&self, buf: &mut [u8]) -> Option<usize> {
+        let frame = interrupts::without_interrupts(|| self.frames.lock().pop_front())?;
+        let len = frame.len.min(buf.len());
+        buf[..len].copy_from_slice(&frame.data[..len]);
+        Some(len)
+    }
+
+    /// 因为队列满或帧过长而丢弃的帧数。
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for FrameQueue {
+    fn default() -> Self {
+        FrameQueue::new()
+    }
+}
+
+/// 把 `NetDevice` 适配成 smoltcp 的 `Device`，收发都经过这里的缓冲区。
+struct Adapter<'a> {
+    device: &'a mut dyn NetDevice,
+    rx: [u8; MAX_FRAME],
+    tx: [u8; MAX_FRAME],
+}
+
+impl<'a> Adapter<'a> {
+    fn new(device: &'a mut dyn NetDevice) -> Self {
+        Adapter {
+            device,
+            rx: [0; MAX_FRAME],
+            tx: [0; MAX_FRAME],
+        }
+    }
+}
+
+struct RxToken<'a> {
+    frame: &'a mut [u8],
+}
+
+struct TxToken<'a> {
+    device: &'a mut dyn NetDevice,
+    buf: &'a mut [u8; MAX_FRAME],
+}
+
+impl phy::RxToken for RxToken<'_> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(self.frame)
+    }
+}
+
+impl phy::TxToken for TxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let TxToken { device, buf } = self;
+        let frame = &mut buf[..len];
+        let result = f(frame);
+        // 发送失败相当于帧在线路上丢失，由上层协议负责重传
+        let _ = device.transmit(frame);
+        result
+    }
+}
+
+impl Device for Adapter<'_> {
+    type RxToken<'b>
+        = RxToken<'b>
+    where
+        Self: 'b;
+    type TxToken<'b>
+        = TxToken<'b>
+    where
+        Self: 'b;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(RxToken<'_>, TxToken<'_>)> {
+        let len = self.device.receive(&mut self.rx)?;
+        Some((
+            RxToken {
+                frame: &mut self.rx[..len],
+            },
+            TxToken {
+                device: &mut *self.device,
+                buf: &mut self.tx,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<TxToken<'_>> {
+        self.device.can_transmit().then_some(TxToken {
+            device: &mut *self.device,
+            buf: &mut self.tx,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = MAX_FRAME;
+        caps
+    }
+}
+
+fn now() -> Instant {
+    Instant::from_millis(time::uptime_ms() as i64)
+}
+
+/// 一个接口和它上面的 socket。
+struct Stack {
+    iface: Interface,
+    sockets: SocketSet<'static>,
+}
+
+impl Stack {
+    fn new(device: &mut dyn NetDevice, storage: &'static mut [SocketStorage<'static>]) -> Self {
+        let mac = EthernetAddress(device.mac_address());
+        let mut config = Config::new(HardwareAddress::Ethernet(mac));
+        // 用于 TCP 初始序号、DHCP 事务号等，时间戳计数器足够分散
+        config.random_seed = rand::next_u64();
+        let iface = Interface::new(config, &mut Adapter::new(device), now());
+        Stack {
+            iface,
+            sockets: SocketSet::new(storage),
+        }
+    }
+
+    /// 处理收到的帧和各 socket 待发送的数据，返回 socket 状态是否可能有变化。
+    fn poll(&mut self, device: &mut dyn NetDevice, now: Instant) -> bool {
+        self.iface
+            .poll(now, &mut Adapter::new(device), &mut self.sockets)
+    }
+}
+
+struct Net {
+    device: SharedNetDevice,
+    stack: Stack,
+}
+
+static NET: Mutex<Option<Net>> = Mutex::new(None);
+
+static mut SOCKET_STORAGE: [SocketStorage<'static>; MAX_SOCKETS] =
+    [const { SocketStorage::EMPTY }; MAX_SOCKETS];
+
+/// 把网卡接入协议栈，由网卡驱动在初始化时调用。
+pub fn attach(device: SharedNetDevice) -> Result<(), NetError> {
+    let mut net = NET.lock();
+    if net.is_some() {
+        return Err(NetError::AlreadyAttached);
+    }
+    // 只有第一次接入会走到这里，socket 表不会被借用两次
+    let storage = &raw mut SOCKET_STORAGE;
+    let storage = unsafe { &mut *storage };
+    let stack = Stack::new(&mut *device.lock(), storage);
+    *net = Some(Net { device, stack });
+    Ok(())
+}
+
+/// 接入的网卡的 MAC 地址。
+pub fn mac_address() -> Option<[u8; 6]> {
+    let device = NET.lock().as_ref()?.device;
+    Some(device.lock().mac_address())
+}
+
+/// 驱动协议栈处理一轮：收帧、应答 ARP/ICMP、发送各 socket 的数据、处理超时。
+/// 阻塞等待网络的调用者应当在循环中调用它。
+pub fn poll() -> bool {
+    let mut net = NET.lock();
+    let Some(net) = net.as_mut() else {
+        return false;
+    };
+    let mut device = net.device.lock();
+    net.stack.poll(&mut *device, now())
+}
+
+/// 内核的主循环：不断轮询协议栈和 DHCP 客户端并刷新状态栏。协议栈这一轮
+/// 有进展时马上再来一轮，空闲时才 `hlt` 等待下一个中断（网卡中断或每个
+/// tick 的时钟中断）。
+///
+/// 停下之前关着中断再轮询一次，仍然没有进展才用 `sti; hlt` 原子地停下，
+/// 免得在两次轮询之间到达的帧要等到下一个 tick 才处理。
+pub fn run() -> ! {
+    loop {
+        let busy = poll();
+        dhcp::poll();
+        crate::status_bar::poll();
+        if busy {
+            continue;
+        }
+        interrupts::disable();
+        if poll() {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+/// 在接口上执行 `f`，用于配置 IP 地址、路由等。
+pub fn with_interface<R>(f: impl FnOnce(&mut Interface) -> R) -> Result<R, NetError> {
+    let mut net = NET.lock();
+    let net = net.as_mut().ok_or(NetError::NoDevice)?;
+    Ok(f(&mut net.stack.iface))
+}
+
+/// 把 socket 加入协议栈。socket 的缓冲区必须是 `'static` 的，可以用
+/// `alloc_buffer` 分配。
+pub fn add_socket<T: AnySocket<'static>>(socket: T) -> Result<SocketHandle, NetError> {
+    let mut net = NET.lock();
+    let net = net.as_mut().ok_or(NetError::NoDevice)?;
+    // 表满时 `SocketSet::add` 会 panic
+    if net.stack.sockets.iter().count() >= MAX_SOCKETS {
+        return Err(NetError::TooManySockets);
+    }
+    Ok(net.stack.sockets.add(socket))
+}
+
+/// 在 `handle` 对应的 socket 上执行 `f`。类型与加入时不一致会 panic。
+pub fn with_socket<T: AnySocket<'static>, R>(
+    handle: SocketHandle,
+    f: impl FnOnce(&mut T) -> R,
+) -> Result<R, NetError> {
+    let mut net = NET.lock();
+    let net = net.as_mut().ok_or(NetError::NoDevice)?;
+    Ok(f(net.stack.sockets.get_mut::<T>(handle)))
+}
+
+/// 从协议栈中移除 socket。它的缓冲区不会被回收。
+pub fn remove_socket(handle: SocketHandle) {
+    if let Some(net) = NET.lock().as_mut() {
+        net.stack.sockets.remove(handle);
+    }
+}
+
+/// 分配 `size` 字节（按页向上取整）的 socket 缓冲区。分配出去的内存不会回收。
+pub fn alloc_buffer(size: usize) -> Option<&'static mut [u8]> {
+    let pages = size.div_ceil(memory::PAGE_SIZE as usize);
+    let buffer = memory::alloc_dma(pages)?.leak();
+    Some(&mut buffer[..size])
+}
+
+#[cfg(test)]
+struct TestDevice {
+    rx: Option<heapless::Vec<u8, 128>>,
+    sent: heapless::Vec<u8, MAX_FRAME>,
+}
+
+/// 10.0.2.2 询问 10.0.2.15 的 MAC 地址的 ARP 请求帧。
+#[cfg(test)]
+fn test_arp_request() -> [u8; 42] {
+    let mut request = [0u8; 42];
+    request[..6].fill(0xff);
+    request[6..12].copy_from_slice(&[0x52, 0x55, 10, 0, 2, 2]);
+    request[12..14].copy_from_slice(&[0x08, 0x06]);
+    request[14..22].copy_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 1]);
+    request[22..28].copy_from_slice(&[0x52, 0x55, 10, 0, 2, 2]);
+    request[28..32].copy_from_slice(&[10, 0, 2, 2]);
+    request[38..42].copy_from_slice(&[10, 0, 2, 15]);
+    request
+}
+
+#[cfg(test)]
+impl NetDevice for TestDevice {
+    fn mac_address(&self) -> [u8; 6] {
+        [0x52, 0x54, 0, 0x12, 0x34, 0x56]
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let frame = self.rx.take()?;
+        buf[..frame.len()].copy_from_slice(&frame);
+        Some(frame.len())
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), NetError> {
+        self.sent.clear();
+        self.sent
+            .extend_from_slice(frame)
+            .map_err(|_| NetError::TooLarge)
+    }
+}
+
+#[test_case]
+fn test_net_frame_queue() {
+    static QUEUE: FrameQueue = FrameQueue::new();
+    assert!(QUEUE.push(b"first"));
+    assert!(QUEUE.push(b"second"));
+    assert!(!QUEUE.push(&[0; MAX_FRAME + 1]));
+    let mut buf = [0; MAX_FRAME];
+    assert_eq!(QUEUE.pop(&mut buf), Some(5));
+    assert_eq!(&buf[..5], b"first");
+    assert_eq!(QUEUE.pop(&mut buf), Some(6));
+    assert_eq!(QUEUE.pop(&mut buf), None);
+    for _ in 0..RX_QUEUE_LEN {
+        assert!(QUEUE.push(b"x"));
+    }
+    assert!(!QUEUE.push(b"x"));
+    assert_eq!(QUEUE.dropped(), 2);
+}
+
+#[test_case]
+fn test_net_answers_arp() {
+    use smoltcp::wire::{IpAddress, IpCidr};
+
+    let request = test_arp_request();
+    let mut device = TestDevice {
+        rx: Some(heapless::Vec::from_slice(&request).unwrap()),
+        sent: heapless::Vec::new(),
+    };
+    let mut stack = Stack::new(&mut device, &mut []);
+    stack.iface.update_ip_addrs(|addrs| {
+        addrs
+            .push(IpCidr::new(IpAddress::v4(10, 0, 2, 15), 24))
+            .unwrap();
+    });
+    stack.poll(&mut device, now());
+
+    let reply = &device.sent;
+    assert!(reply.len() >= 42);
+    assert_eq!(&reply[..6], &request[6..12]);
+    assert_eq!(&reply[6..12], &device.mac_address());
+    // 操作码 2 表示应答，发送方是我们
+    assert_eq!(&reply[20..22], &[0, 2]);
+    assert_eq!(&reply[28..32], &[10, 0, 2, 15]);
+}
@@ -0,0 +1,128 @@
+use smoltcp::{
+    iface::{Interface, SocketHandle},
+    socket::dhcpv4::{self, Event},
+    wire::{IpCidr, Ipv4Address, Ipv4Cidr},
+};
+use spin::Mutex;
+
+use super::NetError;
+
+/// DHCP 服务器分配给我们的配置。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub address: Ipv4Cidr,
+    pub router: Option<Ipv4Address>,
+    pub dns: Option<Ipv4Address>,
+}
+
+struct Client {
+    handle: Option<SocketHandle>,
+    lease: Option<Lease>,
+}
+
+static CLIENT: Mutex<Client> = Mutex::new(Client {
+    handle: None,
+    lease: None,
+});
+
+/// 在网卡上启动 DHCP 客户端。没有网卡时什么也不做。
+///
+/// 发现、请求和续租都由 smoltcp 的 DHCP socket 完成（接口还没有地址时普通的
+/// UDP socket 发不出广播），这里只负责把结果应用到接口上。
+pub fn init() {
+    let mut client = CLIENT.lock();
+    if client.handle.is_some() {
+        return;
+    }
+    match super::add_socket(dhcpv4::Socket::new()) {
+        Ok(handle) => {
+            client.handle = Some(handle);
+            log::info!("discovering");
+        }
+        Err(NetError::NoDevice) => {}
+        Err(err) => log::error!("cannot start: {err:?}"),
+    }
+}
+
+/// 当前的租约。
+pub fn lease() -> Option<Lease> {
+    CLIENT.lock().lease
+}
+
+/// 处理 DHCP socket 的事件（获得、更换或失去租约），在每次轮询协议栈之后调用。
+pub fn poll() {
+    let mut client = CLIENT.lock();
+    let Some(handle) = client.handle else {
+        return;
+    };
+    // 事件借用着 socket，先复制出需要的内容再去配置接口
+    let event = super::with_socket::<dhcpv4::Socket, _>(handle, |socket| {
+        socket.poll().map(|event| match event {
+            Event::Configured(config) => Some(Lease {
+                address: config.address,
+                router: config.router,
+                dns: config.dns_servers.first().copied(),
+            }),
+            Event::Deconfigured => None,
+        })
+    });
+    let Ok(Some(lease)) = event else {
+        return;
+    };
+    match (&client.lease, &lease) {
+        (_, Some(new)) => log::info!("{} via {:?}, dns {:?}", new.address, new.router, new.dns),
+        (Some(old), None) => log::warn!("lost lease for {}", old.address),
+        (None, None) => {}
+    }
+    let _ = super::with_interface(|iface| apply(iface, lease.as_ref()));
+    client.lease = lease;
+}
+
+/// 按租约设置接口的地址和默认路由，`None` 表示清除。
+fn apply(iface: &mut Interface, lease: Option<&Lease>) {
+    iface.update_ip_addrs(|addrs| {
+        addrs.clear();
+        if let Some(lease) = lease {
+            let _ = addrs.push(IpCidr::Ipv4(lease.address));
+        }
+    });
+    match lease.and_then(|lease| lease.router) {
+        Some(router) => {
+            let _ = iface.routes_mut().add_default_ipv4_route(router);
+        }
+        None => {
+            iface.routes_mut().remove_default_ipv4_route();
+        }
+    }
+}
+
+#[test_case]
+fn test_dhcp_apply_lease() {
+    use smoltcp::wire::IpAddress;
+
+    use super::{Stack, TestDevice};
+
+    let mut device = TestDevice {
+        rx: None,
+        sent: heapless::Vec::new(),
+    };
+    let mut stack = Stack::new(&mut device, &mut []);
+    let lease = Lease {
+        address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 15), 24),
+        router: Some(Ipv4Address::new(10, 0, 2, 2)),
+        dns: Some(Ipv4Address::new(10, 0, 2, 3)),
+    };
+    apply(&mut stack.iface, Some(&lease));
+    assert_eq!(
+        stack.iface.ipv4_addr(),
+        Some(Ipv4Address::new(10, 0, 2, 15))
+    );
+    let route = stack.iface.routes_mut().remove_default_ipv4_route();
+    assert_eq!(
+        route.map(|route| route.via_router),
+        Some(IpAddress::v4(10, 0, 2, 2))
+    );
+
+    apply(&mut stack.iface, None);
+    assert_eq!(stack.iface.ipv4_addr(), None);
+}
@@ -0,0 +1,490 @@
+use core::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::{PhysAddr, instructions::interrupts};
+
+use super::{FrameQueue, NetDevice, NetError};
+use crate::{
+    devices::{DeviceId, Driver, Match},
+    memory::{self, DmaBuffer},
+    pci::{self, Bar},
+    time,
+};
+
+// 82540EM（QEMU 的 `-device e1000`，0x100e）和 82545EM（0x100f），两者的
+// 寄存器相同
+const VENDOR_ID: u16 = 0x8086;
+
+// 寄存器在 BAR0 中的偏移
+const CTRL: u32 = 0x0000;
+const STATUS: u32 = 0x0008;
+const EERD: u32 = 0x0014;
+const ICR: u32 = 0x00c0;
+const IMS: u32 = 0x00d0;
+const IMC: u32 = 0x00d8;
+const RCTL: u32 = 0x0100;
+const TCTL: u32 = 0x0400;
+const TIPG: u32 = 0x0410;
+const RDBAL: u32 = 0x2800;
+const RDBAH: u32 = 0x2804;
+const RDLEN: u32 = 0x2808;
+const RDH: u32 = 0x2810;
+const RDT: u32 = 0x2818;
+const TDBAL: u32 = 0x3800;
+const TDBAH: u32 = 0x3804;
+const TDLEN: u32 = 0x3808;
+const TDH: u32 = 0x3810;
+const TDT: u32 = 0x3818;
+const MTA: u32 = 0x5200;
+const RAL0: u32 = 0x5400;
+const RAH0: u32 = 0x5404;
+
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+// 等复位完成和 EEPROM 读出的上限。探测时中断可能还关着，所以用 TSC 计时
+const REGISTER_TIMEOUT_MS: u64 = 10;
+
+// 接收：启用、接收广播、去掉 CRC，缓冲区大小保持默认的 2048 字节
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+const RCTL_SECRC: u32 = 1 << 26;
+// 发送：启用、填充过短的帧，冲突参数取手册推荐值
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x10 << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+const TIPG_DEFAULT: u32 = 10 | (8 << 10) | (6 << 20);
+
+// 中断原因：链路状态变化、接收队列将空、接收溢出、收到帧
+const INT_LSC: u32 = 1 << 2;
+const INT_RXDMT0: u32 = 1 << 4;
+const INT_RXO: u32 = 1 << 6;
+const INT_RXT0: u32 = 1 << 7;
+const INT_RX: u32 = INT_RXDMT0 | INT_RXO | INT_RXT0;
+
+const DESC_DD: u8 = 1 << 0;
+const DESC_EOP: u8 = 1 << 1;
+const CMD_EOP: u8 = 1 << 0;
+const CMD_IFCS: u8 = 1 << 1;
+const CMD_RS: u8 = 1 << 3;
+
+// 环的字节长度必须是 128 的整数倍，即描述符数是 8 的整数倍
+const RX_DESCRIPTORS: usize = 32;
+const TX_DESCRIPTORS: usize = 32;
+const BUFFER_SIZE: usize = 2048;
+
+#[repr(C)]
+struct RxDescriptor {
+    addr: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[repr(C)]
+struct TxDescriptor {
+    addr: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+const DESCRIPTOR_SIZE: usize = 16;
+
+/// 映射好的寄存器。
+#[derive(Clone, Copy)]
+struct Regs(u64);
+
+impl Regs {
+    fn read(self, reg: u32) -> u32 {
+        unsafe { ptr::read_volatile((self.0 + reg as u64) as *const u32) }
+    }
+
+    fn write(self, reg: u32, value: u32) {
+        unsafe { ptr::write_volatile((self.0 + reg as u64) as *mut u32, value) };
+    }
+}
+
+/// 一段 DMA 内存：描述符环和每个描述符对应的缓冲区。
+struct Ring {
+    descriptors: DmaBuffer,
+    buffers: DmaBuffer,
+    next: usize,
+}
+
+impl Ring {
+    fn new(count: usize) -> Option<Ring> {
+        let page = memory::PAGE_SIZE as usize;
+        Some(Ring {
+            descriptors: memory::alloc_dma((count * DESCRIPTOR_SIZE).div_ceil(page))?,
+            buffers: memory::alloc_dma((count * BUFFER_SIZE).div_ceil(page))?,
+            next: 0,
+        })
+    }
+
+    fn buffer_phys(&self, index: usize) -> u64 {
+        self.buffers.phys_addr().as_u64() + (index * BUFFER_SIZE) as u64
+    }
+
+    fn buffer(&self, index: usize, len: usize) -> &[u8] {
+        &self.buffers.as_slice()[index * BUFFER_SIZE..][..len]
+    }
+
+    fn buffer_mut(&mut self, index: usize, len: usize) -> &mut [u8] {
+        &mut self.buffers.as_mut_slice()[index * BUFFER_SIZE..][..len]
+    }
+}
+
+/// 接收环。中断处理函数要访问它，所以不放在设备结构里；
+/// 其他地方必须关中断后才能锁它。
+struct Receiver {
+    regs: Regs,
+    ring: Option<Ring>,
+}
+
+impl Receiver {
+    /// 把网卡写好的帧移到接收队列，再把描述符还给网卡。
+    fn drain(&mut self) {
+        let Some(ring) = self.ring.as_mut() else {
+            return;
+        };
+        loop {
+            let index = ring.next;
+            let descriptor = ring
+                .descriptors
+                .ptr::<RxDescriptor>(index * DESCRIPTOR_SIZE);
+            let status = unsafe { ptr::read_volatile(&raw const (*descriptor).status) };
+            if status & DESC_DD == 0 {
+                break;
+            }
+            let length = unsafe { ptr::read_volatile(&raw const (*descriptor).length) };
+            let errors = unsafe { ptr::read_volatile(&raw const (*descriptor).errors) };
+            // 没有开启长帧，一个帧总能放进一个缓冲区；出错的帧直接丢弃
+            if status & DESC_EOP != 0 && errors == 0 {
+                RX_QUEUE.push(ring.buffer(index, length as usize));
+            }
+            unsafe { ptr::write_volatile(&raw mut (*descriptor).status, 0) };
+            // RDT 指向网卡可用的最后一个描述符之后，把刚处理完的这个还回去
+            self.regs.write(RDT, index as u32);
+            ring.next = (index + 1) % RX_DESCRIPTORS;
+        }
+    }
+}
+
+static RECEIVER: Mutex<Receiver> = Mutex::new(Receiver {
+    regs: Regs(0),
+    ring: None,
+});
+static RX_QUEUE: FrameQueue = FrameQueue::new();
+// 中断处理函数读 ICR 用的寄存器地址，0 表示没有设备
+static IRQ_REGS: AtomicU64 = AtomicU64::new(0);
+
+/// 注册到网卡中断线上的处理函数。读取 ICR 同时清除了中断，为 0
+/// 说明是共享这条线的其他设备。
+fn handle_irq() {
+    let regs = IRQ_REGS.load(Ordering::Relaxed);
+    if regs == 0 {
+        return;
+    }
+    let cause = Regs(regs).read(ICR);
+    if cause & INT_RX != 0 {
+        RECEIVER.lock().drain();
+    }
+}
+
+/// Intel 8254x 千兆网卡，使用传统的描述符格式。
+pub struct E1000 {
+    regs: Regs,
+    mac: [u8; 6],
+    tx: Option<Ring>,
+}
+
+impl E1000 {
+    pub const fn new() -> Self {
+        E1000 {
+            regs: Regs(0),
+            mac: [0; 6],
+            tx: None,
+        }
+    }
+
+    pub fn present(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    pub fn link_up(&self) -> bool {
+        self.present() && self.regs.read(STATUS) & STATUS_LU != 0
+    }
+
+    /// 轮询寄存器 `reg`，直到 `done` 对它的值返回 true，返回这个值。
+    /// 超过 `REGISTER_TIMEOUT_MS` 返回 `None`。
+    fn poll(&self, reg: u32, done: impl Fn(u32) -> bool) -> Option<u32> {
+        let deadline = time::tsc() + REGISTER_TIMEOUT_MS * time::tsc_per_ms();
+        loop {
+            let value = self.regs.read(reg);
+            if done(value) {
+                return Some(value);
+            }
+            if time::tsc() > deadline {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// 读取 EEPROM 中的一个字。
+    fn read_eeprom(&self, word: u8) -> Option<u16> {
+        self.regs.write(EERD, ((word as u32) << 8) | EERD_START);
+        let value = self.poll(EERD, |value| value & EERD_DONE != 0)?;
+        Some((value >> 16) as u16)
+    }
+
+    fn read_mac(&mut self) -> bool {
+        let low = self.regs.read(RAL0);
+        let high = self.regs.read(RAH0);
+        if low != 0 {
+            // 复位后网卡已经把 EEPROM 中的地址装进了接收地址寄存器
+            self.mac[..4].copy_from_slice(&low.to_le_bytes());
+            self.mac[4..].copy_from_slice(&high.to_le_bytes()[..2]);
+            return true;
+        }
+        for word in 0..3 {
+            let Some(value) = self.read_eeprom(word) else {
+                log::error!("e1000: EEPROM read timed out");
+                return false;
+            };
+            let value = value.to_le_bytes();
+            self.mac[word as usize * 2..][..2].copy_from_slice(&value);
+        }
+        // 地址有效位（AV），没有它网卡不会接收发给这个地址的帧
+        self.regs
+            .write(RAL0, u32::from_le_bytes(self.mac[..4].try_into().unwrap()));
+        self.regs.write(
+            RAH0,
+            u16::from_le_bytes([self.mac[4], self.mac[5]]) as u32 | (1 << 31),
+        );
+        true
+    }
+
+    fn probe(&mut self, device: &pci::PciDevice) -> bool {
+        let Bar::Memory { address, size, .. } = device.bars[0] else {
+            return false;
+        };
+        device.enable(false, true, true);
+        let Some(virt) = memory::map_mmio(PhysAddr::new(address), size) else {
+            return false;
+        };
+        self.regs = Regs(virt.as_u64());
+
+        // 复位后屏蔽所有中断，清掉遗留的中断原因
+        self.regs.write(CTRL, self.regs.read(CTRL) | CTRL_RST);
+        if self.poll(CTRL, |ctrl| ctrl & CTRL_RST == 0).is_none() {
+            log::error!("e1000: reset timed out");
+            return false;
+        }
+        self.regs.write(IMC, u32::MAX);
+        self.regs.read(ICR);
+        self.regs
+            .write(CTRL, self.regs.read(CTRL) | CTRL_SLU | CTRL_ASDE);
+        if !self.read_mac() {
+            return false;
+        }
+        for i in 0..128 {
+            self.regs.write(MTA + i * 4, 0);
+        }
+
+        let (Some(rx), Some(tx)) = (Ring::new(RX_DESCRIPTORS), Ring::new(TX_DESCRIPTORS)) else {
+            return false;
+        };
+        for index in 0..RX_DESCRIPTORS {
+            let descriptor = RxDescriptor {
+                addr: rx.buffer_phys(index),
+                length: 0,
+                checksum: 0,
+                status: 0,
+                errors: 0,
+                special: 0,
+            };
+            unsafe { ptr::write_volatile(rx.descriptors.ptr(index * DESCRIPTOR_SIZE), descriptor) };
+        }
+        // 发送描述符初始都标记为已完成，表示空闲
+        for index in 0..TX_DESCRIPTORS {
+            let descriptor = TxDescriptor {
+                addr: tx.buffer_phys(index),
+                length: 0,
+                cso: 0,
+                cmd: 0,
+                status: DESC_DD,
+                css: 0,
+                special: 0,
+            };
+            unsafe { ptr::write_volatile(tx.descriptors.ptr(index * DESCRIPTOR_SIZE), descriptor) };
+        }
+
+        let rx_base = rx.descriptors.phys_addr().as_u64();
+        self.regs.write(RDBAL, rx_base as u32);
+        self.regs.write(RDBAH, (rx_base >> 32) as u32);
+        self.regs
+            .write(RDLEN, (RX_DESCRIPTORS * DESCRIPTOR_SIZE) as u32);
+        self.regs.write(RDH, 0);
+        self.regs.write(RDT, RX_DESCRIPTORS as u32 - 1);
+        self.regs.write(RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+
+        let tx_base = tx.descriptors.phys_addr().as_u64();
+        self.regs.write(TDBAL, tx_base as u32);
+        self.regs.write(TDBAH, (tx_base >> 32) as u32);
+        self.regs
+            .write(TDLEN, (TX_DESCRIPTORS * DESCRIPTOR_SIZE) as u32);
+        self.regs.write(TDH, 0);
+        self.regs.write(TDT, 0);
+        self.regs
+            .write(TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+        self.regs.write(TIPG, TIPG_DEFAULT);
+
+        interrupts::without_interrupts(|| {
+            *RECEIVER.lock() = Receiver {
+                regs: self.regs,
+                ring: Some(rx),
+            };
+        });
+        self.tx = Some(tx);
+        IRQ_REGS.store(self.regs.0, Ordering::Relaxed);
+        // 优先用 MSI，没有 Local APIC 或者设备不支持时退回到中断线
+        if let Some(vector) = device.enable_msi(handle_irq) {
+            log::info!("e1000: using MSI vector {vector:#x}");
+        } else if !crate::interrupts::register_irq_handler(device.interrupt_line, handle_irq) {
+            log::warn!("no handler slot for IRQ {}, polling", device.interrupt_line);
+        }
+        self.regs.write(IMS, INT_LSC | INT_RX);
+        self.regs.read(ICR);
+        true
+    }
+
+    fn tx_descriptor(&self) -> Option<*mut TxDescriptor> {
+        let ring = self.tx.as_ref()?;
+        Some(ring.descriptors.ptr(ring.next * DESCRIPTOR_SIZE))
+    }
+}
+
+impl Default for E1000 {
+    fn default() -> Self {
+        E1000::new()
+    }
+}
+
+impl NetDevice for E1000 {
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Option<usize> {
+        // 中断丢失或没有注册上中断时，也能通过轮询收到帧
+        interrupts::without_interrupts(|| RECEIVER.lock().drain());
+        RX_QUEUE.pop(buf)
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), NetError> {
+        if frame.len() > BUFFER_SIZE {
+            return Err(NetError::TooLarge);
+        }
+        if !self.can_transmit() {
+            return Err(NetError::Busy);
+        }
+        let descriptor = self.tx_descriptor().ok_or(NetError::NoDevice)?;
+        let ring = self.tx.as_mut().ok_or(NetError::NoDevice)?;
+        let index = ring.next;
+        ring.buffer_mut(index, frame.len()).copy_from_slice(frame);
+        unsafe {
+            ptr::write_volatile(&raw mut (*descriptor).length, frame.len() as u16);
+            ptr::write_volatile(&raw mut (*descriptor).cmd, CMD_EOP | CMD_IFCS | CMD_RS);
+            ptr::write_volatile(&raw mut (*descriptor).status, 0);
+        }
+        ring.next = (index + 1) % TX_DESCRIPTORS;
+        self.regs.write(TDT, ring.next as u32);
+        Ok(())
+    }
+
+    fn can_transmit(&self) -> bool {
+        // 网卡发送完成后置上 DD 位（因为设置了 RS）
+        self.tx_descriptor()
+            .is_some_and(|descriptor| unsafe { ptr::read_volatile(&raw const (*descriptor).status) } & DESC_DD != 0)
+    }
+}
+
+static NIC: Mutex<E1000> = Mutex::new(E1000::new());
+
+/// e1000 驱动，只接管第一块网卡并接入协议栈。
+///
+/// QEMU 中这样添加：`-netdev user,id=net0 -device e1000,netdev=net0`。
+pub static DRIVER: Driver = Driver {
+    name: "e1000",
+    matches: &[
+        Match::Pci {
+            vendor_id: VENDOR_ID,
+            device_id: 0x100e,
+        },
+        Match::Pci {
+            vendor_id: VENDOR_ID,
+            device_id: 0x100f,
+        },
+    ],
+    probe,
+};
+
+fn probe(id: &DeviceId) -> bool {
+    let DeviceId::Pci(device) = id else {
+        return false;
+    };
+    let mut nic = NIC.lock();
+    if nic.present() || !nic.probe(device) {
+        return false;
+    }
+    let mac = nic.mac;
+    log::info!(
+        "eth0: e1000 at {}, MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}, link {}",
+        device.address,
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5],
+        if nic.link_up() { "up" } else { "down" }
+    );
+    drop(nic);
+    let _ = super::attach(&NIC);
+    true
+}
+
+#[test_case]
+fn test_e1000_descriptor_layout() {
+    assert_eq!(core::mem::size_of::<RxDescriptor>(), DESCRIPTOR_SIZE);
+    assert_eq!(core::mem::size_of::<TxDescriptor>(), DESCRIPTOR_SIZE);
+    assert!((RX_DESCRIPTORS * DESCRIPTOR_SIZE).is_multiple_of(128));
+    assert!((TX_DESCRIPTORS * DESCRIPTOR_SIZE).is_multiple_of(128));
+}
+
+#[test_case]
+fn test_e1000_mac_address() {
+    // 只有用 `-device e1000` 启动时才有网卡
+    let nic = NIC.lock();
+    if !nic.present() {
+        crate::testing::skip("no e1000 NIC");
+        return;
+    }
+    assert_ne!(nic.mac_address(), [0; 6]);
+    // 单播地址：第一个字节的最低位为 0
+    assert_eq!(nic.mac_address()[0] & 1, 0);
+}
@@ -0,0 +1,250 @@
+use smoltcp::{
+    iface::SocketHandle,
+    phy::ChecksumCapabilities,
+    socket::icmp::{self, PacketBuffer, PacketMetadata},
+    wire::{Icmpv4Packet, Icmpv4Repr, IpAddress, Ipv4Address},
+};
+use spin::Mutex;
+
+use super::NetError;
+use crate::{console, println, time};
+
+// 回显请求的标识符，用来从收到的 ICMP 报文中认出自己的应答
+const IDENT: u16 = 0x4f53;
+// 请求的数据部分：发送时的 TSC，后面补齐到常见的 56 字节
+const PAYLOAD_LEN: usize = 56;
+const PACKET_LEN: usize = 8 + PAYLOAD_LEN;
+const BUFFER_PACKETS: usize = 4;
+const TIMEOUT_MS: u64 = 1000;
+
+/// 一次 ping 的统计。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub sent: u16,
+    pub received: u16,
+    pub min_us: u64,
+    pub max_us: u64,
+    pub total_us: u64,
+}
+
+static SOCKET: Mutex<Option<SocketHandle>> = Mutex::new(None);
+
+static mut RX_METADATA: [PacketMetadata; BUFFER_PACKETS] = [PacketMetadata::EMPTY; BUFFER_PACKETS];
+static mut TX_METADATA: [PacketMetadata; BUFFER_PACKETS] = [PacketMetadata::EMPTY; BUFFER_PACKETS];
+static mut RX_PAYLOAD: [u8; BUFFER_PACKETS * PACKET_LEN] = [0; BUFFER_PACKETS * PACKET_LEN];
+static mut TX_PAYLOAD: [u8; BUFFER_PACKETS * PACKET_LEN] = [0; BUFFER_PACKETS * PACKET_LEN];
+
+/// ping 共用的 ICMP socket，第一次使用时创建。
+fn socket() -> Result<SocketHandle, NetError> {
+    let mut handle = SOCKET.lock();
+    if let Some(handle) = *handle {
+        return Ok(handle);
+    }
+    // 锁保证缓冲区只会被借出一次；加入失败时它们也没有被别人拿走
+    let (rx_metadata, rx_payload) = (&raw mut RX_METADATA, &raw mut RX_PAYLOAD);
+    let (tx_metadata, tx_payload) = (&raw mut TX_METADATA, &raw mut TX_PAYLOAD);
+    let socket = unsafe {
+        let rx_metadata: &mut [PacketMetadata] = &mut *rx_metadata;
+        let rx_payload: &mut [u8] = &mut *rx_payload;
+        let tx_metadata: &mut [PacketMetadata] = &mut *tx_metadata;
+        let tx_payload: &mut [u8] = &mut *tx_payload;
+        icmp::Socket::new(
+            PacketBuffer::new(rx_metadata, rx_payload),
+            PacketBuffer::new(tx_metadata, tx_payload),
+        )
+    };
+    let new = super::add_socket(socket)?;
+    super::with_socket::<icmp::Socket, _>(new, |socket| socket.bind(icmp::Endpoint::Ident(IDENT)))?
+        .map_err(|_| NetError::Busy)?;
+    *handle = Some(new);
+    Ok(new)
+}
+
+fn echo_request(seq_no: u16, sent_at: u64) -> [u8; PACKET_LEN] {
+    let mut data = [0; PAYLOAD_LEN];
+    data[..8].copy_from_slice(&sent_at.to_le_bytes());
+    let repr = Icmpv4Repr::EchoRequest {
+        ident: IDENT,
+        seq_no,
+        data: &data,
+    };
+    let mut packet = [0; PACKET_LEN];
+    repr.emit(
+        &mut Icmpv4Packet::new_unchecked(&mut packet[..]),
+        &ChecksumCapabilities::default(),
+    );
+    packet
+}
+
+/// 从回显应答中取出序号和发送时的 TSC；不是我们的应答时返回 `None`。
+fn parse_reply(packet: &[u8]) -> Option<(u16, u64)> {
+    let packet = Icmpv4Packet::new_checked(packet).ok()?;
+    match Icmpv4Repr::parse(&packet, &ChecksumCapabilities::default()).ok()? {
+        Icmpv4Repr::EchoReply {
+            ident: IDENT,
+            seq_no,
+            data,
+        } if data.len() >= 8 => Some((seq_no, u64::from_le_bytes(data[..8].try_into().ok()?))),
+        _ => None,
+    }
+}
+
+/// 向 `target` 每秒发送一个回显请求，共 `count` 个，打印往返时间和统计。
+///
+/// 运行期间占有控制台，按 Ctrl+C 提前结束，统计只包括已经发出的请求。
+pub fn ping(target: Ipv4Address, count: u16) -> Result<Stats, NetError> {
+    let handle = socket()?;
+    let _foreground = console::enter_foreground("ping", None);
+    let mut stats = Stats {
+        min_us: u64::MAX,
+        ..Stats::default()
+    };
+    let tsc_timeout = TIMEOUT_MS * time::tsc_per_ms();
+    println!("PING {}: {} data bytes", target, PAYLOAD_LEN);
+
+    'requests: for seq_no in 0..count {
+        let sent_at = time::tsc();
+        let packet = echo_request(seq_no, sent_at);
+        // 还没解析出对方 MAC 地址时，smoltcp 会先发 ARP，请求留在发送缓冲区里
+        super::with_socket::<icmp::Socket, _>(handle, |socket| {
+            socket.send_slice(&packet, IpAddress::Ipv4(target))
+        })?
+        .map_err(|_| NetError::Busy)?;
+        stats.sent += 1;
+
+        // 收到应答后也等满一秒再发下一个，最后一个收到应答就不用再等
+        let mut answered = false;
+        while time::tsc() - sent_at < tsc_timeout && !(answered && seq_no + 1 == count) {
+            if console::take_interrupt() {
+                break 'requests;
+            }
+            super::poll();
+            let reply = super::with_socket::<icmp::Socket, _>(handle, |socket| {
+                let mut buf = [0; PACKET_LEN];
+                let (len, from) = socket.recv_slice(&mut buf).ok()?;
+                Some((from, parse_reply(&buf[..len])))
+            })?;
+            match reply {
+                // 只统计本轮的应答，迟到的旧应答直接丢掉
+                Some((from, Some((seq, sent)))) if seq == seq_no && !answered => {
+                    let rtt_us = time::tsc_to_us(time::tsc() - sent);
+                    println!(
+                        "{} bytes from {}: icmp_seq={} time={}.{:03} ms",
+                        PACKET_LEN,
+                        from,
+                        seq,
+                        rtt_us / 1000,
+                        rtt_us % 1000
+                    );
+                    stats.received += 1;
+                    stats.min_us = stats.min_us.min(rtt_us);
+                    stats.max_us = stats.max_us.max(rtt_us);
+                    stats.total_us += rtt_us;
+                    answered = true;
+                }
+                Some(_) => {}
+                None => x86_64::instructions::hlt(),
+            }
+        }
+        if !answered {
+            println!("Request timeout for icmp_seq {}", seq_no);
+        }
+    }
+
+    if stats.received == 0 {
+        stats.min_us = 0;
+    }
+    println!(
+        "{} packets transmitted, {} received, {}% packet loss",
+        stats.sent,
+        stats.received,
+        (stats.sent - stats.received) as u32 * 100 / stats.sent.max(1) as u32
+    );
+    if stats.received > 0 {
+        let avg = stats.total_us / stats.received as u64;
+        println!(
+            "rtt min/avg/max = {}.{:03}/{}.{:03}/{}.{:03} ms",
+            stats.min_us / 1000,
+            stats.min_us % 1000,
+            avg / 1000,
+            avg % 1000,
+            stats.max_us / 1000,
+            stats.max_us % 1000
+        );
+    }
+    Ok(stats)
+}
+
+#[test_case]
+fn test_ping_reply_parsing() {
+    // 应答与请求只差类型字段，把请求改成应答后重新计算校验和
+    let request = echo_request(7, 0x1122_3344);
+    let mut reply = request;
+    let mut packet = Icmpv4Packet::new_unchecked(&mut reply[..]);
+    packet.set_msg_type(smoltcp::wire::Icmpv4Message::EchoReply);
+    packet.fill_checksum();
+    assert_eq!(parse_reply(&reply), Some((7, 0x1122_3344)));
+    assert_eq!(parse_reply(&request), None);
+    reply[4] ^= 1;
+    assert_eq!(parse_reply(&reply), None);
+}
+
+#[test_case]
+fn test_ping_stack_answers_echo() {
+    use smoltcp::wire::{
+        EthernetAddress, EthernetFrame, EthernetProtocol, IpCidr, IpProtocol, Ipv4Packet, Ipv4Repr,
+    };
+
+    use super::{NetDevice, Stack, TestDevice, now, test_arp_request};
+
+    const PEER_MAC: [u8; 6] = [0x52, 0x55, 10, 0, 2, 2];
+    let mut device = TestDevice {
+        rx: Some(heapless::Vec::from_slice(&test_arp_request()).unwrap()),
+        sent: heapless::Vec::new(),
+    };
+    let mut stack = Stack::new(&mut device, &mut []);
+    stack.iface.update_ip_addrs(|addrs| {
+        addrs
+            .push(IpCidr::new(IpAddress::v4(10, 0, 2, 15), 24))
+            .unwrap();
+    });
+    // 先让协议栈从 ARP 请求中记住对方的 MAC 地址
+    stack.poll(&mut device, now());
+
+    let icmp = Icmpv4Repr::EchoRequest {
+        ident: 0x1234,
+        seq_no: 1,
+        data: b"hello",
+    };
+    let ip = Ipv4Repr {
+        src_addr: Ipv4Address::new(10, 0, 2, 2),
+        dst_addr: Ipv4Address::new(10, 0, 2, 15),
+        next_header: IpProtocol::Icmp,
+        payload_len: icmp.buffer_len(),
+        hop_limit: 64,
+    };
+    let caps = ChecksumCapabilities::default();
+    let mut frame = [0u8; 14 + 20 + 8 + 5];
+    let mut ethernet = EthernetFrame::new_unchecked(&mut frame[..]);
+    ethernet.set_src_addr(EthernetAddress(PEER_MAC));
+    ethernet.set_dst_addr(EthernetAddress(device.mac_address()));
+    ethernet.set_ethertype(EthernetProtocol::Ipv4);
+    ip.emit(&mut Ipv4Packet::new_unchecked(&mut frame[14..]), &caps);
+    icmp.emit(&mut Icmpv4Packet::new_unchecked(&mut frame[34..]), &caps);
+    device.rx = Some(heapless::Vec::from_slice(&frame).unwrap());
+    stack.poll(&mut device, now());
+
+    let ethernet = EthernetFrame::new_checked(&device.sent[..]).unwrap();
+    assert_eq!(ethernet.dst_addr(), EthernetAddress(PEER_MAC));
+    let ip = Ipv4Packet::new_checked(ethernet.payload()).unwrap();
+    assert_eq!(ip.dst_addr(), Ipv4Address::new(10, 0, 2, 2));
+    let icmp = Icmpv4Packet::new_checked(ip.payload()).unwrap();
+    assert_eq!(
+        Icmpv4Repr::parse(&icmp, &caps).unwrap(),
+        Icmpv4Repr::EchoReply {
+            ident: 0x1234,
+            seq_no: 1,
+            data: b"hello",
+        }
+    );
+}
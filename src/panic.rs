@@ -0,0 +1,114 @@
+use core::{
+    arch::asm,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use x86_64::{
+    instructions::interrupts,
+    registers::{control::Cr2, rflags},
+};
+
+use crate::{
+    QemuExitCode, backtrace,
+    console::{self, LogLevel, Sinks},
+    debugcon::{self, DebugCon},
+    eventlog, exit_qemu, framebuffer, gdbstub, hlt_loop, percpu, power,
+    regs::{self, msr},
+    serial, vga_buffer,
+};
+
+/// panic 报告完之后做什么。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// 交给 GDB（如果启用了），然后停机
+    Halt,
+    /// 测试失败：以失败码退出 QEMU，没有退出设备时关机
+    ExitQemu,
+}
+
+// 报告过程中再次 panic 时不能再走完整的流程，否则会无限递归
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// 所有 panic 处理函数的共同实现。
+///
+/// 关中断后强行释放控制台用到的锁（panic 可能发生在中断处理函数或持有锁的
+/// 代码中），把消息和寄存器写到所有输出去处，再通过串口打印调用栈和最近的
+/// 事件，最后按 `action` 停机或退出 QEMU。
+pub fn handle(info: &PanicInfo, action: Action) -> ! {
+    interrupts::disable();
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        // 端口写入不需要锁，这里不会再出问题
+        use core::fmt::Write;
+        let _ = writeln!(DebugCon, "nested panic: {info}");
+        // 第一次 panic 可能就发生在 GDB stub 里，不再交给它
+        if action == Action::Halt {
+            hlt_loop();
+        }
+        finish(action);
+    }
+    unsafe {
+        vga_buffer::force_unlock();
+        serial::force_unlock();
+        framebuffer::force_unlock();
+    }
+    let mut sinks = Sinks::VGA.union(Sinks::SERIAL).union(Sinks::FRAMEBUFFER);
+    if debugcon::enabled() || DebugCon::present() {
+        sinks = sinks.union(Sinks::DEBUGCON);
+    }
+    console::set_sinks(sinks);
+
+    // 集成测试可能没有调用 `init()`，GS 基址还是 0，不能读每 CPU 数据
+    let cpu = match unsafe { regs::read_msr(msr::GS_BASE) } {
+        0 => 0,
+        _ => percpu::id(),
+    };
+    console::write_level(LogLevel::Error, format_args!("cpu {cpu} panicked: {info}"));
+    dump_registers();
+    backtrace::print();
+    eventlog::dump();
+    finish(action);
+}
+
+fn finish(action: Action) -> ! {
+    match action {
+        Action::Halt => {
+            gdbstub::handle_panic();
+            hlt_loop();
+        }
+        Action::ExitQemu => {
+            exit_qemu(QemuExitCode::Failed);
+            // 没有 isa-debug-exit 设备时（比如在其他模拟器或真机上）关机
+            power::shutdown();
+        }
+    }
+}
+
+/// 把 panic 时的栈指针和控制寄存器写到所有输出去处。
+fn dump_registers() {
+    let (rsp, rbp): (u64, u64);
+    unsafe {
+        asm!("mov {}, rsp", "mov {}, rbp", out(reg) rsp, out(reg) rbp, options(nomem, nostack));
+    }
+    let cr2 = Cr2::read_raw();
+    let (cr3, _) = regs::cr3();
+    console::write_level(
+        LogLevel::Error,
+        format_args!(
+            "rsp={:#018x} rbp={:#018x} rflags={:#x}",
+            rsp,
+            rbp,
+            rflags::read_raw()
+        ),
+    );
+    console::write_level(
+        LogLevel::Error,
+        format_args!(
+            "cr0={:#x} cr2={:#x} cr3={:#x} cr4={:#x}",
+            regs::cr0::read().bits(),
+            cr2,
+            cr3.start_address().as_u64(),
+            regs::cr4::read().bits()
+        ),
+    );
+}
@@ -0,0 +1,481 @@
+use core::fmt;
+
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr, instructions::port::Port};
+
+use crate::{apic, interrupts, memory, println};
+
+/// 扫描结果最多保存的设备数。
+pub const MAX_DEVICES: usize = 64;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+// 配置空间中常用寄存器的偏移
+const VENDOR_ID: u8 = 0x00;
+const COMMAND: u8 = 0x04;
+const STATUS: u8 = 0x06;
+const CLASS_REVISION: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0e;
+const BAR0: u8 = 0x10;
+const CAPABILITIES: u8 = 0x34;
+const INTERRUPT_LINE: u8 = 0x3c;
+
+// 命令寄存器中禁止 INTx 中断的位，状态寄存器中表示有能力链表的位
+const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// 能力 ID。
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_MSIX: u8 = 0x11;
+
+// MSI 控制寄存器的位
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_ENABLE: u16 = 0b111 << 4;
+const MSI_64BIT: u16 = 1 << 7;
+// MSI-X 控制寄存器的位，表大小减一在低 11 位
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+// MSI-X 表每项 16 字节：地址低、高 32 位，数据，向量控制（位 0 为屏蔽）
+const MSIX_ENTRY_SIZE: u64 = 16;
+
+// 已经映射的 MSI-X 表
+static MSIX_TABLES: Mutex<heapless::Vec<(PciAddress, VirtAddr), MAX_DEVICES>> =
+    Mutex::new(heapless::Vec::new());
+
+/// 总线/设备/功能号组成的配置空间地址。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        PciAddress {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(self, offset: u8) -> u32 {
+        // bit 31 是使能位，低两位必须为 0（按双字访问）
+        0x8000_0000
+            | ((self.bus as u32) << 16)
+            | ((self.device as u32) << 11)
+            | ((self.function as u32) << 8)
+            | (offset & 0xfc) as u32
+    }
+
+    pub fn read_u32(self, offset: u8) -> u32 {
+        let mut address: Port<u32> = Port::new(CONFIG_ADDRESS);
+        let mut data: Port<u32> = Port::new(CONFIG_DATA);
+        unsafe {
+            address.write(self.config_address(offset));
+            data.read()
+        }
+    }
+
+    pub fn write_u32(self, offset: u8, value: u32) {
+        let mut address: Port<u32> = Port::new(CONFIG_ADDRESS);
+        let mut data: Port<u32> = Port::new(CONFIG_DATA);
+        unsafe {
+            address.write(self.config_address(offset));
+            data.write(value);
+        }
+    }
+
+    pub fn read_u16(self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn write_u16(self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let old = self.read_u32(offset) & !(0xffff << shift);
+        self.write_u32(offset, old | ((value as u32) << shift));
+    }
+
+    pub fn read_u8(self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// 基址寄存器（BAR）描述的资源。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    None,
+    Io {
+        port: u16,
+        size: u32,
+    },
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    pub interrupt_line: u8,
+    pub bars: [Bar; 6],
+}
+
+impl PciDevice {
+    fn probe(address: PciAddress) -> Option<PciDevice> {
+        let id = address.read_u32(VENDOR_ID);
+        if id & 0xffff == 0xffff {
+            return None;
+        }
+        let class = address.read_u32(CLASS_REVISION);
+        let header_type = address.read_u8(HEADER_TYPE);
+        let mut bars = [Bar::None; 6];
+        // 只有普通设备（header type 0）有 6 个 BAR，PCI 桥只有 2 个
+        let bar_count = match header_type & 0x7f {
+            0x00 => 6,
+            0x01 => 2,
+            _ => 0,
+        };
+        let mut index = 0;
+        while index < bar_count {
+            let (bar, used) = read_bar(address, index);
+            bars[index] = bar;
+            index += used;
+        }
+        Some(PciDevice {
+            address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type,
+            interrupt_line: address.read_u8(INTERRUPT_LINE),
+            bars,
+        })
+    }
+
+    /// 允许设备响应 I/O 和内存访问，并作为总线主控发起 DMA。
+    pub fn enable(&self, io: bool, memory: bool, bus_master: bool) {
+        let mut command = self.address.read_u16(COMMAND);
+        command |= (io as u16) | ((memory as u16) << 1) | ((bus_master as u16) << 2);
+        self.address.write_u16(COMMAND, command);
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+
+    /// 在能力链表中查找 `id`，返回它在配置空间中的偏移。
+    pub fn capability(&self, id: u8) -> Option<u8> {
+        if self.address.read_u16(STATUS) & STATUS_CAPABILITIES == 0 {
+            return None;
+        }
+        let mut offset = self.address.read_u8(CAPABILITIES) & 0xfc;
+        // 每项至少 4 字节，配置空间里放不下更多；防止损坏的链表成环
+        for _ in 0..48 {
+            if offset == 0 {
+                return None;
+            }
+            if self.address.read_u8(offset) == id {
+                return Some(offset);
+            }
+            offset = self.address.read_u8(offset + 1) & 0xfc;
+        }
+        None
+    }
+
+    /// 改用 MSI：分配一个向量，把消息设置成投递给当前 CPU，并禁止 INTx。
+    /// 设备不支持 MSI 或者没有空闲向量时返回 `None`。
+    pub fn enable_msi(&self, handler: fn()) -> Option<u8> {
+        let cap = self.capability(CAP_MSI)?;
+        let vector = interrupts::allocate_msi_vector(handler)?;
+        let (address, data) = msi_message(vector, apic::id());
+        let control = self.address.read_u16(cap + 2);
+        self.address.write_u32(cap + 4, address);
+        if control & MSI_64BIT != 0 {
+            self.address.write_u32(cap + 8, 0);
+            self.address.write_u16(cap + 12, data as u16);
+        } else {
+            self.address.write_u16(cap + 8, data as u16);
+        }
+        // 只用一个消息
+        self.address
+            .write_u16(cap + 2, (control & !MSI_MULTIPLE_ENABLE) | MSI_ENABLE);
+        self.disable_intx();
+        Some(vector)
+    }
+
+    /// 改用 MSI-X：分配一个向量填进表项 `entry`，打开 MSI-X 并禁止 INTx。
+    /// 设备不支持 MSI-X、表项不存在或者没有空闲向量时返回 `None`。
+    pub fn enable_msix(&self, entry: u16, handler: fn()) -> Option<u8> {
+        let cap = self.capability(CAP_MSIX)?;
+        let control = self.address.read_u16(cap + 2);
+        if entry > control & MSIX_TABLE_SIZE {
+            return None;
+        }
+        let table = self.msix_table(cap, control)?;
+        let vector = interrupts::allocate_msi_vector(handler)?;
+        let (address, data) = msi_message(vector, apic::id());
+        // 填表项期间屏蔽整个功能，设备不会用到写了一半的表项
+        self.address
+            .write_u16(cap + 2, control | MSIX_FUNCTION_MASK | MSIX_ENABLE);
+        unsafe {
+            let regs = (table + entry as u64 * MSIX_ENTRY_SIZE).as_mut_ptr::<u32>();
+            regs.write_volatile(address);
+            regs.add(1).write_volatile(0);
+            regs.add(2).write_volatile(data);
+            regs.add(3).write_volatile(0);
+        }
+        self.address
+            .write_u16(cap + 2, (control & !MSIX_FUNCTION_MASK) | MSIX_ENABLE);
+        self.disable_intx();
+        Some(vector)
+    }
+
+    /// 撤销 [`enable_msix`](Self::enable_msix)：关掉 MSI-X，释放 `vector`，
+    /// 重新允许 INTx。驱动在打开 MSI-X 之后初始化失败时调用。
+    pub fn disable_msix(&self, vector: u8) {
+        if let Some(cap) = self.capability(CAP_MSIX) {
+            let control = self.address.read_u16(cap + 2);
+            self.address
+                .write_u16(cap + 2, control & !(MSIX_ENABLE | MSIX_FUNCTION_MASK));
+        }
+        interrupts::free_msi_vector(vector);
+        let command = self.address.read_u16(COMMAND);
+        self.address
+            .write_u16(COMMAND, command & !COMMAND_INTX_DISABLE);
+    }
+
+    /// MSI-X 表的虚拟地址。每个设备的整张表只映射一次，之后从缓存中取。
+    fn msix_table(&self, cap: u8, control: u16) -> Option<VirtAddr> {
+        let mut tables = MSIX_TABLES.lock();
+        if let Some(&(_, table)) = tables.iter().find(|(address, _)| *address == self.address) {
+            return Some(table);
+        }
+        // 表所在的 BAR（低 3 位）和在 BAR 中的偏移
+        let table = self.address.read_u32(cap + 4);
+        let Bar::Memory { address, .. } = *self.bars.get(table as usize & 0x7)? else {
+            return None;
+        };
+        let size = ((control & MSIX_TABLE_SIZE) as u64 + 1) * MSIX_ENTRY_SIZE;
+        let virt = memory::map_mmio(PhysAddr::new(address + (table & !0x7) as u64), size)?;
+        // 缓存满了也能用，只是下次还要再映射一次
+        let _ = tables.push((self.address, virt));
+        Some(virt)
+    }
+
+    fn disable_intx(&self) {
+        let command = self.address.read_u16(COMMAND);
+        self.address
+            .write_u16(COMMAND, command | COMMAND_INTX_DISABLE);
+    }
+}
+
+/// 投递给 APIC ID 为 `apic_id` 的 CPU、向量为 `vector` 的 MSI 消息的地址和
+/// 数据：固定投递模式、边沿触发。
+fn msi_message(vector: u8, apic_id: u32) -> (u32, u32) {
+    (0xfee0_0000 | (apic_id & 0xff) << 12, vector as u32)
+}
+
+/// 读取并测量第 `index` 个 BAR，返回 BAR 以及它占用的寄存器个数（64 位 BAR
+/// 占两个）。
+fn read_bar(address: PciAddress, index: usize) -> (Bar, usize) {
+    let offset = BAR0 + index as u8 * 4;
+    let original = address.read_u32(offset);
+
+    // 写入全 1 后读回，可以得到地址中可写的位，从而算出大小。测量期间关闭译码，
+    // 避免设备短暂地出现在错误的地址上
+    let command = address.read_u16(COMMAND);
+    address.write_u16(COMMAND, command & !0b11);
+    address.write_u32(offset, 0xffff_ffff);
+    let mask = address.read_u32(offset);
+    address.write_u32(offset, original);
+
+    let result = if original & 1 == 1 {
+        (decode_io_bar(original, mask), 1)
+    } else if (original >> 1) & 0b11 == 0b10 {
+        let high_offset = offset + 4;
+        let high = address.read_u32(high_offset);
+        address.write_u32(high_offset, 0xffff_ffff);
+        let high_mask = address.read_u32(high_offset);
+        address.write_u32(high_offset, high);
+        let bar = decode_memory_bar(
+            ((high as u64) << 32) | original as u64,
+            ((high_mask as u64) << 32) | mask as u64,
+        );
+        (bar, 2)
+    } else {
+        let bar = decode_memory_bar(original as u64, 0xffff_ffff_0000_0000 | mask as u64);
+        (bar, 1)
+    };
+    address.write_u16(COMMAND, command);
+    result
+}
+
+fn decode_io_bar(value: u32, mask: u32) -> Bar {
+    let size = (!(mask & !0b11)).wrapping_add(1) & 0xffff;
+    if value & !0b11 == 0 || size == 0 {
+        return Bar::None;
+    }
+    Bar::Io {
+        port: (value & !0b11) as u16,
+        size,
+    }
+}
+
+fn decode_memory_bar(value: u64, mask: u64) -> Bar {
+    let address = value & !0xf;
+    let size = (!(mask & !0xf)).wrapping_add(1);
+    if address == 0 || mask & !0xf == 0 {
+        return Bar::None;
+    }
+    Bar::Memory {
+        address,
+        size,
+        prefetchable: value & 0b1000 != 0,
+    }
+}
+
+static DEVICES: Mutex<heapless::Vec<PciDevice, MAX_DEVICES>> = Mutex::new(heapless::Vec::new());
+
+/// 扫描所有总线、设备和功能，记录找到的设备。
+pub fn init() {
+    let mut devices = DEVICES.lock();
+    devices.clear();
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let Some(first) = PciDevice::probe(PciAddress::new(bus, device, 0)) else {
+                continue;
+            };
+            // header type 的最高位表示多功能设备，只有这时才需要探测功能 1..7
+            let functions = if first.header_type & 0x80 != 0 { 8 } else { 1 };
+            if devices.push(first).is_err() {
+                return;
+            }
+            for function in 1..functions {
+                if let Some(dev) = PciDevice::probe(PciAddress::new(bus, device, function))
+                    && devices.push(dev).is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// 对每个已发现的设备调用 `f`。
+pub fn for_each(mut f: impl FnMut(&PciDevice)) {
+    for device in DEVICES.lock().iter() {
+        f(device);
+    }
+}
+
+/// 按厂商和设备 ID 查找第一个匹配的设备。
+pub fn find(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|dev| dev.vendor_id == vendor_id && dev.device_id == device_id)
+        .copied()
+}
+
+/// 按类别和子类别查找第一个匹配的设备。
+pub fn find_class(class: u8, subclass: u8) -> Option<PciDevice> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|dev| dev.class == class && dev.subclass == subclass)
+        .copied()
+}
+
+/// 以类似 `lspci` 的格式打印设备列表。
+pub fn print_devices() {
+    for_each(|dev| {
+        println!(
+            "{} {:04x}:{:04x} [{:02x}{:02x}] {}",
+            dev.address,
+            dev.vendor_id,
+            dev.device_id,
+            dev.class,
+            dev.subclass,
+            dev.class_name()
+        );
+    });
+}
+
+fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVM controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x0c, 0x03) => "USB controller",
+        (0x0c, 0x05) => "SMBus",
+        (0x0c, _) => "Serial bus controller",
+        _ => "Unknown device",
+    }
+}
+
+#[test_case]
+fn test_pci_decode_bars() {
+    assert_eq!(
+        decode_io_bar(0xc041, 0xffff_ffe1),
+        Bar::Io {
+            port: 0xc040,
+            size: 0x20
+        }
+    );
+    assert_eq!(
+        decode_memory_bar(0xfebf_0008, 0xffff_ffff_ffff_f008),
+        Bar::Memory {
+            address: 0xfebf_0000,
+            size: 0x1000,
+            prefetchable: true
+        }
+    );
+    assert_eq!(decode_memory_bar(0, 0xffff_ffff_0000_0000), Bar::None);
+}
+
+#[test_case]
+fn test_pci_msi_message() {
+    assert_eq!(msi_message(0x51, 0), (0xfee0_0000, 0x51));
+    assert_eq!(msi_message(0x5f, 3), (0xfee0_3000, 0x5f));
+    // 主桥没有 MSI
+    let bridge = find_class(0x06, 0x00).unwrap();
+    assert_eq!(bridge.capability(CAP_MSI), None);
+}
+
+#[test_case]
+fn test_pci_scan_finds_host_bridge() {
+    init();
+    assert!(find_class(0x06, 0x00).is_some());
+}
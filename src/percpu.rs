@@ -0,0 +1,128 @@
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+
+use crate::{
+    regs::{self, msr},
+    smp,
+};
+
+/// 每个 CPU 的运行队列能容纳的任务数。
+pub const RUN_QUEUE_LEN: usize = 32;
+
+/// 每个 CPU 的统计计数。
+#[derive(Debug)]
+pub struct Stats {
+    /// 收到的外部中断次数
+    pub interrupts: AtomicU64,
+    /// 收到的 Local APIC 定时器中断次数
+    pub timer_ticks: AtomicU64,
+}
+
+/// 每个 CPU 私有的数据块，通过 GS 段访问。
+///
+/// 字段只会被所属的 CPU 频繁读写，其他 CPU 偶尔读取统计信息或者往运行队列
+/// 里放任务，所以都用原子量或锁保护。
+#[repr(C)]
+pub struct PerCpu {
+    // 数据块自己的地址，必须是第一个字段：`gs:[0]` 读出来就是它
+    self_addr: AtomicUsize,
+    /// 在 `smp` 中的编号，BSP 是 0
+    pub id: AtomicUsize,
+    pub apic_id: AtomicU32,
+    /// 正在运行的任务，还没有调度器时一直为 0
+    pub current_task: AtomicUsize,
+    pub stats: Stats,
+    /// 等待在这个 CPU 上运行的任务，留给调度器使用
+    pub run_queue: Mutex<heapless::Deque<usize, RUN_QUEUE_LEN>>,
+}
+
+impl PerCpu {
+    const fn new() -> Self {
+        PerCpu {
+            self_addr: AtomicUsize::new(0),
+            id: AtomicUsize::new(0),
+            apic_id: AtomicU32::new(0),
+            current_task: AtomicUsize::new(0),
+            stats: Stats {
+                interrupts: AtomicU64::new(0),
+                timer_ticks: AtomicU64::new(0),
+            },
+            run_queue: Mutex::new(heapless::Deque::new()),
+        }
+    }
+}
+
+static BLOCKS: [PerCpu; smp::MAX_CPUS] = [const { PerCpu::new() }; smp::MAX_CPUS];
+
+/// 把第 `cpu` 个数据块交给当前 CPU：写入编号，并让 `IA32_GS_BASE` 指向它。
+///
+/// 每个 CPU 启动后要最先调用它，之后才能使用 [`current`] 和 [`per_cpu!`]。
+pub fn init(cpu: usize) {
+    let block = &BLOCKS[cpu];
+    let addr = block as *const PerCpu as usize;
+    // CPUID 叶 1 的 EBX 高 8 位是初始 APIC ID，不需要先映射 Local APIC
+    let apic_id = unsafe { core::arch::x86_64::__cpuid(1) }.ebx >> 24;
+    block.self_addr.store(addr, Ordering::Relaxed);
+    block.id.store(cpu, Ordering::Relaxed);
+    block.apic_id.store(apic_id, Ordering::Relaxed);
+    // 静态变量的地址一定是规范地址。IA32_KERNEL_GS_BASE 留一份，用户程序
+    // 改掉 GS 后从那里恢复
+    unsafe {
+        regs::write_msr(msr::GS_BASE, addr as u64).unwrap();
+        regs::write_msr(msr::KERNEL_GS_BASE, addr as u64).unwrap();
+    }
+}
+
+/// 中断或异常打断的是用户态（`cs` 的 RPL 为 3）时，把 `IA32_GS_BASE` 恢复
+/// 成当前 CPU 的数据块。每个中断入口都要在使用 [`current`] 之前调用它。
+///
+/// 用户程序可以执行 `mov gs, <选择子>`，GS 的基址随之变成描述符里的 0，
+/// 内核再读 `gs:[0]` 就会访问错误的地址。
+#[inline]
+pub fn restore_gs(cs: u64) {
+    if cs & 3 == 3 {
+        unsafe {
+            let addr = regs::read_msr(msr::KERNEL_GS_BASE);
+            regs::write_msr(msr::GS_BASE, addr).unwrap();
+        }
+    }
+}
+
+/// 当前 CPU 的数据块。只能在这个 CPU 调用过 [`init`] 之后使用。
+pub fn current() -> &'static PerCpu {
+    let addr: usize;
+    unsafe { asm!("mov {}, gs:[0]", out(reg) addr, options(nostack, readonly, preserves_flags)) };
+    unsafe { &*(addr as *const PerCpu) }
+}
+
+/// 当前 CPU 的编号。
+pub fn id() -> usize {
+    current().id.load(Ordering::Relaxed)
+}
+
+/// 第 `cpu` 个 CPU 的数据块，用于读取其他 CPU 的统计或者给它派发任务。
+pub fn get(cpu: usize) -> Option<&'static PerCpu> {
+    BLOCKS.get(cpu)
+}
+
+/// 访问当前 CPU 数据块的一个字段：`per_cpu!(stats).interrupts.load(...)`。
+#[macro_export]
+macro_rules! per_cpu {
+    ($field:ident) => {
+        &$crate::percpu::current().$field
+    };
+}
+
+#[test_case]
+fn test_percpu_current() {
+    assert_eq!(id(), 0);
+    assert!(core::ptr::eq(current(), &BLOCKS[0]));
+    let before = crate::per_cpu!(stats).interrupts.load(Ordering::Relaxed);
+    // 等一个时钟中断
+    x86_64::instructions::hlt();
+    assert!(crate::per_cpu!(stats).interrupts.load(Ordering::Relaxed) > before);
+}
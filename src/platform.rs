@@ -0,0 +1,236 @@
+use bootloader::{BootInfo, bootinfo::MemoryRegionType};
+use spin::Once;
+use x86_64::PhysAddr;
+
+use crate::{
+    framebuffer::{self, FrameBufferInfo},
+    multiboot2::{self, Multiboot2Error},
+};
+
+/// 内存布局最多保存的区域数，与 bootloader 的内存布局容量相同。
+pub const MAX_REGIONS: usize = 64;
+/// 最多记录的引导模块数。
+pub const MAX_MODULES: usize = 8;
+
+/// 内核是从哪种固件启动的。目前只有经 GRUB 以 Multiboot2 方式从 UEFI
+/// 启动时才会是 `Uefi`，bootloader 的启动路径总是 `Bios`。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    Bios,
+    Uefi,
+}
+
+/// 启动时可用的显示设备。
+#[derive(Debug, Clone, Copy)]
+pub enum Display {
+    /// 0xb8000 处的 VGA 文本缓冲区，只有 BIOS 启动时才有
+    VgaText,
+    /// 引导程序设置好的线性帧缓冲区（UEFI GOP 或 VBE）
+    FrameBuffer(FrameBufferInfo),
+}
+
+/// 物理内存区域的用途，只区分内核关心的几类。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// 空闲，可以分配
+    Usable,
+    /// 内核、页表、引导信息等引导程序放下的东西
+    Bootloader,
+    /// ACPI 表，读完之后可以回收
+    AcpiReclaimable,
+    /// 固件保留或者不存在
+    Reserved,
+}
+
+/// 一段物理内存 `[start, end)`。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub end: u64,
+    pub kind: RegionKind,
+}
+
+/// 引导程序和内核一起加载的文件（Multiboot2 模块），`[start, end)` 是物理
+/// 地址。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Module {
+    pub start: u64,
+    pub end: u64,
+    pub cmdline: &'static str,
+}
+
+/// 与引导方式无关的启动信息。BIOS 和 UEFI 的差别（显示方式、内存布局的
+/// 格式、RSDP 从哪里来）都在这里抹平，其他模块只通过本模块查询。
+#[derive(Debug)]
+pub struct Platform {
+    pub firmware: Firmware,
+    /// 全部物理内存线性映射到的虚拟地址偏移
+    pub physical_memory_offset: u64,
+    pub display: Display,
+    /// 引导程序给出的 RSDP 物理地址；BIOS 启动时没有，要自己扫描
+    pub rsdp: Option<PhysAddr>,
+    pub memory_map: heapless::Vec<MemoryRegion, MAX_REGIONS>,
+    pub modules: heapless::Vec<Module, MAX_MODULES>,
+}
+
+static PLATFORM: Once<Platform> = Once::new();
+
+/// 从 bootloader 的启动信息中取出平台信息。必须最先调用。
+///
+/// 目前使用的 bootloader 0.9 只支持 BIOS 启动：显示用 VGA 文本模式，也不
+/// 提供 RSDP。内核还不能直接作为 UEFI 程序启动，那需要换成 bootloader
+/// 0.11 并改用它的入口和启动信息，到时只需在这里增加一个转换函数。
+pub fn init(boot_info: &'static BootInfo) {
+    PLATFORM.call_once(|| {
+        let mut memory_map = heapless::Vec::new();
+        for region in boot_info.memory_map.iter() {
+            let kind = match region.region_type {
+                MemoryRegionType::Usable => RegionKind::Usable,
+                MemoryRegionType::AcpiReclaimable => RegionKind::AcpiReclaimable,
+                MemoryRegionType::Kernel
+                | MemoryRegionType::KernelStack
+                | MemoryRegionType::PageTable
+                | MemoryRegionType::Bootloader
+                | MemoryRegionType::BootInfo
+                | MemoryRegionType::Package
+                | MemoryRegionType::FrameZero => RegionKind::Bootloader,
+                _ => RegionKind::Reserved,
+            };
+            let _ = memory_map.push(MemoryRegion {
+                start: region.range.start_addr(),
+                end: region.range.end_addr(),
+                kind,
+            });
+        }
+        Platform {
+            firmware: Firmware::Bios,
+            physical_memory_offset: boot_info.physical_memory_offset,
+            display: Display::VgaText,
+            rsdp: None,
+            memory_map,
+            modules: heapless::Vec::new(),
+        }
+    });
+}
+
+/// 从 Multiboot2 信息结构中取出平台信息，代替 [`init`]。
+///
+/// `magic` 是引导程序放在 EAX 中的值，`info` 是信息结构的物理地址，
+/// `kernel` 是内核映像占用的物理地址范围。调用者（入口处的汇编代码）要
+/// 先进入长模式，并把全部物理内存映射到 `physical_memory_offset` 处。
+///
+/// 内核映像里还没有 Multiboot2 头和这样的入口代码：映像的布局和入口由
+/// bootimage 和 bootloader 0.9 决定，GRUB 或 QEMU `-kernel` 还不能直接加载
+/// 它。这里只负责把信息结构转换成平台信息。
+///
+/// # Safety
+/// `info` 必须指向引导程序留下的完整信息结构，并且之后不会被覆盖。
+pub unsafe fn init_multiboot2(
+    magic: u32,
+    info: PhysAddr,
+    physical_memory_offset: u64,
+    kernel: (u64, u64),
+) -> Result<(), Multiboot2Error> {
+    if magic != multiboot2::BOOTLOADER_MAGIC {
+        return Err(Multiboot2Error::BadMagic(magic));
+    }
+    let virt = (physical_memory_offset + info.as_u64()) as *const u8;
+    let total = unsafe { virt.cast::<u32>().read_unaligned() } as usize;
+    let data = unsafe { core::slice::from_raw_parts(virt, total) };
+    let platform = multiboot2::parse(data, info, physical_memory_offset, kernel)?;
+    PLATFORM.call_once(|| platform);
+    Ok(())
+}
+
+/// 把 `[start, end)` 从可用内存中扣掉，标记为引导程序占用，必要时把一个
+/// 区域拆成两三个。区域表满了返回 false。
+pub(crate) fn reserve(
+    map: &mut heapless::Vec<MemoryRegion, MAX_REGIONS>,
+    start: u64,
+    end: u64,
+) -> bool {
+    let mut index = 0;
+    while index < map.len() {
+        let region = map[index];
+        if region.kind != RegionKind::Usable || end <= region.start || region.end <= start {
+            index += 1;
+            continue;
+        }
+        let pieces = [
+            (region.start, start.max(region.start), RegionKind::Usable),
+            (
+                start.max(region.start),
+                end.min(region.end),
+                RegionKind::Bootloader,
+            ),
+            (end.min(region.end), region.end, RegionKind::Usable),
+        ];
+        map.remove(index);
+        for (start, end, kind) in pieces {
+            if start < end {
+                if map
+                    .insert(index, MemoryRegion { start, end, kind })
+                    .is_err()
+                {
+                    return false;
+                }
+                index += 1;
+            }
+        }
+    }
+    true
+}
+
+/// 平台信息。在 [`init`] 之前调用会 panic。
+pub fn get() -> &'static Platform {
+    PLATFORM.r#try().expect("platform not initialized")
+}
+
+pub fn firmware() -> Firmware {
+    get().firmware
+}
+
+pub fn physical_memory_offset() -> u64 {
+    get().physical_memory_offset
+}
+
+pub fn memory_map() -> &'static [MemoryRegion] {
+    &get().memory_map
+}
+
+/// 引导程序给出的 RSDP 地址，没有时为 `None`。
+pub fn rsdp() -> Option<PhysAddr> {
+    PLATFORM.r#try().and_then(|platform| platform.rsdp)
+}
+
+pub fn display() -> Display {
+    get().display
+}
+
+/// 有帧缓冲区时在上面建立控制台。字体来自 fw_cfg，要在内存管理初始化之后
+/// 调用；没有字体时只能继续使用串口。
+pub fn init_display() {
+    let Display::FrameBuffer(info) = display() else {
+        return;
+    };
+    let Some(font) = framebuffer::load_font() else {
+        log::warn!("platform: no console font, framebuffer unused");
+        return;
+    };
+    // 帧缓冲区由引导程序映射好，之后只有控制台使用它
+    if let Err(err) = unsafe { framebuffer::init(info, font) } {
+        log::error!("platform: framebuffer console: {err:?}");
+    }
+}
+
+#[test_case]
+fn test_platform_memory_map() {
+    let map = memory_map();
+    assert!(map.iter().any(|region| region.kind == RegionKind::Usable));
+    assert!(map.iter().all(|region| region.start <= region.end));
+    // 内核本身所在的内存不能被当作空闲内存
+    assert!(
+        map.iter()
+            .any(|region| region.kind == RegionKind::Bootloader)
+    );
+}
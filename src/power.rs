@@ -0,0 +1,182 @@
+use x86_64::{
+    VirtAddr,
+    instructions::{interrupts, port::Port, tables::lidt},
+    structures::DescriptorTablePointer,
+};
+
+use crate::{acpi, hlt_loop, i8042};
+
+// PM1 控制寄存器中的位
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_EN: u16 = 1 << 13;
+
+// 没有 ACPI 时的退路：(端口, 值)，依次是较新的 QEMU、Bochs 和旧版
+// QEMU、VirtualBox
+const FALLBACK_PORTS: [(u16, u16); 3] = [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+
+// 每次尝试之后等待生效的循环次数
+const SETTLE_SPINS: u32 = 1_000_000;
+
+// AML 操作码
+const NAME_OP: u8 = 0x08;
+const ROOT_CHAR: u8 = b'\\';
+const PACKAGE_OP: u8 = 0x12;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const BYTE_PREFIX: u8 = 0x0a;
+
+fn settle() {
+    for _ in 0..SETTLE_SPINS {
+        core::hint::spin_loop();
+    }
+}
+
+/// 从 DSDT 的 AML 中找出 `\_S5` 对象，返回 PM1a 和 PM1b 的 SLP_TYP。
+///
+/// 只认固件常见的写法：`Name (_S5, Package () { a, b, ... })`，前两个元素是
+/// 字节常量。不需要完整的 AML 解释器。`_S5_` 也可能先以引用或者其他写法
+/// 出现，所以依次尝试每一处，直到找到能解析的定义。
+fn s5_sleep_types(dsdt: &[u8]) -> Option<(u8, u8)> {
+    dsdt.windows(4)
+        .enumerate()
+        .filter(|(_, window)| *window == b"_S5_")
+        .find_map(|(name, _)| parse_s5(dsdt, name))
+}
+
+/// 解析从偏移 `name` 处的 `_S5_` 开始的定义。
+fn parse_s5(dsdt: &[u8], name: usize) -> Option<(u8, u8)> {
+    let is_name = matches!(dsdt.get(..name)?, [.., NAME_OP] | [.., NAME_OP, ROOT_CHAR]);
+    let mut rest = dsdt.get(name + 4..)?;
+    if !is_name || *rest.first()? != PACKAGE_OP {
+        return None;
+    }
+    // PkgLength 第一个字节的高两位是后面还有几个字节，之后是元素个数
+    let extra = (*rest.get(1)? >> 6) as usize;
+    rest = rest.get(2 + extra + 1..)?;
+    let mut integer = || -> Option<u8> {
+        let (value, len) = match *rest.first()? {
+            ZERO_OP => (0, 1),
+            ONE_OP => (1, 1),
+            BYTE_PREFIX => (*rest.get(1)?, 2),
+            _ => return None,
+        };
+        rest = &rest[len..];
+        Some(value)
+    };
+    Some((integer()?, integer()?))
+}
+
+/// 需要时通过 SMI 命令端口把芯片组切换到 ACPI 模式。
+fn enable_acpi(fadt: &acpi::Fadt) {
+    let mut control: Port<u16> = Port::new(fadt.pm1a_control as u16);
+    if unsafe { control.read() } & SCI_EN != 0 || fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    unsafe { Port::<u8>::new(fadt.smi_command as u16).write(fadt.acpi_enable) };
+    for _ in 0..SETTLE_SPINS {
+        if unsafe { control.read() } & SCI_EN != 0 {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// 按 ACPI 的方式进入 S5（软关机）。只有失败时才会返回。
+fn acpi_shutdown() -> Option<()> {
+    let acpi = acpi::get()?;
+    let fadt = acpi.fadt?;
+    if fadt.pm1a_control == 0 {
+        return None;
+    }
+    let (slp_typ_a, slp_typ_b) = s5_sleep_types(acpi.find(b"DSDT")?.data)?;
+    enable_acpi(&fadt);
+    unsafe {
+        let value = |slp_typ: u8| ((slp_typ as u16) << SLP_TYP_SHIFT) | SLP_EN;
+        let mut pm1a: Port<u16> = Port::new(fadt.pm1a_control as u16);
+        let old = pm1a.read() & !(0x7 << SLP_TYP_SHIFT);
+        pm1a.write(old | value(slp_typ_a));
+        if fadt.pm1b_control != 0 {
+            let mut pm1b: Port<u16> = Port::new(fadt.pm1b_control as u16);
+            let old = pm1b.read() & !(0x7 << SLP_TYP_SHIFT);
+            pm1b.write(old | value(slp_typ_b));
+        }
+    }
+    settle();
+    // 还在运行说明没有成功
+    None
+}
+
+/// 关机。先走 ACPI S5，失败时尝试模拟器的关机端口，都不行就停在这里。
+///
+/// 还没有 shell，它是将来 `shutdown` 命令的实现。
+pub fn shutdown() -> ! {
+    log::info!("power: shutting down");
+    #[cfg(feature = "heapcheck")]
+    if let Some(leaked) = crate::fs::tmpfs::heapcheck() {
+        log::info!("power: heapcheck found {leaked} leaked tmpfs blocks");
+    }
+    interrupts::disable();
+    if acpi_shutdown().is_none() {
+        log::warn!("power: ACPI shutdown failed, trying emulator ports");
+    }
+    for (port, value) in FALLBACK_PORTS {
+        unsafe { Port::<u16>::new(port).write(value) };
+        settle();
+    }
+    log::error!("power: shutdown failed, halting");
+    hlt_loop();
+}
+
+/// 重启。先让键盘控制器复位 CPU，失败时故意触发三重错误。
+///
+/// 还没有 shell，它是将来 `reboot` 命令的实现。
+pub fn reboot() -> ! {
+    log::info!("power: rebooting");
+    interrupts::disable();
+    if i8042::pulse_reset().is_ok() {
+        settle();
+    }
+    // IDT 为空时任何异常都无法处理，最终变成三重错误，CPU 复位
+    let empty = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    };
+    unsafe {
+        lidt(&empty);
+        core::arch::asm!("int3", options(nomem, nostack));
+    }
+    hlt_loop();
+}
+
+#[test_case]
+fn test_power_s5_sleep_types() {
+    // QEMU 的 DSDT：Name (\_S5, Package (0x04) { Zero, Zero, Zero, Zero })
+    let qemu = [
+        0x10, NAME_OP, ROOT_CHAR, b'_', b'S', b'5', b'_', PACKAGE_OP, 0x06, 0x04, 0, 0, 0, 0,
+    ];
+    assert_eq!(s5_sleep_types(&qemu), Some((0, 0)));
+    // 两字节的 PkgLength 和 BytePrefix 常量
+    let other = [
+        NAME_OP,
+        b'_',
+        b'S',
+        b'5',
+        b'_',
+        PACKAGE_OP,
+        0x40,
+        0x00,
+        0x02,
+        BYTE_PREFIX,
+        0x07,
+        ONE_OP,
+    ];
+    assert_eq!(s5_sleep_types(&other), Some((7, 1)));
+    // 对 _S5_ 的引用，而不是定义
+    assert_eq!(s5_sleep_types(b"\x70_S5_\x12\x06\x04\0\0"), None);
+    // 引用在前，定义在后
+    assert_eq!(
+        s5_sleep_types(b"\x70_S5_\x00\x08_S5_\x12\x06\x04\x0a\x05\x01"),
+        Some((5, 1))
+    );
+}
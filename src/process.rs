@@ -0,0 +1,317 @@
+use core::{
+    arch::naked_asm,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    registers::control::Cr2,
+    structures::paging::{Page, PageTableFlags, mapper::MapToError},
+};
+
+use crate::{
+    cmdline, console, crash,
+    elf::{self, Elf, ElfError},
+    fs::{self, FsError},
+    gdt,
+    interrupts::FaultFrame,
+    memory::{self, PAGE_SIZE},
+};
+
+/// 启动后运行的第一个用户程序，可以用命令行的 `init=<路径>` 修改。
+pub const DEFAULT_INIT: &str = "/bin/init";
+/// 同时存在的进程数上限。父进程要等子进程退出，所以这也是嵌套的深度。
+pub const MAX_PROCESSES: usize = 4;
+/// 被异常杀死的进程的退出码，与 shell 中被 SIGSEGV 杀死的进程相同。
+pub const EXIT_FAULT: i64 = 128 + 11;
+/// 被 Ctrl+C 打断的进程的退出码，与 shell 中被 SIGINT 杀死的进程相同。
+pub const EXIT_INTERRUPT: i64 = 128 + 2;
+
+// 用户栈位于用户空间的最高处
+const USER_STACK_PAGES: u64 = 4;
+const USER_STACK_BOTTOM: u64 = memory::USER_END - USER_STACK_PAGES * PAGE_SIZE;
+// 每个进程陷入内核时使用的内核栈
+const KERNEL_STACK_PAGES: usize = 4;
+
+pub type Pid = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    Fs(FsError),
+    Elf(ElfError),
+    /// 段不在用户空间中，或者两个段占用了同一页
+    BadLayout,
+    NoMemory,
+    /// 进程数已经达到 `MAX_PROCESSES`
+    TooMany,
+}
+
+// 还没有调度器，存在的进程总是一条父子链，最后一个是正在运行的
+static PROCESSES: Mutex<heapless::Vec<Pid, MAX_PROCESSES>> = Mutex::new(heapless::Vec::new());
+// 每一层进程进入用户态前保存的内核栈指针，进程退出时从这里返回
+static CONTEXTS: [AtomicU64; MAX_PROCESSES] = [const { AtomicU64::new(0) }; MAX_PROCESSES];
+static NEXT_PID: AtomicU32 = AtomicU32::new(1);
+
+/// 运行 init 程序（`DEFAULT_INIT` 或命令行 `init=<路径>`）并等它退出，它的
+/// 进程号是 1。找不到程序（比如没有 initrd）时什么也不做。
+pub fn start_init() {
+    let path = cmdline::get("init").unwrap_or(DEFAULT_INIT);
+    match spawn(path) {
+        Ok(status) => log::info!("process: {path} exited with status {status}"),
+        Err(SpawnError::Fs(FsError::NotFound)) => {}
+        Err(err) => log::error!("process: cannot run {path}: {err:?}"),
+    }
+}
+
+/// 运行 `path` 处的程序，等它退出后返回它的退出码。
+pub fn spawn(path: &str) -> Result<i64, SpawnError> {
+    let mut file = fs::open(path).map_err(SpawnError::Fs)?;
+    let size = file.metadata().map_err(SpawnError::Fs)?.size as usize;
+    let mut buffer =
+        memory::alloc_dma(size.div_ceil(PAGE_SIZE as usize).max(1)).ok_or(SpawnError::NoMemory)?;
+    let result = file
+        .read_all(&mut buffer.as_mut_slice()[..size])
+        .map_err(SpawnError::Fs)
+        .and_then(|len| run(&buffer.as_slice()[..len]));
+    memory::free_dma(buffer);
+    result
+}
+
+/// 在新的进程中运行内存中的 ELF 可执行文件，等它退出后返回它的退出码。
+///
+/// 每个进程有自己的用户空间。还没有调度器，父进程（或者内核）在子进程
+/// 运行期间一直等待，它的用户空间暂时从页表上摘下，子进程退出后再换回。
+pub fn run(image: &[u8]) -> Result<i64, SpawnError> {
+    let elf = Elf::parse(image).map_err(SpawnError::Elf)?;
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    let kernel_stack = memory::alloc_stack(KERNEL_STACK_PAGES).ok_or(SpawnError::NoMemory)?;
+    let depth = {
+        let mut processes = PROCESSES.lock();
+        if processes.push(pid).is_err() {
+            drop(processes);
+            unsafe { memory::free_stack(kernel_stack, KERNEL_STACK_PAGES) };
+            return Err(SpawnError::TooMany);
+        }
+        processes.len() - 1
+    };
+
+    let parent = unsafe { memory::switch_user_space(None) };
+    // 子进程运行期间由它占有控制台，Ctrl+C 结束的是它而不是父进程
+    let foreground = console::enter_foreground("process", None);
+    let result = load(&elf).map(|()| {
+        let (code, data) = gdt::user_selectors();
+        unsafe {
+            let parent_stack = gdt::set_kernel_stack(kernel_stack);
+            // 入口处的栈像是刚被 call 过，与普通函数一样
+            let status = enter_user(
+                elf.entry,
+                memory::USER_END - 8,
+                CONTEXTS[depth].as_ptr(),
+                code.0 as u64,
+                data.0 as u64,
+            );
+            gdt::set_kernel_stack(parent_stack);
+            status
+        }
+    });
+    drop(foreground);
+    unsafe {
+        memory::free_user_space();
+        memory::switch_user_space(parent);
+        memory::free_stack(kernel_stack, KERNEL_STACK_PAGES);
+    }
+    PROCESSES.lock().pop();
+    result
+}
+
+// 把程序的段和用户栈映射到当前（空的）用户空间
+fn load(elf: &Elf) -> Result<(), SpawnError> {
+    let map = |page: Page, flags| {
+        memory::map_user_page(page, flags).map_err(|err| match err {
+            MapToError::PageAlreadyMapped(_) => SpawnError::BadLayout,
+            _ => SpawnError::NoMemory,
+        })
+    };
+    for segment in elf.segments().filter(|segment| segment.mem_size > 0) {
+        let end = segment.vaddr + segment.mem_size;
+        if segment.vaddr < memory::USER_BASE || end > USER_STACK_BOTTOM {
+            return Err(SpawnError::BadLayout);
+        }
+        let mut flags = PageTableFlags::empty();
+        if segment.flags & elf::PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if segment.flags & elf::PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        let data = segment.data(elf);
+        let first = Page::containing_address(VirtAddr::new(segment.vaddr));
+        let last = Page::containing_address(VirtAddr::new(end - 1));
+        for page in Page::range_inclusive(first, last) {
+            let frame = map(page, flags)?;
+            // 页是清零的，只需复制属于文件内容的部分；通过线性映射写入，
+            // 只读的页也能写
+            let page_start = page.start_address().as_u64();
+            let from = page_start.max(segment.vaddr);
+            let to = (page_start + PAGE_SIZE).min(segment.vaddr + segment.file_size);
+            if from < to {
+                let src = &data[(from - segment.vaddr) as usize..(to - segment.vaddr) as usize];
+                let dst = memory::phys_to_virt(frame.start_address()) + (from - page_start);
+                unsafe {
+                    core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), src.len())
+                };
+            }
+        }
+    }
+    for i in 0..USER_STACK_PAGES {
+        let page = Page::containing_address(VirtAddr::new(USER_STACK_BOTTOM + i * PAGE_SIZE));
+        map(page, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)?;
+    }
+    Ok(())
+}
+
+/// 正在运行的进程的进程号，没有进程时为 0。
+pub fn current_pid() -> Pid {
+    PROCESSES.lock().last().copied().unwrap_or(0)
+}
+
+/// 结束当前进程，回到等待它的 [`run`]。由系统调用和异常处理函数调用。
+pub(crate) fn exit(status: i64) -> ! {
+    let depth = PROCESSES.lock().len();
+    assert!(depth > 0, "exit without a process");
+    unsafe { leave_user(CONTEXTS[depth - 1].load(Ordering::Relaxed), status) }
+}
+
+/// 在回到用户态之前调用（时钟中断发送 EOI 之后、系统调用返回之前）：
+/// 被打断的是用户态代码，并且前台收到了 Ctrl+C 时，结束当前进程。
+pub(crate) fn check_interrupt(code_segment: u64) {
+    if code_segment & 3 == 3 && console::take_interrupt() {
+        log::info!("process {}: interrupted", current_pid());
+        exit(EXIT_INTERRUPT);
+    }
+}
+
+/// 用户态代码触发了异常：报告后杀死当前进程。
+pub(crate) fn kill(vector: u8, frame: &FaultFrame) -> ! {
+    let name = crash::exception_name(vector);
+    if vector == 14 {
+        log::error!(
+            "process {}: {} at {:#x} accessing {:#x}, killed",
+            current_pid(),
+            name,
+            frame.rip,
+            Cr2::read_raw()
+        );
+    } else {
+        log::error!(
+            "process {}: {} at {:#x}, killed",
+            current_pid(),
+            name,
+            frame.rip
+        );
+    }
+    exit(EXIT_FAULT)
+}
+
+/// 保存内核的被调用者保存寄存器和 RFLAGS，把栈指针记到 `context`，然后用
+/// iretq 进入用户态。进程退出时 [`leave_user`] 从 `context` 恢复，看起来
+/// 就像这个函数返回了退出码。
+#[unsafe(naked)]
+unsafe extern "C" fn enter_user(
+    entry: u64,
+    stack: u64,
+    context: *mut u64,
+    cs: u64,
+    ss: u64,
+) -> i64 {
+    naked_asm!(
+        "pushfq",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdx], rsp",
+        // iretq 的栈帧：SS、RSP、RFLAGS（只打开中断）、CS、RIP
+        "push r8",
+        "push rsi",
+        "push 0x202",
+        "push rcx",
+        "push rdi",
+        // 不把内核的寄存器值带进用户态
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "iretq",
+    )
+}
+
+/// 丢弃当前的内核栈，回到 [`enter_user`] 保存的 `context`，让它返回 `status`。
+#[unsafe(naked)]
+unsafe extern "C" fn leave_user(context: u64, status: i64) -> ! {
+    naked_asm!(
+        "mov rsp, rdi",
+        "mov rax, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "popfq",
+        "ret",
+    )
+}
+
+#[test_case]
+fn test_process_run() {
+    let mut image = [0u8; 256];
+    // mov edi, 42; xor eax, eax; int 0x80（exit(42)）
+    let exit_42 = [0xbf, 42, 0, 0, 0, 0x31, 0xc0, 0xcd, 0x80];
+    let len = elf::build_test_image(memory::USER_BASE, &exit_42, &mut image);
+    assert_eq!(run(&image[..len]), Ok(42));
+    assert_eq!(current_pid(), 0);
+    assert!(!memory::is_user_range(memory::USER_BASE, 1));
+
+    // 用户态执行 hlt 触发 #GP，进程被杀死，内核继续运行
+    let len = elf::build_test_image(memory::USER_BASE, &[0xf4], &mut image);
+    assert_eq!(run(&image[..len]), Ok(EXIT_FAULT));
+
+    // 用户态把 GS 换成自己的数据段（基址变成 0），之后的时钟中断仍要能
+    // 找到每个 CPU 的数据块
+    #[rustfmt::skip]
+    let reload_gs = [
+        0xb8, 4, 0, 0, 0, 0xcd, 0x80,   // mov eax, UPTIME; int 0x80
+        0x48, 0x8d, 0x58, 50,           // lea rbx, [rax + 50]
+        0x8c, 0xd0, 0x8e, 0xe8,         // 1: mov eax, ss; mov gs, eax
+        0xb9, 0, 0, 0x10, 0,            // mov ecx, 0x100000
+        0xff, 0xc9, 0x75, 0xfc,         // 2: dec ecx; jnz 2b
+        0xb8, 4, 0, 0, 0, 0xcd, 0x80,   // mov eax, UPTIME; int 0x80
+        0x48, 0x39, 0xd8, 0x72, 0xe7,   // cmp rax, rbx; jb 1b
+        0x31, 0xff, 0x31, 0xc0, 0xcd, 0x80, // exit(0)
+    ];
+    let len = elf::build_test_image(memory::USER_BASE, &reload_gs, &mut image);
+    assert_eq!(run(&image[..len]), Ok(0));
+    assert!(core::ptr::eq(
+        crate::percpu::current(),
+        crate::percpu::get(0).unwrap()
+    ));
+
+    // 段落在用户空间之外
+    let len = elf::build_test_image(0x20_0000, &exit_42, &mut image);
+    assert_eq!(run(&image[..len]), Err(SpawnError::BadLayout));
+}
@@ -0,0 +1,180 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{backtrace::Symbols, cmdline, println, time};
+
+/// 直方图能记录的不同地址个数，必须是 2 的幂。
+pub const SLOTS: usize = 4096;
+/// 报告中按函数汇总时最多区分的函数个数，其余计入 “其他”。
+pub const MAX_FUNCTIONS: usize = 256;
+
+// 线性探测的最大步数，超过后这次采样计入 DROPPED
+const MAX_PROBES: usize = 16;
+
+// 地址为 0 表示空槽位；槽位一旦被占用就不再改变，直到 `start` 清空
+static ADDRS: [AtomicU64; SLOTS] = [const { AtomicU64::new(0) }; SLOTS];
+static COUNTS: [AtomicU64; SLOTS] = [const { AtomicU64::new(0) }; SLOTS];
+static TOTAL: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// 命令行带有 `profile` 时从启动开始采样。
+pub fn init() {
+    if cmdline::flag("profile") {
+        start();
+    }
+}
+
+pub fn running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// 清空直方图并开始在每个时钟中断采样被打断的指令地址。
+///
+/// 还没有 shell，它和 [`stop`]、[`report`] 是将来 `profile start/stop/report`
+/// 命令的实现。
+pub fn start() {
+    RUNNING.store(false, Ordering::Relaxed);
+    for (addr, count) in ADDRS.iter().zip(&COUNTS) {
+        addr.store(0, Ordering::Relaxed);
+        count.store(0, Ordering::Relaxed);
+    }
+    TOTAL.store(0, Ordering::Relaxed);
+    DROPPED.store(0, Ordering::Relaxed);
+    RUNNING.store(true, Ordering::Release);
+}
+
+/// 停止采样，已有的数据保留到下次 [`start`]。
+pub fn stop() {
+    RUNNING.store(false, Ordering::Relaxed);
+}
+
+/// 记录一次采样。由时钟中断调用，无锁。
+pub fn sample(rip: u64) {
+    TOTAL.fetch_add(1, Ordering::Relaxed);
+    // 斐波那契散列，让相邻的地址分散开
+    let hash = rip.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - SLOTS.trailing_zeros());
+    for probe in 0..MAX_PROBES {
+        let index = (hash as usize + probe) % SLOTS;
+        let slot = match ADDRS[index].compare_exchange(0, rip, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => rip,
+            Err(current) => current,
+        };
+        if slot == rip {
+            COUNTS[index].fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    }
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 总采样数，包括因为直方图满了而丢弃的。
+pub fn total() -> u64 {
+    TOTAL.load(Ordering::Relaxed)
+}
+
+/// 遍历直方图中的 `(地址, 采样数)`，顺序不定。
+pub fn for_each(mut f: impl FnMut(u64, u64)) {
+    for (addr, count) in ADDRS.iter().zip(&COUNTS) {
+        let addr = addr.load(Ordering::Acquire);
+        let count = count.load(Ordering::Relaxed);
+        if addr != 0 && count != 0 {
+            f(addr, count);
+        }
+    }
+}
+
+/// 一个热点：解析出符号时是整个函数，否则是单个地址。
+#[derive(Debug, Clone, Copy)]
+struct Hotspot {
+    addr: u64,
+    name: Option<&'static str>,
+    samples: u64,
+}
+
+/// 把采样按函数汇总，输出最热的 `limit` 项。
+pub fn report(limit: usize) {
+    let symbols = Symbols::embedded();
+    let mut hotspots = heapless::Vec::<Hotspot, MAX_FUNCTIONS>::new();
+    let mut other = DROPPED.load(Ordering::Relaxed);
+    for_each(|rip, samples| {
+        let (addr, name) = match symbols.and_then(|symbols| symbols.resolve(rip)) {
+            Some((name, offset)) => (rip - offset, Some(name)),
+            None => (rip, None),
+        };
+        let new = Hotspot {
+            addr,
+            name,
+            samples,
+        };
+        if let Some(hotspot) = hotspots.iter_mut().find(|hotspot| hotspot.addr == addr) {
+            hotspot.samples += samples;
+        } else if let Err(new) = hotspots.push(new) {
+            // 满了就挤掉最冷的一项，热点不会因为出现得晚而被归入 “其他”
+            let coldest = hotspots
+                .iter_mut()
+                .min_by_key(|hotspot| hotspot.samples)
+                .unwrap();
+            if coldest.samples < new.samples {
+                other += core::mem::replace(coldest, new).samples;
+            } else {
+                other += new.samples;
+            }
+        }
+    });
+    hotspots.sort_unstable_by(|a, b| b.samples.cmp(&a.samples));
+
+    let total = total();
+    let percent = |samples: u64| {
+        let permille = samples * 1000 / total.max(1);
+        (permille / 10, permille % 10)
+    };
+    println!(
+        "{} samples ({} ms), {}",
+        total,
+        time::ticks_to_ms(total),
+        if running() { "running" } else { "stopped" }
+    );
+    for hotspot in hotspots.iter().take(limit) {
+        let (whole, tenth) = percent(hotspot.samples);
+        println!(
+            "{:>8} {:>3}.{}% {:#018x} {}",
+            hotspot.samples,
+            whole,
+            tenth,
+            hotspot.addr,
+            hotspot.name.unwrap_or("??")
+        );
+    }
+    if other > 0 {
+        let (whole, tenth) = percent(other);
+        println!("{:>8} {:>3}.{}% (other)", other, whole, tenth);
+    }
+    if symbols.is_none() {
+        println!("(no symbol table, run tools/embed_symbols.py on the kernel)");
+    }
+}
+
+#[test_case]
+fn test_profile_histogram() {
+    // 不在内核里的地址，不会和时钟中断的真实采样混在一起
+    const HOT: u64 = 0x10_0000;
+    const COLD: u64 = 0x10_0040;
+
+    start();
+    let before = total();
+    for _ in 0..3 {
+        sample(HOT);
+    }
+    sample(COLD);
+    stop();
+    assert!(total() >= before + 4);
+
+    let (mut hot, mut cold) = (0, 0);
+    for_each(|addr, samples| match addr {
+        HOT => hot += samples,
+        COLD => cold += samples,
+        _ => {}
+    });
+    assert_eq!((hot, cold), (3, 1));
+}
@@ -0,0 +1,134 @@
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use sha2::{Digest, Sha256};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    cpu::{self, Feature},
+    time,
+};
+
+// 熵池的大小（64 位字）
+const POOL_WORDS: usize = 8;
+// RDRAND/RDSEED 暂时没有数据时的重试次数
+const HARDWARE_RETRIES: usize = 10;
+
+// 中断时刻的 TSC 等不可预测的值不断混进这里，写入不加锁
+static POOL: [AtomicU64; POOL_WORDS] = [const { AtomicU64::new(0) }; POOL_WORDS];
+static POOL_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+// 输出的密钥，每次取数后更新：即使泄露了当前的密钥，也推不出之前的输出
+static KEY: Mutex<[u8; 32]> = Mutex::new([0; 32]);
+
+/// 把一个不可预测的值混入熵池。无锁，可以在中断处理函数中调用。
+pub fn add_entropy(value: u64) {
+    let index = POOL_INDEX.fetch_add(1, Ordering::Relaxed) % POOL_WORDS;
+    let value = value.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let _ = POOL[index].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+        Some(old.rotate_left(13) ^ value)
+    });
+}
+
+/// 由中断入口调用：中断到达的时刻相对于 TSC 的抖动是主要的熵来源。
+pub(crate) fn add_interrupt_entropy(irq: u8) {
+    add_entropy(time::tsc() ^ ((irq as u64) << 56));
+}
+
+fn rdrand() -> Option<u64> {
+    (0..HARDWARE_RETRIES).find_map(|_| {
+        let (value, ok): (u64, u8);
+        unsafe {
+            asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack))
+        };
+        (ok != 0).then_some(value)
+    })
+}
+
+fn rdseed() -> Option<u64> {
+    (0..HARDWARE_RETRIES).find_map(|_| {
+        let (value, ok): (u64, u8);
+        unsafe {
+            asm!("rdseed {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack))
+        };
+        (ok != 0).then_some(value)
+    })
+}
+
+/// CPU 提供的随机数，RDSEED 优先。CPU 都不支持时返回 `None`。
+fn hardware() -> Option<u64> {
+    let seed = cpu::has(Feature::Rdseed).then(rdseed).flatten();
+    seed.or_else(|| cpu::has(Feature::Rdrand).then(rdrand).flatten())
+}
+
+/// CPU 是否提供硬件随机数。
+pub fn hardware_available() -> bool {
+    cpu::has(Feature::Rdseed) || cpu::has(Feature::Rdrand)
+}
+
+/// 用随机字节填满 `buf`。
+///
+/// 输出由 SHA-256 从密钥、熵池、TSC 和（如果有）硬件随机数中导出，即使
+/// RDRAND 不可信或者不存在，结果也不会比熵池更差。
+pub fn fill(buf: &mut [u8]) {
+    let mut hasher = Sha256::new();
+    for word in &POOL {
+        hasher.update(word.load(Ordering::Relaxed).to_le_bytes());
+    }
+    hasher.update(time::tsc().to_le_bytes());
+    for _ in 0..4 {
+        if let Some(value) = hardware() {
+            hasher.update(value.to_le_bytes());
+        }
+    }
+    interrupts::without_interrupts(|| {
+        let mut key = KEY.lock();
+        hasher.update(*key);
+        let seed = hasher.finalize();
+        for (counter, chunk) in buf.chunks_mut(32).enumerate() {
+            let block = Sha256::new()
+                .chain_update(seed)
+                .chain_update((counter as u64).to_le_bytes())
+                .finalize();
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        *key = Sha256::new()
+            .chain_update(seed)
+            .chain_update(b"rekey")
+            .finalize()
+            .into();
+    });
+}
+
+/// 一个随机的 `u64`。
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// 用启动时能拿到的 TSC 和硬件随机数给熵池打底。
+pub fn init() {
+    add_entropy(time::tsc());
+    for _ in 0..POOL_WORDS {
+        add_entropy(hardware().unwrap_or_else(time::tsc));
+    }
+    if !hardware_available() {
+        log::warn!("rand: no RDRAND/RDSEED, relying on interrupt timing");
+    }
+}
+
+#[test_case]
+fn test_rand_fill() {
+    let mut a = [0u8; 45];
+    let mut b = [0u8; 45];
+    fill(&mut a);
+    fill(&mut b);
+    assert_ne!(a, b);
+    // 最后一块不满 32 字节也要填上
+    assert!(a[32..].iter().any(|&byte| byte != 0));
+    assert_ne!(next_u64(), next_u64());
+}
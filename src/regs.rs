@@ -0,0 +1,183 @@
+use x86_64::{
+    VirtAddr,
+    registers::{
+        control::{Cr3, Cr3Flags},
+        model_specific::Msr,
+    },
+    structures::paging::PhysFrame,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegError {
+    /// 写入的值设置了保留位（给出这些位）
+    ReservedBits(u64),
+    /// 地址类 MSR 要求规范地址
+    NonCanonical(u64),
+}
+
+/// 常用 MSR 的编号。
+pub mod msr {
+    pub const APIC_BASE: u32 = 0x1b;
+    pub const EFER: u32 = 0xc000_0080;
+    pub const STAR: u32 = 0xc000_0081;
+    pub const LSTAR: u32 = 0xc000_0082;
+    pub const FMASK: u32 = 0xc000_0084;
+    pub const FS_BASE: u32 = 0xc000_0100;
+    pub const GS_BASE: u32 = 0xc000_0101;
+    pub const KERNEL_GS_BASE: u32 = 0xc000_0102;
+}
+
+// 为一个用 bitflags 表示的控制寄存器生成 read/write/update 以及作用域 guard
+macro_rules! control_register {
+    ($module:ident, $name:literal, $register:ty, $flags:ty) => {
+        pub mod $module {
+            use super::RegError;
+
+            pub fn read() -> $flags {
+                <$register>::read()
+            }
+
+            /// 写入前检查保留位，值有变化时记录一个跟踪点。
+            ///
+            /// # Safety
+            /// 调用者必须保证新的值不会破坏内存安全（例如关闭分页或写保护）。
+            pub unsafe fn write(flags: $flags) -> Result<(), RegError> {
+                let reserved = flags.bits() & !<$flags>::all().bits();
+                if reserved != 0 {
+                    return Err(RegError::ReservedBits(reserved));
+                }
+                let old = read();
+                if old != flags {
+                    crate::trace!($name, old.bits(), flags.bits());
+                    unsafe { <$register>::write(flags) };
+                }
+                Ok(())
+            }
+
+            /// 读-改-写。
+            ///
+            /// # Safety
+            /// 同 [`write`]。
+            pub unsafe fn update(f: impl FnOnce(&mut $flags)) -> Result<(), RegError> {
+                let mut flags = read();
+                f(&mut flags);
+                unsafe { write(flags) }
+            }
+
+            /// 在作用域内设置 `flags`，离开时恢复这些位原来的状态。
+            ///
+            /// # Safety
+            /// 同 [`write`]。
+            pub unsafe fn set_while(flags: $flags) -> Result<Guard, RegError> {
+                let previous = read() & flags;
+                unsafe { update(|current| current.insert(flags))? };
+                Ok(Guard { flags, previous })
+            }
+
+            #[must_use = "the flags are restored as soon as the guard is dropped"]
+            pub struct Guard {
+                flags: $flags,
+                previous: $flags,
+            }
+
+            impl Drop for Guard {
+                fn drop(&mut self) {
+                    let (flags, previous) = (self.flags, self.previous);
+                    // 恢复的是 set_while 之前的合法值，不会失败
+                    let _ = unsafe {
+                        update(|current| {
+                            current.remove(flags);
+                            current.insert(previous);
+                        })
+                    };
+                }
+            }
+        }
+    };
+}
+
+control_register!(
+    cr0,
+    "regs.cr0",
+    x86_64::registers::control::Cr0,
+    x86_64::registers::control::Cr0Flags
+);
+control_register!(
+    cr4,
+    "regs.cr4",
+    x86_64::registers::control::Cr4,
+    x86_64::registers::control::Cr4Flags
+);
+control_register!(
+    efer,
+    "regs.efer",
+    x86_64::registers::model_specific::Efer,
+    x86_64::registers::model_specific::EferFlags
+);
+
+/// 当前页表的物理帧和 CR3 标志位。
+pub fn cr3() -> (PhysFrame, Cr3Flags) {
+    Cr3::read()
+}
+
+/// 读取 MSR。
+///
+/// # Safety
+/// 读取不存在的 MSR 会触发 #GP。
+pub unsafe fn read_msr(index: u32) -> u64 {
+    unsafe { Msr::new(index).read() }
+}
+
+/// 写入 MSR。对已知的地址类 MSR 检查规范地址，对 EFER 检查保留位。
+///
+/// 值有变化时记录跟踪点 `regs.msr`，参数为 MSR 编号和新值。
+///
+/// # Safety
+/// 调用者必须保证该 MSR 存在，且新值不会破坏内存安全。
+pub unsafe fn write_msr(index: u32, value: u64) -> Result<(), RegError> {
+    match index {
+        msr::EFER => {
+            let flags = x86_64::registers::model_specific::EferFlags::from_bits_retain(value);
+            return unsafe { efer::write(flags) };
+        }
+        msr::LSTAR | msr::FS_BASE | msr::GS_BASE | msr::KERNEL_GS_BASE => {
+            VirtAddr::try_new(value).map_err(|_| RegError::NonCanonical(value))?;
+        }
+        _ => {}
+    }
+    let mut register = Msr::new(index);
+    let old = unsafe { register.read() };
+    if old != value {
+        // 跟踪点只有两个参数，旧值可以从同一 MSR 的上一条记录得到
+        crate::trace!("regs.msr", index, value);
+        unsafe { register.write(value) };
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_regs_reject_reserved_bits() {
+    use x86_64::registers::control::Cr4Flags;
+
+    let before = cr4::read();
+    let result = unsafe { cr4::write(Cr4Flags::from_bits_retain(before.bits() | (1 << 40))) };
+    assert_eq!(result, Err(RegError::ReservedBits(1 << 40)));
+    assert_eq!(cr4::read(), before);
+    assert_eq!(
+        unsafe { write_msr(msr::GS_BASE, 0x0000_8000_0000_0000) },
+        Err(RegError::NonCanonical(0x0000_8000_0000_0000))
+    );
+}
+
+#[test_case]
+fn test_regs_guard_restores_flags() {
+    use x86_64::registers::control::Cr0Flags;
+
+    // AM 只影响 ring 3 的对齐检查，在内核里切换它是安全的
+    let had_am = cr0::read().contains(Cr0Flags::ALIGNMENT_MASK);
+    {
+        let _guard = unsafe { cr0::set_while(Cr0Flags::ALIGNMENT_MASK) }.unwrap();
+        assert!(cr0::read().contains(Cr0Flags::ALIGNMENT_MASK));
+    }
+    assert_eq!(cr0::read().contains(Cr0Flags::ALIGNMENT_MASK), had_am);
+}
@@ -0,0 +1,104 @@
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+// seq 为 0 表示正在写入，读取方据此丢弃被打断的记录
+struct Slot<const N: usize> {
+    seq: AtomicU64,
+    words: [AtomicU64; N],
+}
+
+/// 无锁的环形缓冲区，每条记录是 `N` 个 `u64`，写满后覆盖最旧的记录。
+///
+/// 读写都不加锁，可以在中断处理函数中使用；
+/// 读取时跳过已被覆盖或者写了一半的记录。
+pub struct Ring<const CAP: usize, const N: usize> {
+    slots: [Slot<N>; CAP],
+    next: AtomicUsize,
+}
+
+impl<const CAP: usize, const N: usize> Ring<CAP, N> {
+    pub const fn new() -> Self {
+        Ring {
+            slots: [const {
+                Slot {
+                    seq: AtomicU64::new(0),
+                    words: [const { AtomicU64::new(0) }; N],
+                }
+            }; CAP],
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// 追加一条记录。
+    pub fn push(&self, words: [u64; N]) {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[index % CAP];
+        slot.seq.store(0, Ordering::Release);
+        for (word, value) in slot.words.iter().zip(words) {
+            word.store(value, Ordering::Relaxed);
+        }
+        slot.seq.store(index as u64 + 1, Ordering::Release);
+    }
+
+    /// 可能还留在缓冲区中的记录的序号，从旧到新。
+    pub fn range(&self) -> Range<usize> {
+        let end = self.next.load(Ordering::Acquire);
+        end.saturating_sub(CAP)..end
+    }
+
+    /// 读取序号为 `index` 的记录，它已被覆盖或者写了一半时返回 `None`。
+    pub fn get(&self, index: usize) -> Option<[u64; N]> {
+        let slot = &self.slots[index % CAP];
+        let seq = slot.seq.load(Ordering::Acquire);
+        let words = core::array::from_fn(|i| slot.words[i].load(Ordering::Relaxed));
+        (seq == index as u64 + 1 && slot.seq.load(Ordering::Acquire) == seq).then_some(words)
+    }
+
+    /// 按写入顺序遍历缓冲区中的记录。
+    pub fn for_each(&self, mut f: impl FnMut([u64; N])) {
+        for index in self.range() {
+            if let Some(words) = self.get(index) {
+                f(words);
+            }
+        }
+    }
+
+    /// 清空缓冲区。
+    pub fn clear(&self) {
+        // 序号继续递增，被清掉的槽位不会和之后的记录混淆
+        for slot in &self.slots {
+            slot.seq.store(0, Ordering::Release);
+        }
+    }
+}
+
+impl<const CAP: usize, const N: usize> Default for Ring<CAP, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test_case]
+fn test_ring_overwrites_oldest() {
+    let ring = Ring::<4, 2>::new();
+    for i in 0..6 {
+        ring.push([i, i * 10]);
+    }
+    assert_eq!(ring.range(), 2..6);
+    assert_eq!(ring.get(1), None);
+    let mut seen = [0; 4];
+    let mut count = 0;
+    ring.for_each(|[i, tens]| {
+        assert_eq!(tens, i * 10);
+        seen[count] = i;
+        count += 1;
+    });
+    assert_eq!(seen, [2, 3, 4, 5]);
+
+    ring.clear();
+    ring.for_each(|_| panic!("cleared ring is not empty"));
+    ring.push([6, 60]);
+    assert_eq!(ring.get(6), Some([6, 60]));
+}
@@ -1,32 +1,338 @@
-use lazy_static::lazy_static;
-use spin::Mutex;
-use uart_16550::SerialPort;
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
+use core::fmt;
+
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::{
+    console::{self, ConsoleSink},
+    debugcon, print,
+};
+
+// 寄存器相对于基地址的偏移；DLAB 置位时前两个是除数的低、高字节
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const LCR_DLAB: u8 = 1 << 7;
+// 启用并清空 FIFO，接收 14 字节时触发中断
+const FCR_ENABLE_CLEAR_14: u8 = 0xc7;
+// DTR、RTS，以及 OUT2（PC 上它控制 UART 的中断能否到达 PIC）
+const MCR_NORMAL: u8 = 0x0b;
+// 回环模式：发送的数据直接回到接收端，用来检测芯片是否存在
+const MCR_LOOPBACK: u8 = 0x1e;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+// 除数为 1 时的波特率（1.8432 MHz / 16）
+const BASE_BAUD: u32 = 115_200;
+
+// 等待发送寄存器空的轮询次数上限，串口不存在时不至于卡死
+const SEND_SPINS: u32 = 100_000;
+
+const COM1_IRQ: u8 = 4;
+
+/// 接收队列的容量，满了之后丢弃新收到的字节。
+pub const RX_QUEUE_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    /// 波特率不能由 115200 整除得到
+    BadBaud,
+    /// 数据位不在 5..=8 或停止位不是 1、2
+    BadFormat,
+    /// 回环测试失败，这个地址上没有 UART
+    NotPresent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// 线路参数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineConfig {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: u8,
+}
+
+impl LineConfig {
+    /// 115200 8N1。
+    pub const DEFAULT: LineConfig = LineConfig {
+        baud: 115_200,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
     };
+
+    /// 波特率除数。
+    fn divisor(&self) -> Result<u16, SerialError> {
+        if self.baud == 0 || BASE_BAUD % self.baud != 0 {
+            return Err(SerialError::BadBaud);
+        }
+        u16::try_from(BASE_BAUD / self.baud).map_err(|_| SerialError::BadBaud)
+    }
+
+    /// 线路控制寄存器的值（不含 DLAB）。
+    fn line_control(&self) -> Result<u8, SerialError> {
+        if !(5..=8).contains(&self.data_bits) || !(1..=2).contains(&self.stop_bits) {
+            return Err(SerialError::BadFormat);
+        }
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Odd => 0b001 << 3,
+            Parity::Even => 0b011 << 3,
+        };
+        Ok((self.data_bits - 5) | (self.stop_bits - 1) << 2 | parity)
+    }
+}
+
+impl Default for LineConfig {
+    fn default() -> Self {
+        LineConfig::DEFAULT
+    }
+}
+
+/// PC 上的四个串口。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Com {
+    Com1 = 0,
+    Com2 = 1,
+    Com3 = 2,
+    Com4 = 3,
+}
+
+impl Com {
+    /// 标准的 I/O 基地址。
+    pub fn default_base(self) -> u16 {
+        [0x3f8, 0x2f8, 0x3e8, 0x2e8][self as usize]
+    }
+}
+
+/// 一个 16550 UART。
+pub struct SerialPort {
+    base: u16,
+    config: Option<LineConfig>,
+    // 调用过 `init`，不管成功与否
+    probed: bool,
+}
+
+impl SerialPort {
+    /// 基地址为 `base` 的 UART，调用 `init` 之前不能使用。
+    pub const fn new(base: u16) -> Self {
+        SerialPort {
+            base,
+            config: None,
+            probed: false,
+        }
+    }
+
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// 是否已经初始化成功。
+    pub fn present(&self) -> bool {
+        self.config.is_some()
+    }
+
+    pub fn config(&self) -> Option<LineConfig> {
+        self.config
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.base + register).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.base + register).write(value) };
+    }
+
+    /// 按 `config` 设置波特率和帧格式，打开 FIFO，并用回环测试确认芯片存在。
+    /// 初始化后所有中断都是关闭的。
+    pub fn init(&mut self, config: LineConfig) -> Result<(), SerialError> {
+        let divisor = config.divisor()?;
+        let line_control = config.line_control()?;
+        self.config = None;
+        self.probed = true;
+
+        self.write(INTERRUPT_ENABLE, 0);
+        self.write(LINE_CONTROL, LCR_DLAB);
+        self.write(DIVISOR_LOW, divisor as u8);
+        self.write(DIVISOR_HIGH, (divisor >> 8) as u8);
+        self.write(LINE_CONTROL, line_control);
+        self.write(FIFO_CONTROL, FCR_ENABLE_CLEAR_14);
+
+        self.write(MODEM_CONTROL, MCR_LOOPBACK);
+        self.write(DATA, 0xae);
+        if self.read(DATA) != 0xae {
+            return Err(SerialError::NotPresent);
+        }
+        self.write(MODEM_CONTROL, MCR_NORMAL);
+        self.config = Some(config);
+        Ok(())
+    }
+
+    /// 打开或关闭“收到数据”中断。
+    pub fn set_rx_interrupt(&mut self, enabled: bool) {
+        self.write(INTERRUPT_ENABLE, if enabled { IER_RX_AVAILABLE } else { 0 });
+    }
+
+    /// 发送一个字节，不做任何转换。没有初始化或发送超时时丢弃。
+    pub fn send_raw(&mut self, byte: u8) {
+        if !self.present() {
+            return;
+        }
+        if (0..SEND_SPINS).any(|_| self.read(LINE_STATUS) & LSR_TRANSMIT_EMPTY != 0) {
+            self.write(DATA, byte);
+        }
+    }
+
+    /// 取出一个收到的字节，没有时返回 `None`。
+    pub fn try_receive(&mut self) -> Option<u8> {
+        (self.present() && self.read(LINE_STATUS) & LSR_DATA_READY != 0).then(|| self.read(DATA))
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send_raw(byte);
+        }
+        Ok(())
+    }
+}
+
+impl ConsoleSink for SerialPort {}
+
+static PORTS: [Mutex<SerialPort>; 4] = [
+    Mutex::new(SerialPort::new(0x3f8)),
+    Mutex::new(SerialPort::new(0x2f8)),
+    Mutex::new(SerialPort::new(0x3e8)),
+    Mutex::new(SerialPort::new(0x2e8)),
+];
+
+/// 取得串口 `com`。第一次使用时按默认地址和 115200 8N1 初始化；芯片不存在
+/// 时之后也不再重试，免得每次输出都做一遍回环测试。
+///
+/// 日志、屏幕镜像、调试器等各自使用不同的串口，互不干扰。
+pub fn port(com: Com) -> MutexGuard<'static, SerialPort> {
+    let mut port = PORTS[com as usize].lock();
+    if !port.probed {
+        let _ = port.init(LineConfig::DEFAULT);
+    }
+    port
+}
+
+/// 强行释放所有串口的锁，只在 panic 时使用。
+///
+/// # Safety
+/// 同 [`vga_buffer::force_unlock`](crate::vga_buffer::force_unlock)。
+pub(crate) unsafe fn force_unlock() {
+    for port in &PORTS {
+        unsafe { port.force_unlock() };
+    }
+}
+
+/// 是否有串口的锁被持有。只在崩溃报告中使用，不等锁。
+pub(crate) fn is_locked() -> bool {
+    PORTS.iter().any(|port| port.try_lock().is_none())
+}
+
+/// 把串口 `com` 换到 `base` 地址并按 `config` 重新初始化。
+pub fn configure(com: Com, base: u16, config: LineConfig) -> Result<(), SerialError> {
+    interrupts::without_interrupts(|| {
+        let mut port = PORTS[com as usize].lock();
+        *port = SerialPort::new(base);
+        port.init(config)
+    })
+}
+
+static RX_QUEUE: Mutex<heapless::Deque<u8, RX_QUEUE_LEN>> = Mutex::new(heapless::Deque::new());
+
+/// 打开 COM1 的接收中断，让串口也能作为输入（`qemu -nographic` 时唯一的输入）。
+pub fn init() {
+    interrupts::without_interrupts(|| port(Com::Com1).set_rx_interrupt(true));
+    crate::interrupts::register_irq_handler(COM1_IRQ, handle_irq);
+}
+
+/// 处理收到的一个字节，返回是否需要回显。
+///
+/// Ctrl+C 交给前台任务，其余字节放入接收队列。
+fn receive(byte: u8) -> bool {
+    if byte == 0x03 {
+        console::interrupt_foreground();
+        return false;
+    }
+    let _ = RX_QUEUE.lock().push_back(byte);
+    true
+}
+
+fn handle_irq() {
+    // 一次中断时 FIFO 里可能已经有好几个字节
+    loop {
+        // 先放开 COM1 的锁：回显可能经控制台又写到 COM1
+        let Some(byte) = port(Com::Com1).try_receive() else {
+            break;
+        };
+        // 像键盘输入一样回显，终端发来的回车显示为换行
+        match byte {
+            _ if !receive(byte) => {}
+            b'\r' => print!("\n"),
+            b' '..=b'~' | b'\n' => print!("{}", byte as char),
+            _ => {}
+        }
+    }
+}
+
+/// 取出一个收到的字节，没有时立即返回 `None`。
+pub fn read_byte() -> Option<u8> {
+    interrupts::without_interrupts(|| RX_QUEUE.lock().pop_front())
+}
+
+/// 等待串口收到下一个字节。
+pub fn wait_byte() -> u8 {
+    loop {
+        // 关中断检查队列后再 `sti; hlt`，避免在两者之间到来的中断被错过
+        interrupts::disable();
+        if let Some(byte) = RX_QUEUE.lock().pop_front() {
+            interrupts::enable();
+            return byte;
+        }
+        interrupts::enable_and_hlt();
+    }
 }
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
 
-    use x86_64::instructions::interrupts;
+    if debugcon::enabled() {
+        debugcon::_print(args);
+        return;
+    }
     interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
+        port(Com::Com1)
             .write_fmt(args)
             .expect("Printing to serial failed");
     });
 }
 
-/// Prints to the host through the serial interface.
+/// Prints to the host through the serial interface (or debugcon, if enabled).
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => {
-        $crate::serial::_print(format_args!($($arg)*));
+        $crate::serial::_print(format_args!($($arg)*))
     };
 }
 
@@ -38,3 +344,49 @@ macro_rules! serial_println {
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
         concat!($fmt, "\n"), $($arg)*));
 }
+
+#[test_case]
+fn test_serial_receive_queue() {
+    while read_byte().is_some() {}
+    assert!(receive(b'o'));
+    assert!(receive(b'k'));
+    assert_eq!(read_byte(), Some(b'o'));
+    assert_eq!(read_byte(), Some(b'k'));
+    assert_eq!(read_byte(), None);
+}
+
+#[test_case]
+fn test_serial_absent_port_probed_once() {
+    // QEMU 默认只模拟 COM1，COM4 的回环测试失败后不再重试
+    let port = interrupts::without_interrupts(|| {
+        let port = port(Com::Com4);
+        (port.probed, port.present())
+    });
+    assert_eq!(port, (true, false));
+}
+
+#[test_case]
+fn test_serial_line_config() {
+    assert_eq!(LineConfig::DEFAULT.divisor(), Ok(1));
+    assert_eq!(LineConfig::DEFAULT.line_control(), Ok(0x03));
+    let config = LineConfig {
+        baud: 9600,
+        data_bits: 7,
+        parity: Parity::Even,
+        stop_bits: 2,
+    };
+    assert_eq!(config.divisor(), Ok(12));
+    assert_eq!(config.line_control(), Ok(0x02 | 0x04 | 0x18));
+    let bad = LineConfig {
+        baud: 7000,
+        ..LineConfig::DEFAULT
+    };
+    assert_eq!(bad.divisor(), Err(SerialError::BadBaud));
+    let bad = LineConfig {
+        data_bits: 9,
+        ..LineConfig::DEFAULT
+    };
+    assert_eq!(bad.line_control(), Err(SerialError::BadFormat));
+    // COM1 在 QEMU 中总是存在
+    assert!(port(Com::Com1).present());
+}
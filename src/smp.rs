@@ -0,0 +1,315 @@
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use x86_64::{PhysAddr, instructions::interrupts};
+
+use crate::{acpi, apic, fpu, gdt, hlt_loop, memory, percpu, regs, time};
+
+/// 最多支持的 CPU 个数。
+pub const MAX_CPUS: usize = acpi::MAX_CPUS;
+
+// 每个 AP 的内核栈
+const AP_STACK_PAGES: usize = 4;
+
+// INIT 之后和两次 SIPI 之间的等待时间，以及等 AP 报到的最长时间
+const INIT_DELAY_US: u64 = 10_000;
+const SIPI_DELAY_US: u64 = 200;
+const STARTUP_TIMEOUT_US: u64 = 100_000;
+
+// 已经在运行的 CPU 数，包括 BSP
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+// MADT 中可以启动的 CPU 数，包括 BSP
+static PRESENT: AtomicUsize = AtomicUsize::new(1);
+// 正在启动的 AP 执行到 Rust 代码后置位，BSP 据此启动下一个
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+// AP 的启动代码。SIPI 让 AP 从实模式开始执行，依次进入保护模式和长模式，然后
+// 用参数区里的页表、栈和入口跳到 `ap_main`。代码会被复制到 1 MiB
+// 以下的某一页，只能用相对于起点的地址，起点的物理地址从 CS 算出来放在 ebx
+// 中。
+global_asm!(
+    r#"
+    .pushsection .rodata.smp_trampoline, "a"
+    .global smp_trampoline_start
+    .global smp_trampoline_params
+    .global smp_trampoline_end
+
+    .code16
+smp_trampoline_start:
+    cli
+    cld
+    movw %cs, %ax
+    movw %ax, %ds
+    xorl %ebx, %ebx
+    movw %ax, %bx
+    shll $4, %ebx
+    leal (trampoline_gdt - smp_trampoline_start)(%ebx), %eax
+    movl %eax, (trampoline_gdt_ptr + 2 - smp_trampoline_start)
+    leal (trampoline_32 - smp_trampoline_start)(%ebx), %eax
+    movl %eax, (trampoline_far32 - smp_trampoline_start)
+    lgdtl (trampoline_gdt_ptr - smp_trampoline_start)
+    movl %cr0, %eax
+    orl $1, %eax
+    movl %eax, %cr0
+    ljmpl *(trampoline_far32 - smp_trampoline_start)
+
+    .code32
+trampoline_32:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    # PAE
+    movl %cr4, %eax
+    orl $(1 << 5), %eax
+    movl %eax, %cr4
+    movl (smp_trampoline_params - smp_trampoline_start)(%ebx), %eax
+    movl %eax, %cr3
+    # EFER.LME 和 EFER.NXE
+    movl $0xc0000080, %ecx
+    rdmsr
+    orl $((1 << 8) | (1 << 11)), %eax
+    wrmsr
+    # 分页和写保护
+    movl %cr0, %eax
+    orl $0x80010000, %eax
+    movl %eax, %cr0
+    leal (trampoline_64 - smp_trampoline_start)(%ebx), %eax
+    movl %eax, (trampoline_far64 - smp_trampoline_start)(%ebx)
+    ljmpl *(trampoline_far64 - smp_trampoline_start)(%ebx)
+
+    .code64
+trampoline_64:
+    xorl %eax, %eax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    # 从兼容模式切换过来后寄存器的高 32 位是未定义的
+    movl %ebx, %ebx
+    movq (smp_trampoline_params + 8 - smp_trampoline_start)(%rbx), %rsp
+    movq (smp_trampoline_params + 24 - smp_trampoline_start)(%rbx), %rdi
+    movq (smp_trampoline_params + 16 - smp_trampoline_start)(%rbx), %rax
+    xorl %ebp, %ebp
+    callq *%rax
+    ud2
+
+    .balign 8
+trampoline_gdt:
+    .quad 0
+    .quad 0x00cf9a000000ffff
+    .quad 0x00cf92000000ffff
+    .quad 0x00af9a000000ffff
+trampoline_gdt_ptr:
+    .word 4 * 8 - 1
+    .long 0
+trampoline_far32:
+    .long 0
+    .word 0x08
+trampoline_far64:
+    .long 0
+    .word 0x18
+
+    .balign 8
+smp_trampoline_params:
+    .quad 0, 0, 0, 0
+smp_trampoline_end:
+    .popsection
+"#,
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    static smp_trampoline_start: u8;
+    static smp_trampoline_params: u8;
+    static smp_trampoline_end: u8;
+}
+
+/// 启动代码末尾的参数区，由 BSP 在发送 SIPI 前填写。
+#[repr(C)]
+struct TrampolineParams {
+    /// 只能是 4 GiB 以下的地址，32 位代码写 CR3 时只有低 32 位
+    cr3: u64,
+    stack_top: u64,
+    entry: u64,
+    cpu: u64,
+}
+
+/// 启动 MADT 中列出的其他 CPU，每个 AP 进入 `ap_main` 后停在空闲循环里。
+///
+/// 需要 ACPI、Local APIC 和时钟已经初始化。AP 一个一个地启动，共用同一份
+/// 启动代码。
+pub fn init() {
+    let Some(madt) = acpi::get().and_then(|acpi| acpi.madt.as_ref()) else {
+        return;
+    };
+    if !apic::available() {
+        return;
+    }
+    let bsp = apic::id();
+    // 只有 xAPIC 能寻址的 CPU，x2APIC ID 超过 255 的需要 x2APIC 模式
+    let aps = madt
+        .cpus
+        .iter()
+        .filter(|cpu| cpu.enabled && cpu.apic_id != bsp && cpu.apic_id <= 0xff)
+        .take(MAX_CPUS - 1);
+    if aps.clone().next().is_none() {
+        return;
+    }
+
+    let Some(trampoline) = install_trampoline() else {
+        log::warn!("smp: no low memory for the AP trampoline");
+        return;
+    };
+    let (cr3, _) = regs::cr3();
+    if cr3.start_address().as_u64() > u32::MAX as u64 {
+        log::warn!("smp: page table above 4 GiB, cannot start APs");
+        return;
+    }
+
+    for cpu in aps {
+        let Some(stack_top) = memory::alloc_stack(AP_STACK_PAGES) else {
+            log::warn!("smp: no memory for AP stacks");
+            break;
+        };
+        PRESENT.fetch_add(1, Ordering::Relaxed);
+        // AP 一个一个地启动，没有报到的 AP 已经被 INIT 停住，不占编号
+        let index = online_cpus();
+        let params = TrampolineParams {
+            cr3: cr3.start_address().as_u64(),
+            stack_top: stack_top.as_u64(),
+            entry: ap_main as usize as u64,
+            cpu: index as u64,
+        };
+        match start_ap(cpu.apic_id, trampoline, params) {
+            Startup::Started => {}
+            Startup::Failed => {
+                log::warn!("smp: cpu {index} (apic {}) did not start", cpu.apic_id);
+                // SAFETY: AP 已经被 INIT 复位，不会再用到这个栈
+                unsafe { memory::free_stack(stack_top, AP_STACK_PAGES) };
+            }
+            Startup::Stuck => {
+                // AP 可能还在执行启动代码，栈和启动代码都不能再动
+                log::error!(
+                    "smp: cannot reach apic {}, not starting more cpus",
+                    cpu.apic_id
+                );
+                break;
+            }
+        }
+    }
+    log::info!("smp: {} cpus online", online_cpus());
+}
+
+/// 把启动代码复制到 1 MiB 以下的恒等映射页中，返回它的物理地址。
+fn install_trampoline() -> Option<PhysAddr> {
+    let page = memory::low_identity_page()?;
+    unsafe {
+        let start = &raw const smp_trampoline_start;
+        let len = (&raw const smp_trampoline_end).offset_from(start) as usize;
+        let dst = memory::phys_to_virt(page).as_mut_ptr::<u8>();
+        core::ptr::copy_nonoverlapping(start, dst, len);
+    }
+    Some(page)
+}
+
+/// [`start_ap`] 的结果。
+enum Startup {
+    /// AP 已经进入 `ap_main`
+    Started,
+    /// AP 没有报到，已经用 INIT 把它停在等待 SIPI 的状态
+    Failed,
+    /// IPI 没有送达，AP 的状态未知
+    Stuck,
+}
+
+/// 写入启动代码的参数区。
+fn write_params(trampoline: PhysAddr, params: TrampolineParams) {
+    unsafe {
+        let offset =
+            (&raw const smp_trampoline_params).offset_from(&raw const smp_trampoline_start);
+        let dst = memory::phys_to_virt(trampoline + offset as u64);
+        dst.as_mut_ptr::<TrampolineParams>().write_volatile(params);
+    }
+}
+
+/// INIT-SIPI-SIPI：复位 AP，然后让它从 `trampoline` 开始执行。
+///
+/// 超时后再发一次 INIT 把 AP 停住并清空参数区，免得它迟到后拿着这次的栈和
+/// 编号进入 `ap_main`，与下一个 AP 冲突。
+fn start_ap(apic_id: u32, trampoline: PhysAddr, params: TrampolineParams) -> Startup {
+    write_params(trampoline, params);
+    STARTED.store(false, Ordering::Release);
+    let vector = (trampoline.as_u64() / memory::PAGE_SIZE) as u8;
+
+    if !apic::send_init(apic_id) {
+        return Startup::Stuck;
+    }
+    time::delay_us(INIT_DELAY_US);
+    // 按规范发两次 SIPI，第一次已经生效时不再发第二次
+    for _ in 0..2 {
+        if !apic::send_startup(apic_id, vector) {
+            return Startup::Stuck;
+        }
+        time::delay_us(SIPI_DELAY_US);
+        if STARTED.load(Ordering::Acquire) {
+            break;
+        }
+    }
+    let deadline = time::tsc() + STARTUP_TIMEOUT_US * time::tsc_per_ms() / 1000;
+    while !STARTED.load(Ordering::Acquire) {
+        if time::tsc() > deadline {
+            if !apic::send_init(apic_id) {
+                return Startup::Stuck;
+            }
+            write_params(
+                trampoline,
+                TrampolineParams {
+                    cr3: 0,
+                    stack_top: 0,
+                    entry: 0,
+                    cpu: 0,
+                },
+            );
+            // AP 恰好在 INIT 之前报到的话，它已经被复位了，不再算作在线
+            if STARTED.load(Ordering::Acquire) {
+                ONLINE.fetch_sub(1, Ordering::AcqRel);
+            }
+            return Startup::Failed;
+        }
+        core::hint::spin_loop();
+    }
+    Startup::Started
+}
+
+/// AP 的 Rust 入口，由启动代码调用。
+extern "C" fn ap_main(cpu: u64) -> ! {
+    let cpu = cpu as usize;
+    percpu::init(cpu);
+    gdt::init_ap(cpu);
+    fpu::init();
+    crate::interrupts::init_idt();
+    apic::init_ap();
+    if apic::timer_active() {
+        apic::start_timer(apic::TimerMode::Periodic, 1_000_000 / time::TICK_HZ);
+    }
+    ONLINE.fetch_add(1, Ordering::AcqRel);
+    // 之后 BSP 就可能改写启动代码去启动下一个 AP
+    STARTED.store(true, Ordering::Release);
+    // 还没有调度器，停在这里直到有工作可做
+    interrupts::enable();
+    hlt_loop();
+}
+
+/// 正在运行的 CPU 个数，包括 BSP。
+pub fn online_cpus() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+#[test_case]
+fn test_smp_cpus_online() {
+    // MADT 中每个可以启动的 CPU 都应当已经报到
+    assert_eq!(online_cpus(), PRESENT.load(Ordering::Relaxed));
+    assert!(online_cpus() >= 1);
+}
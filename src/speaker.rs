@@ -0,0 +1,92 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::instructions::{self, interrupts, port::Port};
+
+use crate::{cmdline, time};
+
+/// 控制台收到 BEL（0x07）时的音高和时长。
+pub const BELL_FREQUENCY: u32 = 880;
+pub const BELL_MS: u64 = 100;
+
+// PIT 的输入时钟频率
+const PIT_FREQUENCY: u32 = 1_193_182;
+
+// 端口 0x61 的位 0 打开 PIT 通道 2 的门控，位 1 把通道 2 的输出接到扬声器
+const GATE: u8 = 0x01;
+const SPEAKER_DATA: u8 = 0x02;
+
+// 正在响的提示音在哪个 tick 停止，0 表示没有
+static STOP_AT: AtomicU64 = AtomicU64::new(0);
+
+/// 让扬声器以 `frequency` Hz 持续发声，直到调用 [`stop`]。
+pub fn start(frequency: u32) {
+    // 分频值只有 16 位，19 Hz 以下的音调不出来
+    let divisor = (PIT_FREQUENCY / frequency.max(1)).clamp(1, u16::MAX as u32) as u16;
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel2: Port<u8> = Port::new(0x42);
+    let mut gate: Port<u8> = Port::new(0x61);
+    interrupts::without_interrupts(|| unsafe {
+        // 通道 2，先低字节后高字节，模式 3（方波）
+        command.write(0xb6);
+        channel2.write(divisor as u8);
+        channel2.write((divisor >> 8) as u8);
+        let value = gate.read();
+        gate.write(value | GATE | SPEAKER_DATA);
+    });
+}
+
+/// 关掉扬声器。
+pub fn stop() {
+    let mut gate: Port<u8> = Port::new(0x61);
+    interrupts::without_interrupts(|| unsafe {
+        let value = gate.read();
+        gate.write(value & !(GATE | SPEAKER_DATA));
+    });
+    STOP_AT.store(0, Ordering::Relaxed);
+}
+
+/// 以 `frequency` Hz 响 `duration_ms` 毫秒，响完才返回。
+///
+/// 中断打开时在等待中 `hlt`，否则忙等。之前还没响完的响铃被取消，免得
+/// 时钟中断到时把这次的声音提前关掉。
+pub fn beep(frequency: u32, duration_ms: u64) {
+    STOP_AT.store(0, Ordering::Relaxed);
+    start(frequency);
+    if interrupts::are_enabled() {
+        let end = time::uptime_ms() + duration_ms;
+        while time::uptime_ms() < end {
+            instructions::hlt();
+        }
+    } else {
+        time::delay_us(duration_ms * 1000);
+    }
+    stop();
+}
+
+/// 控制台的响铃：开始发声后立即返回，由时钟中断在 `BELL_MS` 毫秒后关掉，
+/// 所以可以在持有控制台锁时调用。命令行带 `nobell` 时不响。
+pub fn bell() {
+    if cmdline::flag("nobell") {
+        return;
+    }
+    let ticks = (BELL_MS * time::TICK_HZ).div_ceil(1000).max(1);
+    STOP_AT.store(time::ticks() + ticks, Ordering::Relaxed);
+    start(BELL_FREQUENCY);
+}
+
+/// 由时钟中断调用，到时间后关掉响铃。
+pub(crate) fn tick() {
+    let stop_at = STOP_AT.load(Ordering::Relaxed);
+    if stop_at != 0 && time::ticks() >= stop_at {
+        stop();
+    }
+}
+
+#[test_case]
+fn test_speaker_beep() {
+    let mut gate: Port<u8> = Port::new(0x61);
+    let start = time::uptime_ms();
+    beep(440, 20);
+    assert!(time::uptime_ms() >= start + 20);
+    assert_eq!(unsafe { gate.read() } & (GATE | SPEAKER_DATA), 0);
+}
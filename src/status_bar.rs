@@ -0,0 +1,57 @@
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{config, interrupts, memory, time, vga_buffer};
+
+// 上次刷新时的运行秒数，每秒只刷新一次
+static LAST_SECOND: AtomicU64 = AtomicU64::new(u64::MAX);
+
+type Line = heapless::String<80>;
+
+fn format(uptime_ms: u64, irqs: u64, memory_bytes: u64, terminal: usize, hostname: &str) -> Line {
+    let seconds = uptime_ms / 1000;
+    let mut line = Line::new();
+    // 超出一行的部分被截掉，不影响显示
+    let _ = write!(
+        line,
+        " up {:02}:{:02}:{:02} | irq {} | mem {} KiB | VT{} | {}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        irqs,
+        memory_bytes / 1024,
+        terminal + 1,
+        hostname
+    );
+    line
+}
+
+/// 立即刷新状态栏：运行时间、中断总数、已分配的物理内存、当前终端和主机名。
+pub fn update() {
+    let line = format(
+        time::uptime_ms(),
+        interrupts::irq_total(),
+        memory::allocated_bytes(),
+        vga_buffer::active_terminal(),
+        &config::hostname(),
+    );
+    vga_buffer::set_status(&line);
+}
+
+/// 在主循环中反复调用，每过一秒刷新一次状态栏。
+pub fn poll() {
+    let second = time::uptime_ms() / 1000;
+    if LAST_SECOND.swap(second, Ordering::Relaxed) != second {
+        update();
+    }
+}
+
+#[test_case]
+fn test_status_bar_format() {
+    assert_eq!(
+        format(3_725_500, 42, 3 * 1024 * 1024, 1, "qemu-box").as_str(),
+        " up 01:02:05 | irq 42 | mem 3072 KiB | VT2 | qemu-box"
+    );
+}
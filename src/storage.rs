@@ -0,0 +1,172 @@
+use spin::Mutex;
+
+pub mod ata;
+pub mod virtio_blk;
+
+/// 注册表最多容纳的块设备数。
+pub const MAX_DEVICES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// 访问超出设备容量
+    OutOfRange,
+    /// 缓冲区长度不是块大小的整数倍
+    BadBuffer,
+    /// 设备报告了错误或超时
+    Io,
+    /// 设备只读
+    ReadOnly,
+    /// 注册表已满或名字重复
+    Registry,
+}
+
+/// 按逻辑块地址（LBA）读写的块设备。
+///
+/// 缓冲区长度必须是 `block_size()` 的整数倍，一次可以读写多个连续的块。
+pub trait BlockDevice: Send {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// 把设备缓存中的数据写到介质上。
+    fn flush(&mut self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// 容量（字节）。
+    fn capacity(&self) -> u64 {
+        self.block_count() * self.block_size() as u64
+    }
+}
+
+/// 检查一次访问是否合法，返回涉及的块数。驱动实现读写前应当先调用它。
+pub fn check_request(device: &dyn BlockDevice, lba: u64, len: usize) -> Result<u64, BlockError> {
+    let block_size = device.block_size();
+    if !len.is_multiple_of(block_size) {
+        return Err(BlockError::BadBuffer);
+    }
+    let count = (len / block_size) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= device.block_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// 注册表中保存的设备引用。驱动自己拥有设备（通常是一个 static），
+/// 文件系统通过名字取得它。
+pub type SharedDevice = &'static Mutex<dyn BlockDevice>;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    name: &'static str,
+    device: SharedDevice,
+}
+
+static REGISTRY: Mutex<heapless::Vec<Entry, MAX_DEVICES>> = Mutex::new(heapless::Vec::new());
+
+/// 以 `name`（例如 `"ata0"`、`"ram0"`）注册一个块设备。
+pub fn register(name: &'static str, device: SharedDevice) -> Result<(), BlockError> {
+    let mut registry = REGISTRY.lock();
+    if registry.iter().any(|entry| entry.name == name) {
+        return Err(BlockError::Registry);
+    }
+    registry
+        .push(Entry { name, device })
+        .map_err(|_| BlockError::Registry)
+}
+
+/// 按名字查找块设备。
+pub fn get(name: &str) -> Option<SharedDevice> {
+    REGISTRY
+        .lock()
+        .iter()
+        .find(|entry| entry.name == name)
+        .map(|entry| entry.device)
+}
+
+/// 对每个已注册的设备调用 `f`。
+pub fn for_each(mut f: impl FnMut(&'static str, SharedDevice)) {
+    // 先复制出来，避免 `f` 中再次访问注册表时死锁
+    let registry = REGISTRY.lock().clone();
+    for entry in registry.iter() {
+        f(entry.name, entry.device);
+    }
+}
+
+/// 以一段内存作为介质的块设备，用于测试和 initrd。
+pub struct RamDisk {
+    data: &'static mut [u8],
+    block_size: usize,
+}
+
+impl RamDisk {
+    /// `data` 的长度应当是 `block_size` 的整数倍，多余的部分不会被使用。
+    pub fn new(data: &'static mut [u8], block_size: usize) -> Self {
+        RamDisk { data, block_size }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.len() / self.block_size) as u64
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(&*self, lba, buf.len())?;
+        let start = lba as usize * self.block_size;
+        buf.copy_from_slice(&self.data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_request(&*self, lba, buf.len())?;
+        let start = lba as usize * self.block_size;
+        self.data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use lazy_static::lazy_static;
+
+#[cfg(test)]
+lazy_static! {
+    static ref TEST_DISK: Mutex<RamDisk> = {
+        static mut MEMORY: [u8; 512 * 8] = [0; 512 * 8];
+        let memory = &raw mut MEMORY;
+        Mutex::new(RamDisk::new(unsafe { &mut *memory }, 512))
+    };
+}
+
+#[test_case]
+fn test_ramdisk_read_write() {
+    let mut disk = TEST_DISK.lock();
+    let block = [0x5a; 1024];
+    disk.write_blocks(3, &block).unwrap();
+    let mut read = [0; 1024];
+    disk.read_blocks(3, &mut read).unwrap();
+    assert_eq!(read, block);
+    assert_eq!(disk.capacity(), 4096);
+    assert_eq!(disk.read_blocks(7, &mut read), Err(BlockError::OutOfRange));
+    assert_eq!(
+        disk.write_blocks(0, &block[..100]),
+        Err(BlockError::BadBuffer)
+    );
+}
+
+#[test_case]
+fn test_registry_lookup() {
+    let device: SharedDevice = &*TEST_DISK;
+    if get("test-ram").is_none() {
+        register("test-ram", device).unwrap();
+    }
+    assert_eq!(register("test-ram", device), Err(BlockError::Registry));
+    let found = get("test-ram").unwrap();
+    assert_eq!(found.lock().block_count(), 8);
+    assert!(get("missing").is_none());
+}
@@ -0,0 +1,374 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use super::{BlockDevice, BlockError, check_request};
+use crate::{
+    devices::{DeviceId, Driver, Match},
+    time,
+};
+
+pub const SECTOR_SIZE: usize = 512;
+
+// 命令寄存器组中各寄存器相对 I/O 基址的偏移
+const DATA: u16 = 0;
+const ERROR: u16 = 1;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE_HEAD: u16 = 6;
+const STATUS_COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_CACHE_FLUSH: u8 = 0xe7;
+const CMD_CACHE_FLUSH_EXT: u8 = 0xea;
+const CMD_IDENTIFY: u8 = 0xec;
+
+// 轮询状态寄存器的次数上限，以及等待中断的 tick 上限
+const POLL_LIMIT: usize = 1_000_000;
+const IRQ_TIMEOUT_TICKS: u64 = time::TICK_HZ * 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Primary,
+    Secondary,
+}
+
+impl Channel {
+    fn io_base(self) -> u16 {
+        match self {
+            Channel::Primary => 0x1f0,
+            Channel::Secondary => 0x170,
+        }
+    }
+
+    fn control(self) -> u16 {
+        match self {
+            Channel::Primary => 0x3f6,
+            Channel::Secondary => 0x376,
+        }
+    }
+
+    /// 该通道使用的 ISA 中断号（IRQ 14 / 15）。
+    pub fn irq(self) -> u8 {
+        match self {
+            Channel::Primary => 14,
+            Channel::Secondary => 15,
+        }
+    }
+}
+
+// 中断处理函数只负责记下“完成”，具体状态由等待方检查
+static IRQ_FIRED: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+/// 由 IRQ 14/15 的中断处理函数调用。
+pub fn handle_irq(channel: Channel) {
+    // 读取状态寄存器即向设备确认了中断
+    let _: u8 = unsafe { Port::new(channel.io_base() + STATUS_COMMAND).read() };
+    IRQ_FIRED[channel as usize].store(true, Ordering::SeqCst);
+}
+
+/// 一个 ATA 通道上的主盘或从盘，工作在 PIO 模式。
+pub struct AtaDrive {
+    channel: Channel,
+    slave: bool,
+    present: bool,
+    lba48: bool,
+    sectors: u64,
+    model: [u8; 40],
+}
+
+impl AtaDrive {
+    pub const fn new(channel: Channel, slave: bool) -> Self {
+        AtaDrive {
+            channel,
+            slave,
+            present: false,
+            lba48: false,
+            sectors: 0,
+            model: [b' '; 40],
+        }
+    }
+
+    pub fn present(&self) -> bool {
+        self.present
+    }
+
+    /// IDENTIFY DEVICE 返回的型号字符串。
+    pub fn model(&self) -> &str {
+        core::str::from_utf8(&self.model).unwrap_or("").trim_end()
+    }
+
+    fn reg(&self, offset: u16) -> u16 {
+        self.channel.io_base() + offset
+    }
+
+    fn write_reg(&self, offset: u16, value: u8) {
+        unsafe { Port::new(self.reg(offset)).write(value) }
+    }
+
+    fn read_reg(&self, offset: u16) -> u8 {
+        unsafe { Port::new(self.reg(offset)).read() }
+    }
+
+    fn alt_status(&self) -> u8 {
+        unsafe { Port::new(self.channel.control()).read() }
+    }
+
+    /// 选择驱动器后要等待约 400ns，读 4 次备用状态寄存器即可。
+    fn delay_400ns(&self) {
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    fn select(&self, lba_bits: u8) {
+        // bit 6 = LBA 模式，bit 4 = 从盘
+        self.write_reg(
+            DRIVE_HEAD,
+            0xe0 | ((self.slave as u8) << 4) | (lba_bits & 0x0f),
+        );
+        self.delay_400ns();
+    }
+
+    /// 等待 BSY 清零；`need_drq` 为真时还要求设备准备好传输数据。
+    fn poll(&self, need_drq: bool) -> Result<(), BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.alt_status();
+            if status & STATUS_BSY != 0 {
+                continue;
+            }
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(BlockError::Io);
+            }
+            if !need_drq || status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(BlockError::Io)
+    }
+
+    /// 等待设备通过中断报告完成，中断被关闭时退化为轮询。
+    fn wait(&self, need_drq: bool) -> Result<(), BlockError> {
+        let fired = &IRQ_FIRED[self.channel as usize];
+        if interrupts::are_enabled() {
+            let deadline = time::ticks() + IRQ_TIMEOUT_TICKS;
+            crate::wait_until(|| fired.swap(false, Ordering::SeqCst) || time::ticks() > deadline);
+        }
+        self.poll(need_drq)
+    }
+
+    /// 发送 IDENTIFY DEVICE，记录容量和型号。
+    pub fn identify(&mut self) -> bool {
+        // 总线悬空时读到 0xff，说明这个通道上没有控制器
+        if self.alt_status() == 0xff {
+            return false;
+        }
+        self.select(0);
+        for reg in [SECTOR_COUNT, LBA_LOW, LBA_MID, LBA_HIGH] {
+            self.write_reg(reg, 0);
+        }
+        self.write_reg(STATUS_COMMAND, CMD_IDENTIFY);
+        if self.read_reg(STATUS_COMMAND) == 0 {
+            return false;
+        }
+        if self.poll(false).is_err() {
+            return false;
+        }
+        // ATAPI/SATA 设备会在这两个寄存器中留下签名，不是 PATA 硬盘
+        if self.read_reg(LBA_MID) != 0 || self.read_reg(LBA_HIGH) != 0 {
+            return false;
+        }
+        if self.poll(true).is_err() {
+            return false;
+        }
+
+        let mut data: Port<u16> = Port::new(self.reg(DATA));
+        let mut words = [0u16; 256];
+        for word in words.iter_mut() {
+            *word = unsafe { data.read() };
+        }
+
+        self.lba48 = words[83] & (1 << 10) != 0;
+        self.sectors = if self.lba48 {
+            words[100..104]
+                .iter()
+                .rev()
+                .fold(0, |acc, &w| (acc << 16) | w as u64)
+        } else {
+            ((words[61] as u64) << 16) | words[60] as u64
+        };
+        // 型号字符串中每个字的两个字节是颠倒的
+        for (i, word) in words[27..47].iter().enumerate() {
+            self.model[i * 2] = (word >> 8) as u8;
+            self.model[i * 2 + 1] = *word as u8;
+        }
+        self.present = self.sectors > 0;
+        self.present
+    }
+
+    /// 设置 LBA 和扇区数（1..=256）并发出命令。扇区数寄存器写 0 表示 256。
+    fn issue(&self, lba: u64, sectors: usize, cmd28: u8, cmd48: u8) -> Result<(), BlockError> {
+        IRQ_FIRED[self.channel as usize].store(false, Ordering::SeqCst);
+        let needs_lba48 = lba + sectors as u64 > 1 << 28;
+        if needs_lba48 && !self.lba48 {
+            return Err(BlockError::OutOfRange);
+        }
+        self.poll(false)?;
+        if needs_lba48 {
+            self.select(0);
+            // LBA48 先写高字节，再写低字节
+            self.write_reg(SECTOR_COUNT, (sectors >> 8) as u8);
+            self.write_reg(LBA_LOW, (lba >> 24) as u8);
+            self.write_reg(LBA_MID, (lba >> 32) as u8);
+            self.write_reg(LBA_HIGH, (lba >> 40) as u8);
+            self.write_reg(SECTOR_COUNT, sectors as u8);
+            self.write_reg(LBA_LOW, lba as u8);
+            self.write_reg(LBA_MID, (lba >> 8) as u8);
+            self.write_reg(LBA_HIGH, (lba >> 16) as u8);
+            self.write_reg(STATUS_COMMAND, cmd48);
+        } else {
+            self.select((lba >> 24) as u8);
+            self.write_reg(SECTOR_COUNT, sectors as u8);
+            self.write_reg(LBA_LOW, lba as u8);
+            self.write_reg(LBA_MID, (lba >> 8) as u8);
+            self.write_reg(LBA_HIGH, (lba >> 16) as u8);
+            self.write_reg(STATUS_COMMAND, cmd28);
+        }
+        Ok(())
+    }
+
+    fn error(&self) -> BlockError {
+        // 读取错误寄存器只是为了清除设备的错误状态
+        self.read_reg(ERROR);
+        BlockError::Io
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(&*self, lba, buf.len())?;
+        let mut data: Port<u16> = Port::new(self.reg(DATA));
+        // 每条命令最多 256 个扇区，这样 LBA28 也能处理
+        for (i, chunk) in buf.chunks_mut(256 * SECTOR_SIZE).enumerate() {
+            let sectors = chunk.len() / SECTOR_SIZE;
+            let start = lba + (i * 256) as u64;
+            self.issue(start, sectors, CMD_READ_SECTORS, CMD_READ_SECTORS_EXT)?;
+            // 每个扇区准备好时设备都会触发一次中断
+            for sector in chunk.chunks_mut(SECTOR_SIZE) {
+                self.wait(true).map_err(|_| self.error())?;
+                for pair in sector.chunks_mut(2) {
+                    let word: u16 = unsafe { data.read() };
+                    pair.copy_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_request(&*self, lba, buf.len())?;
+        let mut data: Port<u16> = Port::new(self.reg(DATA));
+        for (i, chunk) in buf.chunks(256 * SECTOR_SIZE).enumerate() {
+            let sectors = chunk.len() / SECTOR_SIZE;
+            let start = lba + (i * 256) as u64;
+            self.issue(start, sectors, CMD_WRITE_SECTORS, CMD_WRITE_SECTORS_EXT)?;
+            // 写命令的第一个扇区不会产生中断，之后每写完一个扇区产生一次
+            self.poll(true).map_err(|_| self.error())?;
+            for (n, sector) in chunk.chunks(SECTOR_SIZE).enumerate() {
+                if n > 0 {
+                    self.wait(true).map_err(|_| self.error())?;
+                }
+                for pair in sector.chunks(2) {
+                    unsafe { data.write(u16::from_le_bytes([pair[0], pair[1]])) };
+                }
+            }
+            self.wait(false).map_err(|_| self.error())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        IRQ_FIRED[self.channel as usize].store(false, Ordering::SeqCst);
+        self.poll(false)?;
+        self.select(0);
+        let cmd = if self.lba48 {
+            CMD_CACHE_FLUSH_EXT
+        } else {
+            CMD_CACHE_FLUSH
+        };
+        self.write_reg(STATUS_COMMAND, cmd);
+        self.wait(false).map_err(|_| self.error())
+    }
+}
+
+const NAMES: [&str; 4] = ["ata0", "ata1", "ata2", "ata3"];
+
+static DRIVES: [Mutex<AtaDrive>; 4] = [
+    Mutex::new(AtaDrive::new(Channel::Primary, false)),
+    Mutex::new(AtaDrive::new(Channel::Primary, true)),
+    Mutex::new(AtaDrive::new(Channel::Secondary, false)),
+    Mutex::new(AtaDrive::new(Channel::Secondary, true)),
+];
+
+/// 主、从两个通道上的 ATA 驱动器，绑定到传统设备 `ata`。
+pub static DRIVER: Driver = Driver {
+    name: "ata",
+    matches: &[Match::Platform("ata")],
+    probe,
+};
+
+/// 探测两个通道上的四个位置，找到的驱动器注册为 `ata0`..`ata3`。
+/// 一个驱动器都没有时不接管设备。
+fn probe(_id: &DeviceId) -> bool {
+    for channel in [Channel::Primary, Channel::Secondary] {
+        crate::interrupts::unmask_irq(channel.irq());
+    }
+    let mut found = false;
+    for (drive, name) in DRIVES.iter().zip(NAMES) {
+        let mut locked = drive.lock();
+        found |= locked.present();
+        if !locked.present() && locked.identify() {
+            found = true;
+            log::info!(
+                "{}: {} ({} MiB)",
+                name,
+                locked.model(),
+                locked.capacity() / (1024 * 1024)
+            );
+            drop(locked);
+            let _ = super::register(name, drive);
+        }
+    }
+    found
+}
+
+#[test_case]
+fn test_ata_reads_boot_sector() {
+    // bootimage 把内核镜像作为主通道主盘交给 QEMU，第一个扇区是引导扇区
+    let Some(disk) = super::get("ata0") else {
+        crate::testing::skip("no ATA disk");
+        return;
+    };
+    let mut sector = [0; SECTOR_SIZE];
+    disk.lock().read_blocks(0, &mut sector).unwrap();
+    assert_eq!(&sector[510..], &[0x55, 0xaa]);
+}
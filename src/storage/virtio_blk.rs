@@ -0,0 +1,497 @@
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU16, Ordering, fence},
+};
+
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use super::{BlockDevice, BlockError, check_request};
+use crate::{
+    devices::{DeviceId, Driver, Match},
+    memory::{self, DmaBuffer, PAGE_SIZE},
+    pci::{self, Bar},
+    time,
+};
+
+pub const SECTOR_SIZE: usize = 512;
+
+const VENDOR_ID: u16 = 0x1af4;
+// 过渡（transitional）设备的 ID，同时支持传统接口；纯现代设备（0x1042）不处理
+const DEVICE_ID: u16 = 0x1001;
+
+// 传统 PCI 接口的寄存器，位于 BAR0 的 I/O 空间
+const DEVICE_FEATURES: u16 = 0x00;
+const GUEST_FEATURES: u16 = 0x04;
+const QUEUE_ADDRESS: u16 = 0x08;
+const QUEUE_SIZE: u16 = 0x0c;
+const QUEUE_SELECT: u16 = 0x0e;
+const QUEUE_NOTIFY: u16 = 0x10;
+const DEVICE_STATUS: u16 = 0x12;
+const ISR_STATUS: u16 = 0x13;
+// 启用 MSI-X 后通用寄存器之后多出两个向量寄存器，设备配置随之后移
+const CONFIG_MSIX_VECTOR: u16 = 0x14;
+const QUEUE_MSIX_VECTOR: u16 = 0x16;
+const CONFIG_CAPACITY: u16 = 0x14;
+const CONFIG_CAPACITY_MSIX: u16 = 0x18;
+// 向量寄存器中表示“不产生中断”的值
+const NO_VECTOR: u16 = 0xffff;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 0x80;
+
+const FEATURE_RO: u32 = 1 << 5;
+const FEATURE_FLUSH: u32 = 1 << 9;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+
+// 一次请求最多传输的数据量，也是中转缓冲区的大小
+const BOUNCE_PAGES: usize = 16;
+const BOUNCE_SIZE: usize = BOUNCE_PAGES * PAGE_SIZE as usize;
+
+// 等待请求完成的时间上限。用 TSC 计时：关着中断时时钟中断不来，tick 不走
+const REQUEST_TIMEOUT_MS: u64 = 2000;
+
+/// 最多支持的 virtio-blk 设备数。
+pub const MAX_DISKS: usize = 4;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+// 请求页中状态字节的位置，紧跟在请求头之后
+const STATUS_OFFSET: usize = core::mem::size_of::<RequestHeader>();
+
+/// 传统接口下大小为 `size` 的 virtqueue 的布局：(可用环偏移, 已用环偏移,
+/// 总字节数)。描述符表在偏移 0，已用环要按页对齐。
+fn queue_layout(size: usize) -> (usize, usize, usize) {
+    let align = |n: usize| n.next_multiple_of(PAGE_SIZE as usize);
+    let avail = 16 * size;
+    let used = align(avail + 6 + 2 * size);
+    (avail, used, align(used + 6 + 8 * size))
+}
+
+struct Queue {
+    memory: DmaBuffer,
+    size: u16,
+    avail: usize,
+    used: usize,
+    // 驱动已提交的可用环下标和已处理到的已用环下标
+    next_avail: u16,
+    last_used: u16,
+}
+
+impl Queue {
+    fn new(size: u16) -> Option<Queue> {
+        let (avail, used, total) = queue_layout(size as usize);
+        let memory = memory::alloc_dma(total / PAGE_SIZE as usize)?;
+        Some(Queue {
+            memory,
+            size,
+            avail,
+            used,
+            next_avail: 0,
+            last_used: 0,
+        })
+    }
+
+    fn set_descriptor(&self, index: u16, descriptor: Descriptor) {
+        let offset = index as usize * core::mem::size_of::<Descriptor>();
+        unsafe { ptr::write_volatile(self.memory.ptr(offset), descriptor) };
+    }
+
+    /// 把以描述符 `head` 开头的链放入可用环。
+    fn push(&mut self, head: u16) {
+        let slot = self.avail + 4 + 2 * (self.next_avail % self.size) as usize;
+        unsafe { ptr::write_volatile(self.memory.ptr(slot), head) };
+        self.next_avail = self.next_avail.wrapping_add(1);
+        // 设备必须先看到环中的内容，再看到新的下标
+        fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile(self.memory.ptr(self.avail + 2), self.next_avail) };
+        fence(Ordering::SeqCst);
+    }
+
+    /// 设备是否完成了新的请求，完成时消耗掉这一项。
+    fn pop_used(&mut self) -> bool {
+        let index: u16 = unsafe { ptr::read_volatile(self.memory.ptr(self.used + 2)) };
+        if index == self.last_used {
+            return false;
+        }
+        fence(Ordering::SeqCst);
+        self.last_used = self.last_used.wrapping_add(1);
+        true
+    }
+}
+
+// 中断处理函数不能去拿设备的锁（等待方正持有它），所以 ISR
+// 端口和完成标志单独存放
+static ISR_PORTS: [AtomicU16; MAX_DISKS] = [const { AtomicU16::new(0) }; MAX_DISKS];
+static IRQ_FIRED: [AtomicBool; MAX_DISKS] = [const { AtomicBool::new(false) }; MAX_DISKS];
+
+/// 注册到设备中断线上的处理函数。中断线可能与其他设备共享，
+/// 通过 ISR 状态寄存器判断是不是自己的中断；读取它同时清除了中断。
+fn handle_irq() {
+    for (port, fired) in ISR_PORTS.iter().zip(&IRQ_FIRED) {
+        let port = port.load(Ordering::Relaxed);
+        if port != 0 && unsafe { Port::<u8>::new(port).read() } & 1 != 0 {
+            fired.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// MSI-X 中断的处理函数。消息中断不共享，也不用读 ISR 状态；不知道是哪个
+/// 磁盘的就都标记上，等待方会再检查已用环。
+fn handle_msi() {
+    for fired in &IRQ_FIRED {
+        fired.store(true, Ordering::SeqCst);
+    }
+}
+
+/// 使用传统 PCI 接口的 virtio 块设备，一次处理一个请求。
+pub struct VirtioBlk {
+    index: usize,
+    io_base: u16,
+    sectors: u64,
+    read_only: bool,
+    flush: bool,
+    queue: Option<Queue>,
+    // 请求头和状态字节
+    request: Option<DmaBuffer>,
+    // 调用者的缓冲区不一定物理连续，数据经由这里中转
+    bounce: Option<DmaBuffer>,
+}
+
+impl VirtioBlk {
+    pub const fn new(index: usize) -> Self {
+        VirtioBlk {
+            index,
+            io_base: 0,
+            sectors: 0,
+            read_only: false,
+            flush: false,
+            queue: None,
+            request: None,
+            bounce: None,
+        }
+    }
+
+    pub fn present(&self) -> bool {
+        self.queue.is_some()
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn port<T>(&self, offset: u16) -> Port<T> {
+        Port::new(self.io_base + offset)
+    }
+
+    fn set_status(&self, status: u8) {
+        unsafe { self.port::<u8>(DEVICE_STATUS).write(status) };
+    }
+
+    /// 按 virtio 规范的顺序初始化设备并建立请求队列 0。
+    fn probe(&mut self, device: &pci::PciDevice) -> bool {
+        let Bar::Io { port, .. } = device.bars[0] else {
+            return false;
+        };
+        device.enable(true, false, true);
+        self.io_base = port;
+
+        // 复位，然后告诉设备我们认识它、并且有驱动
+        self.set_status(0);
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let features = unsafe { self.port::<u32>(DEVICE_FEATURES).read() };
+        let accepted = features & (FEATURE_RO | FEATURE_FLUSH);
+        unsafe { self.port::<u32>(GUEST_FEATURES).write(accepted) };
+        self.read_only = accepted & FEATURE_RO != 0;
+        self.flush = accepted & FEATURE_FLUSH != 0;
+
+        // 设备配置的位置取决于是否启用了 MSI-X，所以要先决定中断方式
+        let mut msix = device.enable_msix(0, handle_msi);
+        let config = match msix {
+            Some(_) => CONFIG_CAPACITY_MSIX,
+            None => CONFIG_CAPACITY,
+        };
+        let low = unsafe { self.port::<u32>(config).read() };
+        let high = unsafe { self.port::<u32>(config + 4).read() };
+        self.sectors = ((high as u64) << 32) | low as u64;
+
+        // 传统接口下队列大小由设备决定，驱动只能照办
+        unsafe { self.port::<u16>(QUEUE_SELECT).write(0) };
+        let size = unsafe { self.port::<u16>(QUEUE_SIZE).read() };
+        let queue = if size == 0 { None } else { Queue::new(size) };
+        let (Some(queue), Some(request), Some(bounce)) =
+            (queue, memory::alloc_dma(1), memory::alloc_dma(BOUNCE_PAGES))
+        else {
+            if let Some(vector) = msix {
+                device.disable_msix(vector);
+            }
+            self.set_status(STATUS_FAILED);
+            return false;
+        };
+        let pfn = queue.memory.phys_addr().as_u64() / PAGE_SIZE;
+        unsafe { self.port::<u32>(QUEUE_ADDRESS).write(pfn as u32) };
+
+        if let Some(vector) = msix {
+            // 队列 0 用表项 0，配置变化不需要中断；设备分配不了资源时读回 NO_VECTOR
+            unsafe {
+                self.port::<u16>(CONFIG_MSIX_VECTOR).write(NO_VECTOR);
+                self.port::<u16>(QUEUE_MSIX_VECTOR).write(0);
+            }
+            if unsafe { self.port::<u16>(QUEUE_MSIX_VECTOR).read() } == NO_VECTOR {
+                log::warn!("virtio-blk: device rejected MSI-X vector, using INTx");
+                device.disable_msix(vector);
+                msix = None;
+            } else {
+                log::info!("virtio-blk: using MSI-X vector {vector:#x}");
+            }
+        }
+        if msix.is_none() {
+            ISR_PORTS[self.index].store(self.io_base + ISR_STATUS, Ordering::Relaxed);
+            if !crate::interrupts::register_irq_handler(device.interrupt_line, handle_irq) {
+                log::warn!("no handler slot for IRQ {}, polling", device.interrupt_line);
+            }
+        }
+        self.queue = Some(queue);
+        self.request = Some(request);
+        self.bounce = Some(bounce);
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+        true
+    }
+
+    /// 提交一个请求并等待完成。`len` 字节的数据在中转缓冲区中，
+    /// `device_writes` 表示数据由设备写入（读请求）。
+    fn submit(
+        &mut self,
+        kind: u32,
+        sector: u64,
+        len: usize,
+        device_writes: bool,
+    ) -> Result<(), BlockError> {
+        let (Some(queue), Some(request), Some(bounce)) = (
+            self.queue.as_mut(),
+            self.request.as_ref(),
+            self.bounce.as_ref(),
+        ) else {
+            return Err(BlockError::Io);
+        };
+        let header = RequestHeader {
+            kind,
+            reserved: 0,
+            sector,
+        };
+        unsafe {
+            ptr::write_volatile(request.ptr(0), header);
+            ptr::write_volatile(request.ptr::<u8>(STATUS_OFFSET), 0xff);
+        }
+
+        let request_phys = request.phys_addr().as_u64();
+        queue.set_descriptor(
+            0,
+            Descriptor {
+                addr: request_phys,
+                len: STATUS_OFFSET as u32,
+                flags: DESC_NEXT,
+                next: 1,
+            },
+        );
+        let mut status_index = 1;
+        if len > 0 {
+            queue.set_descriptor(
+                1,
+                Descriptor {
+                    addr: bounce.phys_addr().as_u64(),
+                    len: len as u32,
+                    flags: DESC_NEXT | if device_writes { DESC_WRITE } else { 0 },
+                    next: 2,
+                },
+            );
+            status_index = 2;
+        }
+        queue.set_descriptor(
+            status_index,
+            Descriptor {
+                addr: request_phys + STATUS_OFFSET as u64,
+                len: 1,
+                flags: DESC_WRITE,
+                next: 0,
+            },
+        );
+
+        let fired = &IRQ_FIRED[self.index];
+        fired.store(false, Ordering::SeqCst);
+        queue.push(0);
+        unsafe { Port::<u16>::new(self.io_base + QUEUE_NOTIFY).write(0) };
+
+        let deadline = time::tsc() + REQUEST_TIMEOUT_MS * time::tsc_per_ms();
+        loop {
+            if queue.pop_used() {
+                break;
+            }
+            if time::tsc() > deadline {
+                return Err(BlockError::Io);
+            }
+            if interrupts::are_enabled() {
+                crate::wait_until(|| fired.swap(false, Ordering::SeqCst) || time::tsc() > deadline);
+            } else {
+                core::hint::spin_loop();
+            }
+        }
+
+        match unsafe { ptr::read_volatile(request.ptr::<u8>(STATUS_OFFSET)) } {
+            0 => Ok(()),
+            _ => Err(BlockError::Io),
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(&*self, lba, buf.len())?;
+        for (i, chunk) in buf.chunks_mut(BOUNCE_SIZE).enumerate() {
+            let sector = lba + (i * BOUNCE_SIZE / SECTOR_SIZE) as u64;
+            self.submit(REQUEST_IN, sector, chunk.len(), true)?;
+            let bounce = self.bounce.as_ref().ok_or(BlockError::Io)?;
+            chunk.copy_from_slice(&bounce.as_slice()[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_request(&*self, lba, buf.len())?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        for (i, chunk) in buf.chunks(BOUNCE_SIZE).enumerate() {
+            let sector = lba + (i * BOUNCE_SIZE / SECTOR_SIZE) as u64;
+            let bounce = self.bounce.as_mut().ok_or(BlockError::Io)?;
+            bounce.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            self.submit(REQUEST_OUT, sector, chunk.len(), false)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        // 没有协商 FLUSH 特性时设备是直写的，不需要刷新
+        if !self.flush {
+            return Ok(());
+        }
+        self.submit(REQUEST_FLUSH, 0, 0, false)
+    }
+}
+
+const NAMES: [&str; MAX_DISKS] = ["vda", "vdb", "vdc", "vdd"];
+
+static DISKS: [Mutex<VirtioBlk>; MAX_DISKS] = [
+    Mutex::new(VirtioBlk::new(0)),
+    Mutex::new(VirtioBlk::new(1)),
+    Mutex::new(VirtioBlk::new(2)),
+    Mutex::new(VirtioBlk::new(3)),
+];
+
+/// virtio-blk 驱动，按发现顺序把设备注册为 `vda`..`vdd`。
+///
+/// QEMU 中这样添加：`-drive file=disk.img,if=virtio,format=raw`。
+pub static DRIVER: Driver = Driver {
+    name: "virtio-blk",
+    matches: &[Match::Pci {
+        vendor_id: VENDOR_ID,
+        device_id: DEVICE_ID,
+    }],
+    probe,
+};
+
+/// 用第一个空闲的磁盘槽位接管设备。
+fn probe(id: &DeviceId) -> bool {
+    let DeviceId::Pci(device) = id else {
+        return false;
+    };
+    for (disk, name) in DISKS.iter().zip(NAMES) {
+        let mut locked = disk.lock();
+        if locked.present() {
+            continue;
+        }
+        if !locked.probe(device) {
+            return false;
+        }
+        log::info!(
+            "{}: virtio-blk at {} ({} MiB{})",
+            name,
+            device.address,
+            locked.capacity() / (1024 * 1024),
+            if locked.read_only() {
+                ", read-only"
+            } else {
+                ""
+            }
+        );
+        drop(locked);
+        let _ = super::register(name, disk);
+        return true;
+    }
+    log::warn!("virtio-blk: no free disk slot for {}", device.address);
+    false
+}
+
+#[test_case]
+fn test_virtio_queue_layout() {
+    assert_eq!(queue_layout(256), (4096, 8192, 12288));
+    assert_eq!(queue_layout(128), (2048, 4096, 8192));
+}
+
+#[test_case]
+fn test_virtio_blk_read_write() {
+    // 只有用 `-drive if=virtio` 启动时才有设备
+    let Some(disk) = super::get("vda") else {
+        crate::testing::skip("no virtio disk");
+        return;
+    };
+    let mut disk = disk.lock();
+    if disk.block_count() < 2 {
+        crate::testing::skip("virtio disk too small");
+        return;
+    }
+    let last = disk.block_count() - 1;
+    let mut saved = [0; SECTOR_SIZE];
+    disk.read_blocks(last, &mut saved).unwrap();
+    let pattern = [0xa5; SECTOR_SIZE];
+    if disk.write_blocks(last, &pattern) == Err(BlockError::ReadOnly) {
+        crate::testing::skip("virtio disk is read-only");
+        return;
+    }
+    let mut read = [0; SECTOR_SIZE];
+    disk.read_blocks(last, &mut read).unwrap();
+    assert_eq!(read, pattern);
+    disk.write_blocks(last, &saved).unwrap();
+    disk.flush().unwrap();
+}
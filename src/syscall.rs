@@ -0,0 +1,183 @@
+use x86_64::instructions::interrupts;
+
+use crate::{
+    config::{self, ConfigError},
+    console,
+    fs::FsError,
+    interrupts::TrapFrame,
+    keyboard, memory, print,
+    process::{self, SpawnError},
+    time, trace,
+};
+
+/// 系统调用使用的中断向量（`int 0x80`），用户态可以触发。
+pub const VECTOR: u8 = 0x80;
+
+// 调用号放在 rax 中，参数依次放在 rdi、rsi、rdx，返回值放回 rax
+/// `exit(status)`：结束当前进程
+pub const EXIT: u64 = 0;
+/// `write(fd, buf, len)`：写到标准输出（1）或标准错误（2），返回写入的字节数
+pub const WRITE: u64 = 1;
+/// `getpid()`
+pub const GETPID: u64 = 2;
+/// `spawn(path, len)`：运行一个程序并等它退出，返回它的退出码
+pub const SPAWN: u64 = 3;
+/// `uptime()`：启动以来的毫秒数
+pub const UPTIME: u64 = 4;
+/// `read(fd, buf, len)`：从标准输入（0，键盘）读，等到至少有一个字节后
+/// 返回读到的字节数；等待时按下 Ctrl+C 返回 `-EINTR`
+pub const READ: u64 = 5;
+/// `setconf(buf, len)`：修改一项设置并保存，`buf` 是 `keymap=de` 这样的
+/// `键=值`，键可以是 `keymap`、`hostname` 或 `loglevel`
+pub const SETCONF: u64 = 6;
+/// `trace(op)`：控制跟踪缓冲区，`op` 为 [`TRACE_DUMP`]、[`TRACE_ON`]、
+/// [`TRACE_OFF`] 或 [`TRACE_CLEAR`]
+pub const TRACE: u64 = 7;
+
+/// 按时间顺序输出所有记录
+pub const TRACE_DUMP: u64 = 0;
+/// 开始记录
+pub const TRACE_ON: u64 = 1;
+/// 停止记录
+pub const TRACE_OFF: u64 = 2;
+/// 清空缓冲区
+pub const TRACE_CLEAR: u64 = 3;
+
+// 出错时返回负的错误号，取值与 Linux 相同
+pub const ENOENT: i64 = 2;
+pub const EINTR: i64 = 4;
+pub const EIO: i64 = 5;
+pub const ENOEXEC: i64 = 8;
+pub const EBADF: i64 = 9;
+pub const EAGAIN: i64 = 11;
+pub const ENOMEM: i64 = 12;
+pub const EFAULT: i64 = 14;
+pub const EINVAL: i64 = 22;
+pub const ENOSYS: i64 = 38;
+
+/// 处理一次系统调用，由 `int 0x80` 的入口调用。
+pub(crate) fn dispatch(frame: &mut TrapFrame) {
+    // 经由中断门进来时中断是关着的，而读文件可能要等磁盘中断
+    interrupts::enable();
+    let result = match frame.rax {
+        EXIT => process::exit(frame.rdi as i64),
+        WRITE => write(frame.rdi, frame.rsi, frame.rdx),
+        GETPID => process::current_pid() as i64,
+        SPAWN => spawn(frame.rdi, frame.rsi),
+        UPTIME => time::uptime_ms() as i64,
+        READ => read(frame.rdi, frame.rsi, frame.rdx),
+        SETCONF => setconf(frame.rdi, frame.rsi),
+        TRACE => control_trace(frame.rdi),
+        _ => -ENOSYS,
+    };
+    frame.rax = result as u64;
+    // 系统调用期间按下的 Ctrl+C 在回到用户态之前处理
+    process::check_interrupt(frame.cs);
+}
+
+/// 用户传进来的一段内存。不在用户空间中或者没有映射时返回 `None`。
+fn user_slice(ptr: u64, len: u64) -> Option<&'static [u8]> {
+    memory::is_user_range(ptr, len)
+        .then(|| unsafe { core::slice::from_raw_parts(ptr as *const u8, len as usize) })
+}
+
+fn write(fd: u64, ptr: u64, len: u64) -> i64 {
+    if fd != 1 && fd != 2 {
+        return -EBADF;
+    }
+    let Some(bytes) = user_slice(ptr, len) else {
+        return -EFAULT;
+    };
+    for chunk in bytes.utf8_chunks() {
+        print!("{}", chunk.valid());
+        if !chunk.invalid().is_empty() {
+            print!("\u{fffd}");
+        }
+    }
+    len as i64
+}
+
+fn read(fd: u64, ptr: u64, len: u64) -> i64 {
+    if fd != 0 {
+        return -EBADF;
+    }
+    if !memory::is_user_writable(ptr, len) {
+        return -EFAULT;
+    }
+    if len == 0 {
+        return 0;
+    }
+    let buf = unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, len as usize) };
+    loop {
+        // 关着中断检查，按键不会在检查之后、hlt 之前到达而多等一个时钟
+        interrupts::disable();
+        let read = keyboard::read_input(buf);
+        if read > 0 {
+            interrupts::enable();
+            return read as i64;
+        }
+        // 等输入的程序（比如 shell）自己决定怎么处理 Ctrl+C，不杀死它
+        if console::take_interrupt() {
+            interrupts::enable();
+            return -EINTR;
+        }
+        interrupts::enable_and_hlt();
+    }
+}
+
+fn setconf(ptr: u64, len: u64) -> i64 {
+    let Some(bytes) = user_slice(ptr, len) else {
+        return -EFAULT;
+    };
+    let Some((key, value)) = core::str::from_utf8(bytes)
+        .ok()
+        .and_then(|setting| setting.split_once('='))
+    else {
+        return -EINVAL;
+    };
+    let result = match key {
+        "keymap" => config::set_keymap(value),
+        "hostname" => config::set_hostname(value),
+        "loglevel" => config::set_log_level(value),
+        _ => return -EINVAL,
+    };
+    match result {
+        Ok(()) => 0,
+        Err(ConfigError::Io) => -EIO,
+        Err(ConfigError::TooLong | ConfigError::Invalid) => -EINVAL,
+    }
+}
+
+fn control_trace(op: u64) -> i64 {
+    match op {
+        TRACE_DUMP => trace::dump(),
+        TRACE_ON => trace::set_enabled(true),
+        TRACE_OFF => trace::set_enabled(false),
+        TRACE_CLEAR => trace::clear(),
+        _ => return -EINVAL,
+    }
+    0
+}
+
+fn spawn(ptr: u64, len: u64) -> i64 {
+    let Some(bytes) = user_slice(ptr, len) else {
+        return -EFAULT;
+    };
+    let Ok(path) = core::str::from_utf8(bytes) else {
+        return -EINVAL;
+    };
+    match process::spawn(path) {
+        Ok(status) => status,
+        Err(err) => -errno(err),
+    }
+}
+
+fn errno(err: SpawnError) -> i64 {
+    match err {
+        SpawnError::Fs(FsError::NotFound) => ENOENT,
+        SpawnError::Fs(_) => EIO,
+        SpawnError::Elf(_) | SpawnError::BadLayout => ENOEXEC,
+        SpawnError::NoMemory => ENOMEM,
+        SpawnError::TooMany => EAGAIN,
+    }
+}
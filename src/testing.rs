@@ -0,0 +1,379 @@
+use core::{
+    arch::global_asm,
+    fmt,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{QemuExitCode, cmdline, exit_qemu, panic, power, serial_print, serial_println, time};
+
+/// 单个测试默认的时限，可以用命令行的 `test_timeout=<毫秒>` 修改。
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+
+pub trait Testable {
+    /// 报告中显示的名字。
+    fn name(&self) -> &'static str;
+    fn run(&self);
+}
+
+impl<T> Testable for T
+where
+    T: Fn(),
+{
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+
+    fn run(&self) {
+        self();
+    }
+}
+
+/// 应当 panic 的测试，用 [`should_panic_case!`](crate::should_panic_case)
+/// 定义。
+pub struct ShouldPanic {
+    pub name: &'static str,
+    pub test: fn(),
+}
+
+impl Testable for ShouldPanic {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&self) {
+        assert!(catch_panic(self.test), "test did not panic");
+    }
+}
+
+/// 定义一个应当 panic 的测试：测试体 panic 时报告 `[ok]` 并继续运行后面的
+/// 测试，没有 panic 反而算失败。
+///
+/// ```ignore
+/// should_panic_case!(test_index_out_of_bounds, {
+///     let array = [1, 2, 3];
+///     let _ = array[core::hint::black_box(3)];
+/// });
+/// ```
+#[macro_export]
+macro_rules! should_panic_case {
+    ($name:ident, $body:block) => {
+        #[test_case]
+        #[allow(non_upper_case_globals)]
+        static $name: $crate::testing::ShouldPanic = $crate::testing::ShouldPanic {
+            name: concat!(module_path!(), "::", stringify!($name)),
+            test: || $body,
+        };
+    };
+}
+
+// 内核不支持栈展开，panic 之后无法回到测试的调用者。`testing_catch` 保存
+// 被调用者保存的寄存器和栈指针后调用测试，正常返回 0；panic 处理函数调用
+// `testing_throw` 恢复这些寄存器，让 `testing_catch` 返回 1。被跳过的栈帧
+// 不会析构，测试持有的锁也不会释放
+global_asm!(
+    ".global testing_catch",
+    "testing_catch:",
+    "mov [rdi], rbx",
+    "mov [rdi + 8], rbp",
+    "mov [rdi + 16], r12",
+    "mov [rdi + 24], r13",
+    "mov [rdi + 32], r14",
+    "mov [rdi + 40], r15",
+    "mov [rdi + 48], rsp",
+    // 进来时 rsp 模 16 余 8，调用前对齐到 16
+    "sub rsp, 8",
+    "mov rdi, rdx",
+    "call rsi",
+    "add rsp, 8",
+    "xor eax, eax",
+    "ret",
+    ".global testing_throw",
+    "testing_throw:",
+    "mov rbx, [rdi]",
+    "mov rbp, [rdi + 8]",
+    "mov r12, [rdi + 16]",
+    "mov r13, [rdi + 24]",
+    "mov r14, [rdi + 32]",
+    "mov r15, [rdi + 40]",
+    "mov rsp, [rdi + 48]",
+    "mov eax, 1",
+    "ret",
+);
+
+unsafe extern "C" {
+    fn testing_catch(
+        checkpoint: *mut [u64; 7],
+        f: extern "C" fn(*const ()),
+        data: *const (),
+    ) -> u64;
+    fn testing_throw(checkpoint: *const [u64; 7]) -> !;
+}
+
+// `testing_catch` 保存的寄存器，只在测试运行的 CPU 上访问
+static mut CHECKPOINT: [u64; 7] = [0; 7];
+// 正在运行的测试期望 panic
+static EXPECT_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// 运行 `test`，返回它是否 panic 了。
+fn catch_panic(test: fn()) -> bool {
+    extern "C" fn call(data: *const ()) {
+        let test: fn() = unsafe { core::mem::transmute(data) };
+        test();
+    }
+    let enabled = interrupts::are_enabled();
+    EXPECT_PANIC.store(true, Ordering::SeqCst);
+    let panicked = unsafe { testing_catch(&raw mut CHECKPOINT, call, test as *const ()) } != 0;
+    EXPECT_PANIC.store(false, Ordering::SeqCst);
+    // panic 可能发生在关中断的代码里
+    if enabled {
+        interrupts::enable();
+    }
+    panicked
+}
+
+/// 测试结果的输出格式，由命令行 `test_format=` 选择，默认给人看。
+///
+/// TAP 和 JSON 每个测试一行，外部脚本可以据此生成报告、比较耗时。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    /// TAP 13：`ok 1 - name # time=0.123ms`
+    Tap,
+    /// 每行一个 JSON 对象：`{"type":"test","name":...,"result":"ok","
+    /// duration_us":123}`
+    Json,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Format> {
+        match s {
+            "human" => Some(Format::Human),
+            "tap" => Some(Format::Tap),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn current() -> Format {
+        cmdline::get("test_format")
+            .and_then(Format::parse)
+            .unwrap_or(Format::Human)
+    }
+}
+
+/// 把字符串按 JSON 字符串的规则转义后输出（不含两边的引号）。
+pub(crate) struct JsonEscaped<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for JsonEscaped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Escape<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+        impl fmt::Write for Escape<'_, '_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                for c in s.chars() {
+                    match c {
+                        '"' => self.0.write_str("\\\"")?,
+                        '\\' => self.0.write_str("\\\\")?,
+                        '\n' => self.0.write_str("\\n")?,
+                        c if (c as u32) < 0x20 => write!(self.0, "\\u{:04x}", c as u32)?,
+                        c => self.0.write_char(c)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        use fmt::Write;
+        write!(Escape(f), "{}", self.0)
+    }
+}
+
+// 正在运行的测试的序号（从 1 开始）、名字（指针和长度）及其截止的 tick，
+// 0 表示没有
+static CURRENT_INDEX: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_NAME: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_LEN: AtomicUsize = AtomicUsize::new(0);
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+// 正在运行的测试调用 `skip` 时给出的原因
+static SKIP_REASON: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// 在测试中调用，表示测试需要的设备或数据不存在，实际上什么也没有检查。
+/// 调用后测试应当直接返回，运行器把它报告为跳过而不是通过。
+pub fn skip(reason: &'static str) {
+    *SKIP_REASON.lock() = Some(reason);
+}
+
+/// 正在运行的测试的名字，不在测试中时为空。
+pub(crate) fn current_name() -> &'static str {
+    unsafe {
+        let bytes = core::slice::from_raw_parts(
+            CURRENT_NAME.load(Ordering::Relaxed) as *const u8,
+            CURRENT_LEN.load(Ordering::Relaxed),
+        );
+        core::str::from_utf8_unchecked(bytes)
+    }
+}
+
+fn timeout_ms() -> u64 {
+    cmdline::get("test_timeout")
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(DEFAULT_TIMEOUT_MS)
+}
+
+pub fn test_runner(tests: &[&dyn Testable]) {
+    let format = Format::current();
+    match format {
+        Format::Human => serial_println!("Running {} tests", tests.len()),
+        Format::Tap => serial_println!("TAP version 13\n1..{}", tests.len()),
+        Format::Json => serial_println!(r#"{{"type":"suite","tests":{}}}"#, tests.len()),
+    }
+    let timeout = timeout_ms();
+    // 没有调用 `time::init` 的集成测试测不出耗时，不打印
+    let timed = time::calibrated();
+    let suite_start = time::tsc();
+    let mut skipped = 0;
+    for (index, test) in tests.iter().enumerate() {
+        let name = test.name();
+        if format == Format::Human {
+            serial_print!("{}...\t", name);
+        }
+        CURRENT_INDEX.store(index + 1, Ordering::Relaxed);
+        CURRENT_NAME.store(name.as_ptr() as usize, Ordering::Relaxed);
+        CURRENT_LEN.store(name.len(), Ordering::Relaxed);
+        DEADLINE.store(
+            time::ticks() + (timeout * time::TICK_HZ).div_ceil(1000),
+            Ordering::Relaxed,
+        );
+        let start = time::tsc();
+        test.run();
+        let us = time::tsc_to_us(time::tsc() - start);
+        DEADLINE.store(0, Ordering::Relaxed);
+        if let Some(reason) = SKIP_REASON.lock().take() {
+            skipped += 1;
+            match format {
+                Format::Human => serial_println!("[skipped: {}]", reason),
+                Format::Tap => serial_println!("ok {} - {} # SKIP {}", index + 1, name, reason),
+                Format::Json => serial_println!(
+                    r#"{{"type":"test","name":"{}","result":"skipped","reason":"{}"}}"#,
+                    JsonEscaped(name),
+                    JsonEscaped(reason)
+                ),
+            }
+            continue;
+        }
+        match format {
+            // 人看的输出保持原样，耗时只出现在机器读的格式和最后的汇总里
+            Format::Human => serial_println!("[ok]"),
+            Format::Tap if timed => serial_println!(
+                "ok {} - {} # time={}.{:03}ms",
+                index + 1,
+                name,
+                us / 1000,
+                us % 1000
+            ),
+            Format::Tap => serial_println!("ok {} - {}", index + 1, name),
+            Format::Json if timed => serial_println!(
+                r#"{{"type":"test","name":"{}","result":"ok","duration_us":{}}}"#,
+                JsonEscaped(name),
+                us
+            ),
+            Format::Json => serial_println!(
+                r#"{{"type":"test","name":"{}","result":"ok"}}"#,
+                JsonEscaped(name)
+            ),
+        }
+    }
+    CURRENT_INDEX.store(0, Ordering::Relaxed);
+    let us = time::tsc_to_us(time::tsc() - suite_start);
+    let passed = tests.len() - skipped;
+    match format {
+        Format::Human => {
+            serial_print!("{} tests passed", passed);
+            if skipped > 0 {
+                serial_print!(", {} skipped", skipped);
+            }
+            if timed {
+                serial_print!(" in {} ms", us / 1000);
+            }
+            serial_println!();
+        }
+        Format::Tap => {}
+        Format::Json if timed => serial_println!(
+            r#"{{"type":"done","passed":{},"skipped":{},"duration_us":{}}}"#,
+            passed,
+            skipped,
+            us
+        ),
+        Format::Json => serial_println!(
+            r#"{{"type":"done","passed":{},"skipped":{}}}"#,
+            passed,
+            skipped
+        ),
+    }
+    exit_qemu(QemuExitCode::Success);
+    // 没有 isa-debug-exit 设备时（比如在其他模拟器或真机上）关机
+    power::shutdown();
+}
+
+/// 由时钟中断调用：当前测试超时后在中断里 panic，经 [`test_panic_handler`]
+/// 报告是哪个测试并以失败码退出，而不是让 CI 一直挂着。
+///
+/// 测试卡在关中断的代码里、或者时钟中断丢了 EOI 时这里也收不到中断，
+/// 只能靠外面的超时。
+pub(crate) fn tick() {
+    let deadline = DEADLINE.load(Ordering::Relaxed);
+    if deadline == 0 || time::ticks() < deadline {
+        return;
+    }
+    DEADLINE.store(0, Ordering::Relaxed);
+    // 超时不算预期中的 panic
+    EXPECT_PANIC.store(false, Ordering::SeqCst);
+    panic!(
+        "test {} timed out after {} ms",
+        current_name(),
+        timeout_ms()
+    );
+}
+
+/// 测试用的 panic 处理函数：[`ShouldPanic`] 测试中的 panic 回到测试运行器，
+/// 其他的按输出格式报告失败，再以失败码退出 QEMU，见 [`panic::handle`]。
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    if EXPECT_PANIC.swap(false, Ordering::SeqCst) {
+        unsafe { testing_throw(&raw const CHECKPOINT) };
+    }
+    let index = CURRENT_INDEX.load(Ordering::Relaxed);
+    match Format::current() {
+        Format::Human => serial_println!("[failed]\n"),
+        Format::Tap => serial_println!("not ok {} - {}", index, current_name()),
+        Format::Json => serial_println!(
+            r#"{{"type":"test","name":"{}","result":"failed","message":"{}"}}"#,
+            JsonEscaped(current_name()),
+            JsonEscaped(info.message())
+        ),
+    }
+    panic::handle(info, panic::Action::ExitQemu)
+}
+
+#[test_case]
+fn test_testing_watchdog_armed() {
+    assert!(DEADLINE.load(Ordering::Relaxed) > time::ticks());
+    assert!(current_name().ends_with("test_testing_watchdog_armed"));
+}
+
+should_panic_case!(test_testing_should_panic, {
+    let array = [1, 2, 3];
+    let _ = array[core::hint::black_box(3)];
+});
+
+#[test_case]
+fn test_testing_json_escape() {
+    use core::fmt::Write;
+
+    let mut out = heapless::String::<64>::new();
+    write!(out, "{}", JsonEscaped("a\"b\\c\n\x01")).unwrap();
+    assert_eq!(out.as_str(), "a\\\"b\\\\c\\n\\u0001");
+}
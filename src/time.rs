@@ -0,0 +1,116 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::instructions::port::Port;
+
+/// 时钟中断频率。
+pub const TICK_HZ: u64 = 100;
+
+// PIT 的输入时钟频率
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+// 每毫秒的 TSC 周期数，启动时用 PIT 通道 2 校准
+static TSC_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+// 校准时让 PIT 通道 2 计时的长度
+const CALIBRATE_MS: u64 = 10;
+
+/// 把 PIT 通道 0 设置为每秒 `TICK_HZ` 次中断（BIOS 默认约 18.2 Hz），并校准
+/// TSC。`apic::init_timer` 会改用 Local APIC 定时器，之后 PIT 只用于校准。
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICK_HZ) as u16;
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel0: Port<u8> = Port::new(0x40);
+    unsafe {
+        // 通道 0，先低字节后高字节，模式 3（方波），二进制计数
+        command.write(0x36);
+        channel0.write(divisor as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+    TSC_PER_MS.store(calibrate_tsc().max(1), Ordering::Relaxed);
+}
+
+/// 用 PIT 通道 2 单次计时 `CALIBRATE_MS` 毫秒，数出这期间的 TSC
+/// 周期数。不需要中断。
+fn calibrate_tsc() -> u64 {
+    let count = (PIT_FREQUENCY * CALIBRATE_MS / 1000) as u16;
+    let mut gate: Port<u8> = Port::new(0x61);
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel2: Port<u8> = Port::new(0x42);
+    unsafe {
+        // 打开通道 2 的门控（位 0），关掉扬声器（位 1）
+        let saved = gate.read();
+        gate.write((saved & !0x02) | 0x01);
+        // 通道 2，先低字节后高字节，模式 0（计数结束时输出变高）
+        command.write(0xb0);
+        channel2.write(count as u8);
+        channel2.write((count >> 8) as u8);
+        let start = tsc();
+        // 通道 2 的输出反映在端口 0x61 的位 5
+        while gate.read() & 0x20 == 0 {
+            core::hint::spin_loop();
+        }
+        let end = tsc();
+        gate.write(saved);
+        (end - start) / CALIBRATE_MS
+    }
+}
+
+/// 时间戳计数器的当前值。
+pub fn tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// TSC 是否已经由 [`init`] 校准过。没有校准时换算出的时间没有意义。
+pub fn calibrated() -> bool {
+    TSC_PER_MS.load(Ordering::Relaxed) != 0
+}
+
+/// 每毫秒的 TSC 周期数。
+pub fn tsc_per_ms() -> u64 {
+    TSC_PER_MS.load(Ordering::Relaxed).max(1)
+}
+
+/// 把 TSC 周期数换算成微秒。
+pub fn tsc_to_us(cycles: u64) -> u64 {
+    (cycles as u128 * 1000 / tsc_per_ms() as u128) as u64
+}
+
+/// 忙等 `us` 微秒，不依赖中断。
+pub fn delay_us(us: u64) {
+    let end = tsc() + us * tsc_per_ms() / 1000;
+    while tsc() < end {
+        core::hint::spin_loop();
+    }
+}
+
+/// 由时钟中断调用。
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 启动以来的时钟中断次数。
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// 启动以来的毫秒数，精度为一个 tick。
+pub fn uptime_ms() -> u64 {
+    ticks_to_ms(ticks())
+}
+
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks * 1000 / TICK_HZ
+}
+
+#[test_case]
+fn test_time_tsc_calibrated() {
+    // 校准值至少对应 1 MHz，而且 TSC 应当在一个 tick 内明显前进
+    assert!(tsc_per_ms() > 1000);
+    let start = tsc();
+    let ticks = ticks();
+    crate::wait_until(|| self::ticks() != ticks);
+    let elapsed_us = tsc_to_us(tsc() - start);
+    assert!(elapsed_us <= 2 * 1000 * 1000 / TICK_HZ + 1000);
+}
@@ -0,0 +1,187 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{cmdline, percpu, println, ring::Ring, time};
+
+/// 每个 CPU 的环形缓冲区容量。
+pub const CAPACITY: usize = 1024;
+/// 有跟踪缓冲区的 CPU 个数，编号更大的 CPU 上的跟踪点被忽略。
+pub const MAX_CPUS: usize = 8;
+/// 一个跟踪点最多记录的参数个数。
+pub const MAX_ARGS: usize = 2;
+
+/// 一个跟踪点。
+///
+/// 每个 `trace!` 调用处有一个静态的 `Event`，缓冲区里只存它的地址。
+pub struct Event {
+    pub name: &'static str,
+}
+
+impl Event {
+    pub const fn new(name: &'static str) -> Self {
+        Event { name }
+    }
+}
+
+/// 记录一个跟踪点：`trace!("irq", irq)`，参数会被转换成 `u64`。
+///
+/// 关闭跟踪时只有一次原子读，参数也不会被求值。
+#[macro_export]
+macro_rules! trace {
+    ($event:literal $(, $arg:expr)* $(,)?) => {
+        if $crate::trace::enabled() {
+            static EVENT: $crate::trace::Event = $crate::trace::Event::new($event);
+            $crate::trace::record(&EVENT, [$($arg as u64),*]);
+        }
+    };
+}
+
+// 每条记录依次是 TSC、`Event` 的地址和参数
+const WORDS: usize = 2 + MAX_ARGS;
+
+// 每个 CPU 只往自己的缓冲区写，写入方之间不需要同步
+static RINGS: [Ring<CAPACITY, WORDS>; MAX_CPUS] = [const { Ring::new() }; MAX_CPUS];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 命令行带有 `trace` 时从启动开始记录。
+pub fn init() {
+    if cmdline::flag("trace") {
+        set_enabled(true);
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// 一条跟踪记录。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub tsc: u64,
+    pub cpu: usize,
+    pub name: &'static str,
+    pub args: [u64; MAX_ARGS],
+}
+
+/// 记录一个跟踪点，通常经由 [`trace!`] 调用。无锁，可以在中断处理函数中调用。
+pub fn record<const N: usize>(event: &'static Event, args: [u64; N]) {
+    const { assert!(N <= MAX_ARGS, "too many tracepoint arguments") };
+    // 编号超出的 CPU 不记录，写入方之间仍然不需要同步
+    let Some(ring) = RINGS.get(percpu::id()) else {
+        return;
+    };
+    let mut words = [0; WORDS];
+    words[0] = time::tsc();
+    words[1] = event as *const Event as u64;
+    words[2..2 + N].copy_from_slice(&args);
+    ring.push(words);
+}
+
+fn decode(cpu: usize, words: [u64; WORDS]) -> Record {
+    // 地址来自 `record` 收到的 `&'static Event`
+    let event = unsafe { &*(words[1] as *const Event) };
+    Record {
+        tsc: words[0],
+        cpu,
+        name: event.name,
+        args: core::array::from_fn(|i| words[2 + i]),
+    }
+}
+
+/// 按时间顺序遍历 CPU `cpu` 缓冲区中的记录。
+pub fn for_each(cpu: usize, mut f: impl FnMut(Record)) {
+    if let Some(ring) = RINGS.get(cpu) {
+        ring.for_each(|words| f(decode(cpu, words)));
+    }
+}
+
+/// 按 TSC 顺序遍历所有 CPU 的记录。
+pub fn for_each_merged(mut f: impl FnMut(Record)) {
+    // 每个缓冲区本身是有序的，每次取各个缓冲区的下一条中最早的一条
+    let mut cursors = RINGS.each_ref().map(Ring::range);
+    let mut heads = [None; MAX_CPUS];
+    loop {
+        for (cpu, head) in heads.iter_mut().enumerate() {
+            while head.is_none()
+                && let Some(index) = cursors[cpu].next()
+            {
+                *head = RINGS[cpu].get(index).map(|words| decode(cpu, words));
+            }
+        }
+        let earliest = heads
+            .iter_mut()
+            .filter(|head| head.is_some())
+            .min_by_key(|head| head.map(|record| record.tsc));
+        let Some(record) = earliest.and_then(Option::take) else {
+            return;
+        };
+        f(record);
+    }
+}
+
+/// 清空所有缓冲区。
+pub fn clear() {
+    for ring in &RINGS {
+        ring.clear();
+    }
+}
+
+/// 把所有 CPU 的记录按 TSC 顺序输出到控制台，时间相对于最早的一条。
+/// shell 的 `trace` 命令经由系统调用使用它。
+pub fn dump() {
+    let mut start = None;
+    for_each_merged(|record| {
+        let start = *start.get_or_insert(record.tsc);
+        let us = time::tsc_to_us(record.tsc.saturating_sub(start));
+        println!(
+            "[{:>6}.{:06}] cpu{} {} {:#x} {:#x}",
+            us / 1_000_000,
+            us % 1_000_000,
+            record.cpu,
+            record.name,
+            record.args[0],
+            record.args[1]
+        );
+    });
+}
+
+#[test_case]
+fn test_trace_records() {
+    let was_enabled = enabled();
+    set_enabled(false);
+    let mut evaluated = false;
+    crate::trace!("test.disabled", {
+        evaluated = true;
+        1
+    });
+    assert!(!evaluated);
+
+    set_enabled(true);
+    crate::trace!("test.first", 1, 2);
+    crate::trace!("test.second", 3);
+    set_enabled(was_enabled);
+
+    let mut seen = heapless::Vec::<Record, 2>::new();
+    for_each(0, |record| {
+        if record.name.starts_with("test.") {
+            if seen.is_full() {
+                seen.remove(0);
+            }
+            let _ = seen.push(record);
+        }
+    });
+    assert_eq!(seen.len(), 2);
+    assert_eq!((seen[0].name, seen[0].args), ("test.first", [1, 2]));
+    assert_eq!((seen[1].name, seen[1].args), ("test.second", [3, 0]));
+    assert!(seen[0].tsc <= seen[1].tsc);
+
+    let mut last = 0;
+    for_each_merged(|record| {
+        assert!(record.tsc >= last);
+        last = record.tsc;
+    });
+}
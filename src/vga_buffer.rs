@@ -1,5 +1,4 @@
 // 编译器忽略未使用代码的警告
-use volatile::Volatile;
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 // Rust 枚举的底层类型是平台相关的（通常是 isize 或 usize）。通过使用
@@ -51,73 +50,133 @@ struct ScreenChar {
 
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
+/// 第一个文字行。第 0 行留给状态栏，普通输出和滚屏都不会碰它。
+pub const TEXT_TOP: usize = 1;
 
 #[repr(transparent)]
 // 如果没有 #[repr(transparent)]，你就不能直接将 Buffer 作为 ScreenChar
 // 数组来处理。例如，&Buffer 和 &Buffer.chars 的类型可能会不同，但通过
 // #[repr(transparent)]，&Buffer 和 &Buffer.chars
 // 的类型在内存中是一致的，这样你可以方便地操作和传递数组。
+//
+// 终端只在普通内存中的这份影子缓冲区上读写，由 `Writer::flush` 把变化的行
+// 复制到 0xb8000。显存的读写很慢，逐个字符 volatile 访问会让滚屏成为输出的瓶颈
 struct Buffer {
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    chars: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
+
+// 显存里的一行
+type HardwareRow = [ScreenChar; BUFFER_WIDTH];
+const HARDWARE_BUFFER: *mut HardwareRow = 0xb8000 as *mut HardwareRow;
+// 所有行都需要复制到显存
+const ALL_ROWS: u32 = (1 << BUFFER_HEIGHT) - 1;
+
 pub struct Writer {
     column_position: usize,
+    // 光标所在的行。平时总在最后一行，只有 ANSI 光标定位会把它移到别处
+    row_position: usize,
     color_code: ColorCode,
+    ansi: ansi::Parser,
     // 我们对借用使用显式生命周期（explicit lifetime），告诉编译器这个借用在何时有效
     buffer: &'static mut Buffer,
+    // 所属的虚拟终端。只有正在显示的终端的内容会被复制到 0xb8000
+    vt: usize,
+    // 影子缓冲区中还没有复制到显存的行，每行一位
+    dirty: u32,
 }
 
 impl Writer {
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
-
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
+            byte => self.write_glyph(byte),
+        }
+    }
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
-            }
+    // 输出代码页 437 中的一个字形，0x0a 也只是字形 ◙，不换行
+    fn write_glyph(&mut self, byte: u8) {
+        if self.column_position >= BUFFER_WIDTH {
+            self.new_line();
         }
+
+        let row = self.row_position;
+        let col = self.column_position;
+
+        let color_code = self.color_code;
+        self.buffer.chars[row][col] = ScreenChar {
+            ascii_character: byte,
+            color_code,
+        };
+        self.mark_dirty(row..row + 1);
+        self.column_position += 1;
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
+        // 按行刷新：写完一整行才复制到显存
+        let flush = s.contains('\n');
+        for c in s.chars() {
+            // 非 ASCII 字符不参与转义序列，按代码页 437 找字形，找不到的用 ■ 替代
+            if !c.is_ascii() {
+                self.write_glyph(cp437::encode(c).unwrap_or(0xfe));
+                continue;
+            }
+            match self.ansi.advance(c as u8) {
                 // 可打印的 ASCII 字符（0x20 空格到 0x7e ~）
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
+                ansi::Action::Print(byte @ (0x20..=0x7e | b'\n')) => self.write_byte(byte),
+                // BEL 响铃
+                ansi::Action::Print(0x07) => speaker::bell(),
                 // 不可打印的字符用 ■ 替代
-                _ => self.write_byte(0xfe),
+                ansi::Action::Print(_) => self.write_byte(0xfe),
+                ansi::Action::Csi(csi) => self.apply_csi(&csi),
+                ansi::Action::None => {}
             }
         }
+        if flush {
+            self.flush();
+        }
     }
     pub fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
-            }
+        if self.row_position < BUFFER_HEIGHT - 1 {
+            self.row_position += 1;
+            self.column_position = 0;
+            return;
         }
+        SCROLLBACKS[self.vt].lock().push(self.buffer);
+        self.buffer
+            .chars
+            .copy_within(TEXT_TOP + 1..BUFFER_HEIGHT, TEXT_TOP);
+        self.mark_dirty(TEXT_TOP..BUFFER_HEIGHT);
         self.clear_row(BUFFER_HEIGHT - 1);
         self.column_position = 0;
     }
 
+    fn mark_dirty(&mut self, rows: core::ops::Range<usize>) {
+        self.dirty |= (ALL_ROWS >> (BUFFER_HEIGHT - rows.end)) & (ALL_ROWS << rows.start);
+    }
+
+    /// 把影子缓冲区中变化过的行复制到显存。不在显示的终端什么也不做。
+    pub fn flush(&mut self) {
+        let dirty = core::mem::take(&mut self.dirty);
+        if dirty == 0 || !self.active() {
+            return;
+        }
+        for row in (0..BUFFER_HEIGHT).filter(|row| dirty & (1 << row) != 0) {
+            // 整行一次写入，不再逐个字符访问显存
+            unsafe {
+                HARDWARE_BUFFER
+                    .add(row)
+                    .write_volatile(self.buffer.chars[row])
+            };
+        }
+    }
+
     fn clear_row(&mut self, row: usize) {
         let blank = ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
-        }
+        self.buffer.chars[row] = [blank; BUFFER_WIDTH];
+        self.mark_dirty(row..row + 1);
     }
 }
 #[allow(dead_code)]
@@ -125,8 +184,12 @@ pub fn print_something() {
     use core::fmt::Write;
     let mut writer = Writer {
         column_position: 0,
+        row_position: BUFFER_HEIGHT - 1,
+        ansi: ansi::Parser::new(),
         color_code: ColorCode::new(Color::Yellow, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        vt: CONSOLE_VT,
+        dirty: 0,
     };
 
     writer.write_byte(b'H');
@@ -135,7 +198,244 @@ pub fn print_something() {
     write!(writer, "The numbers are {} and {}", 42, 1.0 / 3.0).unwrap();
 }
 
-use core::fmt;
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::{
+    console::{ConsoleSink, LogLevel},
+    speaker,
+};
+
+// 启用 `vga-mirror` 特性时，屏幕的变化同时以增量帧发到 COM2，
+// 主机上用 tools/vga_viewer.py 在终端里还原画面
+#[cfg(feature = "vga-mirror")]
+mod mirror;
+
+// 滚出屏幕的行，Shift+PageUp/PageDown 翻看
+mod scrollback;
+
+// 控制台支持的 ANSI 转义序列：SGR 颜色、光标定位和擦除
+mod ansi;
+
+// 把 Unicode 字符换成 VGA 字体（代码页 437）中的字形
+mod cp437;
+
+// ANSI 颜色 0..=7 对应的 VGA 颜色；加亮的版本是 VGA 的 8..=15
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+
+// CRT 控制器：先向索引端口写寄存器号，再读写数据端口
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+const CRTC_CURSOR_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOW: u8 = 0x0f;
+// 光标起始寄存器的位 5 关闭光标
+const CURSOR_DISABLE: u8 = 1 << 5;
+// 字符高 16 条扫描线，光标占最下面的两条
+const CURSOR_START_LINE: u8 = 14;
+const CURSOR_END_LINE: u8 = 15;
+
+fn crtc_write(register: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(CRTC_INDEX).write(register);
+        Port::<u8>::new(CRTC_DATA).write(value);
+    }
+}
+
+fn ansi_color(index: u16, bright: bool) -> u8 {
+    ANSI_COLORS[index as usize % 8] as u8 | if bright { 8 } else { 0 }
+}
+
+impl Writer {
+    fn apply_csi(&mut self, csi: &ansi::Csi) {
+        let row = self.row_position;
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        // 光标移动的参数省略时为 1，定位的行列从 1 开始
+        let step = csi.param(0, 1) as usize;
+        match csi.action {
+            b'm' => self.apply_sgr(csi.params()),
+            b'H' | b'f' => {
+                let row = TEXT_TOP + csi.param(0, 1) as usize - 1;
+                let col = csi.param(1, 1) as usize - 1;
+                self.set_position(row, col);
+            }
+            b'A' => self.set_position(row.saturating_sub(step), col),
+            b'B' => self.set_position(row + step, col),
+            b'C' => self.set_position(row, col + step),
+            b'D' => self.set_position(row, col.saturating_sub(step)),
+            b'G' => self.set_position(row, step - 1),
+            b'J' => match csi.param(0, 0) {
+                0 => {
+                    self.clear_cells(row, col..BUFFER_WIDTH);
+                    (row + 1..BUFFER_HEIGHT).for_each(|row| self.clear_row(row));
+                }
+                1 => {
+                    (TEXT_TOP..row).for_each(|row| self.clear_row(row));
+                    self.clear_cells(row, 0..col + 1);
+                }
+                _ => (TEXT_TOP..BUFFER_HEIGHT).for_each(|row| self.clear_row(row)),
+            },
+            // `ESC [ ? 25 l` 和 `ESC [ ? 25 h`：隐藏、显示光标
+            b'l' if csi.param(0, 0) == 25 => self.hide_cursor(),
+            b'h' if csi.param(0, 0) == 25 => self.show_cursor(),
+            b'K' => match csi.param(0, 0) {
+                0 => self.clear_cells(row, col..BUFFER_WIDTH),
+                1 => self.clear_cells(row, 0..col + 1),
+                _ => self.clear_row(row),
+            },
+            _ => {}
+        }
+    }
+
+    /// 处理 SGR（`ESC [ ... m`），不认识的属性被忽略。
+    fn apply_sgr(&mut self, params: &[u16]) {
+        let theme = theme();
+        let default = ColorCode::new(theme.foreground, theme.background).0;
+        let mut code = self.color_code.0;
+        // 没有参数的 `ESC [ m` 等同于 `ESC [ 0 m`
+        for &param in params.iter().chain(params.is_empty().then_some(&0)) {
+            let (fg, bg) = (code & 0x0f, code & 0xf0);
+            code = match param {
+                0 => default,
+                // 文本模式没有粗体，用加亮代替
+                1 => code | 0x08,
+                22 => code & !0x08,
+                30..=37 => bg | ansi_color(param - 30, fg & 0x08 != 0),
+                39 => bg | (default & 0x0f),
+                40..=47 => ansi_color(param - 40, false) << 4 | fg,
+                49 => (default & 0xf0) | fg,
+                90..=97 => bg | ansi_color(param - 90, true),
+                100..=107 => ansi_color(param - 100, true) << 4 | fg,
+                _ => code,
+            };
+        }
+        self.color_code = ColorCode(code);
+    }
+
+    /// 之后输出的文字使用的颜色。
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background);
+    }
+
+    /// 用当前颜色清空所有文字行，光标回到最后一行开头。
+    pub fn clear_screen(&mut self) {
+        (TEXT_TOP..BUFFER_HEIGHT).for_each(|row| self.clear_row(row));
+        self.set_position(BUFFER_HEIGHT - 1, 0);
+    }
+
+    /// 从 `row` 行 `col` 列开始用当前颜色写入 `s`，不移动光标，也不换行：
+    /// 超出这一行的部分被丢弃。`s` 中的转义序列和换行没有特殊含义。
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str) {
+        self.put_chars(row, col, s.chars(), self.color_code);
+    }
+
+    // 把 `chars` 的字形放到 `row` 行，从 `col` 列开始，到行尾为止
+    fn put_chars(
+        &mut self,
+        row: usize,
+        col: usize,
+        chars: impl Iterator<Item = char>,
+        color_code: ColorCode,
+    ) {
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        let cols = col.min(BUFFER_WIDTH)..BUFFER_WIDTH;
+        for (col, c) in cols.zip(chars) {
+            self.buffer.chars[row][col] = ScreenChar {
+                ascii_character: cp437::encode(c).unwrap_or(0xfe),
+                color_code,
+            };
+        }
+        self.mark_dirty(row..row + 1);
+    }
+
+    /// 把光标移到 `row` 行 `col` 列（从 0 开始），超出屏幕的部分被截到边上，
+    /// 状态栏所在的行也不行。之后的输出从这里开始。
+    pub fn set_cursor(&mut self, row: usize, col: usize) {
+        self.set_position(row, col);
+        self.update_cursor();
+    }
+
+    /// 当前光标的位置（行、列）。
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    /// 这个终端是否正在显示。
+    fn active(&self) -> bool {
+        self.vt == active_terminal()
+    }
+
+    /// 让硬件光标停在下一个字符将要出现的位置。
+    fn update_cursor(&self) {
+        if !self.active() {
+            return;
+        }
+        // 一行写满时下一个字符会出现在下一行开头，在那之前光标停在最后一列
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        let offset = (self.row_position * BUFFER_WIDTH + col) as u16;
+        crtc_write(CRTC_CURSOR_LOW, offset as u8);
+        crtc_write(CRTC_CURSOR_HIGH, (offset >> 8) as u8);
+    }
+
+    /// 隐藏闪烁的硬件光标。
+    pub fn hide_cursor(&mut self) {
+        if !self.active() {
+            return;
+        }
+        crtc_write(CRTC_CURSOR_START, CURSOR_DISABLE);
+    }
+
+    /// 重新显示硬件光标，形状是字符底部的两行扫描线。
+    pub fn show_cursor(&mut self) {
+        if !self.active() {
+            return;
+        }
+        crtc_write(CRTC_CURSOR_START, CURSOR_START_LINE);
+        crtc_write(CRTC_CURSOR_END, CURSOR_END_LINE);
+        self.update_cursor();
+    }
+
+    fn set_position(&mut self, row: usize, col: usize) {
+        self.row_position = row.clamp(TEXT_TOP, BUFFER_HEIGHT - 1);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+    }
+
+    fn clear_cells(&mut self, row: usize, cols: core::ops::Range<usize>) {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        self.buffer.chars[row][cols].fill(blank);
+        self.mark_dirty(row..row + 1);
+    }
+}
+
+impl Writer {
+    /// 把屏幕变化同步给串口镜像；未启用 `vga-mirror` 时什么也不做。
+    fn mirror(&self) {
+        #[cfg(feature = "vga-mirror")]
+        if self.active() {
+            mirror::sync(&*self.buffer);
+        }
+    }
+}
+
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.write_string(s);
@@ -145,17 +445,83 @@ impl fmt::Write for Writer {
 
 use lazy_static::lazy_static;
 use spin::Mutex;
+
+/// 虚拟终端的个数，用 Alt+F1..F4 切换。
+pub const VT_COUNT: usize = 4;
+/// `print!`/`println!` 输出到的终端（VT1），启动时显示的就是它。
+pub const CONSOLE_VT: usize = 0;
+/// 日志输出到的终端（VT2）。
+pub const LOG_VT: usize = 1;
+
+// 各终端的影子缓冲区。与 `Buffer` 布局相同，各终端只借用自己的那一份
+static mut SCREENS: [[[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT]; VT_COUNT] =
+    [[[ScreenChar {
+        ascii_character: 0,
+        color_code: ColorCode(0),
+    }; BUFFER_WIDTH]; BUFFER_HEIGHT]; VT_COUNT];
+
+static ACTIVE: AtomicUsize = AtomicUsize::new(CONSOLE_VT);
+
+// 调用者保证同一时刻只有终端 `vt` 自己持有这个引用
+fn screen_buffer(vt: usize) -> &'static mut Buffer {
+    unsafe { &mut *(&raw mut SCREENS[vt]).cast::<Buffer>() }
+}
+
+impl Writer {
+    fn new(vt: usize) -> Self {
+        let buffer = screen_buffer(vt);
+        if vt == CONSOLE_VT {
+            // 保留引导程序留在屏幕上的内容
+            for (row, line) in buffer.chars.iter_mut().enumerate() {
+                *line = unsafe { HARDWARE_BUFFER.add(row).read_volatile() };
+            }
+        }
+        Writer {
+            column_position: 0,
+            row_position: BUFFER_HEIGHT - 1,
+            ansi: ansi::Parser::new(),
+            color_code: ColorCode::new(Color::Yellow, Color::Black),
+            buffer,
+            vt,
+            dirty: 0,
+        }
+    }
+}
+
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+    /// 各个虚拟终端的输出。
+    pub static ref TERMINALS: [Mutex<Writer>; VT_COUNT] =
+        core::array::from_fn(|vt| Mutex::new(Writer::new(vt)));
+}
+
+/// 正在显示的终端。
+pub fn active_terminal() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// 显示终端 `vt`：原来的终端退出翻看历史，再把 `vt` 的内容整屏画到显存。
+pub fn switch_terminal(vt: usize) {
+    let from = active_terminal();
+    if vt >= VT_COUNT || vt == from {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let mut old = TERMINALS[from].lock();
+        SCROLLBACKS[from].lock().reset(old.buffer);
+        drop(old);
+
+        let mut new = TERMINALS[vt].lock();
+        ACTIVE.store(vt, Ordering::Relaxed);
+        new.dirty = ALL_ROWS;
+        new.flush();
+        new.show_cursor();
+        new.mirror();
     });
 }
 
 #[macro_export]
 macro_rules! print {
-    ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
 }
 
 #[macro_export]
@@ -172,10 +538,270 @@ macro_rules! println {
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
 
-    use x86_64::instructions::interrupts;
+    with_writer(|writer| writer.write_fmt(args).unwrap());
+}
+
+// 各终端滚出屏幕的行。在终端的锁内使用，锁的顺序总是先终端后它
+static SCROLLBACKS: [Mutex<scrollback::Scrollback>; VT_COUNT] =
+    [const { Mutex::new(scrollback::Scrollback::new()) }; VT_COUNT];
+
+/// 强行释放所有终端的锁，只在 panic 时使用：被打断的代码可能正持有它们。
+///
+/// # Safety
+/// 调用者必须保证之后不会再有别的代码按原来的方式使用这些锁。
+pub(crate) unsafe fn force_unlock() {
+    for vt in 0..VT_COUNT {
+        unsafe {
+            TERMINALS[vt].force_unlock();
+            SCROLLBACKS[vt].force_unlock();
+        }
+    }
+}
+
+/// 是否有终端的锁被持有。只在崩溃报告中使用，不等锁。
+pub(crate) fn is_locked() -> bool {
+    TERMINALS
+        .iter()
+        .any(|terminal| terminal.try_lock().is_none())
+}
+
+/// 关中断后锁住控制台终端交给 `f`，见 [`with_terminal`]。
+pub(crate) fn with_writer<R>(f: impl FnOnce(&mut Writer) -> R) -> R {
+    with_terminal(CONSOLE_VT, f)
+}
+
+/// 关中断后锁住终端 `vt` 交给 `f`，写完后刷新显存、同步光标和镜像。
+///
+/// 正在翻看历史时先回到当前屏幕。
+pub(crate) fn with_terminal<R>(vt: usize, f: impl FnOnce(&mut Writer) -> R) -> R {
+    interrupts::without_interrupts(|| {
+        let mut writer = TERMINALS[vt].lock();
+        if SCROLLBACKS[vt].lock().reset(writer.buffer) {
+            writer.dirty = ALL_ROWS;
+            // 翻看历史时藏起来的光标
+            writer.show_cursor();
+        }
+        let result = f(&mut writer);
+        // 没有换行的输出（比如提示符）也要显示出来
+        writer.flush();
+        // 每次输出结束后才移动硬件光标，不必每个字符都访问一次端口
+        writer.update_cursor();
+        writer.mirror();
+        result
+    })
+}
+
+/// 向上（`lines` 为正）或向下翻看滚出屏幕的历史，有新的输出时自动回到当前屏幕。
+pub fn scroll(lines: isize) {
+    interrupts::without_interrupts(|| {
+        let vt = active_terminal();
+        let mut writer = TERMINALS[vt].lock();
+        let mut scrollback = SCROLLBACKS[vt].lock();
+        scrollback.scroll(writer.buffer, lines);
+        writer.dirty = ALL_ROWS;
+        writer.flush();
+        // 翻看历史时光标没有意义
+        if scrollback.offset() == 0 {
+            writer.show_cursor();
+        } else {
+            writer.hide_cursor();
+        }
+        writer.mirror();
+    });
+}
+
+/// 翻一页时移动的行数，保留一行上下文。
+pub const SCROLL_PAGE: isize = (BUFFER_HEIGHT - TEXT_TOP) as isize - 1;
+
+/// 在正在显示的终端的状态栏（第 0 行）上显示 `text`，不足一行的部分用空格补齐。
+///
+/// 与其他输出不同，刷新状态栏不会退出翻看历史：历史只占状态栏以下的行。
+pub fn set_status(text: &str) {
+    let theme = theme();
+    interrupts::without_interrupts(|| {
+        let mut writer = TERMINALS[active_terminal()].lock();
+        // 状态栏用反色，和下面的文字区分开
+        let color_code = ColorCode::new(theme.background, theme.foreground);
+        let padded = text.chars().chain(core::iter::repeat(' '));
+        writer.put_chars(0, 0, padded, color_code);
+        writer.flush();
+        writer.mirror();
+    });
+}
+
+/// 清空屏幕。
+pub fn clear_screen() {
+    with_writer(|writer| writer.clear_screen());
+}
+
+/// 设置之后输出的颜色，直到下一次设置或切换主题。
+pub fn set_color(foreground: Color, background: Color) {
+    with_writer(|writer| writer.set_color(foreground, background));
+}
+
+/// 在 `row` 行 `col` 列写入 `s`，不影响光标位置，见 [`Writer::write_at`]。
+pub fn write_at(row: usize, col: usize, s: &str) {
+    with_writer(|writer| writer.write_at(row, col, s));
+}
+
+/// 以 `foreground` 为前景色输出，完成后恢复原来的颜色。
+#[doc(hidden)]
+pub fn _print_color(foreground: Color, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    with_writer(|writer| {
+        let saved = writer.color_code;
+        writer.color_code = ColorCode((saved.0 & 0xf0) | foreground as u8);
+        writer.write_fmt(args).unwrap();
+        writer.color_code = saved;
+    });
+}
+
+/// 用指定的前景色在 VGA 屏幕上输出，例如 `print_color!(Color::Red, "{}",
+/// err)`。
+#[macro_export]
+macro_rules! print_color {
+    ($color:expr, $($arg:tt)*) => ($crate::vga_buffer::_print_color($color, format_args!($($arg)*)));
+}
+
+/// 用指定的前景色在 VGA 屏幕上输出一行。
+#[macro_export]
+macro_rules! println_color {
+    ($color:expr) => ($crate::print_color!($color, "\n"));
+    ($color:expr, $($arg:tt)*) => ($crate::print_color!($color, "{}\n", format_args!($($arg)*)));
+}
+
+/// 控制台配色方案。日志级别除了颜色之外还总是带有文字前缀（见
+/// [`LogLevel::prefix`]），所以单色主题下也能区分严重程度。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub name: &'static str,
+    pub foreground: Color,
+    pub background: Color,
+    /// 按 `LogLevel` 的数值索引：error, warn, info, debug, trace
+    pub levels: [Color; 5],
+}
+
+impl Theme {
+    pub fn level_color(&self, level: LogLevel) -> Color {
+        self.levels[level as usize]
+    }
+}
+
+pub const THEMES: [Theme; 4] = [
+    Theme {
+        name: "normal",
+        foreground: Color::Yellow,
+        background: Color::Black,
+        levels: [
+            Color::LightRed,
+            Color::Pink,
+            Color::LightGreen,
+            Color::LightGray,
+            Color::DarkGray,
+        ],
+    },
+    // 16 色文本模式下对 solarized dark 的近似
+    Theme {
+        name: "solarized",
+        foreground: Color::LightGray,
+        background: Color::Black,
+        levels: [
+            Color::Red,
+            Color::Brown,
+            Color::Cyan,
+            Color::Blue,
+            Color::DarkGray,
+        ],
+    },
+    Theme {
+        name: "high-contrast",
+        foreground: Color::White,
+        background: Color::Black,
+        levels: [
+            Color::LightRed,
+            Color::Yellow,
+            Color::White,
+            Color::LightCyan,
+            Color::LightGray,
+        ],
+    },
+    // 不依赖颜色区分信息，适合色觉障碍用户
+    Theme {
+        name: "monochrome",
+        foreground: Color::LightGray,
+        background: Color::Black,
+        levels: [
+            Color::White,
+            Color::White,
+            Color::LightGray,
+            Color::LightGray,
+            Color::LightGray,
+        ],
+    },
+];
+
+static THEME: AtomicUsize = AtomicUsize::new(0);
+
+/// 当前使用的主题。
+pub fn theme() -> &'static Theme {
+    &THEMES[THEME.load(Ordering::Relaxed)]
+}
+
+/// 按名字切换主题，未知的名字返回 `false`。
+pub fn set_theme(name: &str) -> bool {
+    let Some(index) = THEMES.iter().position(|theme| theme.name == name) else {
+        return false;
+    };
+    THEME.store(index, Ordering::Relaxed);
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        for terminal in TERMINALS.iter() {
+            let mut writer = terminal.lock();
+            writer.apply_theme(&THEMES[index]);
+            writer.flush();
+            writer.mirror();
+        }
     });
+    true
+}
+
+impl Writer {
+    /// 切换默认颜色，屏幕上已有的默认颜色文字一起换成新颜色。
+    fn apply_theme(&mut self, theme: &Theme) {
+        let old = self.color_code;
+        let new = ColorCode::new(theme.foreground, theme.background);
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let mut character = self.buffer.chars[row][col];
+                if character.color_code == old {
+                    character.color_code = new;
+                    self.buffer.chars[row][col] = character;
+                }
+            }
+        }
+        self.dirty = ALL_ROWS;
+        self.color_code = new;
+    }
+}
+
+impl ConsoleSink for Writer {
+    /// 以日志级别对应的颜色和前缀输出一行。
+    fn write_level(&mut self, level: LogLevel, args: fmt::Arguments) -> fmt::Result {
+        use core::fmt::Write;
+
+        let theme = theme();
+        let saved = self.color_code;
+        self.color_code = ColorCode::new(theme.level_color(level), theme.background);
+        let result = writeln!(self, "{} {}", level.prefix(), args);
+        self.color_code = saved;
+        result
+    }
+}
+
+/// 以日志级别对应的颜色和前缀输出一行。
+#[doc(hidden)]
+pub fn _print_level(level: LogLevel, args: fmt::Arguments) {
+    with_terminal(LOG_VT, |writer| writer.write_level(level, args).unwrap());
 }
 
 #[test_case]
@@ -190,20 +816,212 @@ fn test_println_many() {
     }
 }
 
+#[test_case]
+fn test_theme_colors_level_output() {
+    assert!(!set_theme("no-such-theme"));
+    assert!(set_theme("high-contrast"));
+    _print_level(LogLevel::Warn, format_args!("careful"));
+    interrupts::without_interrupts(|| {
+        let writer = TERMINALS[LOG_VT].lock();
+        let first = writer.buffer.chars[BUFFER_HEIGHT - 2][0];
+        assert_eq!(first.ascii_character, b'[');
+        assert_eq!(
+            first.color_code,
+            ColorCode::new(Color::Yellow, Color::Black)
+        );
+        assert_eq!(
+            writer.color_code,
+            ColorCode::new(Color::White, Color::Black)
+        );
+    });
+    assert!(set_theme("normal"));
+}
+
 #[test_case]
 fn test_println_output() {
     use core::fmt::Write;
 
-    use x86_64::instructions::interrupts;
     let s = "Some test string that fits on a single line";
     interrupts::without_interrupts(|| {
-        let mut writer = WRITER.lock();
+        let mut writer = TERMINALS[CONSOLE_VT].lock();
         #[allow(clippy::uninlined_format_args)]
         writeln!(writer, "\n{}", s).expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
-            let screen_char = WRITER.lock().buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i];
             // 从 u8 转换为 char
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
     });
 }
+
+#[test_case]
+fn test_ansi_escapes() {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = TERMINALS[CONSOLE_VT].lock();
+        let saved = writer.color_code;
+        write!(writer, "\n\x1b[31mred\x1b[0m ok").unwrap();
+        let row = BUFFER_HEIGHT - 1;
+        let first = writer.buffer.chars[row][0];
+        assert_eq!(first.ascii_character, b'r');
+        assert_eq!(
+            first.color_code,
+            ColorCode::new(Color::Red, theme().background)
+        );
+        assert_eq!(writer.buffer.chars[row][4].ascii_character, b'o');
+        assert_eq!(writer.buffer.chars[row][4].color_code, saved);
+
+        // 回到行首改写一个字符，然后擦掉光标之后的部分
+        write!(writer, "\x1b[1GR\x1b[K").unwrap();
+        assert_eq!(writer.buffer.chars[row][0].ascii_character, b'R');
+        assert_eq!(writer.buffer.chars[row][1].ascii_character, b' ');
+        assert_eq!(writer.column_position, 1);
+    });
+}
+
+#[test_case]
+fn test_cp437_output() {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = TERMINALS[CONSOLE_VT].lock();
+        // → 的字形是 0x1a，不能被当成控制字符
+        write!(writer, "\nö─→\u{1f600}\n").unwrap();
+        let glyphs: [u8; 4] =
+            core::array::from_fn(|col| writer.buffer.chars[BUFFER_HEIGHT - 2][col].ascii_character);
+        assert_eq!(glyphs, [0x94, 0xc4, 0x1a, 0xfe]);
+    });
+}
+
+#[test_case]
+fn test_cursor_position() {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = TERMINALS[CONSOLE_VT].lock();
+        writer.set_cursor(3, 200);
+        assert_eq!(writer.cursor(), (3, BUFFER_WIDTH - 1));
+        writer.set_cursor(2, 10);
+        writeln!(writer, "ab").unwrap();
+        assert_eq!(writer.buffer.chars[2][11].ascii_character, b'b');
+        assert_eq!(writer.cursor(), (3, 0));
+        writer.hide_cursor();
+        writer.show_cursor();
+        // 回到最后一行，其他测试依赖这一点
+        writer.set_cursor(BUFFER_HEIGHT - 1, 0);
+    });
+}
+
+#[test_case]
+fn test_scrollback() {
+    for i in 0..BUFFER_HEIGHT {
+        _print(format_args!("scrollback line {i}\n"));
+    }
+    scroll(1);
+    interrupts::without_interrupts(|| {
+        let writer = TERMINALS[CONSOLE_VT].lock();
+        // 最上面的文字行是刚滚出去的那一行，当前屏幕整体下移一行；
+        // 25 行输出在 24 行的文字区里滚出去了两行
+        let top: [u8; 16] =
+            core::array::from_fn(|col| writer.buffer.chars[TEXT_TOP][col].ascii_character);
+        assert_eq!(&top, b"scrollback line ");
+        assert_eq!(writer.buffer.chars[TEXT_TOP][16].ascii_character, b'1');
+        assert_eq!(writer.buffer.chars[TEXT_TOP + 1][16].ascii_character, b'2');
+    });
+    // 刷新状态栏不打断翻看历史
+    set_status("status");
+    interrupts::without_interrupts(|| {
+        let writer = TERMINALS[CONSOLE_VT].lock();
+        assert_eq!(writer.buffer.chars[0][0].ascii_character, b's');
+        assert_eq!(SCROLLBACKS[CONSOLE_VT].lock().offset(), 1);
+    });
+    // 新的输出回到当前屏幕
+    _print(format_args!("live\n"));
+    interrupts::without_interrupts(|| {
+        let writer = TERMINALS[CONSOLE_VT].lock();
+        assert_eq!(
+            writer.buffer.chars[BUFFER_HEIGHT - 2][0].ascii_character,
+            b'l'
+        );
+        assert_eq!(writer.buffer.chars[TEXT_TOP][16].ascii_character, b'3');
+    });
+}
+
+#[test_case]
+fn test_screen_api() {
+    write_at(0, BUFFER_WIDTH - 2, "xyz");
+    println_color!(Color::LightGreen, "green");
+    interrupts::without_interrupts(|| {
+        let writer = TERMINALS[CONSOLE_VT].lock();
+        // 超出行尾的部分被丢弃
+        assert_eq!(
+            writer.buffer.chars[0][BUFFER_WIDTH - 1].ascii_character,
+            b'y'
+        );
+        let green = writer.buffer.chars[BUFFER_HEIGHT - 2][0];
+        assert_eq!(green.ascii_character, b'g');
+        assert_eq!(green.color_code.0 & 0x0f, Color::LightGreen as u8);
+        assert_ne!(writer.color_code, green.color_code);
+    });
+    set_color(Color::White, Color::Blue);
+    clear_screen();
+    interrupts::without_interrupts(|| {
+        let writer = TERMINALS[CONSOLE_VT].lock();
+        let blank = writer.buffer.chars[TEXT_TOP][0];
+        assert_eq!(blank.ascii_character, b' ');
+        assert_eq!(blank.color_code, ColorCode::new(Color::White, Color::Blue));
+        assert_eq!(writer.cursor(), (BUFFER_HEIGHT - 1, 0));
+    });
+    let theme = theme();
+    set_color(theme.foreground, theme.background);
+}
+
+#[test_case]
+fn test_virtual_terminals() {
+    // 终端 `vt` 的影子缓冲区倒数第二行的第三个字符
+    let cell = |vt: usize| {
+        interrupts::without_interrupts(|| {
+            TERMINALS[vt].lock().buffer.chars[BUFFER_HEIGHT - 2][2].ascii_character
+        })
+    };
+    with_terminal(2, |writer| writer.write_string("vt3\n"));
+    _print(format_args!("vt1\n"));
+    // VT3 不在显示，它的输出不会出现在屏幕上
+    assert_eq!(cell(2), b'3');
+    assert_eq!(cell(active_terminal()), b'1');
+
+    switch_terminal(2);
+    assert_eq!(active_terminal(), 2);
+    assert_eq!(cell(2), b'3');
+    assert_eq!(cell(CONSOLE_VT), b'1');
+    assert_eq!(hardware_char(BUFFER_HEIGHT - 2, 2), b'3');
+    switch_terminal(CONSOLE_VT);
+    assert_eq!(cell(active_terminal()), b'1');
+    assert_eq!(hardware_char(BUFFER_HEIGHT - 2, 2), b'1');
+}
+
+#[cfg(test)]
+fn hardware_char(row: usize, col: usize) -> u8 {
+    unsafe { HARDWARE_BUFFER.add(row).read_volatile()[col].ascii_character }
+}
+
+#[test_case]
+fn test_shadow_buffer_flush() {
+    interrupts::without_interrupts(|| {
+        let mut writer = TERMINALS[CONSOLE_VT].lock();
+        writer.write_string("\n");
+        assert_eq!(hardware_char(BUFFER_HEIGHT - 1, 0), b' ');
+        // 没有换行时只写到影子缓冲区
+        writer.write_string("x");
+        assert_eq!(
+            writer.buffer.chars[BUFFER_HEIGHT - 1][0].ascii_character,
+            b'x'
+        );
+        assert_eq!(hardware_char(BUFFER_HEIGHT - 1, 0), b' ');
+        writer.flush();
+        assert_eq!(hardware_char(BUFFER_HEIGHT - 1, 0), b'x');
+        writer.write_string("\n");
+        assert_eq!(hardware_char(BUFFER_HEIGHT - 2, 0), b'x');
+    });
+}
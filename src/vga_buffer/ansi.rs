@@ -0,0 +1,142 @@
+// 只解析 CSI 序列（ESC '[' 参数 最终字节），其他转义序列被整个丢弃
+
+const ESC: u8 = 0x1b;
+
+/// 一个 CSI 序列最多保留的参数个数，多出的被忽略。
+pub const MAX_PARAMS: usize = 8;
+
+/// 解析出的一个 CSI 序列。省略的参数为 0，由使用者决定它的默认值。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    pub params: [u16; MAX_PARAMS],
+    pub len: usize,
+    pub action: u8,
+}
+
+impl Csi {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// 第 `index` 个参数，省略或为 0 时返回 `default`。
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// 普通字节，照常输出
+    Print(u8),
+    /// 一个完整的 CSI 序列
+    Csi(Csi),
+    /// 转义序列中间的字节，不输出
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Params,
+}
+
+/// 逐字节识别 ANSI 转义序列的状态机。
+#[derive(Debug, Clone, Copy)]
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            csi: Csi {
+                params: [0; MAX_PARAMS],
+                len: 0,
+                action: 0,
+            },
+        }
+    }
+
+    pub fn advance(&mut self, byte: u8) -> Action {
+        match (self.state, byte) {
+            (State::Ground, ESC) => {
+                self.state = State::Escape;
+                Action::None
+            }
+            (State::Ground, _) => Action::Print(byte),
+            (State::Escape, b'[') => {
+                self.state = State::Params;
+                self.csi.params = [0; MAX_PARAMS];
+                self.csi.len = 0;
+                Action::None
+            }
+            // 不认识的双字节序列
+            (State::Escape, _) => {
+                self.state = State::Ground;
+                Action::None
+            }
+            (State::Params, b'0'..=b'9') => {
+                if self.csi.len == 0 {
+                    self.csi.len = 1;
+                }
+                if let Some(param) = self.csi.params.get_mut(self.csi.len - 1) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add((byte - b'0') as u16);
+                }
+                Action::None
+            }
+            (State::Params, b';') => {
+                // 开头的 `;` 表示省略了第一个参数
+                self.csi.len = (self.csi.len.max(1) + 1).min(MAX_PARAMS + 1);
+                Action::None
+            }
+            // 私有前缀（如 `?`）和中间字节，直接跳过
+            (State::Params, 0x20..=0x3f) => Action::None,
+            (State::Params, 0x40..=0x7e) => {
+                self.state = State::Ground;
+                self.csi.len = self.csi.len.min(MAX_PARAMS);
+                self.csi.action = byte;
+                Action::Csi(self.csi)
+            }
+            // 序列中出现控制字符或非法字节时放弃它
+            (State::Params, _) => {
+                self.state = State::Ground;
+                Action::None
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_ansi_parser() {
+    let mut parser = Parser::new();
+    let mut last = Action::None;
+    for &byte in b"\x1b[1;31m" {
+        last = parser.advance(byte);
+    }
+    let Action::Csi(csi) = last else {
+        panic!("expected a CSI sequence");
+    };
+    assert_eq!((csi.action, csi.params()), (b'm', &[1, 31][..]));
+    assert_eq!(parser.advance(b'x'), Action::Print(b'x'));
+
+    for &byte in b"\x1b[;5H" {
+        last = parser.advance(byte);
+    }
+    let Action::Csi(csi) = last else {
+        panic!("expected a CSI sequence");
+    };
+    assert_eq!((csi.param(0, 1), csi.param(1, 1)), (1, 5));
+
+    // 不认识的 `ESC c` 被整个吞掉
+    assert_eq!(parser.advance(0x1b), Action::None);
+    assert_eq!(parser.advance(b'c'), Action::None);
+    assert_eq!(parser.advance(b'y'), Action::Print(b'y'));
+}
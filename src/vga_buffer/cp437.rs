@@ -0,0 +1,69 @@
+// 代码页 437：VGA 文本模式字符发生器里固化的字形。ASCII 可打印字符与它一致，
+// 其余的 Unicode 字符按下表找到对应的字形
+
+// 0x01..=0x1f 和 0x80..=0xff 处的字形。0x00 不是字形，只用来占位
+const LOW: [char; 32] = [
+    // 0x00
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    // 0x10
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+const HIGH: [char; 128] = [
+    // 0x80
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    // 0x90
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    // 0xa0
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    // 0xb0
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    // 0xc0
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    // 0xd0
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    // 0xe0
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    // 0xf0
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// 字形相同、码位不同的字符
+const ALIASES: [(char, u8); 2] = [('β', 0xe1), ('μ', 0xe6)];
+
+/// `c` 在代码页 437 中的编码，没有对应字形时返回 `None`。
+///
+/// ASCII 控制字符不是字形，也返回 `None`。
+pub fn encode(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        '⌂' => Some(0x7f),
+        '\0'..='\u{7f}' => None,
+        _ => LOW
+            .iter()
+            .position(|&glyph| glyph == c)
+            .or_else(|| HIGH.iter().position(|&glyph| glyph == c).map(|i| i + 0x80))
+            .map(|i| i as u8)
+            .or_else(|| {
+                ALIASES
+                    .iter()
+                    .find(|&&(alias, _)| alias == c)
+                    .map(|&(_, byte)| byte)
+            }),
+    }
+}
+
+#[test_case]
+fn test_cp437_encode() {
+    assert_eq!(encode('A'), Some(b'A'));
+    assert_eq!(encode('ö'), Some(0x94));
+    assert_eq!(encode('é'), Some(0x82));
+    assert_eq!(encode('─'), Some(0xc4));
+    assert_eq!(encode('╔'), Some(0xc9));
+    assert_eq!(encode('→'), Some(0x1a));
+    assert_eq!(encode('■'), Some(0xfe));
+    assert_eq!(encode('μ'), encode('µ'));
+    assert_eq!(encode('\n'), None);
+    assert_eq!(encode('\0'), None);
+    assert_eq!(encode('中'), None);
+}
@@ -0,0 +1,52 @@
+use spin::Mutex;
+
+use super::{BUFFER_HEIGHT, BUFFER_WIDTH, Buffer};
+use crate::serial::{self, Com};
+
+// 帧格式：ESC 'V' row col len，随后是 len 个 (字符, 属性) 字节对。
+// 每帧描述一行中发生变化的连续区间，由 tools/vga_viewer.py 解码
+const FRAME_START: [u8; 2] = [0x1b, b'V'];
+
+// 镜像使用 COM2，不和 COM1 上的测试输出/日志混在一起
+struct Mirror {
+    // 已经发送给主机的屏幕内容；初始值不可能出现在屏幕上，保证第一次同步是全屏
+    sent: [[u16; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+static MIRROR: Mutex<Mirror> = Mutex::new(Mirror {
+    sent: [[0xffff; BUFFER_WIDTH]; BUFFER_HEIGHT],
+});
+
+/// 把与上次发送不同的部分发到 COM2。调用者需要已经关闭中断。
+pub(super) fn sync(buffer: &Buffer) {
+    let mut mirror = MIRROR.lock();
+    let mut port = serial::port(Com::Com2);
+    for row in 0..BUFFER_HEIGHT {
+        let mut current = [0u16; BUFFER_WIDTH];
+        for (col, cell) in current.iter_mut().enumerate() {
+            let character = buffer.chars[row][col];
+            *cell = u16::from_le_bytes([character.ascii_character, character.color_code.0]);
+        }
+        let sent = &mirror.sent[row];
+        let Some(first) = (0..BUFFER_WIDTH).find(|&col| current[col] != sent[col]) else {
+            continue;
+        };
+        let last = (0..BUFFER_WIDTH)
+            .rev()
+            .find(|&col| current[col] != sent[col])
+            .unwrap_or(first);
+
+        for byte in FRAME_START {
+            port.send_raw(byte);
+        }
+        port.send_raw(row as u8);
+        port.send_raw(first as u8);
+        port.send_raw((last - first + 1) as u8);
+        for cell in &current[first..=last] {
+            for byte in cell.to_le_bytes() {
+                port.send_raw(byte);
+            }
+        }
+        mirror.sent[row] = current;
+    }
+}
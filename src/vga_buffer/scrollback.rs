@@ -0,0 +1,84 @@
+use super::{BUFFER_HEIGHT, BUFFER_WIDTH, Buffer, ScreenChar, TEXT_TOP};
+
+/// 保留的历史行数。
+pub const LINES: usize = 200;
+
+type Line = [ScreenChar; BUFFER_WIDTH];
+
+// 全零，让四个终端的历史都放在 .bss 里。这些内容只会被覆盖，不会被显示
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: 0,
+    color_code: super::ColorCode(0),
+};
+
+/// 从屏幕顶端滚出去的行，以及翻看历史时被盖住的当前屏幕。
+pub(super) struct Scrollback {
+    lines: [Line; LINES],
+    // 最旧一行的下标
+    head: usize,
+    len: usize,
+    // 向上翻了多少行，0 表示正在显示当前屏幕
+    offset: usize,
+    live: [Line; BUFFER_HEIGHT],
+}
+
+impl Scrollback {
+    pub(super) const fn new() -> Self {
+        Scrollback {
+            lines: [[BLANK; BUFFER_WIDTH]; LINES],
+            head: 0,
+            len: 0,
+            offset: 0,
+            live: [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT],
+        }
+    }
+
+    /// 记下即将从屏幕顶端滚出去的一行，满了之后覆盖最旧的。
+    pub(super) fn push(&mut self, buffer: &Buffer) {
+        let index = (self.head + self.len) % LINES;
+        self.lines[index] = buffer.chars[TEXT_TOP];
+        if self.len < LINES {
+            self.len += 1;
+        } else {
+            self.head = (self.head + 1) % LINES;
+        }
+    }
+
+    pub(super) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// 向上（`lines` 为正）或向下翻看历史并重画屏幕。
+    pub(super) fn scroll(&mut self, buffer: &mut Buffer, lines: isize) {
+        let offset = self.offset.saturating_add_signed(lines).min(self.len);
+        if offset == self.offset {
+            return;
+        }
+        if self.offset == 0 {
+            self.live[TEXT_TOP..].copy_from_slice(&buffer.chars[TEXT_TOP..]);
+        }
+        self.offset = offset;
+        self.render(buffer);
+    }
+
+    /// 回到当前屏幕，返回是否重画了屏幕。已经在显示当前屏幕时什么也不做。
+    pub(super) fn reset(&mut self, buffer: &mut Buffer) -> bool {
+        if self.offset == 0 {
+            return false;
+        }
+        self.offset = 0;
+        self.render(buffer);
+        true
+    }
+
+    // 把历史的最后 `offset` 行和当前屏幕拼在一起，取最上面的一屏。状态栏不动
+    fn render(&self, buffer: &mut Buffer) {
+        for row in TEXT_TOP..BUFFER_HEIGHT {
+            let line = match (row - TEXT_TOP).checked_sub(self.offset) {
+                Some(live) => &self.live[TEXT_TOP + live],
+                None => &self.lines[(self.head + self.len - self.offset + row - TEXT_TOP) % LINES],
+            };
+            buffer.chars[row] = *line;
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use spin::Mutex;
+
+use crate::{
+    apic, cmdline, crash,
+    interrupts::{self, PicState},
+    serial, serial_print, serial_println, time,
+};
+
+/// 默认的超时时间（秒），可以用命令行的 `watchdog=<秒>` 修改。
+pub const DEFAULT_TIMEOUT_S: u64 = 5;
+
+// 级联线，从片有中断在服务时它也在服务
+const CASCADE_IRQ: usize = 2;
+
+/// 一次检查时采集到的中断状态。
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    pic: PicState,
+    counts: [u64; 16],
+    apic_in_service: Option<u8>,
+}
+
+/// 持续超过超时时间的异常情况，位 n 对应 IRQ n。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Findings {
+    /// 处理完却没有 EOI：在服务位一直不清除，同级和更低优先级的中断都被挡住
+    missed_eoi: u16,
+    /// 有请求、没屏蔽，处理函数却一直没有运行
+    stuck: u16,
+    /// Local APIC 上一直没有 EOI 的向量
+    apic_missed_eoi: Option<u8>,
+}
+
+impl Findings {
+    fn any(&self) -> bool {
+        self.missed_eoi != 0 || self.stuck != 0 || self.apic_missed_eoi.is_some()
+    }
+}
+
+/// 看门狗的状态：每种情况从哪个 tick 开始出现。
+struct Watchdog {
+    next_check: u64,
+    in_service_since: [Option<u64>; 16],
+    pending_since: [Option<u64>; 16],
+    last_counts: [u64; 16],
+    apic_since: Option<(u8, u64)>,
+    // 当前这次异常已经报告过，恢复正常之前不再重复
+    reported: bool,
+}
+
+impl Watchdog {
+    const fn new() -> Self {
+        Watchdog {
+            next_check: 0,
+            in_service_since: [None; 16],
+            pending_since: [None; 16],
+            last_counts: [0; 16],
+            apic_since: None,
+            reported: false,
+        }
+    }
+
+    /// 用新的采样更新状态，返回已经持续 `limit` 个 tick 的异常。
+    fn check(&mut self, now: u64, limit: u64, sample: &Sample) -> Findings {
+        let mut findings = Findings::default();
+        let expired = |since: u64| now.saturating_sub(since) >= limit;
+        // 检查是在时钟中断里做的，用 PIT 作时钟时 IRQ 0 总在服务
+        for irq in (1..16).filter(|&irq| irq != CASCADE_IRQ) {
+            let bit = 1 << irq;
+            let since = &mut self.in_service_since[irq];
+            if sample.pic.in_service & bit == 0 {
+                *since = None;
+            } else if expired(*since.get_or_insert(now)) {
+                findings.missed_eoi |= bit;
+            }
+
+            let pending = sample.pic.requested & bit != 0 && sample.pic.masked & bit == 0;
+            let since = &mut self.pending_since[irq];
+            if !pending || sample.counts[irq] != self.last_counts[irq] {
+                *since = None;
+            } else if expired(*since.get_or_insert(now)) {
+                findings.stuck |= bit;
+            }
+            self.last_counts[irq] = sample.counts[irq];
+        }
+
+        // 除了时钟中断自己（采样时已经去掉），Local APIC 上不应有向量在服务
+        self.apic_since = match (sample.apic_in_service, self.apic_since) {
+            (None, _) => None,
+            (Some(vector), Some((last, since))) if vector == last => {
+                if expired(since) {
+                    findings.apic_missed_eoi = Some(vector);
+                }
+                Some((last, since))
+            }
+            (Some(vector), _) => Some((vector, now)),
+        };
+        findings
+    }
+}
+
+static WATCHDOG: Mutex<Watchdog> = Mutex::new(Watchdog::new());
+
+fn timeout_s() -> u64 {
+    cmdline::get("watchdog")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_TIMEOUT_S)
+}
+
+/// 由时钟中断调用，每秒检查一次各条中断线有没有前进。命令行带
+/// `nowatchdog` 或 `watchdog=0` 时不检查。
+///
+/// 只采样 PIC 的状态、16 条 IRQ 线各自的计数和 Local APIC 的在服务向量，
+/// 不看任务执行器或者驱动内部的进度（内核还没有执行器）：某条线有请求却
+/// 长时间不计数，或者在服务位长时间不清除，就把中断统计和锁的状态写到
+/// 串口。
+///
+/// 看门狗本身靠时钟中断运行，下面这些情况它发现不了：
+/// - 时钟自己的 EOI 丢了，之后不再有 tick。
+/// - 改用 Local APIC 定时器之后，优先级不低于定时器向量（同一个优先级组
+///   或者更高）的向量没有 EOI 时会挡住定时器，同样不再有 tick。
+/// - 比定时器向量低的向量丢了 EOI 也看不出：检查时定时器本身就在服务，
+///   `apic::in_service` 只报告最高的那一个向量。
+pub(crate) fn tick() {
+    let now = time::ticks();
+    let Some(mut watchdog) = WATCHDOG.try_lock() else {
+        return;
+    };
+    if now < watchdog.next_check {
+        return;
+    }
+    watchdog.next_check = now + time::TICK_HZ;
+    let timeout = timeout_s();
+    if timeout == 0 || cmdline::flag("nowatchdog") {
+        return;
+    }
+    let Some(pic) = interrupts::pic_state() else {
+        return;
+    };
+    let mut sample = Sample {
+        pic,
+        apic_in_service: apic::in_service().filter(|&vector| vector != apic::TIMER_VECTOR),
+        ..Sample::default()
+    };
+    for (irq, count) in sample.counts.iter_mut().enumerate() {
+        *count = interrupts::irq_count(irq as u8);
+    }
+    let findings = watchdog.check(now, timeout * time::TICK_HZ, &sample);
+    if !findings.any() {
+        watchdog.reported = false;
+    } else if !watchdog.reported && !serial::is_locked() {
+        // 串口正被占用时等下一次检查再报告
+        watchdog.reported = true;
+        report(timeout, &findings, &sample);
+    }
+}
+
+fn report(timeout: u64, findings: &Findings, sample: &Sample) {
+    let held = crash::held_locks();
+    serial_println!("watchdog: no interrupt progress for {}s", timeout);
+    for irq in 0..16 {
+        if findings.missed_eoi & (1 << irq) != 0 {
+            serial_println!("  IRQ {} in service without EOI", irq);
+        }
+        if findings.stuck & (1 << irq) != 0 {
+            serial_println!("  IRQ {} requested but never handled", irq);
+        }
+    }
+    if let Some(vector) = findings.apic_missed_eoi {
+        serial_println!("  local APIC vector {:#x} in service without EOI", vector);
+    }
+    serial_println!(
+        "  PIC irr={:#06x} isr={:#06x} mask={:#06x}",
+        sample.pic.requested,
+        sample.pic.in_service,
+        sample.pic.masked
+    );
+    serial_print!("  IRQ counts:");
+    for count in sample.counts {
+        serial_print!(" {}", count);
+    }
+    serial_println!();
+    crash::print_held_locks(&held);
+}
+
+#[test_case]
+fn test_watchdog_detects_missed_eoi() {
+    let mut watchdog = Watchdog::new();
+    let mut sample = Sample::default();
+    // IRQ 1 处理完没有 EOI，IRQ 4 有请求却一直没处理
+    sample.pic.in_service = 1 << 1 | 1 << CASCADE_IRQ;
+    sample.pic.requested = 1 << 4;
+    sample.counts[1] = 7;
+    assert_eq!(watchdog.check(100, 50, &sample), Findings::default());
+    assert_eq!(watchdog.check(120, 50, &sample), Findings::default());
+    let findings = watchdog.check(150, 50, &sample);
+    assert_eq!(findings.missed_eoi, 1 << 1);
+    assert_eq!(findings.stuck, 1 << 4);
+
+    // 屏蔽的请求不算；计数在变说明还在前进
+    sample.pic.masked = 1 << 4;
+    sample.pic.in_service = 0;
+    assert!(!watchdog.check(200, 50, &sample).any());
+    sample.pic.masked = 0;
+    assert!(!watchdog.check(210, 50, &sample).any());
+    sample.counts[4] += 1;
+    assert!(!watchdog.check(300, 50, &sample).any());
+
+    sample.apic_in_service = Some(0x50);
+    assert!(!watchdog.check(400, 50, &sample).any());
+    assert_eq!(watchdog.check(450, 50, &sample).apic_missed_eoi, Some(0x50));
+}
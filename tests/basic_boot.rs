@@ -10,8 +10,7 @@ use os_rust::println;
 #[unsafe(no_mangle)] // don't mangle the name of this function
 pub extern "C" fn _start() -> ! {
     test_main();
-    #[allow(clippy::empty_loop)]
-    loop {}
+    os_rust::hlt_loop();
 }
 
 #[panic_handler]
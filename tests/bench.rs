@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_rust::bench::runner)]
+#![reexport_test_harness_main = "bench_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use os_rust::{
+    bench_case,
+    console::{self, Sinks},
+    println, rand, vga_buffer,
+};
+
+entry_point!(bench_kernel_main);
+
+fn bench_kernel_main(boot_info: &'static BootInfo) -> ! {
+    os_rust::init(boot_info);
+    // 结果经 serial_println! 直接写串口；被测的输出只进 VGA，免得淹没结果
+    console::set_sinks(Sinks::VGA);
+    bench_main();
+    os_rust::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_rust::test_panic_handler(info)
+}
+
+// 每次都写满一行，触发整屏上滚
+bench_case!(vga_scroll, 2000, {
+    println!("{:<79}", "scroll");
+});
+
+bench_case!(vga_write_at, 2000, {
+    vga_buffer::write_at(0, 0, "benchmark");
+});
+
+bench_case!(rand_next_u64, 2000, {
+    core::hint::black_box(rand::next_u64());
+});
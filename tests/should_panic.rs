@@ -12,15 +12,14 @@ pub extern "C" fn _start() -> ! {
     should_fail();
     serial_println!("[test did not panic]");
     exit_qemu(QemuExitCode::Failed);
-    #[allow(clippy::empty_loop)]
-    loop {}
+    os_rust::hlt_loop();
 }
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     serial_println!("[ok]");
     exit_qemu(QemuExitCode::Success);
-    loop {}
+    os_rust::hlt_loop();
 }
 
 use os_rust::serial_print;
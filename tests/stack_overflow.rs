@@ -18,7 +18,7 @@ pub extern "C" fn _start() -> ! {
 #[allow(unconditional_recursion)]
 fn stack_overflow() {
     stack_overflow(); // for each recursion, the return address is pushed
-    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+    core::hint::black_box(0); // prevent tail recursion optimizations
 }
 
 #[panic_handler]
@@ -52,8 +52,16 @@ extern "x86-interrupt" fn test_double_fault_handler(
     _stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    // 溢出的内核栈已经不能用了，处理函数必须运行在 IST 指定的栈上
+    let rsp: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+    let top = os_rust::gdt::double_fault_stack_top().as_u64();
+    if rsp > top || rsp < top - os_rust::gdt::DOUBLE_FAULT_STACK_SIZE {
+        serial_println!("[failed]\nhandler ran on {:#x}, not the IST stack", rsp);
+        exit_qemu(QemuExitCode::Failed);
+        os_rust::hlt_loop();
+    }
     serial_println!("[ok]");
     exit_qemu(QemuExitCode::Success);
-    #[allow(clippy::empty_loop)]
-    loop {}
+    os_rust::hlt_loop();
 }
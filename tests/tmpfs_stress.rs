@@ -0,0 +1,161 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os_rust::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// 内核还没有堆分配器，tmpfs 的块分配器（FAT 式的空闲链表）是唯一会分配
+// 又释放的分配器。这里用固定种子的随机操作反复写、截断、删除文件，每个
+// 阶段结束时对照影子副本检查内容，并检查空闲块数与文件大小是否一致。
+
+use core::panic::PanicInfo;
+
+use os_rust::{
+    cmdline,
+    fs::{
+        FileSystem, FileType, InodeId,
+        tmpfs::{BLOCK_SIZE, Tmpfs},
+    },
+};
+use spin::Mutex;
+
+const FILES: usize = 16;
+const MAX_FILE: usize = 16 * BLOCK_SIZE;
+// 比所有文件都写满还多留几块，保证不会出现空间不足
+const BLOCKS: usize = FILES * MAX_FILE / BLOCK_SIZE + 16;
+const OPERATIONS: usize = 20_000;
+const PHASE: usize = 1000;
+
+static FS: Mutex<Tmpfs> = Mutex::new(Tmpfs::new());
+static mut STORAGE: [u8; BLOCKS * BLOCK_SIZE] = [0; BLOCKS * BLOCK_SIZE];
+// 每个文件应有的内容
+static mut SHADOW: [[u8; MAX_FILE]; FILES] = [[0; MAX_FILE]; FILES];
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    test_main();
+    os_rust::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os_rust::test_panic_handler(info)
+}
+
+/// splitmix64，种子可以用命令行的 `stress_seed=<数字>` 指定以便复现。
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+fn name(file: usize) -> &'static str {
+    const NAMES: [&str; FILES] = [
+        "f0", "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12", "f13",
+        "f14", "f15",
+    ];
+    NAMES[file]
+}
+
+/// 对照影子副本检查每个文件的大小和内容，再检查空闲空间。
+fn check(
+    fs: &mut Tmpfs,
+    shadow: &[[u8; MAX_FILE]; FILES],
+    inodes: &[Option<InodeId>; FILES],
+    sizes: &[usize; FILES],
+) {
+    let mut buf = [0u8; MAX_FILE];
+    let mut used_blocks = 0;
+    for file in 0..FILES {
+        let Some(inode) = inodes[file] else {
+            assert_eq!(
+                fs.lookup(0, name(file)),
+                Err(os_rust::fs::FsError::NotFound)
+            );
+            continue;
+        };
+        let size = sizes[file];
+        assert_eq!(fs.metadata(inode).unwrap().size, size as u64);
+        assert_eq!(fs.read_at(inode, 0, &mut buf), Ok(size));
+        if let Some(offset) = (0..size).find(|&i| buf[i] != shadow[file][i]) {
+            panic!("{}: mismatch at offset {}", name(file), offset);
+        }
+        used_blocks += size.div_ceil(BLOCK_SIZE);
+    }
+    assert_eq!(fs.free_space(), (BLOCKS - used_blocks) * BLOCK_SIZE);
+}
+
+#[test_case]
+fn test_tmpfs_block_allocator_stress() {
+    let seed = cmdline::get("stress_seed")
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(0x5eed);
+    let mut rng = Rng(seed);
+    let (storage, shadow) = (&raw mut STORAGE, &raw mut SHADOW);
+    let mut fs = FS.lock();
+    fs.format(unsafe { &mut *storage });
+    let shadow = unsafe { &mut *shadow };
+    let mut inodes = [None; FILES];
+    let mut sizes = [0usize; FILES];
+    let mut data = [0u8; MAX_FILE];
+
+    for operation in 1..=OPERATIONS {
+        let file = rng.below(FILES);
+        let Some(inode) = inodes[file] else {
+            inodes[file] = Some(fs.create(0, name(file), FileType::File).unwrap());
+            shadow[file].fill(0);
+            sizes[file] = 0;
+            continue;
+        };
+        match rng.below(10) {
+            // 写入：起点可能超出文件末尾，留下读作 0 的空洞
+            0..=5 => {
+                let offset = rng.below(MAX_FILE);
+                let len = 1 + rng.below(MAX_FILE - offset);
+                let pattern = rng.next() as u8;
+                for (i, byte) in data[..len].iter_mut().enumerate() {
+                    *byte = pattern ^ (i as u8).wrapping_mul(31);
+                }
+                assert_eq!(fs.write_at(inode, offset as u64, &data[..len]), Ok(len));
+                shadow[file][offset..offset + len].copy_from_slice(&data[..len]);
+                sizes[file] = sizes[file].max(offset + len);
+            }
+            // 截断，变短或变长
+            6..=8 => {
+                let size = rng.below(MAX_FILE + 1);
+                fs.truncate(inode, size as u64).unwrap();
+                if size < sizes[file] {
+                    shadow[file][size..].fill(0);
+                }
+                sizes[file] = size;
+            }
+            // 删除，下次选中时重新创建
+            _ => {
+                fs.remove(0, name(file)).unwrap();
+                inodes[file] = None;
+            }
+        }
+        if operation % PHASE == 0 {
+            check(&mut fs, shadow, &inodes, &sizes);
+        }
+    }
+
+    // 全部删除后所有块都应当回到空闲链表
+    for (file, inode) in inodes.iter_mut().enumerate() {
+        if inode.take().is_some() {
+            fs.remove(0, name(file)).unwrap();
+        }
+    }
+    check(&mut fs, shadow, &inodes, &sizes);
+    assert_eq!(fs.free_space(), BLOCKS * BLOCK_SIZE);
+}
@@ -0,0 +1,92 @@
+#!/usr/bin/env python3
+"""Build the user-space programs in user/ and pack them into an initrd.
+
+Every top-level .rs file in user/ except sys.rs is a freestanding program. It
+is compiled for x86_64-unknown-none (`rustup target add x86_64-unknown-none`)
+and linked into the user half of the address space (see src/process.rs):
+
+    python3 tools/build_user.py [--rootfs DIR] [-o target/user/initrd.tar]
+
+The programs end up under bin/ in the archive, next to the contents of
+--rootfs if given. `cargo run` does this through tools/run.py and boots with
+
+    -fw_cfg name=opt/os-rust/initrd,file=target/user/initrd.tar
+
+and the kernel runs /bin/init once it has started.
+"""
+
+import argparse
+import io
+import pathlib
+import subprocess
+import sys
+import tarfile
+
+ROOT = pathlib.Path(__file__).resolve().parent.parent
+USER = ROOT / "user"
+OUT = ROOT / "target" / "user"
+INITRD = OUT / "initrd.tar"
+
+# Must match memory::USER_BASE
+USER_BASE = 0x4000_0000_0000
+
+RUSTC_FLAGS = [
+    "--edition=2024",
+    "--target=x86_64-unknown-none",
+    "-Copt-level=s",
+    "-Cpanic=abort",
+    # The kernel loads ELF segments as they are and does no relocation, so
+    # link a fixed-address executable. Code above 2 GiB needs the large model.
+    "-Crelocation-model=static",
+    "-Ccode-model=large",
+    f"-Clink-arg=--image-base={USER_BASE:#x}",
+    # Segments with different permissions must not share a page
+    "-Clink-arg=-zseparate-loadable-segments",
+]
+
+
+def build(source):
+    output = OUT / "bin" / source.stem
+    output.parent.mkdir(parents=True, exist_ok=True)
+    subprocess.run(
+        ["rustc", *RUSTC_FLAGS, "-o", str(output), str(source)],
+        cwd=ROOT,
+        check=True,
+    )
+    return output
+
+
+def add_file(archive, name, data, mode=0o755):
+    info = tarfile.TarInfo(name)
+    info.size = len(data)
+    info.mode = mode
+    archive.addfile(info, io.BytesIO(data))
+
+
+def build_initrd(output, rootfs=None):
+    programs = [build(source) for source in sorted(USER.glob("*.rs")) if source.name != "sys.rs"]
+    if not any(program.name == "init" for program in programs):
+        sys.exit("user/init.rs is missing")
+
+    output.parent.mkdir(parents=True, exist_ok=True)
+    with tarfile.open(output, "w", format=tarfile.USTAR_FORMAT) as archive:
+        if rootfs:
+            archive.add(rootfs, arcname=".")
+        for program in programs:
+            add_file(archive, f"bin/{program.name}", program.read_bytes())
+    return programs
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("--rootfs", type=pathlib.Path, help="extra files for the archive")
+    parser.add_argument("-o", "--output", type=pathlib.Path, default=INITRD)
+    args = parser.parse_args()
+
+    programs = build_initrd(args.output, args.rootfs)
+    names = ", ".join(program.name for program in programs)
+    print(f"{args.output}: {names}")
+
+
+if __name__ == "__main__":
+    main()
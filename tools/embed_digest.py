@@ -0,0 +1,57 @@
+#!/usr/bin/env python3
+"""Embed the SHA-256 of the kernel's .text into its .kernel_digest section.
+
+Run on the linked kernel ELF before building the boot image, e.g.
+
+    python3 tools/embed_digest.py target/x86_64-os/debug/os-rust
+
+At boot `integrity::verify()` hashes .text in memory and compares it with the
+record written here (see src/integrity.rs for the layout).
+"""
+
+import hashlib
+import struct
+import sys
+
+MAGIC = b"OSRDIGST"
+
+
+def sections(elf):
+    (shoff,) = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+    headers = [
+        struct.unpack_from("<IIQQQQIIQQ", elf, shoff + i * shentsize)
+        for i in range(shnum)
+    ]
+    strtab_offset = headers[shstrndx][4]
+    for name, _type, _flags, addr, offset, size, *_ in headers:
+        end = elf.index(b"\0", strtab_offset + name)
+        yield elf[strtab_offset + name : end].decode(), addr, offset, size
+
+
+def main(path):
+    with open(path, "rb") as f:
+        elf = bytearray(f.read())
+    if elf[:4] != b"\x7fELF" or elf[4] != 2:
+        sys.exit(f"{path}: not an ELF64 file")
+
+    found = {name: (addr, offset, size) for name, addr, offset, size in sections(elf)}
+    if ".text" not in found or ".kernel_digest" not in found:
+        sys.exit(f"{path}: missing .text or .kernel_digest section")
+
+    text_addr, text_offset, text_size = found[".text"]
+    _, record_offset, _ = found[".kernel_digest"]
+    if elf[record_offset : record_offset + 8] != MAGIC:
+        sys.exit(f"{path}: .kernel_digest does not start with {MAGIC!r}")
+
+    digest = hashlib.sha256(elf[text_offset : text_offset + text_size]).digest()
+    struct.pack_into("<8sQQ32s", elf, record_offset, MAGIC, text_addr, text_size, digest)
+    with open(path, "wb") as f:
+        f.write(elf)
+    print(f".text {text_addr:#x}+{text_size:#x} sha256={digest.hex()}")
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 2:
+        sys.exit(f"usage: {sys.argv[0]} <kernel-elf>")
+    main(sys.argv[1])
@@ -0,0 +1,141 @@
+#!/usr/bin/env python3
+"""Embed the kernel's function symbols into its .kernel_symbols section.
+
+Run on the linked kernel ELF before building the boot image, e.g.
+
+    python3 tools/embed_symbols.py target/x86_64-os/debug/os-rust
+
+The panic handler walks the frame-pointer chain and resolves return addresses
+against this table (see src/backtrace.rs for the layout).
+"""
+
+import re
+import struct
+import sys
+
+MAGIC = b"OSRSYMS\0"
+HEADER = struct.Struct("<8sQQQ")
+ENTRY = struct.Struct("<QII")
+SYM = struct.Struct("<IBBHQQ")
+STT_FUNC = 2
+# Long generic paths are cut from the front; the function name is at the end
+MAX_NAME = 120
+
+ESCAPES = {
+    "SP": "@",
+    "BP": "*",
+    "RF": "&",
+    "LT": "<",
+    "GT": ">",
+    "LP": "(",
+    "RP": ")",
+    "C": ",",
+}
+
+
+def sections(elf):
+    (shoff,) = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+    headers = [
+        struct.unpack_from("<IIQQQQIIQQ", elf, shoff + i * shentsize)
+        for i in range(shnum)
+    ]
+    strtab_offset = headers[shstrndx][4]
+    for name, _type, _flags, addr, offset, size, link, *_ in headers:
+        end = elf.index(b"\0", strtab_offset + name)
+        yield elf[strtab_offset + name : end].decode(), addr, offset, size, link
+
+
+def unescape(ident):
+    def replace(match):
+        code = match.group(1)
+        if code.startswith("u"):
+            return chr(int(code[1:], 16))
+        return ESCAPES.get(code, match.group(0))
+
+    if ident.startswith("_$"):
+        ident = ident[1:]
+    return re.sub(r"\$([A-Za-z0-9]+)\$", replace, ident).replace("..", "::")
+
+
+def demangle(name):
+    """Demangle a legacy Rust symbol (`_ZN...E`), dropping the hash suffix."""
+    if not name.startswith("_ZN") or not name.endswith("E"):
+        return name
+    parts, pos = [], 3
+    while name[pos] != "E":
+        digits = re.match(r"\d+", name[pos:])
+        if not digits:
+            return name
+        pos += len(digits.group(0))
+        length = int(digits.group(0))
+        parts.append(name[pos : pos + length])
+        pos += length
+    if parts and re.fullmatch(r"h[0-9a-f]{16}", parts[-1]):
+        parts.pop()
+    return "::".join(unescape(part) for part in parts)
+
+
+def functions(elf, table):
+    _, _, symtab_offset, symtab_size, strtab_index = next(
+        section for section in table if section[0] == ".symtab"
+    )
+    strtab_offset = table[strtab_index][2]
+    for i in range(symtab_size // SYM.size):
+        name, info, _other, _shndx, value, size = SYM.unpack_from(
+            elf, symtab_offset + i * SYM.size
+        )
+        if info & 0xF != STT_FUNC or value == 0 or size == 0:
+            continue
+        end = elf.index(b"\0", strtab_offset + name)
+        yield value, elf[strtab_offset + name : end].decode()
+
+
+def main(path):
+    with open(path, "rb") as f:
+        elf = bytearray(f.read())
+    if elf[:4] != b"\x7fELF" or elf[4] != 2:
+        sys.exit(f"{path}: not an ELF64 file")
+
+    table = list(sections(elf))
+    found = {name: rest for name, *rest in table}
+    for name in (".text", ".symtab", ".kernel_symbols"):
+        if name not in found:
+            sys.exit(f"{path}: missing {name} section")
+
+    text_addr, _, text_size, _ = found[".text"]
+    _, record_offset, record_size, _ = found[".kernel_symbols"]
+    if elf[record_offset : record_offset + len(MAGIC)] != MAGIC:
+        sys.exit(f"{path}: .kernel_symbols does not start with {MAGIC!r}")
+
+    symbols = {}
+    for addr, name in functions(elf, table):
+        name = demangle(name)
+        if len(name) > MAX_NAME:
+            name = "..." + name[-(MAX_NAME - 3) :]
+        symbols.setdefault(addr, name)
+
+    entries, names = bytearray(), bytearray()
+    for addr, name in sorted(symbols.items()):
+        encoded = name.encode()
+        entries += ENTRY.pack(addr, len(names), len(encoded))
+        names += encoded
+    space = record_size - HEADER.size
+    if len(entries) + len(names) > space:
+        sys.exit(
+            f"{path}: {len(symbols)} symbols need {len(entries) + len(names)} bytes, "
+            f"only {space} reserved (raise SYMBOL_SPACE in src/backtrace.rs)"
+        )
+
+    HEADER.pack_into(elf, record_offset, MAGIC, text_addr, text_size, len(symbols))
+    data_offset = record_offset + HEADER.size
+    elf[data_offset : data_offset + len(entries) + len(names)] = entries + names
+    with open(path, "wb") as f:
+        f.write(elf)
+    print(f"{len(symbols)} symbols, {len(entries) + len(names)} of {space} bytes")
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 2:
+        sys.exit(f"usage: {sys.argv[0]} <kernel-elf>")
+    main(sys.argv[1])
@@ -0,0 +1,43 @@
+#!/usr/bin/env python3
+"""Cargo runner: rebuild the initrd, then boot the kernel with bootimage.
+
+Set as the runner in .cargo/config.toml, so `cargo run` first packs the user
+programs into target/user/initrd.tar (see tools/build_user.py). The run-args
+in Cargo.toml hand that archive to QEMU. Test kernels do not load the initrd;
+they get a blank scratch disk instead, which the test-args attach as a virtio
+drive so the block driver tests have a device to write to.
+"""
+
+import pathlib
+import subprocess
+import sys
+
+import build_user
+
+TEST_DISK = build_user.ROOT / "target" / "test-disk.img"
+TEST_DISK_SIZE = 1 << 20
+
+
+def main():
+    if len(sys.argv) < 2:
+        sys.exit("usage: run.py <kernel> [args...]")
+    # Cargo puts test executables under deps/; bootimage tells them apart the
+    # same way
+    if pathlib.Path(sys.argv[1]).parent.name == "deps":
+        TEST_DISK.parent.mkdir(parents=True, exist_ok=True)
+        with open(TEST_DISK, "wb") as disk:
+            disk.truncate(TEST_DISK_SIZE)
+    else:
+        try:
+            build_user.build_initrd(build_user.INITRD)
+        except subprocess.CalledProcessError:
+            sys.exit(
+                "building the user programs failed; the x86_64-unknown-none target is "
+                "needed (rustup target add x86_64-unknown-none)"
+            )
+    # run-args name the initrd relative to the repository root
+    sys.exit(subprocess.call(["bootimage", "runner", *sys.argv[1:]], cwd=build_user.ROOT))
+
+
+if __name__ == "__main__":
+    main()
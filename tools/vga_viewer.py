@@ -0,0 +1,140 @@
+#!/usr/bin/env python3
+"""Render the kernel's VGA text buffer in a host terminal.
+
+Build the kernel with `--features vga-mirror`; every screen update is then
+sent over COM2 as delta frames (see src/vga_buffer/mirror.rs):
+
+    ESC 'V' row col len, followed by len (char, attribute) byte pairs
+
+Start QEMU with a second serial port and point the viewer at it, e.g.
+
+    qemu-system-x86_64 ... -serial stdio -serial unix:/tmp/vga.sock,server,nowait
+    python3 tools/vga_viewer.py unix:/tmp/vga.sock
+
+or record to a file (`-serial file:vga.bin`) and replay it, optionally
+following the file as it grows:
+
+    python3 tools/vga_viewer.py vga.bin --follow
+"""
+
+import argparse
+import socket
+import sys
+import time
+
+WIDTH, HEIGHT = 80, 25
+FRAME_START = b"\x1bV"
+
+# VGA 颜色编号 -> ANSI 颜色编号（VGA 的蓝/红位与 ANSI 相反）
+ANSI = [0, 4, 2, 6, 1, 5, 3, 7]
+
+# CP437 0x80-0xff
+CP437_HIGH = (
+    "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐"
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■ "
+)
+
+
+def cp437(byte):
+    if 0x20 <= byte < 0x7F:
+        return chr(byte)
+    if byte >= 0x80:
+        return CP437_HIGH[byte - 0x80]
+    return " "
+
+
+def sgr(attribute):
+    fg, bg = attribute & 0x0F, (attribute >> 4) & 0x07
+    fg_code = (90 if fg & 8 else 30) + ANSI[fg & 7]
+    return f"\x1b[{fg_code};{40 + ANSI[bg]}m"
+
+
+class Screen:
+    def __init__(self):
+        self.cells = [[(0x20, 0x07)] * WIDTH for _ in range(HEIGHT)]
+        self.pending = bytearray()
+
+    def feed(self, data):
+        """Apply complete frames from `data`; returns the rows that changed."""
+        self.pending += data
+        changed = set()
+        while True:
+            start = self.pending.find(FRAME_START)
+            if start < 0:
+                # 保留最后一个字节，它可能是被截断的帧头
+                del self.pending[: max(len(self.pending) - 1, 0)]
+                return changed
+            del self.pending[:start]
+            if len(self.pending) < 5:
+                return changed
+            row, col, length = self.pending[2:5]
+            if row >= HEIGHT or length == 0 or col + length > WIDTH:
+                # 不是合法帧头，跳过后重新同步
+                del self.pending[:1]
+                continue
+            end = 5 + 2 * length
+            if len(self.pending) < end:
+                return changed
+            body = self.pending[5:end]
+            for i in range(length):
+                self.cells[row][col + i] = (body[2 * i], body[2 * i + 1])
+            changed.add(row)
+            del self.pending[:end]
+
+    def render(self, rows, out):
+        for row in sorted(rows):
+            line = [f"\x1b[{row + 1};1H"]
+            attribute = None
+            for char, attr in self.cells[row]:
+                if attr != attribute:
+                    line.append(sgr(attr))
+                    attribute = attr
+                line.append(cp437(char))
+            out.write("".join(line))
+        out.write("\x1b[0m")
+        out.flush()
+
+
+def chunks(source, follow):
+    if source.startswith("unix:"):
+        sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        sock.connect(source[len("unix:") :])
+        while data := sock.recv(4096):
+            yield data
+        return
+    with open(source, "rb") as f:
+        while True:
+            data = f.read(4096)
+            if data:
+                yield data
+            elif follow:
+                time.sleep(0.05)
+            else:
+                return
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("source", help="recorded file or unix:/path/to/socket")
+    parser.add_argument(
+        "--follow", action="store_true", help="keep reading a file as it grows"
+    )
+    args = parser.parse_args()
+
+    screen = Screen()
+    out = sys.stdout
+    out.write("\x1b[2J\x1b[?25l")
+    try:
+        for data in chunks(args.source, args.follow):
+            changed = screen.feed(data)
+            if changed:
+                screen.render(changed, out)
+    except KeyboardInterrupt:
+        pass
+    finally:
+        out.write(f"\x1b[0m\x1b[{HEIGHT + 1};1H\x1b[?25h\n")
+        out.flush()
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,16 @@
+//! 最小的用户程序：打印一行问候然后退出。
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod sys;
+
+#[unsafe(no_mangle)]
+extern "C" fn _start() -> ! {
+    println!(
+        "hello from user space (pid {}, {} ms since boot)",
+        sys::getpid(),
+        sys::uptime_ms()
+    );
+    sys::exit(0)
+}
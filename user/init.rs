@@ -0,0 +1,21 @@
+//! 进程号为 1 的第一个用户程序：启动 shell 并等它退出，退出码就是 shell
+//! 的退出码。
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod sys;
+
+const SHELL: &str = "/bin/sh";
+
+#[unsafe(no_mangle)]
+extern "C" fn _start() -> ! {
+    println!("init: running as pid {}", sys::getpid());
+    let status = sys::spawn(SHELL);
+    if status < 0 {
+        println!("init: cannot run {}: error {}", SHELL, -status);
+    } else {
+        println!("init: {} exited with status {}", SHELL, status);
+    }
+    sys::exit(status)
+}
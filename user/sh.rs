@@ -0,0 +1,131 @@
+//! 交互式 shell：读入一行命令，运行 `/bin/<命令>`（以 `/` 开头时就是这个
+//! 路径）并等它退出。内建命令有 `exit [状态]`、`set <键>=<值>`、
+//! `trace [on|off|clear]` 和 `help`。还不能给程序传参数。Ctrl+C 结束正在
+//! 运行的程序；在提示符下按只会丢掉已经输入的内容。
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod sys;
+
+const LINE_LEN: usize = 128;
+const PATH_LEN: usize = 64;
+const BACKSPACE: u8 = 0x08;
+
+#[unsafe(no_mangle)]
+extern "C" fn _start() -> ! {
+    let mut line = [0; LINE_LEN];
+    loop {
+        print!("$ ");
+        let len = read_line(&mut line);
+        let Ok(line) = core::str::from_utf8(&line[..len]) else {
+            println!("sh: invalid UTF-8");
+            continue;
+        };
+        let mut words = line.split_ascii_whitespace();
+        match words.next() {
+            None => {}
+            Some("exit") => sys::exit(
+                words
+                    .next()
+                    .and_then(|status| status.parse().ok())
+                    .unwrap_or(0),
+            ),
+            Some("set") => match words.next() {
+                Some(setting) => set(setting),
+                None => println!("usage: set keymap|hostname|loglevel=<value>"),
+            },
+            Some("trace") => trace(words.next()),
+            Some("help") => {
+                println!(
+                    "builtins: exit [status], set <key>=<value>, trace [on|off|clear], help; \
+                     other commands run /bin/<name>"
+                )
+            }
+            Some(name) => run(name),
+        }
+    }
+}
+
+// 读到换行为止，返回这一行的长度。字符已经由内核回显，这里只处理退格；
+// 超出 `line` 的部分被丢弃。Ctrl+C 丢掉已经输入的内容，重新显示提示符
+fn read_line(line: &mut [u8]) -> usize {
+    let mut len = 0;
+    let mut buf = [0; 16];
+    loop {
+        let read = sys::read(sys::STDIN, &mut buf);
+        if read == -sys::EINTR {
+            print!("^C\n$ ");
+            len = 0;
+            continue;
+        }
+        if read < 0 {
+            return len;
+        }
+        for &byte in &buf[..read as usize] {
+            match byte {
+                b'\n' => return len,
+                BACKSPACE => {
+                    // 连同 UTF-8 的后续字节一起删掉整个字符
+                    while len > 0 {
+                        len -= 1;
+                        if line[len] & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                }
+                _ if len < line.len() => {
+                    line[len] = byte;
+                    len += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn set(setting: &str) {
+    let status = sys::setconf(setting);
+    if status < 0 {
+        println!("sh: set: {}: error {}", setting, -status);
+    }
+}
+
+// 不带参数时按时间顺序输出内核记录的跟踪点
+fn trace(op: Option<&str>) {
+    let op = match op {
+        None => sys::TRACE_DUMP,
+        Some("on") => sys::TRACE_ON,
+        Some("off") => sys::TRACE_OFF,
+        Some("clear") => sys::TRACE_CLEAR,
+        Some(_) => {
+            println!("usage: trace [on|off|clear]");
+            return;
+        }
+    };
+    sys::trace(op);
+}
+
+fn run(name: &str) {
+    let mut buf = [0; PATH_LEN];
+    let path = if name.starts_with('/') {
+        name
+    } else {
+        let prefix = b"/bin/";
+        let len = prefix.len() + name.len();
+        if len > buf.len() {
+            println!("sh: {}: name too long", name);
+            return;
+        }
+        buf[..prefix.len()].copy_from_slice(prefix);
+        buf[prefix.len()..len].copy_from_slice(name.as_bytes());
+        // 两段都是 UTF-8
+        core::str::from_utf8(&buf[..len]).unwrap()
+    };
+    match sys::spawn(path) {
+        0 => {}
+        status if status == -sys::ENOENT => println!("sh: {}: command not found", name),
+        status if status < 0 => println!("sh: {}: error {}", name, -status),
+        status => println!("sh: {} exited with status {}", name, status),
+    }
+}
@@ -0,0 +1,115 @@
+//! 用户程序共用的系统调用封装和 `println!`。调用号和约定见内核的
+//! src/syscall.rs。
+
+// 每个程序只用到其中一部分
+#![allow(dead_code)]
+
+use core::{arch::asm, fmt, panic::PanicInfo};
+
+const EXIT: u64 = 0;
+const WRITE: u64 = 1;
+const GETPID: u64 = 2;
+const SPAWN: u64 = 3;
+const UPTIME: u64 = 4;
+const READ: u64 = 5;
+const SETCONF: u64 = 6;
+const TRACE: u64 = 7;
+
+pub const TRACE_DUMP: u64 = 0;
+pub const TRACE_ON: u64 = 1;
+pub const TRACE_OFF: u64 = 2;
+pub const TRACE_CLEAR: u64 = 3;
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+
+/// 找不到文件
+pub const ENOENT: i64 = 2;
+/// 等待时被 Ctrl+C 打断
+pub const EINTR: i64 = 4;
+
+// 调用号放在 rax 中，参数依次放在 rdi、rsi、rdx，返回值放回 rax
+fn syscall(number: u64, a: u64, b: u64, c: u64) -> i64 {
+    let result: i64;
+    unsafe {
+        asm!(
+            "int 0x80",
+            inlateout("rax") number => result,
+            in("rdi") a,
+            in("rsi") b,
+            in("rdx") c,
+            options(nostack),
+        );
+    }
+    result
+}
+
+pub fn exit(status: i64) -> ! {
+    syscall(EXIT, status as u64, 0, 0);
+    // exit 不会返回
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn write(fd: u64, bytes: &[u8]) -> i64 {
+    syscall(WRITE, fd, bytes.as_ptr() as u64, bytes.len() as u64)
+}
+
+pub fn getpid() -> u32 {
+    syscall(GETPID, 0, 0, 0) as u32
+}
+
+/// 运行一个程序并等它退出，返回它的退出码；出错时返回负的错误号。
+pub fn spawn(path: &str) -> i64 {
+    syscall(SPAWN, path.as_ptr() as u64, path.len() as u64, 0)
+}
+
+/// 读标准输入，等到至少有一个字节后返回读到的字节数。等待时按下 Ctrl+C
+/// 返回 `-EINTR`。
+pub fn read(fd: u64, buf: &mut [u8]) -> i64 {
+    syscall(READ, fd, buf.as_mut_ptr() as u64, buf.len() as u64)
+}
+
+/// 修改一项设置并保存，`setting` 形如 `keymap=de`。成功时返回 0，
+/// 出错时返回负的错误号。
+pub fn setconf(setting: &str) -> i64 {
+    syscall(SETCONF, setting.as_ptr() as u64, setting.len() as u64, 0)
+}
+
+/// 控制内核的跟踪缓冲区，`op` 是 `TRACE_*` 之一。
+pub fn trace(op: u64) -> i64 {
+    syscall(TRACE, op, 0, 0)
+}
+
+/// 启动以来的毫秒数。
+pub fn uptime_ms() -> u64 {
+    syscall(UPTIME, 0, 0, 0) as u64
+}
+
+struct Stdout;
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write(STDOUT, s.as_bytes());
+        Ok(())
+    }
+}
+
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Stdout, args);
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::sys::_print(format_args!($($arg)*)));
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => (print!("{}\n", format_args!($($arg)*)));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("panic: {}", info);
+    exit(101)
+}